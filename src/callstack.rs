@@ -0,0 +1,269 @@
+use std::mem;
+
+// A shadow of the call stack, kept by the CPU from the CALL, RST and
+// interrupts entering functions and the RET and RETI leaving them, for
+// backtraces.
+//
+// Code doesn't always return the way it was called: it can move SP
+// itself, drop return addresses or jump with PUSH and RET. Those are
+// recorded as imbalances, and the frames that can't be returned to anymore
+// are dropped.
+
+// Deeper calls drop the outermost frames
+const MAX_DEPTH: usize = 256;
+
+// Imbalances kept, the oldest are dropped
+const MAX_IMBALANCES: usize = 16;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Entry {
+    Call,
+    Rst,
+    Interrupt,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Frame {
+    pub entry: Entry,
+    // Address of the CALL or RST, or of the instruction interrupted
+    pub caller: u16,
+    // Address of the function or interrupt handler
+    pub function: u16,
+    pub return_address: u16,
+    // Where the return address is on the stack
+    pub sp: u16,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Problem {
+    // SP moved above these many frames, that were never returned from
+    Dropped(usize),
+    // A return not to the address pushed by the call
+    Mismatch { expected: u16, actual: u16 },
+    // A return without a call, e.g. jumping with PUSH and RET
+    Unmatched { address: u16 },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Imbalance {
+    // Address of the instruction it was found at
+    pub pc: u16,
+    pub problem: Problem,
+}
+
+#[derive(Default)]
+pub struct CallStack {
+    // The innermost frame last
+    frames: Vec<Frame>,
+    imbalances: Vec<Imbalance>,
+    // Imbalances found since the last take_imbalances(), including the
+    // ones dropped
+    imbalance_count: usize,
+}
+
+impl CallStack {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The innermost frame last
+    #[must_use]
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    // After the return address was pushed
+    pub fn enter(&mut self, frame: Frame) {
+        // The slot of the return address was already below SP
+        self.unwind(frame.caller, frame.sp.wrapping_add(1));
+
+        if self.frames.len() == MAX_DEPTH {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+    }
+
+    // pc is the address of the RET or RETI, and sp where the return address
+    // was popped from
+    pub fn exit(&mut self, pc: u16, sp: u16, return_address: u16) {
+        self.unwind(pc, sp);
+
+        match self.frames.last() {
+            Some(frame) if frame.sp == sp => {
+                if frame.return_address != return_address {
+                    self.imbalance(
+                        pc,
+                        Problem::Mismatch {
+                            expected: frame.return_address,
+                            actual: return_address,
+                        },
+                    );
+                }
+
+                self.frames.pop();
+            }
+            _ => self.imbalance(
+                pc,
+                Problem::Unmatched {
+                    address: return_address,
+                },
+            ),
+        }
+    }
+
+    // The imbalances found since the last call, and how many there were
+    // including the ones dropped
+    pub fn take_imbalances(&mut self) -> (Vec<Imbalance>, usize) {
+        let count = self.imbalance_count;
+        self.imbalance_count = 0;
+
+        (mem::take(&mut self.imbalances), count)
+    }
+
+    // Drop the frames with their return address below sp
+    fn unwind(&mut self, pc: u16, sp: u16) {
+        let count = self
+            .frames
+            .iter()
+            .rev()
+            .take_while(|frame| frame.sp < sp)
+            .count();
+
+        if count > 0 {
+            self.frames.truncate(self.frames.len() - count);
+            self.imbalance(pc, Problem::Dropped(count));
+        }
+    }
+
+    fn imbalance(&mut self, pc: u16, problem: Problem) {
+        if self.imbalances.len() == MAX_IMBALANCES {
+            self.imbalances.remove(0);
+        }
+        self.imbalances.push(Imbalance { pc, problem });
+        self.imbalance_count += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // CALL at caller, with SP at sp before it
+    fn call(caller: u16, function: u16, sp: u16) -> Frame {
+        Frame {
+            entry: Entry::Call,
+            caller,
+            function,
+            return_address: caller + 3,
+            sp: sp - 2,
+        }
+    }
+
+    #[test]
+    fn balanced() {
+        let mut stack = CallStack::new();
+        stack.enter(call(0x0150, 0x0200, 0xfffe));
+        stack.enter(call(0x0210, 0x0300, 0xfffc));
+        assert_eq!(stack.frames().len(), 2);
+
+        stack.exit(0x0300, 0xfffa, 0x0213);
+        stack.exit(0x0220, 0xfffc, 0x0153);
+
+        assert!(stack.frames().is_empty());
+        assert_eq!(stack.take_imbalances(), (Vec::new(), 0));
+    }
+
+    #[test]
+    fn mismatch() {
+        let mut stack = CallStack::new();
+        stack.enter(call(0x0150, 0x0200, 0xfffe));
+
+        // The return address was overwritten on the stack
+        stack.exit(0x0200, 0xfffc, 0x1234);
+
+        assert!(stack.frames().is_empty());
+        let imbalance = Imbalance {
+            pc: 0x0200,
+            problem: Problem::Mismatch {
+                expected: 0x0153,
+                actual: 0x1234,
+            },
+        };
+        assert_eq!(stack.take_imbalances(), (vec![imbalance], 1));
+    }
+
+    #[test]
+    fn unmatched() {
+        let mut stack = CallStack::new();
+
+        // PUSH and RET
+        stack.exit(0x0200, 0xfffc, 0x4000);
+
+        let imbalance = Imbalance {
+            pc: 0x0200,
+            problem: Problem::Unmatched { address: 0x4000 },
+        };
+        assert_eq!(stack.take_imbalances(), (vec![imbalance], 1));
+    }
+
+    #[test]
+    fn dropped() {
+        let mut stack = CallStack::new();
+        stack.enter(call(0x0150, 0x0200, 0xfffe));
+        stack.enter(call(0x0210, 0x0300, 0xfffc));
+        stack.enter(call(0x0310, 0x0400, 0xfffa));
+
+        // SP reset, e.g. with LD SP, dropping the two inner frames, then a
+        // return from the outer one
+        stack.exit(0x0220, 0xfffc, 0x0153);
+
+        assert!(stack.frames().is_empty());
+        let imbalance = Imbalance {
+            pc: 0x0220,
+            problem: Problem::Dropped(2),
+        };
+        assert_eq!(stack.take_imbalances(), (vec![imbalance], 1));
+    }
+
+    #[test]
+    fn dropped_by_call() {
+        let mut stack = CallStack::new();
+        stack.enter(call(0x0150, 0x0200, 0xfffe));
+
+        // SP moved back above the frame before calling again
+        stack.enter(call(0x0210, 0x0300, 0xfffe));
+
+        assert_eq!(stack.frames(), &[call(0x0210, 0x0300, 0xfffe)]);
+        let imbalance = Imbalance {
+            pc: 0x0210,
+            problem: Problem::Dropped(1),
+        };
+        assert_eq!(stack.take_imbalances(), (vec![imbalance], 1));
+    }
+
+    #[test]
+    fn limits() {
+        let mut stack = CallStack::new();
+        let mut sp = 0xfffe;
+        for _ in 0..=MAX_DEPTH {
+            stack.enter(call(0x0150, 0x0200, sp));
+            sp -= 2;
+        }
+        assert_eq!(stack.frames().len(), MAX_DEPTH);
+        // The outermost frame was dropped
+        assert_eq!(stack.frames()[0].sp, 0xfffa);
+
+        for i in 0..20 {
+            stack.exit(0x0200 + i, 0xfffe, 0x4000);
+        }
+        let (imbalances, count) = stack.take_imbalances();
+        assert_eq!(count, 21);
+        assert_eq!(imbalances.len(), MAX_IMBALANCES);
+        assert_eq!(
+            imbalances.last().map(|imbalance| imbalance.pc),
+            Some(0x0213)
+        );
+        assert_eq!(stack.take_imbalances(), (Vec::new(), 0));
+    }
+}
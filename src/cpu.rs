@@ -2,128 +2,93 @@ use std::cell::RefCell;
 use std::fmt;
 use std::rc::Rc;
 
-use crate::memory::Memory;
-
-#[derive(Debug)]
-pub enum Register8 {
-    A,
-    B,
-    C,
-    D,
-    E,
-    H,
-    L,
-}
+use crate::callstack::{CallStack, Entry, Frame};
+use crate::memory::Bus;
+use crate::opcodes::{Mnemonic, Opcode, Operand, CB_OPCODES, OPCODES};
 
-#[derive(Debug)]
-pub enum Register16 {
-    BC,
-    DE,
-    HL,
-}
+pub struct Cpu {
+    pub regs: Registers,
 
-#[derive(Debug)]
-pub enum Location {
-    Register(Register8),
-    DoubleRegister(Register16),
-    Address(u16),
-}
+    // Stack pointer
+    pub sp: u16,
 
-impl Location {
-    fn load8(&self, regs: &Registers, address_space: &Memory) -> u8 {
-        match self {
-            Self::Register(reg) => match reg {
-                Register8::A => regs.a,
-                Register8::B => regs.b,
-                Register8::C => regs.c,
-                Register8::D => regs.d,
-                Register8::E => regs.e,
-                Register8::H => regs.h,
-                Register8::L => regs.l,
-            },
-            Self::DoubleRegister(_) => {
-                panic!("load8() called on a 16bit register");
-            }
-            Self::Address(address) => address_space.load(*address as usize),
-        }
-    }
+    // Program counter
+    pub pc: u16,
 
-    fn store8(&self, regs: &mut Registers, address_space: &mut Memory, value: u8) {
-        match self {
-            Self::Register(reg) => match reg {
-                Register8::A => regs.a = value,
-                Register8::B => regs.b = value,
-                Register8::C => regs.c = value,
-                Register8::D => regs.d = value,
-                Register8::E => regs.e = value,
-                Register8::H => regs.h = value,
-                Register8::L => regs.l = value,
-            },
-            Self::DoubleRegister(_) => {
-                panic!("store8() called on a 16bit register");
-            }
-            Self::Address(address) => {
-                address_space.write(*address as usize, value);
-            }
-        }
-    }
-}
+    memory: Rc<RefCell<dyn Bus>>,
 
-impl fmt::Display for Location {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Self::Register(reg8) => match reg8 {
-                Register8::A => write!(f, "A"),
-                Register8::B => write!(f, "B"),
-                Register8::C => write!(f, "C"),
-                Register8::D => write!(f, "D"),
-                Register8::E => write!(f, "E"),
-                Register8::H => write!(f, "H"),
-                Register8::L => write!(f, "L"),
-            },
-            Self::DoubleRegister(reg16) => match reg16 {
-                Register16::BC => write!(f, "BC"),
-                Register16::HL => write!(f, "HL"),
-                Register16::DE => write!(f, "DE"),
-            },
-            Self::Address(addr) => write!(f, "({:#06x})", addr),
-        }
-    }
-}
+    // IME - Interrupt Master Enable
+    ime: Ime,
 
-pub struct Cpu {
-    regs: Registers,
+    state: State,
 
-    // Stack pointer
-    sp: u16,
+    // T-cycles elapsed in the instruction being executed
+    cycles: u8,
 
-    // Program counter
-    pub pc: u16,
+    // Instructions executed since power on
+    instructions: u64,
+
+    // The functions and interrupt handlers being executed
+    call_stack: CallStack,
+}
 
-    memory: Rc<RefCell<Memory>>,
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Ime {
+    Disabled,
+    // EI enables interrupts only after the instruction following it
+    Scheduled,
+    Enabled,
+}
 
-    pub interrupts_enabled: bool,
-    current_op: String,
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Running,
+    // Stopped by HALT, waiting for an interrupt to be pending
+    Halted,
+    // HALT executed with IME=0 and an interrupt pending: the byte after
+    // HALT is read twice because PC fails to increment.
+    HaltBug,
+    // Stopped by STOP, waiting for a button to be pressed
+    Stopped,
+    // Locked up by an illegal opcode
+    Locked,
 }
 
 #[derive(Default)]
-struct Registers {
-    flags: u8,
+pub struct Registers {
+    // F: Z N H C 0 0 0 0
+    pub flags: u8,
 
     // Accumulator
-    a: u8,
+    pub a: u8,
 
-    b: u8,
-    c: u8,
+    pub b: u8,
+    pub c: u8,
 
-    d: u8,
-    e: u8,
+    pub d: u8,
+    pub e: u8,
 
-    h: u8,
-    l: u8,
+    pub h: u8,
+    pub l: u8,
 }
 
 impl Registers {
+    // State of registers as set by the DMG boot ROM after power on, the
+    // one of gameboy-doctor's logs
+    #[must_use]
+    pub const fn dmg() -> Self {
+        Self {
+            a: 0x01,
+            b: 0x00,
+            c: 0x13,
+            d: 0x00,
+            e: 0xd8,
+            h: 0x01,
+            l: 0x4d,
+            flags: 0xb0,
+        }
+    }
+
     // State of registers as set by the DMG0 boot room after power on
     pub fn new() -> Self {
         Self {
@@ -192,129 +157,495 @@ impl fmt::Display for Registers {
     }
 }
 
-const ZERO_FLAG: u8 = 1 << 7;
-const SUBTRACT_FLAG: u8 = 1 << 6;
-const HALF_CARRY_FLAG: u8 = 1 << 5;
-const CARRY_FLAG: u8 = 1 << 4;
+pub const ZERO_FLAG: u8 = 1 << 7;
+pub const SUBTRACT_FLAG: u8 = 1 << 6;
+pub const HALF_CARRY_FLAG: u8 = 1 << 5;
+pub const CARRY_FLAG: u8 = 1 << 4;
 
 impl Cpu {
-    pub fn new(memory: Rc<RefCell<Memory>>) -> Cpu {
+    pub fn new(memory: Rc<RefCell<dyn Bus>>) -> Self {
         Self {
             regs: Registers::default(),
             sp: 0,
             pc: 0,
             memory,
-            interrupts_enabled: false,
-            current_op: String::new(),
+            ime: Ime::Disabled,
+            state: State::Running,
+            cycles: 0,
+            instructions: 0,
+            call_stack: CallStack::new(),
         }
     }
 
     // State as set by the DMG0 boot room after power on
-    pub fn new_initialized(memory: Rc<RefCell<Memory>>) -> Cpu {
+    pub fn new_initialized(memory: Rc<RefCell<dyn Bus>>) -> Self {
         Self {
             regs: Registers::new(),
             sp: 0xfffe,
             pc: 0x0100,
-            memory,
-            interrupts_enabled: false,
-            current_op: String::new(),
+            ..Self::new(memory)
+        }
+    }
+
+    // An illegal opcode was executed, nothing else will be.
+    #[must_use]
+    pub fn locked(&self) -> bool {
+        self.state == State::Locked
+    }
+
+    // IME
+    #[must_use]
+    pub fn interrupts_enabled(&self) -> bool {
+        self.ime == Ime::Enabled
+    }
+
+    pub const fn set_interrupts_enabled(&mut self, enabled: bool) {
+        self.ime = if enabled { Ime::Enabled } else { Ime::Disabled };
+    }
+
+    // EI was just executed, IME will be set after the next instruction.
+    #[must_use]
+    pub fn ime_scheduled(&self) -> bool {
+        self.ime == Ime::Scheduled
+    }
+
+    // Instructions executed since power on, not counting the steps spent
+    // halted or servicing interrupts
+    #[must_use]
+    pub const fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    #[must_use]
+    pub const fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    pub const fn call_stack_mut(&mut self) -> &mut CallStack {
+        &mut self.call_stack
+    }
+
+    // Advance the rest of the system by one M-cycle (4 T-cycles).
+    //
+    // Every memory access takes one M-cycle, instructions doing internal
+    // work (16 bit arithmetic, taken branches, ...) take extra ones.
+    fn tick(&mut self, memory: &mut dyn Bus) {
+        memory.tick();
+        self.cycles += 4;
+    }
+
+    fn read(&mut self, memory: &mut dyn Bus, address: u16) -> u8 {
+        self.tick(memory);
+        memory.read(address)
+    }
+
+    fn write(&mut self, memory: &mut dyn Bus, address: u16, value: u8) {
+        self.tick(memory);
+        memory.write(address, value);
+    }
+
+    // Read the byte pointed by PC and advance it.
+    fn fetch(&mut self, memory: &mut dyn Bus) -> u8 {
+        let value = self.read(memory, self.pc);
+        self.pc = self.pc.wrapping_add(1);
+
+        value
+    }
+
+    // Push a 16 bit value onto the stack, high byte first.
+    fn push(&mut self, memory: &mut dyn Bus, value: u16) {
+        let [high, low] = value.to_be_bytes();
+
+        self.sp = self.sp.wrapping_sub(1);
+        self.write(memory, self.sp, high);
+        self.sp = self.sp.wrapping_sub(1);
+        self.write(memory, self.sp, low);
+    }
+
+    // Pop a 16 bit value off the stack, low byte first.
+    fn pop(&mut self, memory: &mut dyn Bus) -> u16 {
+        let low = self.read(memory, self.sp);
+        self.sp = self.sp.wrapping_add(1);
+        let high = self.read(memory, self.sp);
+        self.sp = self.sp.wrapping_add(1);
+
+        u16::from_be_bytes([high, low])
+    }
+
+    // Read the 16 bit immediate value following the opcode, low byte first.
+    fn fetch16(&mut self, memory: &mut dyn Bus) -> u16 {
+        let low = self.fetch(memory);
+        let high = self.fetch(memory);
+
+        u16::from_be_bytes([high, low])
+    }
+
+    // 8 bit operands: registers, memory pointed by a register or an
+    // immediate value (or an address) following the opcode.
+    fn load8(&mut self, memory: &mut dyn Bus, operand: Operand) -> u8 {
+        match operand {
+            Operand::A => self.regs.a,
+            Operand::B => self.regs.b,
+            Operand::C => self.regs.c,
+            Operand::D => self.regs.d,
+            Operand::E => self.regs.e,
+            Operand::H => self.regs.h,
+            Operand::L => self.regs.l,
+            Operand::D8 => self.fetch(memory),
+            operand => {
+                let address = self.address(memory, operand);
+                self.read(memory, address)
+            }
         }
     }
 
-    pub fn index(&self, index: u8) -> Location {
-        match index {
-            0 => Location::Register(Register8::B),
-            1 => Location::Register(Register8::C),
-            2 => Location::Register(Register8::D),
-            3 => Location::Register(Register8::E),
-            4 => Location::Register(Register8::H),
-            5 => Location::Register(Register8::L),
-            6 => Location::Address(self.regs.hl()),
-            7 => Location::Register(Register8::A),
-            _ => panic!("Invalid index {}", index),
+    fn store8(&mut self, memory: &mut dyn Bus, operand: Operand, value: u8) {
+        match operand {
+            Operand::A => self.regs.a = value,
+            Operand::B => self.regs.b = value,
+            Operand::C => self.regs.c = value,
+            Operand::D => self.regs.d = value,
+            Operand::E => self.regs.e = value,
+            Operand::H => self.regs.h = value,
+            Operand::L => self.regs.l = value,
+            operand => {
+                let address = self.address(memory, operand);
+                self.write(memory, address, value);
+            }
         }
     }
 
-    #[allow(clippy::too_many_lines)]
+    // Address of a memory operand, (HL+) and (HL-) update HL.
+    fn address(&mut self, memory: &mut dyn Bus, operand: Operand) -> u16 {
+        match operand {
+            Operand::IndBC => self.regs.bc(),
+            Operand::IndDE => self.regs.de(),
+            Operand::IndHL => self.regs.hl(),
+            Operand::IndHLI => {
+                let hl = self.regs.hl();
+                self.regs.write_hl(hl.wrapping_add(1));
+                hl
+            }
+            Operand::IndHLD => {
+                let hl = self.regs.hl();
+                self.regs.write_hl(hl.wrapping_sub(1));
+                hl
+            }
+            Operand::IndC => 0xff00 | u16::from(self.regs.c),
+            Operand::A8 => 0xff00 | u16::from(self.fetch(memory)),
+            Operand::A16 => self.fetch16(memory),
+            operand => panic!("{:?} is not a memory operand", operand),
+        }
+    }
+
+    const fn is_register16(operand: Operand) -> bool {
+        matches!(
+            operand,
+            Operand::AF | Operand::BC | Operand::DE | Operand::HL | Operand::SP
+        )
+    }
+
+    fn load16(&self, operand: Operand) -> u16 {
+        match operand {
+            Operand::AF => u16::from_be_bytes([self.regs.a, self.regs.flags]),
+            Operand::BC => self.regs.bc(),
+            Operand::DE => self.regs.de(),
+            Operand::HL => self.regs.hl(),
+            Operand::SP => self.sp,
+            operand => panic!("{:?} is not a 16 bit register", operand),
+        }
+    }
+
+    fn store16(&mut self, operand: Operand, value: u16) {
+        match operand {
+            Operand::AF => self.regs.write_af(value),
+            Operand::BC => self.regs.write_bc(value),
+            Operand::DE => self.regs.write_de(value),
+            Operand::HL => self.regs.write_hl(value),
+            Operand::SP => self.sp = value,
+            operand => panic!("{:?} is not a 16 bit register", operand),
+        }
+    }
+
+    // Interrupts pending and enabled (IF & IE)
+    fn pending_interrupts(memory: &dyn Bus) -> u8 {
+        memory.peek(0xffff) & memory.peek(0xff0f) & 0x1f
+    }
+
+    // Service the highest priority pending interrupt.
+    //
+    // Takes 5 M-cycles: 2 wait states, 2 for pushing PC and 1 to set PC
+    // to the interrupt vector.
+    //
+    // The interrupt to service is chosen after the high byte of PC is pushed,
+    // so if that write overwrites IE the dispatch can be cancelled and PC
+    // is set to 0x0000.
+    fn interrupt(&mut self, memory: &mut dyn Bus) {
+        self.ime = Ime::Disabled;
+
+        self.tick(memory);
+        self.tick(memory);
+
+        let [high, low] = self.pc.to_be_bytes();
+
+        self.sp = self.sp.wrapping_sub(1);
+        self.write(memory, self.sp, high);
+
+        let pending = Self::pending_interrupts(memory);
+
+        self.sp = self.sp.wrapping_sub(1);
+        self.write(memory, self.sp, low);
+
+        // The lowest bit pending has the highest priority
+        self.pc = (0..5_u8)
+            .find(|bit| pending & (1 << bit) != 0)
+            .map_or(0x0000, |bit| {
+                let interrupt_flag = memory.peek(0xff0f);
+                memory.write(0xff0f, interrupt_flag & !(1 << bit));
+
+                // VBlank 0x40, LCD STAT 0x48, Timer 0x50, Serial 0x58, Joypad 0x60
+                0x40 + 8 * u16::from(bit)
+            });
+
+        self.call_stack.enter(Frame {
+            entry: Entry::Interrupt,
+            caller: u16::from_be_bytes([high, low]),
+            function: self.pc,
+            return_address: u16::from_be_bytes([high, low]),
+            sp: self.sp,
+        });
+
+        self.tick(memory);
+    }
+
+    // Execute the next instruction, or service an interrupt.
+    //
+    // Returns the number of T-cycles elapsed.
     pub fn decode(&mut self) -> u8 {
-        let mut memory = self.memory.borrow_mut();
-
-        // REMOVE ME
-        // Tetris vblank routine
-        /*
-        if self.pc == 0x17e {
-            panic!();
-        }*/
-
-        let opcode = memory.load(self.pc as usize);
-        let mut cycles = 1;
-
-        match opcode {
-            0x00 => (),
-            0x01 | 0x11 | 0x21 | 0x31 => {
-                // LD r16,n
-                //
-                // Store 16 bit immediate value n into 16 bit register
-                // (BC, DE, HL, SP).
+        let memory = Rc::clone(&self.memory);
+        let mut memory = memory.borrow_mut();
+        let memory = &mut *memory;
 
-                self.pc += 1;
-                let low = memory.load(self.pc as usize);
-                self.pc += 1;
-                let high = memory.load(self.pc as usize);
+        self.cycles = 0;
 
-                let immediate: u16 = u16::from(high) << 8 | u16::from(low);
+        // An illegal opcode hangs the CPU for good, not even interrupts
+        // can wake it up.
+        if self.state == State::Locked {
+            self.tick(memory);
 
-                let reg_name = match (opcode & 0xF0) >> 4 {
-                    0 => {
-                        self.regs.write_bc(immediate);
-                        "BC"
-                    }
-                    1 => {
-                        self.regs.write_de(immediate);
-                        "DE"
-                    }
-                    2 => {
-                        self.regs.write_hl(immediate);
-                        "HL"
-                    }
-                    3 => {
-                        self.sp = immediate;
-                        "SP"
-                    }
-                    _ => unreachable!(),
-                };
+            return self.cycles;
+        }
 
-                self.current_op = format!(
-                    "{:#04x} {:#04x} LD {},{:#x}",
-                    low, high, reg_name, immediate
-                );
-                cycles = 12;
+        // STOP halts the system clock until a button is pressed, so
+        // nothing else advances. Time still passes for the caller.
+        if self.state == State::Stopped {
+            if memory.peek(0xff00) & 0x0f == 0x0f {
+                return 4;
+            }
+            self.state = State::Running;
+        }
+
+        let pending = Self::pending_interrupts(memory);
+
+        // HALT ends as soon as an interrupt is pending, even with IME=0
+        if self.state == State::Halted {
+            if pending == 0 {
+                self.tick(memory);
+
+                return self.cycles;
+            }
+            self.state = State::Running;
+        }
+
+        if self.ime == Ime::Enabled && pending != 0 {
+            self.interrupt(memory);
+
+            return self.cycles;
+        }
+
+        if self.ime == Ime::Scheduled {
+            self.ime = Ime::Enabled;
+        }
+
+        let opcode = self.fetch(memory);
+
+        if self.state == State::HaltBug {
+            self.pc = self.pc.wrapping_sub(1);
+            self.state = State::Running;
+        }
+
+        self.execute(memory, &OPCODES[opcode as usize], pending);
+        self.instructions += 1;
+
+        self.cycles
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn execute(&mut self, memory: &mut dyn Bus, opcode: &Opcode, pending: u8) {
+        let [dst, src] = opcode.operands;
+
+        match opcode.mnemonic {
+            Mnemonic::Nop => (),
+            Mnemonic::Ld | Mnemonic::Ldh => match (dst, src) {
+                (Some(Operand::A16), Some(Operand::SP)) => {
+                    // LD (a16), SP
+                    //
+                    // Put SP value into memory address a16.
+
+                    let addr = self.fetch16(memory);
+
+                    self.write(memory, addr, (self.sp & 0xff) as u8);
+                    self.write(
+                        memory,
+                        addr.wrapping_add(1),
+                        ((self.sp & 0xff00) >> 8) as u8,
+                    );
+                }
+                (Some(Operand::SP), Some(Operand::HL)) => {
+                    // LD SP,HL
+                    //
+                    // Store the value of HL in SP
+
+                    self.sp = self.regs.hl();
+                    self.tick(memory);
+                }
+                (Some(Operand::HL), Some(Operand::SPR8)) => {
+                    // LD HL, SP+r8
+                    //
+                    // Put SP + n effective address into HL.
+                    //
+                    // flags:
+                    // Z: 0
+                    // N: 0
+                    // H: Set if carry from bit 3
+                    // C: Set if carry from bit 7
+
+                    let result = self.add_sp(memory);
+                    self.regs.write_hl(result);
+                    self.tick(memory);
+                }
+                (Some(dst), Some(Operand::D16)) => {
+                    // LD r16,n
+                    //
+                    // Store 16 bit immediate value n into 16 bit register
+                    // (BC, DE, HL, SP).
+
+                    let immediate = self.fetch16(memory);
+                    self.store16(dst, immediate);
+                }
+                (Some(dst), Some(src)) => {
+                    // LD r1,r2
+                    //
+                    // Store 8 bit value r2 into r1, either can be a register,
+                    // a memory location or an immediate value.
+
+                    let value = self.load8(memory, src);
+                    self.store8(memory, dst, value);
+                }
+                _ => unreachable!(),
+            },
+            Mnemonic::Inc | Mnemonic::Dec => {
+                let operand = dst.unwrap();
+                let increment = opcode.mnemonic == Mnemonic::Inc;
+
+                if Self::is_register16(operand) {
+                    // 16bit INC/DEC
+                    //
+                    // No flags affected.
+
+                    let value = self.load16(operand);
+                    let value = if increment {
+                        value.wrapping_add(1)
+                    } else {
+                        value.wrapping_sub(1)
+                    };
+
+                    self.store16(operand, value);
+                    self.tick(memory);
+                } else if increment {
+                    // INC r8
+                    //
+                    // Increment 8 bit register r8.
+                    //
+                    // flags:
+                    // Z: Set if result is zero, unset otherwise
+                    // N: 0
+                    // H: Set if carry from bit 3, unset otherwise
+                    // C: no change.
+
+                    let value = self.load8(memory, operand).wrapping_add(1);
+                    self.store8(memory, operand, value);
+
+                    self.regs.set_flag(ZERO_FLAG, value == 0);
+                    self.regs.set_flag(SUBTRACT_FLAG, false);
+                    self.regs
+                        .set_flag(HALF_CARRY_FLAG, value.trailing_zeros() >= 4);
+                } else {
+                    // DEC r8
+                    //
+                    // Decrement 8 bit register r8.
+                    //
+                    // flags:
+                    // Z: Set if result is zero, unset otherwise
+                    // N: 1
+                    // H: Set if no borrow from bit 4, unset otherwise
+                    // C: no change.
+
+                    let value = self.load8(memory, operand).wrapping_sub(1);
+                    self.store8(memory, operand, value);
+
+                    self.regs.set_flag(ZERO_FLAG, value == 0);
+                    self.regs.set_flag(SUBTRACT_FLAG, true);
+                    self.regs.set_flag(HALF_CARRY_FLAG, value & 0x0f == 0x0f);
+                }
             }
-            0x05 | 0x15 | 0x25 | 0x35 | 0x0d | 0x1d | 0x2d | 0x3d => {
-                // DEC r8
+            Mnemonic::Add if dst == Some(Operand::HL) => {
+                // ADD HL,n
                 //
-                // Decrement 8 bit register r8.
+                // Add n to HL.
                 //
                 // flags:
-                // Z: Set if result is zero, unset otherwise
-                // N: 1
-                // H: Set if no borrow from bit 4, unset otherwise
-                // C: no change.
+                // Z: No change
+                // N: 0
+                // H: Set if carry from bit 11
+                // C: Set if carry from bit 15.
 
-                let reg_index = (opcode & 0b0011_1000) >> 3;
+                let operand = self.load16(src.unwrap());
 
-                let location = self.index(reg_index);
-                let value = location.load8(&self.regs, &memory).wrapping_sub(1);
+                let half_carry = (self.regs.hl() & 0xfff) + (operand & 0xfff) > 0xfff;
+                self.regs.set_flag(HALF_CARRY_FLAG, half_carry);
 
-                location.store8(&mut self.regs, &mut memory, value);
+                let (result, carry) = self.regs.hl().overflowing_add(operand);
+                self.regs.write_hl(result);
+                self.tick(memory);
 
-                self.regs.set_flag(ZERO_FLAG, value == 0);
-                self.regs.set_flag(SUBTRACT_FLAG, true);
-                self.regs.set_flag(HALF_CARRY_FLAG, value & 0x0f == 0x0f);
+                self.regs.set_flag(SUBTRACT_FLAG, false);
+                self.regs.set_flag(CARRY_FLAG, carry);
+            }
+            Mnemonic::Add if dst == Some(Operand::SP) => {
+                // ADD SP, n
+                //
+                // Add n (signed) to Stack Pointer (SP).
+                //
+                // flags:
+                // Z: 0
+                // N: 0
+                // H: Set if carry from bit 3
+                // C: Set if carry from bit 7
 
-                cycles = if opcode == 0x35 { 12 } else { 4 };
+                self.sp = self.add_sp(memory);
+                self.tick(memory);
+                self.tick(memory);
+            }
+            Mnemonic::Add | Mnemonic::Adc | Mnemonic::Sbc => {
+                let n = self.load8(memory, src.unwrap());
+                self.alu(opcode.mnemonic, n);
             }
-            0x07 => {
+            Mnemonic::Sub | Mnemonic::And | Mnemonic::Xor | Mnemonic::Or | Mnemonic::Cp => {
+                let n = self.load8(memory, dst.unwrap());
+                self.alu(opcode.mnemonic, n);
+            }
+            Mnemonic::Rlca => {
                 // RLCA
                 //
                 // Rotate A left. Old bit 7 to Carry flag.
@@ -339,38 +670,10 @@ impl Cpu {
                 // H: 0
                 // C: Contains old bit 7 data.
 
-                self.regs
-                    .set_flag(CARRY_FLAG, self.regs.a & 0b1000_0000 != 0);
-
-                self.regs.a = self.regs.a.rotate_left(1);
-
+                self.regs.a = self.rotate(Mnemonic::Rlc, self.regs.a);
                 self.regs.set_flag(ZERO_FLAG, false);
-                self.regs.set_flag(SUBTRACT_FLAG, false);
-                self.regs.set_flag(HALF_CARRY_FLAG, false);
-
-                self.current_op = format!("{:10} RLCA", " ");
-                cycles = 4;
-            }
-            0x08 => {
-                // LD (a16), SP
-                //
-                // Put SP value into memory address a16.
-
-                self.pc += 1;
-                let low = memory.load(self.pc as usize);
-                self.pc += 1;
-                let high = memory.load(self.pc as usize);
-
-                let addr: u16 = u16::from_be_bytes([high, low]);
-
-                memory.write(addr as usize, (self.sp & 0xff) as u8);
-                memory.write(addr as usize + 1, ((self.sp & 0xff00) >> 8) as u8);
-
-                self.current_op = format!("{:9} LD ({:#06x}),SP", " ", addr);
-
-                cycles = 20;
             }
-            0x0f => {
+            Mnemonic::Rrca => {
                 // RRCA
                 //
                 // Rotate A right. Old bit 0 to Carry flag.
@@ -393,21 +696,12 @@ impl Cpu {
                 // Z: 0 (GBCPUman.pdf v1.01 is wrong on this one)
                 // N: 0
                 // H: 0
-                // C: Contains old bit 7 data.
-
-                self.regs
-                    .set_flag(CARRY_FLAG, self.regs.a & 0x1 != 0);
-
-                self.regs.a = self.regs.a.rotate_right(1);
+                // C: Contains old bit 0 data.
 
+                self.regs.a = self.rotate(Mnemonic::Rrc, self.regs.a);
                 self.regs.set_flag(ZERO_FLAG, false);
-                self.regs.set_flag(SUBTRACT_FLAG, false);
-                self.regs.set_flag(HALF_CARRY_FLAG, false);
-
-                self.current_op = format!("{:10} RRCA", " ");
-                cycles = 4;
             }
-            0x17 => {
+            Mnemonic::Rla => {
                 // RLA
                 // (Note this is different from RL A)
                 //
@@ -436,188 +730,51 @@ impl Cpu {
                 // H: 0
                 // C: Contains old bit 7 data.
 
-                let carry = (self.regs.flags & CARRY_FLAG) >> 4;
-
-                self.regs
-                    .set_flag(CARRY_FLAG, self.regs.a & 0b1000_0000 != 0);
-
-                self.regs.a = self.regs.a << 1 | carry;
-
+                self.regs.a = self.rotate(Mnemonic::Rl, self.regs.a);
                 self.regs.set_flag(ZERO_FLAG, false);
-                self.regs.set_flag(SUBTRACT_FLAG, false);
-                self.regs.set_flag(HALF_CARRY_FLAG, false);
-
-                self.current_op = format!("{:10} RLA", " ");
-                cycles = 4;
             }
-            0x1f => {
+            Mnemonic::Rra => {
                 // RRA
-                // (Note this not the same instruction as RL A)
-
-                let carry = (self.regs.flags & CARRY_FLAG) >> 4;
-
-                self.regs
-                    .set_flag(CARRY_FLAG, self.regs.a & 0x1 != 0);
-
-                self.regs.a = self.regs.a >> 1 | carry << 7;
-
-                self.regs.set_flag(ZERO_FLAG, false);
-                self.regs.set_flag(SUBTRACT_FLAG, false);
-                self.regs.set_flag(HALF_CARRY_FLAG, false);
-
-                cycles = 4;
-            }
-            0x09 | 0x19 | 0x29 | 0x39 => {
-                // ADD HL,n
+                // (Note this not the same instruction as RR A)
                 //
-                // Add n to HL.
+                // 9-bit rotation to the right using the carry flag.
                 //
                 // flags:
-                // Z: No change
+                // Z: 0
                 // N: 0
-                // H: Set if carry from bit 11
-                // C: Set if carry from bit 15.
-
-                let operand = match opcode {
-                    0x09 => self.regs.bc(),
-                    0x19 => self.regs.de(),
-                    0x29 => self.regs.hl(),
-                    0x39 => self.sp,
-                    _ => unreachable!(),
-                };
-
-                let half_carry = (self.regs.hl() & 0xfff) + (operand & 0xfff) > 0xfff;
-                self.regs.set_flag(HALF_CARRY_FLAG, half_carry);
-
-                let (result, carry) = self.regs.hl().overflowing_add(operand);
-                self.regs.write_hl(result);
-
-                self.regs.set_flag(SUBTRACT_FLAG, false);
-                self.regs.set_flag(CARRY_FLAG, carry);
-
-                cycles = 8;
-            }
-            0x0a | 0x1a | 0x2a | 0x3a => {
-                // LD A,(r16)
-                //
-                // Store 16 bit value from memory location at register r16 to A.
-                // (BC, DE, HL).
-
-                let (value, reg_name) = match (opcode & 0xF0) >> 4 {
-                    0 => (memory.load(self.regs.bc() as usize), "BC"),
-                    1 => {
-                        self.current_op = format!("DE: {:#06x}", self.regs.de());
-                        (memory.load(self.regs.de() as usize), "DE")
-                    }
-                    2 => {
-                        let hl = self.regs.hl();
-                        self.regs.write_hl(hl + 1);
-
-                        (memory.load(hl as usize), "HL+")
-                    }
-                    3 => {
-                        let hl = self.regs.hl();
-                        self.regs.write_hl(hl - 1);
-
-                        (memory.load(hl as usize), "HL-")
-                    }
-                    _ => unreachable!(),
-                };
-
-                self.regs.a = value;
-
-                self.current_op = format!("{:10} LD A,({})", " ", reg_name);
-                cycles = 8;
-            }
-            0x06 | 0x16 | 0x26 | 0x36 | 0x0e | 0x1e | 0x2e | 0x3e => {
-                // LD r8,n
-                //
-                // Store 8 bit immediate value n into 8 bit register
-
-                self.pc += 1;
-                let immediate = memory.load(self.pc as usize);
-
-                let reg_index = (opcode & 0b0011_1000) >> 3;
-
-                let location = self.index(reg_index);
-                location.store8(&mut self.regs, &mut memory, immediate);
-
-                self.current_op = format!("{1:#04x}      LD {0},{1:#x}", location, immediate);
-                cycles = if opcode == 0x36 { 12 } else { 8 };
-            }
-            0x03 | 0x13 | 0x23 | 0x33 => {
-                // 16bit INC
-                //
-                // No flags affected.
-
-                let reg_name = match (opcode & 0xF0) >> 4 {
-                    0x0 => {
-                        self.regs.write_bc(self.regs.bc().wrapping_add(1));
-
-                        "BC"
-                    }
-                    0x1 => {
-                        self.regs.write_de(self.regs.de().wrapping_add(1));
-
-                        "DE"
-                    }
-                    0x2 => {
-                        self.regs.write_hl(self.regs.hl().wrapping_add(1));
-
-                        "HL"
-                    }
-                    0x3 => {
-                        self.sp = self.sp.wrapping_add(1);
-
-                        "SP"
-                    }
-                    _ => unreachable!(),
-                };
-
-                self.current_op = format!("{:9} INC {}", " ", reg_name);
-                cycles = 8;
-            }
-            0x18 => {
-                // JR n
-                //
-                // Jump to the current address + n (signed).
-
-                self.pc += 1;
-                let immediate = memory.load(self.pc as usize) as i8;
-
-                self.current_op = format!("{0:#04x}      JR {0:#04x} {0}", immediate);
-
-                // Relative jump is calculated starting from the next instruction
-                self.pc += 1;
-
-                self.pc = self.pc.wrapping_add_signed(immediate as i16);
-                cycles = 12;
+                // H: 0
+                // C: Contains old bit 0 data.
 
-                return cycles;
+                self.regs.a = self.rotate(Mnemonic::Rr, self.regs.a);
+                self.regs.set_flag(ZERO_FLAG, false);
             }
-            0x27 => {
-                // DAA (TODO: most likely wrong)
+            Mnemonic::Daa => {
+                // DAA
                 //
                 // Decimal adjust register A.
                 //
                 // Set the A register to its content's representation of
-                // Binary Coded Decimal (BCD).
+                // Binary Coded Decimal (BCD), after an addition or
+                // a subtraction (N flag) of two BCD numbers.
                 //
                 // flags:
                 // Z - Set if register A is zero.
                 // N - Not affected.
                 // H - Reset.
-                // C - Set or reset according to operation. TODO and doc (?)
+                // C - Set if the adjustment carried past 0x99, otherwise
+                //     not affected.
 
                 let mut reg = 0;
                 let mut carry = false;
 
                 if self.regs.flags & HALF_CARRY_FLAG != 0
-                    || (self.regs.flags & SUBTRACT_FLAG == 0 && self.regs.a & 0x0f > 0x09) {
+                    || (self.regs.flags & SUBTRACT_FLAG == 0 && self.regs.a & 0x0f > 0x09)
+                {
                     reg = 0x06;
                 }
                 if self.regs.flags & CARRY_FLAG != 0
-                    || (self.regs.flags & SUBTRACT_FLAG == 0 && self.regs.a > 0x99) {
+                    || (self.regs.flags & SUBTRACT_FLAG == 0 && self.regs.a > 0x99)
+                {
                     reg += 0x60;
                     carry = true;
                 }
@@ -631,31 +788,24 @@ impl Cpu {
                 self.regs.set_flag(ZERO_FLAG, self.regs.a == 0);
                 self.regs.set_flag(HALF_CARRY_FLAG, false);
                 self.regs.set_flag(CARRY_FLAG, carry);
-
-                cycles = 4;
             }
-            0x28 => {
-                // JR Z,n
+            Mnemonic::Cpl => {
+                // CPL
                 //
-                // Jump to the current address + n (signed) if Z is 1.
-
-                self.pc += 1;
-                let immediate = memory.load(self.pc as usize) as i8;
-
-                self.current_op = format!("{0:#04x}      JR Z,{0:#04x} {0}", immediate);
-
-                cycles = 8;
-                if (self.regs.flags & ZERO_FLAG) != 0 {
-                    // Relative jump is calculated starting from the next instruction
-                    self.pc += 1;
+                // Complement A register. (Flip all bits)
+                //
+                // flags:
+                // Z: no change
+                // N: 1
+                // H: 1
+                // C: no change
 
-                    cycles = 12;
-                    self.pc = self.pc.wrapping_add_signed(immediate as i16);
+                self.regs.a = !self.regs.a;
 
-                    return cycles;
-                }
+                self.regs.set_flag(SUBTRACT_FLAG, true);
+                self.regs.set_flag(HALF_CARRY_FLAG, true);
             }
-            0x37 => {
+            Mnemonic::Scf => {
                 // SCF
                 //
                 // Set the carry flag and clears the N and H flags
@@ -663,789 +813,239 @@ impl Cpu {
                 self.regs.set_flag(CARRY_FLAG, true);
                 self.regs.set_flag(SUBTRACT_FLAG, false);
                 self.regs.set_flag(HALF_CARRY_FLAG, false);
-
-                cycles = 4
             }
-            0x38 => {
-                // JR C,n
-                //
-                // Jump to the current address + n (signed) if C is 1.
-
-                self.pc += 1;
-                let immediate = memory.load(self.pc as usize) as i8;
-
-                self.current_op = format!("{0:#04x}      JR C,{0:#04x} {0}", immediate);
-
-                cycles = 8;
-                if (self.regs.flags & CARRY_FLAG) != 0 {
-                    self.pc += 1;
-                    cycles = 12;
-
-                    self.pc = self.pc.wrapping_add_signed(immediate as i16);
-
-                    return cycles;
-                }
-            }
-            0x3f => {
+            Mnemonic::Ccf => {
                 // CCF
                 //
                 // Flips the carry flag, and clears the N and H flags
 
-                self.regs.set_flag(CARRY_FLAG, !(self.regs.flags & CARRY_FLAG != 0));
+                self.regs
+                    .set_flag(CARRY_FLAG, self.regs.flags & CARRY_FLAG == 0);
                 self.regs.set_flag(SUBTRACT_FLAG, false);
                 self.regs.set_flag(HALF_CARRY_FLAG, false);
-
-                cycles = 4
             }
-            0x0b | 0x1b | 0x2b | 0x3b => {
-                // 16bit DEC
+            Mnemonic::Jr => {
+                // JR [condition,] n
                 //
-                // No flags affected.
-
-                let reg_name = match (opcode & 0xF0) >> 4 {
-                    0x0 => {
-                        self.regs.write_bc(self.regs.bc().wrapping_sub(1));
+                // Jump to the current address + n (signed), if condition
+                // is met.
 
-                        "BC"
-                    }
-                    0x1 => {
-                        self.regs.write_de(self.regs.de().wrapping_sub(1));
-
-                        "DE"
-                    }
-                    0x2 => {
-                        self.regs.write_hl(self.regs.hl().wrapping_sub(1));
-
-                        "HL"
-                    }
-                    0x3 => {
-                        self.sp = self.sp.wrapping_sub(1);
-
-                        "SP"
-                    }
-                    _ => unreachable!(),
-                };
+                let condition = if src.is_some() { dst } else { None };
+                let immediate = i8::from_le_bytes([self.fetch(memory)]);
 
-                self.current_op = format!("{:9} DEC {}", " ", reg_name);
-                cycles = 8;
+                if self.condition(condition) {
+                    // Relative jump is calculated starting from the next instruction
+                    self.pc = self.pc.wrapping_add_signed(immediate.into());
+                    self.tick(memory);
+                }
             }
-            0x04 | 0x14 | 0x24 | 0x34 | 0x0c | 0x1c | 0x2c | 0x3c => {
-                // INC r8
-                //
-                // Increment 8 bit register r8.
+            Mnemonic::Jp if dst == Some(Operand::HL) => {
+                // JP (HL)
                 //
-                // flags:
-                // Z: Set if result is zero, unset otherwise
-                // N: 0
-                // H: Set if carry from bit 3, unset otherwise
-                // C: no change.
-
-                let reg_index = (opcode & 0b0011_1000) >> 3;
-
-                let location = self.index(reg_index);
-                let value = location.load8(&self.regs, &memory).wrapping_add(1);
-
-                location.store8(&mut self.regs, &mut memory, value);
-
-                self.regs.set_flag(ZERO_FLAG, value == 0);
-                self.regs.set_flag(SUBTRACT_FLAG, false);
-                self.regs.set_flag(HALF_CARRY_FLAG, value & 0xf == 0);
-
-                self.current_op = format!("{:9} INC {}", " ", location);
-                cycles = if opcode == 0x34 { 12 } else { 4 };
-            }
-            0x02 => {
-                // LD (BC), A
-
-                let address = self.regs.bc() as usize;
-                memory.write(address, self.regs.a);
-
-                self.current_op = format!("LD (BC),A");
-                cycles = 8;
-            }
-            0x12 => {
-                // LD (DE),A
-
-                let address = self.regs.de() as usize;
-                memory.write(address, self.regs.a);
-
-                self.current_op = format!("LD (DE),A");
-                cycles = 8;
-            }
-
-            0x22 => {
-                // LD (HL+),A
-
-                let address = self.regs.hl() as usize;
-                memory.write(address, self.regs.a);
-                self.regs.write_hl(self.regs.hl() + 1);
+                // Jump to address contained in HL.
 
-                self.current_op = format!("LD (HL+),A");
-                cycles = 8;
+                self.pc = self.regs.hl();
             }
-            0x32 => {
-                // LD (HL-),A
+            Mnemonic::Jp => {
+                // JP [condition,] nn
                 //
-                // Copy A into memory address HL and decrement HL.
+                // Jump to address nn, if condition is met.
 
-                let address = self.regs.hl() as usize;
-                memory.write(address, self.regs.a);
-                self.regs.write_hl(self.regs.hl() - 1);
+                let condition = if src.is_some() { dst } else { None };
+                let addr = self.fetch16(memory);
 
-                self.current_op = format!("LD (HL-),A");
-                cycles = 8;
-            }
-            0x76 => {
-                // HALT
-                // TODO check if cycles are ok
-                cycles = 16;
+                if self.condition(condition) {
+                    self.pc = addr;
+                    self.tick(memory);
+                }
             }
-            (0x40..=0x7f) => {
-                // LD r1,r2
+            Mnemonic::Call => {
+                // CALL [condition,] nn
                 //
-                // Store 8 bit register r2 into 8 bit register r1
-
-                let src_index = opcode & 0b0000_0111;
-                let src_location = self.index(src_index);
-                let src_value = src_location.load8(&self.regs, &memory);
-
-                let dst_index = (opcode & 0b0011_1000) >> 3;
-                let dst_location = self.index(dst_index);
-
-                dst_location.store8(&mut self.regs, &mut memory, src_value);
-
-                self.current_op = format!("LD {},{}", dst_location, src_location);
-                cycles = if let (Location::Address(_), Location::Address(_)) =
-                    (src_location, dst_location)
-                {
-                    8
-                } else {
-                    4
-                };
-            }
-            0x80..=0xbf | 0xc6 | 0xd6 | 0xe6 | 0xf6 | 0xce | 0xde | 0xee | 0xfe => {
-                // Opcodes operating on A and 8 bit n,
-                // where n = A, B, C, D, E, H, L, (HL), immediate.
-
-                let n = if opcode > 0xbf {
-                    // immediate
-                    self.pc += 1;
-                    cycles = 8;
-
-                    memory.load(self.pc as usize)
-                } else {
-                    let reg_index = opcode & 0b0111;
-                    let location = self.index(reg_index);
-                    cycles = if let Location::Address(_) = location {
-                        8
-                    } else {
-                        4
-                    };
-
-                    location.load8(&self.regs, &memory)
-                };
-
-                match (opcode & 0b0011_1000) >> 3 {
-                    0 => {
-                        // ADD A, n
-                        //
-                        // Add n to A.
-                        //
-                        // flags:
-                        // Z: Set if result is zero.
-                        // N: 0
-                        // H: Set if carry from bit 3.
-                        // C: Set if carry from bit 7.
-
-                        let half_carry = (self.regs.a & 0xf) + (n & 0xf) > 0xf;
-                        self.regs.set_flag(HALF_CARRY_FLAG, half_carry);
-
-                        let (result, carry) = self.regs.a.overflowing_add(n);
-
-                        self.regs.a = result;
-                        self.regs.set_flag(ZERO_FLAG, self.regs.a == 0);
-                        self.regs.set_flag(SUBTRACT_FLAG, false);
-                        self.regs.set_flag(CARRY_FLAG, carry);
-                    }
-                    1 => {
-                        // ADC A, n
-                        //
-                        // Add n + Carry flag to A.
-                        //
-                        // flags:
-                        // Z: Set if result is zero.
-                        // N: 0
-                        // H: Set if carry from bit 3.
-                        // C: Set if carry from bit 7.
-
-                        let carry = if self.regs.flags & CARRY_FLAG == 0 {
-                            0
-                        } else {
-                            1
-                        };
-
-                        let half_carry = (self.regs.a & 0xf) + (n & 0xf) + carry > 0xf;
-                        self.regs.set_flag(HALF_CARRY_FLAG, half_carry);
-
-                        let result = self.regs.a as u16 + carry as u16 + n as u16;
-
-                        self.regs.a = result as u8;
-
-                        self.regs.set_flag(ZERO_FLAG, self.regs.a == 0);
-                        self.regs.set_flag(SUBTRACT_FLAG, false);
-
-                        self.regs.set_flag(CARRY_FLAG, result > 0xff);
-                    }
-                    2 => {
-                        // SUB n
-                        //
-                        // Subtract n from A.
-                        //
-                        // flags:
-                        // Z: Set if result is zero
-                        // N: 1
-                        // H: Set if borrow from bit 4
-                        // C: Set if no borrow
-
-                        let (_, borrow) = (self.regs.a & 0xf).overflowing_sub(n & 0xf);
-                        self.regs.set_flag(HALF_CARRY_FLAG, borrow);
-
-                        let (result, carry) = self.regs.a.overflowing_sub(n);
-
-                        self.regs.a = result;
-                        self.regs.set_flag(CARRY_FLAG, carry);
-                        self.regs.set_flag(ZERO_FLAG, self.regs.a == 0);
-                        self.regs.set_flag(SUBTRACT_FLAG, true);
-                    }
-                    3 => {
-                        // SBC A,n
-                        //
-                        // Subtract n + Carry flag from A.
-                        //
-                        // flags:
-                        // Z - Set if result is zero.
-                        // N - Set.
-                        // H - Set if borrow from bit 4.
-                        // C - Set if borrow.
-
-                        let c = if self.regs.flags & CARRY_FLAG == 0 { 0 } else { 1 };
-                        let result = self.regs.a.wrapping_sub(n).wrapping_sub(c);
-
-                        self.regs.set_flag(HALF_CARRY_FLAG, (self.regs.a & 0xf) < (n & 0xf) + c) ;
-                        self.regs.set_flag(CARRY_FLAG, (self.regs.a as u16) < (n as u16) + (c as u16));
-
-                        self.regs.a = result;
-
-                        self.regs.set_flag(ZERO_FLAG, self.regs.a == 0);
-                        self.regs.set_flag(SUBTRACT_FLAG, true);
-                    }
-                    4 => {
-                        // AND n
-                        //
-                        // Logically AND n with A, result in A.
-                        //
-                        // flags:
-                        // Z: Set if result is zero.
-                        // N: 0, H: 1, C: 0
-
-                        self.regs.a &= n as u8;
-
-                        self.regs.set_flag(ZERO_FLAG, self.regs.a == 0);
-                        self.regs.set_flag(SUBTRACT_FLAG, false);
-                        self.regs.set_flag(HALF_CARRY_FLAG, true);
-                        self.regs.set_flag(CARRY_FLAG, false);
-                    }
-                    5 => {
-                        // XOR n
-                        //
-                        // Logical exclusive OR n with register A, result in A.
-                        //
-                        //
-                        // flags:
-                        // Z: Set if result is zero
-                        // N: 0, H: 0, C: 0
-
-                        self.regs.a ^= n as u8;
+                // Push address of next instruction onto stack and jump to
+                // address nn, if condition is met.
+                //
+                // condition:
+                //   None: Always call
+                //   NZ: Call if Z flag is 0
+                //   Z:  Call if Z flag is 1
+                //   NC: Call if C flag is 0
+                //   C:  Call if C flag is 1
 
-                        self.regs.flags = 0;
-                        self.regs.set_flag(ZERO_FLAG, self.regs.a == 0);
-                    }
-                    6 => {
-                        // OR n
-                        //
-                        // Logical OR n with register A, result in A.
-                        //
-                        // flags:
-                        // Z: Set if result is zero.
-                        // N: 0, H: 0, C: 0
-                        self.regs.a |= n as u8;
+                let condition = if src.is_some() { dst } else { None };
+                let addr = self.fetch16(memory);
 
-                        self.regs.flags = 0;
-                        self.regs.set_flag(ZERO_FLAG, self.regs.a == 0);
-                    }
-                    7 => {
-                        // CP n
-                        //
-                        // Compare A with n. This is basically an A - n  subtraction instruction
-                        // but the results are thrown away.
-                        //
-                        // flags:
-                        // Z: Set if result is zero. (A == n)
-                        // N: 1
-                        // H: Set if borrow from bit 4.
-                        // C: Set for no borrow. (Set if A < n)
+                if self.condition(condition) {
+                    // PC already points to the next instruction
+                    self.tick(memory);
+                    self.push(memory, self.pc);
 
-                        self.regs.set_flag(ZERO_FLAG, self.regs.a == n);
-                        self.regs.set_flag(SUBTRACT_FLAG, true);
-                        self.regs.set_flag(CARRY_FLAG, n > self.regs.a);
+                    self.call_stack.enter(Frame {
+                        entry: Entry::Call,
+                        caller: self.pc.wrapping_sub(3),
+                        function: addr,
+                        return_address: self.pc,
+                        sp: self.sp,
+                    });
 
-                        let (_, borrow) = (self.regs.a & 0xf).overflowing_sub(n & 0xf);
-                        self.regs.set_flag(HALF_CARRY_FLAG, borrow);
-                    }
-                    _ => panic!("Invalid opcode {:#04x} at {:#04x}", opcode, self.pc),
+                    self.pc = addr;
                 }
             }
-            0xc0 => {
-                // RET NZ
+            Mnemonic::Ret => {
+                // RET [condition]
+                //
+                // Pop two bytes from stack and jump to that address, if
+                // condition is met.
                 //
-                // Return if Z flag is 0.
+                // A conditional RET takes an extra M-cycle to evaluate
+                // the condition.
 
-                if (self.regs.flags & ZERO_FLAG) == 0 {
-                    let low = memory.load(self.sp as usize);
-                    self.sp += 1;
-                    let high = memory.load(self.sp as usize);
-                    self.sp += 1;
+                if dst.is_some() {
+                    self.tick(memory);
+                }
 
-                    self.pc = u16::from_be_bytes([high, low]);
+                if self.condition(dst) {
+                    let (ret, sp) = (self.pc.wrapping_sub(1), self.sp);
+                    self.pc = self.pop(memory);
+                    self.tick(memory);
 
-                    cycles = 20;
-                    return cycles;
+                    self.call_stack.exit(ret, sp, self.pc);
                 }
-
-                cycles = 8;
             }
-            0xc8 => {
-                // RET NZ
+            Mnemonic::Reti => {
+                // RETI
                 //
-                // Return if Z flag is 1.
+                // Pop two bytes from stack and jump to that address then
+                // enable interrupts (without the delay of EI).
 
-                if (self.regs.flags & ZERO_FLAG) != 0 {
-                    let low = memory.load(self.sp as usize);
-                    self.sp += 1;
-                    let high = memory.load(self.sp as usize);
-                    self.sp += 1;
+                let (reti, sp) = (self.pc.wrapping_sub(1), self.sp);
+                self.pc = self.pop(memory);
+                self.tick(memory);
 
-                    self.pc = u16::from_be_bytes([high, low]);
+                self.call_stack.exit(reti, sp, self.pc);
 
-                    cycles = 20;
-                    return cycles;
-                }
-                cycles = 8;
+                self.ime = Ime::Enabled;
             }
-            0xd0 => {
-                // RET NC
+            Mnemonic::Rst => {
+                // RST n
                 //
-                // Return if C flag is 0.
+                // Push current address onto stack and jump to address n.
+                // n = 0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30, 0x38 depending on
+                // the opcode.
 
-                if (self.regs.flags & CARRY_FLAG) == 0 {
-                    let low = memory.load(self.sp as usize);
-                    self.sp += 1;
-                    let high = memory.load(self.sp as usize);
-                    self.sp += 1;
+                let Some(Operand::Vector(vector)) = dst else {
+                    unreachable!()
+                };
 
-                    self.pc = u16::from_be_bytes([high, low]);
+                self.tick(memory);
+                self.push(memory, self.pc);
 
-                    cycles = 20;
-                    return cycles;
-                }
-                cycles = 8;
+                self.call_stack.enter(Frame {
+                    entry: Entry::Rst,
+                    caller: self.pc.wrapping_sub(1),
+                    function: u16::from(vector),
+                    return_address: self.pc,
+                    sp: self.sp,
+                });
+
+                self.pc = u16::from(vector);
             }
-            0xd8 => {
-                // RET C
+            Mnemonic::Push => {
+                // PUSH r16
                 //
-                // Return if C flag is 1.
-
-                if (self.regs.flags & CARRY_FLAG) != 0 {
-                    let low = memory.load(self.sp as usize);
-                    self.sp += 1;
-                    let high = memory.load(self.sp as usize);
-                    self.sp += 1;
+                // Push register r16 onto stack and decrement the
+                // stack pointer twice.
+                // (BC, DE, HL, AF)
 
-                    self.pc = u16::from_be_bytes([high, low]);
+                let value = self.load16(dst.unwrap());
 
-                    cycles = 20;
-                    return cycles;
-                }
-                cycles = 8;
+                self.tick(memory);
+                self.push(memory, value);
             }
-            0xc1 | 0xd1 | 0xe1 | 0xf1 => {
+            Mnemonic::Pop => {
                 // POP r16
                 //
                 // Pop two bytes off stack into 16 bits register r16 and
                 // increment the stack pointer twice.
                 // (AF, BC, DE, HL)
 
-                let low = memory.load(self.sp as usize);
-                self.sp += 1;
-
-                let high = memory.load(self.sp as usize);
-                self.sp += 1;
-
-                let value = u16::from_be_bytes([high, low]);
-
-                let reg_name = match (opcode & 0xF0) >> 4 {
-                    0xc => {
-                        self.regs.write_bc(value);
-
-                        "BC"
-                    }
-                    0xd => {
-                        self.regs.write_de(value);
-
-                        "DE"
-                    }
-                    0xe => {
-                        self.regs.write_hl(value);
-
-                        "HL"
-                    }
-                    0xf => {
-                        self.regs.write_af(value);
-
-                        "AF"
-                    }
-                    _ => unreachable!(),
-                };
-
-                self.current_op = format!("{:9} POP {}", " ", reg_name);
-                cycles = 12;
+                let value = self.pop(memory);
+                self.store16(dst.unwrap(), value);
             }
-            0xc9 => {
-                // RET
+            Mnemonic::Di => {
+                // DI
                 //
-                // Pop two bytes from stack and jump to that address.
-
-                let low = memory.load(self.sp as usize);
-                self.sp += 1;
-                let high = memory.load(self.sp as usize);
-                self.sp += 1;
-
-                self.pc = u16::from_be_bytes([high, low]);
+                // Disable interrupts immediately.
 
-                self.current_op = format!("{:10} RET {:#04x}", " ", self.pc);
-
-                cycles = 16;
-                return cycles;
+                self.ime = Ime::Disabled;
             }
-            0xc5 | 0xd5 | 0xe5 | 0xf5 => {
-                // PUSH r16
+            Mnemonic::Ei => {
+                // EI
                 //
-                // Push register r16 onto stack and decrement the
-                // stack pointer twice.
-                // (BC, DE, HL, AF)
-
-                self.sp -= 1;
-
-                let reg_name = match (opcode & 0xF0) >> 4 {
-                    0xc => {
-                        memory.write(self.sp as usize, self.regs.b);
-
-                        self.sp -= 1;
-                        memory.write(self.sp as usize, self.regs.c);
-
-                        "BC"
-                    }
-                    0xd => {
-                        memory.write(self.sp as usize, self.regs.d);
-
-                        self.sp -= 1;
-                        memory.write(self.sp as usize, self.regs.e);
-
-                        "DE"
-                    }
-                    0xe => {
-                        memory.write(self.sp as usize, self.regs.h);
-
-                        self.sp -= 1;
-                        memory.write(self.sp as usize, self.regs.l);
-
-                        "HL"
-                    }
-                    0xf => {
-                        memory.write(self.sp as usize, self.regs.a);
-
-                        self.sp -= 1;
-                        memory.write(self.sp as usize, self.regs.flags);
-
-                        "AF"
-                    }
-                    _ => unreachable!(),
-                };
+                // Enable interrupts.
+                // Interrupts are enabled after the instruction after EI is executed.
 
-                self.current_op = format!("{:9} PUSH {}", " ", reg_name);
-                cycles = 16;
+                // Already enabled, it stays so
+                if self.ime == Ime::Disabled {
+                    self.ime = Ime::Scheduled;
+                }
             }
-            0xc4 | 0xcc | 0xcd | 0xd4 | 0xdc => {
-                // CALL condition, nn
+            Mnemonic::Halt => {
+                // HALT
                 //
-                // Push address of next instruction onto stack and jump to
-                // address nn.
+                // Stop executing instructions until an interrupt is pending.
                 //
-                // condition:
-                //   None: Always call
-                //   NZ: Call if Z flag is 0
-                //   Z:  Call if Z flag is 1
-                //   NC: Call if C flag is 0
-                //   C:  Call if C flag is 1
+                // If IME is 0 and an interrupt is already pending, HALT
+                // exits immediately and the next byte is read twice (HALT bug).
 
-                self.pc += 1;
-                let low = memory.load(self.pc as usize);
-                self.pc += 1;
-                let high = memory.load(self.pc as usize);
-
-                let condition = match opcode {
-                    // CALL
-                    0xcd => true,
-                    // CALL NZ
-                    0xc4 => self.regs.flags & ZERO_FLAG == 0,
-                    // CALL Z
-                    0xcc => self.regs.flags & ZERO_FLAG != 0,
-                    // CALL NC
-                    0xd4 => self.regs.flags & CARRY_FLAG == 0,
-                    // CALL C
-                    0xdc => self.regs.flags & CARRY_FLAG != 0,
-                    _ => unreachable!(),
+                self.state = if self.ime != Ime::Enabled && pending != 0 {
+                    State::HaltBug
+                } else {
+                    State::Halted
                 };
-
-                cycles = if condition { 24 } else { 12 };
-
-                if condition {
-                    let next_inst_addr = self.pc + 1;
-
-                    self.sp -= 1;
-                    memory.write(self.sp as usize, next_inst_addr.to_be_bytes()[0]);
-
-                    self.sp -= 1;
-                    memory.write(self.sp as usize, next_inst_addr.to_be_bytes()[1]);
-
-                    let addr = u16::from_be_bytes([high, low]);
-
-                    self.pc = addr;
-                    return cycles;
-                }
             }
-            0xcb => {
-                self.pc += 1;
-                let cb_opcode = memory.load(self.pc as usize);
-
-                match (cb_opcode & 0b1100_0000) >> 6 {
-                    0 => {
-                        let reg_index = cb_opcode & 0b111;
-
-                        match (cb_opcode & 0b0011_1000) >> 3 {
-                            0 => {
-                                // 8-bit rotation to the left. The bit leaving on the left
-                                // is copied into the carry, and to bit 0.
-
-                                let location = self.index(reg_index);
-
-                                let value = location.load8(&self.regs, &memory);
-
-                                self.regs.set_flag(CARRY_FLAG, value & 0b1000_0000 != 0);
-
-                                let result = value.rotate_left(1);
-                                location.store8(&mut self.regs, &mut memory, result);
-
-                                self.regs.set_flag(ZERO_FLAG, result == 0);
-                                self.regs.flags &= !SUBTRACT_FLAG;
-                                self.regs.flags &= !HALF_CARRY_FLAG;
-
-                                self.current_op =
-                                    format!("{:#04x}      RLC {}", cb_opcode, location);
-                            }
-                            1 => {
-                                // 8-bit rotation to the right. The bit leaving on the right
-                                // is copied into the carry, and to bit 7.
-
-                                let location = self.index(reg_index);
-
-                                let value = location.load8(&self.regs, &memory);
-
-                                self.regs.set_flag(CARRY_FLAG, value & 0x1 != 0);
-
-                                let result = value.rotate_right(1);
-                                location.store8(&mut self.regs, &mut memory, result);
-
-                                self.regs.set_flag(ZERO_FLAG, result == 0);
-                                self.regs.flags &= !SUBTRACT_FLAG;
-                                self.regs.flags &= !HALF_CARRY_FLAG;
-
-                                self.current_op =
-                                    format!("{:#04x}      RRC {}", cb_opcode, location);
-                            }
-                            2 => {
-                                // RL r8
-                                //
-                                // 9-bit rotation to the left using the carry flag.
-                                // The 8 bit register r8's bits are shifted left,
-                                // the carry value is put into 0th bit of the register,
-                                // and the leaving 7th bit is put into the carry.
-                                //
-                                //  before:
-                                //
-                                //  +---+  +-------------------------------+
-                                //  | 8 |  | 7 | 6 | 5 | 4 | 3 | 2 | 1 | 0 |
-                                //  +---+  +-------------------------------+
-                                //      C                                 r8
-                                //
-                                //  after
-                                //
-                                //  +---+  +-------------------------------+
-                                //  | 7 |  | 6 | 5 | 4 | 3 | 2 | 1 | 0 | 8 |
-                                //  +---+  +-------------------------------+
-                                //      C                                 r8
-                                //
-                                // flags:
-                                // Z: Set if result is zero, unset otherwise
-                                // N: 0
-                                // H: 0
-                                // C: Contains old bit 7 data.
-
-                                let carry = (self.regs.flags & CARRY_FLAG) >> 4;
-
-                                let location = self.index(reg_index);
-                                let initial_value = location.load8(&self.regs, &memory);
-
-                                let value = initial_value << 1 | carry;
-
-                                location.store8(&mut self.regs, &mut memory, value);
-
-                                self.regs
-                                    .set_flag(CARRY_FLAG, initial_value & 0b1000_0000 != 0);
-                                self.regs.set_flag(ZERO_FLAG, value == 0);
-
-                                self.regs.set_flag(SUBTRACT_FLAG, false);
-                                self.regs.set_flag(HALF_CARRY_FLAG, false);
-
-                                self.current_op =
-                                    format!("{:#04x}      RL {}", cb_opcode, location);
-                            }
-                            3 => {
-                                // 9-bit rotation to the right. The carry is copied into bit 7,
-                                // and the bit leaving on the right is copied into the carry.
-
-                                let carry = (self.regs.flags & CARRY_FLAG) >> 4;
-
-                                let location = self.index(reg_index);
-                                let initial_value = location.load8(&self.regs, &memory);
-
-                                let value = carry << 7 | (initial_value >> 1);
-
-                                location.store8(&mut self.regs, &mut memory, value);
-
-                                self.regs.set_flag(CARRY_FLAG, initial_value & 0x1 != 0);
-                                self.regs.set_flag(ZERO_FLAG, value == 0);
-
-                                self.regs.set_flag(HALF_CARRY_FLAG, false);
-                                self.regs.set_flag(SUBTRACT_FLAG, false);
-
-                                self.current_op =
-                                    format!("{:#04x}      RR {}", cb_opcode, location);
-                            }
-                            4 => {
-                                // SLA n
-                                //
-                                // Aritmetic shift n left into Carry. MSB (sign bit)
-                                // is untouched.
-                                //   Z - Set if result is zero.
-                                //   N - Reset.
-                                //   H - Reset.
-                                //   C - Contains old bit 7 data.
-
-                                let location = self.index(reg_index);
-                                let initial_value = location.load8(&self.regs, &memory);
-
-                                let value = initial_value << 1;
-                                location.store8(&mut self.regs, &mut memory, value);
-
-                                self.regs.set_flag(CARRY_FLAG, initial_value & 0b1000_0000 != 0);
-                                self.regs.set_flag(ZERO_FLAG, value == 0);
-
-                                self.regs.set_flag(HALF_CARRY_FLAG, false);
-                                self.regs.set_flag(SUBTRACT_FLAG, false);
-
-                                self.current_op = "SLA".to_string();
-                            }
-                            5 => {
-                                // SRA n
-                                //
-                                // Aritmetic shift n right into Carry.
-                                // MSB (sign bit) is untouched.
-                                //
-                                //   Z - Set if result is zero.
-                                //   N - Reset.
-                                //   H - Reset.
-                                //   C - Contains old bit 0 data.
-
-                                let location = self.index(reg_index);
-                                let initial_value = location.load8(&self.regs, &memory);
-
-                                let value = initial_value >> 1 | initial_value & 0b1000_0000;
-                                location.store8(&mut self.regs, &mut memory, value);
-
-                                self.regs.set_flag(CARRY_FLAG, initial_value & 0x1 != 0);
-                                self.regs.set_flag(ZERO_FLAG, value == 0);
-
-                                self.regs.set_flag(HALF_CARRY_FLAG, false);
-                                self.regs.set_flag(SUBTRACT_FLAG, false);
-
-                                self.current_op = "SRA".to_string();
-                            }
-                            6 => {
-                                // TODO doc
-
-                                let location = self.index(reg_index);
-                                let value = location.load8(&self.regs, &memory);
+            Mnemonic::Stop => {
+                // STOP
+                //
+                // Enter very low power mode, until a button is pressed.
+                // The byte following STOP is skipped and DIV is reset.
 
-                                let swapped = (value & 0xf) << 4 | (value & 0xf0) >> 4;
+                self.fetch(memory);
+                memory.write(0xff04, 0);
 
-                                location.store8(&mut self.regs, &mut memory, swapped);
+                self.state = State::Stopped;
+            }
+            Mnemonic::Prefix => {
+                let cb_opcode = self.fetch(memory);
 
-                                self.regs.set_flag(ZERO_FLAG, swapped == 0);
-                                self.regs.set_flag(CARRY_FLAG, false);
-                                self.regs.set_flag(HALF_CARRY_FLAG, false);
-                                self.regs.set_flag(SUBTRACT_FLAG, false);
+                self.execute(memory, &CB_OPCODES[cb_opcode as usize], pending);
+            }
+            Mnemonic::Rlc
+            | Mnemonic::Rrc
+            | Mnemonic::Rl
+            | Mnemonic::Rr
+            | Mnemonic::Sla
+            | Mnemonic::Sra
+            | Mnemonic::Swap
+            | Mnemonic::Srl => {
+                let operand = dst.unwrap();
+
+                let value = self.load8(memory, operand);
+                let result = self.rotate(opcode.mnemonic, value);
+                self.store8(memory, operand, result);
+
+                self.regs.set_flag(ZERO_FLAG, result == 0);
+            }
+            Mnemonic::Bit | Mnemonic::Res | Mnemonic::Set => {
+                let (Some(Operand::BitNum(bit_pos)), Some(operand)) = (dst, src) else {
+                    unreachable!()
+                };
+                let mask = 1 << bit_pos;
 
-                                self.current_op = "SWAP".to_string();
-                            }
-                            7 => {
-                                // TODO better doc
-                                // SRL n
-                                //
-                                // Shift n right into Carry. MSB set to 0.
-                                //   Z - Set if result is zero.
-                                //   N - Reset.
-                                //   H - Reset.
-                                //   C - Contains old bit 0 data.
-
-                                let location = self.index(reg_index);
-                                let initial_value = location.load8(&self.regs, &memory);
-
-                                let value = initial_value >> 1;
-                                location.store8(&mut self.regs, &mut memory, value);
+                let value = self.load8(memory, operand);
 
-                                self.regs.set_flag(CARRY_FLAG, initial_value & 0x1 != 0);
-                                self.regs.set_flag(ZERO_FLAG, value == 0);
-
-                                self.regs.set_flag(HALF_CARRY_FLAG, false);
-                                self.regs.set_flag(SUBTRACT_FLAG, false);
-
-                                self.current_op = "SRL".to_string();
-                            }
-                            _ => unreachable!(),
-                        }
-                    }
-                    1 => {
+                match opcode.mnemonic {
+                    Mnemonic::Bit => {
                         // BIT n,r8
                         //
                         // Test nth bit in register r8.
@@ -1454,414 +1054,306 @@ impl Cpu {
                         // Z: Set if nth bit of register r8 is 0.
                         // N: 0  H: 1, C: no change
 
-                        let bit_pos = (cb_opcode & 0b0011_1000) >> 3;
-                        let mask = 1 << bit_pos;
-
-                        let reg_index = cb_opcode & 0b111;
-
-                        let location = self.index(reg_index);
-                        let value = location.load8(&self.regs, &memory);
-
                         self.regs.set_flag(ZERO_FLAG, value & mask == 0);
 
                         self.regs.set_flag(SUBTRACT_FLAG, false);
                         self.regs.set_flag(HALF_CARRY_FLAG, true);
-
-                        self.current_op = format!("BIT {},{}", bit_pos, location);
-                    }
-                    2 => {
-                        // TODO check and document
-                        let bit_pos = (cb_opcode & 0b0011_1000) >> 3;
-                        let mask = !(1 << bit_pos);
-
-                        let reg_index = cb_opcode & 0b111;
-
-                        let location = self.index(reg_index);
-                        let value = location.load8(&self.regs, &memory);
-
-                        location.store8(&mut self.regs, &mut memory, value & mask);
-
-                        self.current_op = "RES opcode".to_string();
                     }
-                    3 => {
-                        // TODO check and document
-                        let bit_pos = (cb_opcode & 0b0011_1000) >> 3;
-                        let mask = 1 << bit_pos;
-
-                        let reg_index = cb_opcode & 0b111;
-
-                        let location = self.index(reg_index);
-                        let value = location.load8(&self.regs, &memory);
-
-                        location.store8(&mut self.regs, &mut memory, value | mask);
-
-                        self.current_op = "SET, opcode".to_string();
-                    }
-                    _ => panic!("Unknown CB opcode"),
+                    // RES n,r8
+                    //
+                    // Reset nth bit in register r8. No flags affected.
+                    Mnemonic::Res => self.store8(memory, operand, value & !mask),
+                    // SET n,r8
+                    //
+                    // Set nth bit in register r8. No flags affected.
+                    _ => self.store8(memory, operand, value | mask),
                 }
-
-                /* FIXME */
-                cycles = 4;
             }
-            0x20 => {
-                // JR NZ, n
-                //
-                // Jump to the current address + n (signed) if Z is 0.
-
-                self.pc += 1;
-                let immediate = memory.load(self.pc as usize) as i8;
-
-                self.current_op = format!("{0:#04x}      JR NZ,{0:#04x} {0}", immediate);
+            Mnemonic::Illegal => {
+                // 0xd3, 0xdb, 0xdd, 0xe3, 0xe4, 0xeb, 0xec, 0xed, 0xf4, 0xfc, 0xfd
+                // are not SM83 instructions and lock up the CPU.
 
-                cycles = 8;
-
-                if (self.regs.flags & ZERO_FLAG) == 0 {
-                    // Relative jump is calculated starting from the next instruction
-                    self.pc += 1;
+                self.state = State::Locked;
+            }
+        }
 
-                    cycles = 12;
-                    self.pc = self.pc.wrapping_add_signed(immediate as i16);
+        // The prefix is accounted in the CB opcode itself.
+        debug_assert!(
+            opcode.mnemonic == Mnemonic::Prefix
+                || self.cycles == opcode.cycles
+                || self.cycles == opcode.cycles_taken,
+            "{} took {} cycles",
+            opcode.mnemonic,
+            self.cycles
+        );
+    }
 
-                    return cycles;
-                }
-            }
-            0x2f => {
-                // CPL
+    // Opcodes operating on A and 8 bit n,
+    // where n = A, B, C, D, E, H, L, (HL), immediate.
+    fn alu(&mut self, mnemonic: Mnemonic, n: u8) {
+        match mnemonic {
+            Mnemonic::Add => {
+                // ADD A, n
                 //
-                // Complement A register. (Flip all bits)
+                // Add n to A.
                 //
                 // flags:
-                // Z: no change
-                // N: 1
-                // H: 1
-                // C: no change
+                // Z: Set if result is zero.
+                // N: 0
+                // H: Set if carry from bit 3.
+                // C: Set if carry from bit 7.
 
-                self.regs.a = !self.regs.a;
+                let half_carry = (self.regs.a & 0xf) + (n & 0xf) > 0xf;
+                self.regs.set_flag(HALF_CARRY_FLAG, half_carry);
 
-                self.regs.set_flag(SUBTRACT_FLAG, true);
-                self.regs.set_flag(HALF_CARRY_FLAG, true);
+                let (result, carry) = self.regs.a.overflowing_add(n);
 
-                cycles = 4;
+                self.regs.a = result;
+                self.regs.set_flag(ZERO_FLAG, self.regs.a == 0);
+                self.regs.set_flag(SUBTRACT_FLAG, false);
+                self.regs.set_flag(CARRY_FLAG, carry);
             }
-            0x30 => {
-                // JR NC,n
+            Mnemonic::Adc => {
+                // ADC A, n
                 //
-                // Jump to the current address + n (signed) if C is 0.
-
-                self.pc += 1;
-                let immediate = memory.load(self.pc as usize) as i8;
-
-                self.current_op = format!("{0:#04x}      JR NC,{0:#04x} {0}", immediate);
-
-                cycles = 8;
-                if (self.regs.flags & CARRY_FLAG) == 0 {
-                    self.pc += 1;
-                    cycles = 12;
-
-                    self.pc = self.pc.wrapping_add_signed(immediate as i16);
-
-                    return cycles;
-                }
-            }
-            0xc3 => {
-                self.pc += 1;
-                let low = memory.load(self.pc as usize);
-                self.pc += 1;
-                let high = memory.load(self.pc as usize);
-
-                let addr: u16 = u16::from(high) << 8 | u16::from(low);
-                self.pc = addr;
-
-                self.current_op = format!("{:9} JP {:#04x}", " ", addr);
-
-                cycles = 16;
-                return cycles;
-            }
-            0xc2 | 0xca | 0xd2 | 0xda => {
-                // JP cc,nn
-                self.pc += 1;
-                let low = memory.load(self.pc as usize);
-                self.pc += 1;
-                let high = memory.load(self.pc as usize);
-
-                let condition = match opcode {
-                    // JP NZ, nn
-                    0xc2 => self.regs.flags & ZERO_FLAG == 0,
-                    // JP Z, nn
-                    0xca => self.regs.flags & ZERO_FLAG != 0,
-                    // JP NC, nn
-                    0xd2 => self.regs.flags & CARRY_FLAG == 0,
-                    // JP C, nn
-                    0xda => self.regs.flags & CARRY_FLAG != 0,
-                    _ => unreachable!(),
-                };
-
-                // TODO check 16? 12?
-                cycles = 12;
-
-                if condition {
-                    let addr = u16::from_be_bytes([high, low]);
-
-                    self.pc = addr;
-                    return cycles;
-                }
-            }
-            0xe0 => {
-                // LDH (n),A
+                // Add n + Carry flag to A.
                 //
-                // Store A into memory address 0xff00 + n (signed).
-
-                self.pc += 1;
-                let immediate = memory.load(self.pc as usize) as usize;
-
-                self.current_op = format!("{0:#04x}      LD (0xff00+{0:#04x}),A", immediate);
-
-                memory.write(0xff00 + immediate, self.regs.a);
+                // flags:
+                // Z: Set if result is zero.
+                // N: 0
+                // H: Set if carry from bit 3.
+                // C: Set if carry from bit 7.
 
-                cycles = 12;
-            }
-            0xe8 => {
-                // ADD SP, n
-                //
-                // Add n (signed) to Stack Pointer (SP).
+                let carry = u8::from(self.regs.flags & CARRY_FLAG != 0);
 
-                self.pc += 1;
-                let n = memory.load(self.pc as usize);
+                let half_carry = (self.regs.a & 0xf) + (n & 0xf) + carry > 0xf;
+                self.regs.set_flag(HALF_CARRY_FLAG, half_carry);
 
-                self.regs.set_flag(HALF_CARRY_FLAG, (self.sp & 0xf) + ((n & 0xf) as u16) > 0xf);
-                self.regs.set_flag(CARRY_FLAG, (self.sp & 0xff) + (n as u16) > 0xff);
+                let result = u16::from(self.regs.a) + u16::from(carry) + u16::from(n);
 
-                (self.sp, _) = self.sp.overflowing_add_signed((n as i8).into());
+                self.regs.a = result.to_le_bytes()[0];
 
-                self.regs.set_flag(ZERO_FLAG, false);
+                self.regs.set_flag(ZERO_FLAG, self.regs.a == 0);
                 self.regs.set_flag(SUBTRACT_FLAG, false);
 
-                cycles = 16;
+                self.regs.set_flag(CARRY_FLAG, result > 0xff);
             }
-            0xe9 => {
-                // JP (HL)
+            Mnemonic::Sub => {
+                // SUB n
                 //
-                // Jump to address contained in HL.
-
-                self.pc = self.regs.hl();
-
-                cycles = 4;
-                return cycles;
-            }
-            0xf0 => {
-                // LDH A,(n)
+                // Subtract n from A.
                 //
-                // Store the value from memory address 0xff00 + n (signed)
-                // into A.
-
-                self.pc += 1;
-                let immediate = memory.load(self.pc as usize) as usize;
+                // flags:
+                // Z: Set if result is zero
+                // N: 1
+                // H: Set if borrow from bit 4
+                // C: Set if borrow
 
-                self.current_op = format!("{0:#04x}      LD (0xff00+{0:#04x}),A", immediate);
+                let (_, borrow) = (self.regs.a & 0xf).overflowing_sub(n & 0xf);
+                self.regs.set_flag(HALF_CARRY_FLAG, borrow);
 
-                self.regs.a = memory.load(0xff00 + immediate);
+                let (result, carry) = self.regs.a.overflowing_sub(n);
 
-                cycles = 12;
+                self.regs.a = result;
+                self.regs.set_flag(CARRY_FLAG, carry);
+                self.regs.set_flag(ZERO_FLAG, self.regs.a == 0);
+                self.regs.set_flag(SUBTRACT_FLAG, true);
             }
-            0xe2 => {
-                // LD (0xff00+C),A
+            Mnemonic::Sbc => {
+                // SBC A,n
                 //
-                // Store A into address 0xff0 + register C.
-
-                memory.write(0xff00 + self.regs.c as usize, self.regs.a);
-
-                self.current_op = "LD (0xff00+C),A".to_string();
-
-                cycles = 8;
-            }
-            0xea => {
-                // LD (a16), A
+                // Subtract n + Carry flag from A.
                 //
-                // Put value A into memory address a16.
-
-                self.pc += 1;
-                let low = memory.load(self.pc as usize);
-                self.pc += 1;
-                let high = memory.load(self.pc as usize);
+                // flags:
+                // Z - Set if result is zero.
+                // N - Set.
+                // H - Set if borrow from bit 4.
+                // C - Set if borrow.
 
-                let addr: u16 = u16::from(high) << 8 | u16::from(low);
+                let c = u8::from(self.regs.flags & CARRY_FLAG != 0);
+                let result = self.regs.a.wrapping_sub(n).wrapping_sub(c);
 
-                memory.write(addr as usize, self.regs.a);
+                self.regs
+                    .set_flag(HALF_CARRY_FLAG, (self.regs.a & 0xf) < (n & 0xf) + c);
+                self.regs.set_flag(
+                    CARRY_FLAG,
+                    u16::from(self.regs.a) < u16::from(n) + u16::from(c),
+                );
 
-                self.current_op = format!("{:9} LD ({:#06x}),A", " ", addr);
+                self.regs.a = result;
 
-                cycles = 16;
+                self.regs.set_flag(ZERO_FLAG, self.regs.a == 0);
+                self.regs.set_flag(SUBTRACT_FLAG, true);
             }
-            0xc7 | 0xcf | 0xd7 | 0xdf | 0xe7 | 0xef | 0xf7 | 0xff => {
-                // RST n
+            Mnemonic::And => {
+                // AND n
                 //
-                // Push current address onto stack and jump to address n.
-                // n = 0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30, 0x38 depending on
-                // the opcode.
-
-                // XXX check!
-                self.pc += 1;
-
-                self.sp -= 1;
-                memory.write(self.sp as usize, (self.pc >> 8) as u8);
-                self.sp -= 1;
-                memory.write(self.sp as usize, (self.pc & 0xff) as u8);
-
-                self.pc = u16::from(opcode - 0xc7);
-
-                cycles = 16;
-                return cycles;
-            }
-            0xd9 => {
-                // RETI
+                // Logically AND n with A, result in A.
                 //
-                // Pop two bytes from stack and jump to that address then enable interrupts.
-
-                let low = memory.load(self.sp as usize);
-                self.sp += 1;
-                let high = memory.load(self.sp as usize);
-                self.sp += 1;
+                // flags:
+                // Z: Set if result is zero.
+                // N: 0, H: 1, C: 0
 
-                self.pc = u16::from_be_bytes([high, low]);
-                self.interrupts_enabled = true;
+                self.regs.a &= n;
 
-                cycles = 16;
-                return cycles;
+                self.regs.set_flag(ZERO_FLAG, self.regs.a == 0);
+                self.regs.set_flag(SUBTRACT_FLAG, false);
+                self.regs.set_flag(HALF_CARRY_FLAG, true);
+                self.regs.set_flag(CARRY_FLAG, false);
             }
-            0xfa => {
-                // LD A,(a16)
+            Mnemonic::Xor => {
+                // XOR n
                 //
-                // Store the value from memory address a16 into A.
-
-                self.pc += 1;
-                let low = memory.load(self.pc as usize);
-                self.pc += 1;
-                let high = memory.load(self.pc as usize);
-
-                let immediate = u16::from(high) << 8 | u16::from(low);
-
-                self.regs.a = memory.load(immediate as usize);
-
-                cycles = 16;
-            }
-            0xf2 => {
-                // LD A,(C)
-                // aka LD A,($FF00+C)
+                // Logical exclusive OR n with register A, result in A.
                 //
-                // Put value at address 0xFF00 + register C into A.
-
-                self.regs.a = memory.load(self.regs.c as usize + 0xff00);
+                // flags:
+                // Z: Set if result is zero
+                // N: 0, H: 0, C: 0
 
-                self.current_op = format!("{:10} LD A,(C)", " ");
+                self.regs.a ^= n;
 
-                cycles = 8;
+                self.regs.flags = 0;
+                self.regs.set_flag(ZERO_FLAG, self.regs.a == 0);
             }
-            0xf3 => {
-                // DI
+            Mnemonic::Or => {
+                // OR n
                 //
-                // Disable interrupts.
-                // Interrupts are disabled after instruction after DI is executed.
-
-                self.interrupts_enabled = false;
-                // TODO skip next instruction
-
-                cycles = 4;
-            }
-            0xfb => {
-                // EI
+                // Logical OR n with register A, result in A.
                 //
-                // Enable interrupts.
-                // Interrupts are enabled after the instruction after EI is executed.
+                // flags:
+                // Z: Set if result is zero.
+                // N: 0, H: 0, C: 0
 
-                self.interrupts_enabled = true;
-                // TODO skip next instruction
+                self.regs.a |= n;
 
-                cycles = 4;
+                self.regs.flags = 0;
+                self.regs.set_flag(ZERO_FLAG, self.regs.a == 0);
             }
-            0xf8 => {
-                // LD HL, SP+r8
+            Mnemonic::Cp => {
+                // CP n
                 //
-                // Put SP + n effective address into HL.
-
-                self.pc += 1;
-                let n = memory.load(self.pc as usize);
-
-                self.regs.set_flag(HALF_CARRY_FLAG, (self.sp & 0xf) + ((n & 0xf) as u16) > 0xf);
-                self.regs.set_flag(CARRY_FLAG, (self.sp & 0xff) + (n as u16) > 0xff);
-
-                let (result, _) = self.sp.overflowing_add_signed((n as i8).into());
-                self.regs.write_hl(result);
-
-                self.regs.set_flag(ZERO_FLAG, false);
-                self.regs.set_flag(SUBTRACT_FLAG, false);
-
-                cycles = 12
-            }
-            0xf9 => {
-                // LD SP,HL
+                // Compare A with n. This is basically an A - n  subtraction instruction
+                // but the results are thrown away.
                 //
-                // Store the value of HL in SP
+                // flags:
+                // Z: Set if result is zero. (A == n)
+                // N: 1
+                // H: Set if borrow from bit 4.
+                // C: Set for no borrow. (Set if A < n)
 
-                self.sp = self.regs.hl();
-                cycles = 8;
-            }
-            _ => {
-                println!("Unknown opcode");
-                println!("{:08x}:\t{:#04x} ", self.pc, opcode);
-                panic!();
+                self.regs.set_flag(ZERO_FLAG, self.regs.a == n);
+                self.regs.set_flag(SUBTRACT_FLAG, true);
+                self.regs.set_flag(CARRY_FLAG, n > self.regs.a);
+
+                let (_, borrow) = (self.regs.a & 0xf).overflowing_sub(n & 0xf);
+                self.regs.set_flag(HALF_CARRY_FLAG, borrow);
             }
+            _ => unreachable!(),
         }
-
-        self.pc += 1;
-
-        cycles
     }
 
-    pub fn mem_next(&self) {
-        println!(
-            "-> pc: {:#08x}\n   {:#04x} {:#04x}",
-            self.pc,
-            self.memory.borrow().load(self.pc as usize),
-            self.memory.borrow().load((self.pc + 1) as usize)
-        );
+    // Rotations and shifts, the 0xcb prefixed ones and RLCA, RRCA, RLA, RRA.
+    //
+    // flags:
+    // Z: Set if result is zero (the caller takes care of it)
+    // N: 0
+    // H: 0
+    // C: Contains the bit leaving the value (0 for SWAP)
+    fn rotate(&mut self, mnemonic: Mnemonic, value: u8) -> u8 {
+        let carry = (self.regs.flags & CARRY_FLAG) >> 4;
+
+        let (result, carry_out) = match mnemonic {
+            // 8-bit rotation to the left. The bit leaving on the left
+            // is copied into the carry, and to bit 0.
+            Mnemonic::Rlc => (value.rotate_left(1), value & 0b1000_0000 != 0),
+
+            // 8-bit rotation to the right. The bit leaving on the right
+            // is copied into the carry, and to bit 7.
+            Mnemonic::Rrc => (value.rotate_right(1), value & 0x1 != 0),
+
+            // RL r8
+            //
+            // 9-bit rotation to the left using the carry flag.
+            // The 8 bit register r8's bits are shifted left,
+            // the carry value is put into 0th bit of the register,
+            // and the leaving 7th bit is put into the carry.
+            //
+            //  before:
+            //
+            //  +---+  +-------------------------------+
+            //  | 8 |  | 7 | 6 | 5 | 4 | 3 | 2 | 1 | 0 |
+            //  +---+  +-------------------------------+
+            //      C                                 r8
+            //
+            //  after
+            //
+            //  +---+  +-------------------------------+
+            //  | 7 |  | 6 | 5 | 4 | 3 | 2 | 1 | 0 | 8 |
+            //  +---+  +-------------------------------+
+            //      C                                 r8
+            Mnemonic::Rl => (value << 1 | carry, value & 0b1000_0000 != 0),
+
+            // 9-bit rotation to the right. The carry is copied into bit 7,
+            // and the bit leaving on the right is copied into the carry.
+            Mnemonic::Rr => (carry << 7 | value >> 1, value & 0x1 != 0),
+
+            // SLA n
+            //
+            // Aritmetic shift n left into Carry. Bit 0 is set to 0.
+            Mnemonic::Sla => (value << 1, value & 0b1000_0000 != 0),
+
+            // SRA n
+            //
+            // Aritmetic shift n right into Carry.
+            // MSB (sign bit) is untouched.
+            Mnemonic::Sra => (value >> 1 | value & 0b1000_0000, value & 0x1 != 0),
+
+            // SWAP n
+            //
+            // Swap the upper and lower nibbles of n.
+            Mnemonic::Swap => ((value & 0xf) << 4 | (value & 0xf0) >> 4, false),
+
+            // SRL n
+            //
+            // Shift n right into Carry. MSB set to 0.
+            Mnemonic::Srl => (value >> 1, value & 0x1 != 0),
+            _ => unreachable!(),
+        };
+
+        self.regs.set_flag(CARRY_FLAG, carry_out);
+        self.regs.set_flag(SUBTRACT_FLAG, false);
+        self.regs.set_flag(HALF_CARRY_FLAG, false);
+
+        result
     }
 
-    pub fn vblank_int(&mut self) {
-        self.sp -= 1;
-        self.memory
-            .borrow_mut()
-            .write(self.sp as usize, (self.pc >> 8) as u8);
+    // SP + n (signed immediate), for ADD SP,n and LD HL,SP+n.
+    //
+    // The flags are computed on the low byte, as an unsigned addition.
+    fn add_sp(&mut self, memory: &mut dyn Bus) -> u16 {
+        let n = self.fetch(memory);
 
-        self.sp -= 1;
-        self.memory
-            .borrow_mut()
-            .write(self.sp as usize, (self.pc & 0xff) as u8);
+        self.regs
+            .set_flag(HALF_CARRY_FLAG, (self.sp & 0xf) + u16::from(n & 0xf) > 0xf);
+        self.regs
+            .set_flag(CARRY_FLAG, (self.sp & 0xff) + u16::from(n) > 0xff);
 
-        // XXX: is this right?
-        self.interrupts_enabled = false;
+        self.regs.set_flag(ZERO_FLAG, false);
+        self.regs.set_flag(SUBTRACT_FLAG, false);
 
-        self.pc = 0x40;
+        self.sp.wrapping_add_signed(i8::from_le_bytes([n]).into())
     }
 
-    pub fn status_int(&mut self) {
-        self.sp -= 1;
-        self.memory
-            .borrow_mut()
-            .write(self.sp as usize, (self.pc >> 8) as u8);
-        self.sp -= 1;
-        self.memory
-            .borrow_mut()
-            .write(self.sp as usize, (self.pc & 0xff) as u8);
-
-
-        println!("STATUS pc {:#04x}", self.sp);
-
-        // XXX: is this right?
-        self.interrupts_enabled = false;
-
-        self.pc = 0x48;
+    fn condition(&self, condition: Option<Operand>) -> bool {
+        match condition {
+            None => true,
+            Some(Operand::CondNZ) => self.regs.flags & ZERO_FLAG == 0,
+            Some(Operand::CondZ) => self.regs.flags & ZERO_FLAG != 0,
+            Some(Operand::CondNC) => self.regs.flags & CARRY_FLAG == 0,
+            Some(Operand::CondC) => self.regs.flags & CARRY_FLAG != 0,
+            Some(operand) => panic!("{:?} is not a condition", operand),
+        }
     }
-
 }
 
 impl fmt::Display for Cpu {
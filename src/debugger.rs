@@ -0,0 +1,1219 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::mem;
+
+use crate::callstack::{Entry, Problem};
+use crate::cpu::{CARRY_FLAG, HALF_CARRY_FLAG, SUBTRACT_FLAG, ZERO_FLAG};
+use crate::disassembler::{self, Symbols};
+use crate::expression::Expression;
+use crate::gameboy::GameBoy;
+use crate::opcodes::{self, Mnemonic};
+use crate::watchpoint::{Condition, Hit, Kind, Operator, Watchpoint, Watchpoints};
+
+// Interactive debugger, reading commands from any input so that it works
+// with or without a window.
+//
+// The frontend calls check() before every instruction, and repl() when it
+// returns true.
+pub struct Debugger {
+    // Watchpoints are kept by Memory, see Memory::watchpoints_mut()
+    breakpoints: Vec<Breakpoint>,
+    // Number for the next breakpoint or watchpoint without a name
+    next_id: u32,
+    // Why the emulation stopped: the breakpoints at PC and the watchpoints
+    // hit by the last instruction
+    triggered: Vec<String>,
+    watchpoint_hits: Vec<Hit>,
+
+    mode: Mode,
+    // Stop before the next instruction, whatever the mode
+    stop_requested: bool,
+    // Just resumed, don't stop before the instruction it stopped at
+    resumed: bool,
+
+    // PC at the last check
+    last_pc: u16,
+    // The instruction at the last check was a return
+    returning: bool,
+
+    history: Vec<String>,
+
+    // Names shown in the disassembly and usable as addresses
+    symbols: Symbols,
+}
+
+pub struct Breakpoint {
+    pub name: String,
+    pub address: u16,
+    pub enabled: bool,
+    // Only stop when true
+    pub condition: Option<Expression>,
+    // Times reached with the condition true
+    pub hits: u32,
+    // Only stop from this hit on, e.g. on the 50th call to a function
+    pub after: u32,
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:8} ${:04x}", self.name, self.address)?;
+
+        if let Some(condition) = &self.condition {
+            write!(f, " if {condition}")?;
+        }
+        if self.after > 1 {
+            write!(f, " after {}", self.after)?;
+        }
+        if self.hits > 0 {
+            write!(f, ", hit count {}", self.hits)?;
+        }
+        if !self.enabled {
+            write!(f, " (disabled)")?;
+        }
+
+        Ok(())
+    }
+}
+
+// When to stop, besides breakpoints
+enum Mode {
+    Run,
+    // After this many instructions
+    Step(u32),
+    // At the instruction after a CALL or RST, once it returns
+    Over { address: u16, sp: u16 },
+    // When the function being executed returns
+    Out { sp: u16 },
+    // At an address
+    To(u16),
+}
+
+// What to do after the debugger prompt
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Resume,
+    Quit,
+}
+
+const HELP: &str = "\
+c, continue           run until a breakpoint
+s, step [N]           execute N instructions (1)
+n, next               execute an instruction, stepping over calls
+finish                run until the current function returns
+until ADDR            run until PC is ADDR
+b, break ADDR [NAME] [if EXPR]
+                      set a breakpoint, stopping only when EXPR is true
+cond NAME [EXPR]      set or remove the condition of a breakpoint
+after NAME N          only stop from the Nth hit of a breakpoint on
+p, print EXPR         evaluate an expression
+d, delete NAME|all    delete breakpoints and watchpoints
+enable NAME           enable a breakpoint or watchpoint
+disable NAME          disable a breakpoint or watchpoint
+watch r|w|rw|c ADDR[-END] [OP VALUE] [NAME]
+                      set a watchpoint on reads, writes, both or writes
+                      changing the value, optionally only when the value
+                      matches (OP is == != < <= > >= &)
+bl, breakpoints       list breakpoints and watchpoints
+bt, backtrace         show the calls and interrupts that led to PC
+r, regs               show the registers
+set REG VALUE         set a register (a f b c d e h l af bc de hl sp pc)
+flag FLAG 0|1         set a flag (z n h c) or IME (ime)
+x ADDR [LEN]          dump LEN bytes of memory ($40)
+dis [ADDR] [N]        disassemble N instructions at ADDR (around PC)
+j, jump ADDR          set PC
+history               list the commands entered, !N runs the Nth again
+q, quit               quit the emulator
+An empty line repeats the last command. Numbers are hexadecimal, with an
+optional $ or 0x prefix, except in expressions where they are decimal unless
+prefixed with $ or 0x. Addresses can also be symbols, e.g. b Main.loop.
+Expressions are made of registers (a, hl, sp...), flags (zf nf hf cf ime),
+I/O registers (ly, lcdc, stat...), [ADDR] for memory and the operators of C,
+e.g. a == 0x3f && [hl] != 0 && ly > 140";
+
+// There's no MBC, bank 1 is always at 0x4000-0x7fff
+pub const MAPPED_BANK: u16 = 1;
+
+// Bytes shown by x by default
+const DUMP_LENGTH: u16 = 0x40;
+
+// Instructions shown before and after PC by dis
+const DISASSEMBLY_BEFORE: u16 = 4;
+const DISASSEMBLY_AFTER: usize = 6;
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            breakpoints: Vec::new(),
+            next_id: 1,
+            triggered: Vec::new(),
+            watchpoint_hits: Vec::new(),
+            mode: Mode::Run,
+            stop_requested: false,
+            resumed: false,
+            last_pc: 0,
+            returning: false,
+            history: Vec::new(),
+            symbols: Symbols::new(),
+        }
+    }
+
+    // Names shown in the disassembly and usable as addresses
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    #[must_use]
+    pub const fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    // An address, as a symbol or a number
+    pub fn parse_address(&self, text: &str) -> Result<u16, String> {
+        match self.symbols.find(text) {
+            Some((bank, address)) if bank != disassembler::bank_at(MAPPED_BANK, address) => {
+                Err(format!("{text} is in bank {bank:02x}, which isn't mapped"))
+            }
+            Some((_, address)) => Ok(address),
+            None => parse_number(text),
+        }
+    }
+
+    // Where address is, e.g. Main+$12
+    #[must_use]
+    pub fn location(&self, address: u16) -> String {
+        self.symbols.describe(MAPPED_BANK, address)
+    }
+
+    // Stop before the next instruction
+    pub const fn stop(&mut self) {
+        self.stop_requested = true;
+    }
+
+    // The name given, or the next number no breakpoint or watchpoint has
+    // as its name
+    fn name(&mut self, name: Option<String>, watchpoints: &Watchpoints) -> String {
+        name.unwrap_or_else(|| loop {
+            let id = self.next_id.to_string();
+            self.next_id += 1;
+
+            let taken = self
+                .breakpoints
+                .iter()
+                .any(|breakpoint| breakpoint.name == id)
+                || watchpoints.iter().any(|watchpoint| watchpoint.name == id);
+            if !taken {
+                return id;
+            }
+        })
+    }
+
+    pub fn add_breakpoint(
+        &mut self,
+        gameboy: &GameBoy,
+        address: u16,
+        name: Option<String>,
+        condition: Option<Expression>,
+    ) -> &Breakpoint {
+        let name = self.name(name, gameboy.memory().watchpoints());
+
+        self.breakpoints
+            .retain(|breakpoint| breakpoint.name != name);
+        self.breakpoints.push(Breakpoint {
+            name,
+            address,
+            enabled: true,
+            condition,
+            hits: 0,
+            after: 1,
+        });
+
+        &self.breakpoints[self.breakpoints.len() - 1]
+    }
+
+    // Returns false if there's no breakpoint with that name
+    pub fn remove_breakpoint(&mut self, name: &str) -> bool {
+        let count = self.breakpoints.len();
+        self.breakpoints
+            .retain(|breakpoint| breakpoint.name != name);
+
+        self.breakpoints.len() != count
+    }
+
+    // Continue running after a stop, for a front end other than repl()
+    pub const fn resume(&mut self) {
+        self.mode = Mode::Run;
+        self.resumed = true;
+    }
+
+    // Execute a single instruction after a stop, as resume()
+    pub const fn step(&mut self) {
+        self.mode = Mode::Step(1);
+        self.resumed = true;
+    }
+
+    // Names of the breakpoints the last stop was at
+    #[must_use]
+    pub fn breakpoint_hits(&self) -> &[String] {
+        &self.triggered
+    }
+
+    // The watchpoints hit by the last instruction before the stop
+    #[must_use]
+    pub fn watchpoint_hits(&self) -> &[Hit] {
+        &self.watchpoint_hits
+    }
+
+    fn breakpoint_mut(&mut self, name: &str) -> Result<&mut Breakpoint, String> {
+        self.breakpoints
+            .iter_mut()
+            .find(|breakpoint| breakpoint.name == name)
+            .ok_or_else(|| format!("No breakpoint {name}"))
+    }
+
+    // Call before executing every instruction. Returns true if the
+    // emulation should stop there, see repl().
+    pub fn check(&mut self, gameboy: &GameBoy) -> bool {
+        let (pc, sp) = (gameboy.cpu.pc, gameboy.cpu.sp);
+
+        // Watchpoints are checked by Memory, on every access
+        self.watchpoint_hits = gameboy.memory().watchpoints().take_hits();
+        self.triggered.clear();
+
+        let resumed = mem::take(&mut self.resumed);
+        // Don't stop again and again at a HALT or a jump to itself
+        let moved = pc != self.last_pc;
+        self.last_pc = pc;
+
+        let returned = self.returning;
+        if let Mode::Out { .. } = self.mode {
+            self.returning = is_return(gameboy, pc);
+        }
+
+        if resumed {
+            return false;
+        }
+
+        let stop = match &mut self.mode {
+            Mode::Run => false,
+            Mode::Step(count) => {
+                *count -= 1;
+                *count == 0
+            }
+            Mode::Over {
+                address,
+                sp: call_sp,
+            } => pc == *address && sp >= *call_sp,
+            Mode::Out { sp: function_sp } => returned && sp > *function_sp,
+            Mode::To(address) => pc == *address,
+        };
+
+        if moved {
+            for breakpoint in &mut self.breakpoints {
+                if !breakpoint.enabled
+                    || breakpoint.address != pc
+                    || !breakpoint
+                        .condition
+                        .as_ref()
+                        .is_none_or(|condition| condition.is_true(gameboy))
+                {
+                    continue;
+                }
+
+                breakpoint.hits += 1;
+                if breakpoint.hits >= breakpoint.after {
+                    self.triggered.push(breakpoint.name.clone());
+                }
+            }
+        }
+
+        mem::take(&mut self.stop_requested)
+            || stop
+            || !self.triggered.is_empty()
+            || !self.watchpoint_hits.is_empty()
+    }
+
+    // GameBoy::run_frame(), stopping before an instruction when check()
+    // says so. Returns None when stopped, then whether the GPU completed the
+    // frame at its end.
+    pub fn run_frame(&mut self, gameboy: &mut GameBoy) -> Option<bool> {
+        loop {
+            if self.check(gameboy) {
+                return None;
+            }
+
+            gameboy.step();
+
+            if let Some(drawn) = gameboy.end_frame() {
+                return Some(drawn);
+            }
+        }
+    }
+
+    // Read and execute commands until one resumes the emulation.
+    pub fn repl(
+        &mut self,
+        gameboy: &mut GameBoy,
+        input: &mut dyn BufRead,
+        output: &mut dyn Write,
+    ) -> io::Result<Action> {
+        // Stepping shows where it stopped, but not how it got there
+        let stepping = !matches!(self.mode, Mode::Run);
+        self.mode = Mode::Run;
+        self.show_stop(gameboy, stepping, output)?;
+
+        loop {
+            write!(output, "(gameperson) ")?;
+            output.flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(Action::Quit);
+            }
+
+            let line = match self.history_line(line.trim()) {
+                Ok(line) => line,
+                Err(message) => {
+                    writeln!(output, "{message}")?;
+                    continue;
+                }
+            };
+            if line.is_empty() {
+                continue;
+            }
+
+            if self.history.last() != Some(&line) {
+                self.history.push(line.clone());
+            }
+
+            match self.execute(gameboy, &line, output) {
+                Ok(Some(action)) => {
+                    self.resumed = true;
+                    return Ok(action);
+                }
+                Ok(None) => (),
+                Err(message) => writeln!(output, "{message}")?,
+            }
+        }
+    }
+
+    // The command to execute for line: the last one for an empty line, or
+    // one from the history for !N.
+    fn history_line(&self, line: &str) -> Result<String, String> {
+        if line.is_empty() {
+            return Ok(self.history.last().cloned().unwrap_or_default());
+        }
+
+        let Some(n) = line.strip_prefix('!') else {
+            return Ok(line.to_string());
+        };
+
+        n.parse::<usize>()
+            .ok()
+            .and_then(|n| self.history.get(n.checked_sub(1)?))
+            .cloned()
+            .ok_or_else(|| format!("No command {line} in the history"))
+    }
+
+    // Execute a command, returning what to do next if it resumes the
+    // emulation.
+    #[allow(clippy::too_many_lines)]
+    fn execute(
+        &mut self,
+        gameboy: &mut GameBoy,
+        line: &str,
+        output: &mut dyn Write,
+    ) -> Result<Option<Action>, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = words.split_first().ok_or("Empty command")?;
+
+        match (*command, args) {
+            ("h" | "help" | "?", []) => writeln!(output, "{HELP}").map_err(|e| e.to_string())?,
+
+            ("c" | "continue", []) => return Ok(Some(Action::Resume)),
+            ("s" | "step", _) => {
+                let count = match args {
+                    [] => 1,
+                    [count] => count
+                        .parse()
+                        .ok()
+                        .filter(|&count| count > 0)
+                        .ok_or_else(|| format!("Invalid count {count}"))?,
+                    _ => return Err("Usage: step [N]".to_string()),
+                };
+
+                self.mode = Mode::Step(count);
+                return Ok(Some(Action::Resume));
+            }
+            ("n" | "next", []) => {
+                let (pc, sp) = (gameboy.cpu.pc, gameboy.cpu.sp);
+                let bytes = read_instruction(gameboy, pc);
+                let opcode = opcodes::lookup(&bytes);
+
+                self.mode = match opcode.mnemonic {
+                    Mnemonic::Call | Mnemonic::Rst => Mode::Over {
+                        address: pc.wrapping_add(u16::from(opcode.length)),
+                        sp,
+                    },
+                    _ => Mode::Step(1),
+                };
+                return Ok(Some(Action::Resume));
+            }
+            ("finish", []) => {
+                self.mode = Mode::Out { sp: gameboy.cpu.sp };
+                return Ok(Some(Action::Resume));
+            }
+            ("until", [address]) => {
+                self.mode = Mode::To(self.parse_address(address)?);
+                return Ok(Some(Action::Resume));
+            }
+
+            ("b" | "break", [address, ..]) => {
+                let address = self.parse_address(address)?;
+                let (name, condition) = match &args[1..] {
+                    [] => (None, None),
+                    ["if", condition @ ..] => (None, Some(condition)),
+                    [name] => (Some(name), None),
+                    [name, "if", condition @ ..] => (Some(name), Some(condition)),
+                    _ => return Err("Usage: break ADDR [NAME] [if EXPR]".to_string()),
+                };
+                let condition = condition
+                    .map(|condition| condition.join(" ").parse())
+                    .transpose()?;
+
+                let breakpoint = self.add_breakpoint(
+                    gameboy,
+                    address,
+                    name.map(|name| (*name).to_string()),
+                    condition,
+                );
+                writeln!(output, "Breakpoint {breakpoint}").map_err(|e| e.to_string())?;
+            }
+            ("cond", [name, condition @ ..]) => {
+                let condition = if condition.is_empty() {
+                    None
+                } else {
+                    Some(condition.join(" ").parse()?)
+                };
+
+                self.breakpoint_mut(name)?.condition = condition;
+            }
+            ("after", [name, count]) => {
+                let count = count
+                    .parse()
+                    .map_err(|_| format!("Invalid count {count}"))?;
+                let breakpoint = self.breakpoint_mut(name)?;
+
+                breakpoint.after = count;
+                breakpoint.hits = 0;
+            }
+            ("p" | "print", [_, ..]) => {
+                let expression: Expression = args.join(" ").parse()?;
+                let value = expression.evaluate(gameboy);
+
+                writeln!(output, "{value} (${value:x})").map_err(|e| e.to_string())?;
+            }
+            ("watch", [kind, range, ..]) => {
+                let watchpoint = self.parse_watchpoint(gameboy, kind, range, &args[2..])?;
+
+                writeln!(output, "Watchpoint {watchpoint}").map_err(|e| e.to_string())?;
+                gameboy.memory_mut().watchpoints_mut().add(watchpoint);
+            }
+
+            ("d" | "delete", ["all"]) => {
+                self.breakpoints.clear();
+                gameboy.memory_mut().watchpoints_mut().clear();
+            }
+            ("d" | "delete", [name]) => {
+                let breakpoint = self.remove_breakpoint(name);
+                let watchpoint = gameboy.memory_mut().watchpoints_mut().remove(name);
+
+                if !breakpoint && !watchpoint {
+                    return Err(format!("No breakpoint {name}"));
+                }
+            }
+            ("enable" | "disable", [name]) => {
+                let enabled = *command == "enable";
+                let mut memory = gameboy.memory_mut();
+
+                if let Some(breakpoint) = self
+                    .breakpoints
+                    .iter_mut()
+                    .find(|breakpoint| breakpoint.name == *name)
+                {
+                    breakpoint.enabled = enabled;
+                } else if let Some(watchpoint) = memory.watchpoints_mut().get_mut(name) {
+                    watchpoint.enabled = enabled;
+                } else {
+                    return Err(format!("No breakpoint {name}"));
+                }
+            }
+            ("bl" | "breakpoints", []) => {
+                self.list_breakpoints(gameboy, output)
+                    .map_err(|e| e.to_string())?;
+            }
+
+            ("bt" | "backtrace", []) => {
+                self.backtrace(gameboy, output).map_err(|e| e.to_string())?;
+            }
+            ("r" | "regs", []) => show_registers(gameboy, output).map_err(|e| e.to_string())?,
+            ("set", [register, value]) => set_register(gameboy, register, parse_number(value)?)?,
+            ("flag", [flag, value]) => set_flag(gameboy, flag, value)?,
+
+            ("x", [address] | [address, _]) => {
+                let address = self.parse_address(address)?;
+                let length = args
+                    .get(1)
+                    .map_or(Ok(DUMP_LENGTH), |length| parse_number(length))?;
+
+                hexdump(gameboy, address, length, output).map_err(|e| e.to_string())?;
+            }
+            ("dis", []) => {
+                let pc = gameboy.cpu.pc;
+                let start = disassembly_start(gameboy, pc, DISASSEMBLY_BEFORE);
+
+                self.disassemble(
+                    gameboy,
+                    start,
+                    usize::from(DISASSEMBLY_BEFORE) + 1 + DISASSEMBLY_AFTER,
+                    output,
+                )
+                .map_err(|e| e.to_string())?;
+            }
+            ("dis", [address] | [address, _]) => {
+                let address = self.parse_address(address)?;
+                let count = args.get(1).map_or(Ok(DISASSEMBLY_AFTER), |count| {
+                    parse_number(count).map(usize::from)
+                })?;
+
+                self.disassemble(gameboy, address, count, output)
+                    .map_err(|e| e.to_string())?;
+            }
+            ("j" | "jump", [address]) => gameboy.cpu.pc = self.parse_address(address)?,
+
+            ("history", []) => {
+                for (i, line) in self.history.iter().enumerate() {
+                    writeln!(output, "{:4}  {line}", i + 1).map_err(|e| e.to_string())?;
+                }
+            }
+
+            ("q" | "quit", []) => return Ok(Some(Action::Quit)),
+
+            _ => return Err(format!("Invalid command {line}, try help")),
+        }
+
+        Ok(None)
+    }
+
+    fn show_stop(
+        &self,
+        gameboy: &mut GameBoy,
+        stepping: bool,
+        output: &mut dyn Write,
+    ) -> io::Result<()> {
+        let pc = gameboy.cpu.pc;
+
+        let (imbalances, count) = gameboy.cpu.call_stack_mut().take_imbalances();
+        for imbalance in &imbalances {
+            let problem = match imbalance.problem {
+                Problem::Dropped(1) => "SP moved above a frame".to_string(),
+                Problem::Dropped(frames) => format!("SP moved above {frames} frames"),
+                Problem::Mismatch { expected, actual } => {
+                    format!("returned to ${actual:04x} instead of ${expected:04x}")
+                }
+                Problem::Unmatched { address } => {
+                    format!("returned to ${address:04x} without a call")
+                }
+            };
+
+            writeln!(
+                output,
+                "Stack imbalance at {}: {problem}",
+                self.frame_location(imbalance.pc)
+            )?;
+        }
+        if count > imbalances.len() {
+            writeln!(output, "{} more stack imbalances", count - imbalances.len())?;
+        }
+
+        for name in &self.triggered {
+            let hits = self
+                .breakpoints
+                .iter()
+                .find(|breakpoint| breakpoint.name == *name)
+                .map_or(0, |breakpoint| breakpoint.hits);
+
+            writeln!(output, "Breakpoint {name}, hit count {hits}")?;
+        }
+        for hit in &self.watchpoint_hits {
+            writeln!(output, "{hit}")?;
+        }
+
+        if !stepping && !gameboy.cpu.call_stack().frames().is_empty() {
+            self.backtrace(gameboy, output)?;
+        } else if matches!(self.symbols.locate(MAPPED_BANK, pc), Some((_, offset)) if offset > 0) {
+            // At a symbol, it's shown as a label in the disassembly
+            writeln!(output, "In {}", self.location(pc))?;
+        }
+        if gameboy.cpu.locked() {
+            writeln!(output, "CPU locked up by an illegal opcode")?;
+        }
+        show_registers(gameboy, output)?;
+        self.disassemble(gameboy, pc, 1, output)
+    }
+
+    // The functions being executed, from the innermost, as tracked by the
+    // CPU
+    fn backtrace(&self, gameboy: &GameBoy, output: &mut dyn Write) -> io::Result<()> {
+        writeln!(output, "#0  {}", self.frame_location(gameboy.cpu.pc))?;
+
+        for (i, frame) in gameboy.cpu.call_stack().frames().iter().rev().enumerate() {
+            let entry = match frame.entry {
+                Entry::Call => "CALL",
+                Entry::Rst => "RST",
+                Entry::Interrupt => "interrupt",
+            };
+            let function = self
+                .symbols
+                .label(MAPPED_BANK, frame.function, true)
+                .unwrap_or_else(|| format!("${:04x}", frame.function));
+
+            writeln!(
+                output,
+                "#{:<2} {}, {entry} {function}",
+                i + 1,
+                self.frame_location(frame.caller)
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // address, and the symbol it's in
+    fn frame_location(&self, address: u16) -> String {
+        match self.symbols.locate(MAPPED_BANK, address) {
+            Some(_) => format!("${address:04x} in {}", self.location(address)),
+            None => format!("${address:04x}"),
+        }
+    }
+
+    // watch KIND ADDR[-END] [OP VALUE] [NAME]
+    fn parse_watchpoint(
+        &mut self,
+        gameboy: &GameBoy,
+        kind: &str,
+        range: &str,
+        args: &[&str],
+    ) -> Result<Watchpoint, String> {
+        let kind: Kind = kind.parse()?;
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (self.parse_address(start)?, self.parse_address(end)?),
+            None => (self.parse_address(range)?, self.parse_address(range)?),
+        };
+        if end < start {
+            return Err(format!("Invalid range {range}"));
+        }
+
+        let (condition, name) = match args {
+            [] => (None, None),
+            [name] if name.parse::<Operator>().is_err() => (None, Some(name)),
+            [operator, value] | [operator, value, _] => {
+                let value = parse_number(value)?;
+                let condition = Condition {
+                    operator: operator.parse()?,
+                    value: u8::try_from(value)
+                        .map_err(|_| format!("${value:x} doesn't fit in a byte"))?,
+                };
+
+                (Some(condition), args.get(2))
+            }
+            _ => return Err("Usage: watch r|w|rw|c ADDR[-END] [OP VALUE] [NAME]".to_string()),
+        };
+
+        Ok(Watchpoint {
+            name: self.name(
+                name.map(|name| (*name).to_string()),
+                gameboy.memory().watchpoints(),
+            ),
+            range: start..=end,
+            kind,
+            condition,
+            enabled: true,
+        })
+    }
+
+    fn list_breakpoints(&self, gameboy: &GameBoy, output: &mut dyn Write) -> io::Result<()> {
+        let memory = gameboy.memory();
+
+        if self.breakpoints.is_empty() && memory.watchpoints().is_empty() {
+            writeln!(output, "No breakpoints")?;
+        }
+
+        for breakpoint in &self.breakpoints {
+            match self.symbols.locate(MAPPED_BANK, breakpoint.address) {
+                Some(_) => writeln!(
+                    output,
+                    "{breakpoint} in {}",
+                    self.location(breakpoint.address)
+                )?,
+                None => writeln!(output, "{breakpoint}")?,
+            }
+        }
+
+        for watchpoint in memory.watchpoints().iter() {
+            writeln!(output, "{watchpoint}")?;
+        }
+
+        Ok(())
+    }
+
+    // Disassemble count instructions from address, marking PC with => and
+    // breakpoints with *.
+    fn disassemble(
+        &self,
+        gameboy: &GameBoy,
+        mut address: u16,
+        count: usize,
+        output: &mut dyn Write,
+    ) -> io::Result<()> {
+        let memory = gameboy.memory();
+        let read = |address| memory.peek(address);
+
+        for _ in 0..count {
+            let line = disassembler::instruction(&read, MAPPED_BANK, address, &self.symbols);
+
+            let marker = if address == gameboy.cpu.pc {
+                "=>"
+            } else if self
+                .breakpoints
+                .iter()
+                .any(|breakpoint| breakpoint.address == address)
+            {
+                " *"
+            } else {
+                "  "
+            };
+
+            let hex: Vec<String> = line
+                .bytes
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect();
+
+            if let Some(label) = &line.label {
+                writeln!(output, "{label}:")?;
+            }
+            writeln!(
+                output,
+                "{marker} {address:04x}  {:8}  {}",
+                hex.join(" "),
+                line.text
+            )?;
+
+            address = address.wrapping_add(u16::from(opcodes::lookup(&line.bytes).length));
+        }
+
+        Ok(())
+    }
+}
+
+// Numbers are hexadecimal, as in the disassembly
+pub fn parse_number(text: &str) -> Result<u16, String> {
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid number {text}"))
+}
+
+// The longest instruction starting at address
+fn read_instruction(gameboy: &GameBoy, address: u16) -> [u8; 3] {
+    let memory = gameboy.memory();
+
+    [0, 1, 2].map(|i| memory.peek(address.wrapping_add(i)))
+}
+
+fn is_return(gameboy: &GameBoy, address: u16) -> bool {
+    let opcode = opcodes::lookup(&read_instruction(gameboy, address));
+
+    matches!(opcode.mnemonic, Mnemonic::Ret | Mnemonic::Reti)
+}
+
+// Where to start disassembling to show up to count instructions before
+// address. Instructions have different lengths, so this is the earliest
+// address decoding into instructions that end right at address.
+fn disassembly_start(gameboy: &GameBoy, address: u16, count: u16) -> u16 {
+    // At most 3 bytes per instruction
+    for back in (1..=count * 3).rev() {
+        let start = address.wrapping_sub(back);
+        let mut current = start;
+        let mut instructions = 0;
+
+        while current != address && address.wrapping_sub(current) <= back {
+            let length = opcodes::lookup(&read_instruction(gameboy, current)).length;
+
+            current = current.wrapping_add(u16::from(length));
+            instructions += 1;
+        }
+
+        if current == address && instructions <= count {
+            return start;
+        }
+    }
+
+    address
+}
+
+fn show_registers(gameboy: &GameBoy, output: &mut dyn Write) -> io::Result<()> {
+    let cpu = &gameboy.cpu;
+    let regs = &cpu.regs;
+
+    let flag = |mask: u8, name: char| if regs.flags & mask == 0 { '-' } else { name };
+
+    writeln!(
+        output,
+        "af ${:02x}{:02x}  bc ${:04x}  de ${:04x}  hl ${:04x}  sp ${:04x}  pc ${:04x}  {}{}{}{}  ime {}",
+        regs.a,
+        regs.flags,
+        regs.bc(),
+        regs.de(),
+        regs.hl(),
+        cpu.sp,
+        cpu.pc,
+        flag(ZERO_FLAG, 'z'),
+        flag(SUBTRACT_FLAG, 'n'),
+        flag(HALF_CARRY_FLAG, 'h'),
+        flag(CARRY_FLAG, 'c'),
+        u8::from(cpu.interrupts_enabled()),
+    )
+}
+
+fn set_register(gameboy: &mut GameBoy, register: &str, value: u16) -> Result<(), String> {
+    let cpu = &mut gameboy.cpu;
+    let regs = &mut cpu.regs;
+
+    let byte = || u8::try_from(value).map_err(|_| format!("${value:x} doesn't fit in {register}"));
+
+    match register {
+        "a" => regs.a = byte()?,
+        // The low nibble of F is always 0
+        "f" => regs.flags = byte()? & 0xf0,
+        "b" => regs.b = byte()?,
+        "c" => regs.c = byte()?,
+        "d" => regs.d = byte()?,
+        "e" => regs.e = byte()?,
+        "h" => regs.h = byte()?,
+        "l" => regs.l = byte()?,
+        "af" => regs.write_af(value),
+        "bc" => regs.write_bc(value),
+        "de" => regs.write_de(value),
+        "hl" => regs.write_hl(value),
+        "sp" => cpu.sp = value,
+        "pc" => cpu.pc = value,
+        _ => return Err(format!("Invalid register {register}")),
+    }
+
+    Ok(())
+}
+
+fn set_flag(gameboy: &mut GameBoy, flag: &str, value: &str) -> Result<(), String> {
+    let value = match value {
+        "0" => false,
+        "1" => true,
+        _ => return Err(format!("Invalid flag value {value}, expected 0 or 1")),
+    };
+
+    let mask = match flag {
+        "z" => ZERO_FLAG,
+        "n" => SUBTRACT_FLAG,
+        "h" => HALF_CARRY_FLAG,
+        "c" => CARRY_FLAG,
+        "ime" => {
+            gameboy.cpu.set_interrupts_enabled(value);
+            return Ok(());
+        }
+        _ => return Err(format!("Invalid flag {flag}")),
+    };
+
+    gameboy.cpu.regs.set_flag(mask, value);
+
+    Ok(())
+}
+
+fn hexdump(gameboy: &GameBoy, address: u16, length: u16, output: &mut dyn Write) -> io::Result<()> {
+    let memory = gameboy.memory();
+
+    for line in (0..length).step_by(16) {
+        let start = address.wrapping_add(line);
+        let bytes: Vec<u8> = (0..(length - line).min(16))
+            .map(|i| memory.peek(start.wrapping_add(i)))
+            .collect();
+
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+        let text: String = bytes
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    char::from(byte)
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+
+        writeln!(output, "{start:04x}  {:47}  |{text}|", hex.join(" "))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use crate::testing::{self, run};
+
+    // $0100 INC A; CALL $0110; RST $38; JR $0100
+    // $0110 INC B; INC C; RET
+    // $0038 RET
+    fn gameboy() -> GameBoy {
+        let mut gameboy = testing::gameboy(&[
+            (0x0038, &[0xc9]),
+            (0x0100, &[0x3c, 0xcd, 0x10, 0x01, 0xff, 0x18, 0xf9]),
+            (0x0110, &[0x04, 0x0c, 0xc9]),
+        ]);
+        // Counted by the functions
+        gameboy.cpu.regs.b = 0;
+        gameboy.cpu.regs.c = 0;
+
+        gameboy
+    }
+
+    // A debugger stopped before the first instruction
+    fn stopped() -> (Debugger, GameBoy) {
+        let mut debugger = Debugger::new();
+        let mut gameboy = gameboy();
+
+        debugger.stop();
+        assert_eq!(run(&mut debugger, &mut gameboy), 0x0100);
+
+        (debugger, gameboy)
+    }
+
+    fn repl(debugger: &mut Debugger, gameboy: &mut GameBoy, input: &str) -> (Action, String) {
+        let mut output = Vec::new();
+        let action = debugger
+            .repl(gameboy, &mut Cursor::new(input), &mut output)
+            .unwrap();
+
+        (action, String::from_utf8(output).unwrap())
+    }
+
+    // The PC where the command stops
+    fn stop_after(debugger: &mut Debugger, gameboy: &mut GameBoy, command: &str) -> u16 {
+        let input = format!("{command}\n");
+        assert_eq!(repl(debugger, gameboy, &input).0, Action::Resume);
+
+        run(debugger, gameboy)
+    }
+
+    #[test]
+    fn commands() {
+        let (mut debugger, mut gameboy) = stopped();
+
+        let (action, output) = repl(
+            &mut debugger,
+            &mut gameboy,
+            "bogus\ns 0\nset a 12\np a + 1\nb 110 f if b == 0\nq\n",
+        );
+        assert_eq!(action, Action::Quit);
+        assert!(
+            output.contains("Invalid command bogus, try help"),
+            "{}",
+            output
+        );
+        assert!(output.contains("Invalid count 0"), "{}", output);
+        assert!(output.contains("19 ($13)"), "{}", output);
+        assert!(output.contains("Breakpoint f"), "{}", output);
+        assert_eq!(gameboy.cpu.regs.a, 0x12);
+
+        // The breakpoint only stops the first time, while B is 0
+        assert_eq!(stop_after(&mut debugger, &mut gameboy, "c"), 0x0110);
+        assert_eq!(debugger.breakpoint_hits(), ["f"]);
+
+        // End of input
+        assert_eq!(repl(&mut debugger, &mut gameboy, "").0, Action::Quit);
+    }
+
+    #[test]
+    fn repeat() {
+        let (mut debugger, mut gameboy) = stopped();
+
+        assert_eq!(stop_after(&mut debugger, &mut gameboy, "s 2"), 0x0110);
+        // An empty line repeats it
+        assert_eq!(stop_after(&mut debugger, &mut gameboy, ""), 0x0112);
+        // RET, RST $38
+        assert_eq!(stop_after(&mut debugger, &mut gameboy, "  "), 0x0038);
+
+        assert_eq!(debugger.history, ["s 2"]);
+    }
+
+    #[test]
+    fn history() {
+        let (mut debugger, mut gameboy) = stopped();
+
+        let (_, output) = repl(
+            &mut debugger,
+            &mut gameboy,
+            "set b 42\nset b 0\n!1\n!1\n!9\n!x\nhistory\nq\n",
+        );
+        assert_eq!(gameboy.cpu.regs.b, 0x42);
+        assert!(
+            output.contains("No command !9 in the history"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("No command !x in the history"),
+            "{}",
+            output
+        );
+
+        // !N adds the command again, once
+        assert!(
+            output.contains("   1  set b 42\n   2  set b 0\n   3  set b 42\n   4  history\n"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn next() {
+        let (mut debugger, mut gameboy) = stopped();
+
+        assert_eq!(stop_after(&mut debugger, &mut gameboy, "n"), 0x0101);
+        // Over the CALL, which did run
+        assert_eq!(stop_after(&mut debugger, &mut gameboy, "n"), 0x0104);
+        assert_eq!(gameboy.cpu.regs.b, 1);
+        assert_eq!(gameboy.cpu.sp, 0xfffe);
+        // Over the RST
+        assert_eq!(stop_after(&mut debugger, &mut gameboy, "n"), 0x0105);
+        assert_eq!(stop_after(&mut debugger, &mut gameboy, "n"), 0x0100);
+    }
+
+    #[test]
+    fn next_breakpoint() {
+        let (mut debugger, mut gameboy) = stopped();
+
+        // A breakpoint in the function called stops first
+        debugger.add_breakpoint(&gameboy, 0x0111, None, None);
+        assert_eq!(stop_after(&mut debugger, &mut gameboy, "s"), 0x0101);
+        assert_eq!(stop_after(&mut debugger, &mut gameboy, "n"), 0x0111);
+    }
+
+    #[test]
+    fn finish() {
+        let (mut debugger, mut gameboy) = stopped();
+
+        assert_eq!(stop_after(&mut debugger, &mut gameboy, "s 2"), 0x0110);
+        assert_eq!(stop_after(&mut debugger, &mut gameboy, "finish"), 0x0104);
+        assert_eq!(gameboy.cpu.regs.c, 1);
+        assert_eq!(gameboy.cpu.sp, 0xfffe);
+    }
+
+    #[test]
+    fn until() {
+        let (mut debugger, mut gameboy) = stopped();
+
+        assert_eq!(stop_after(&mut debugger, &mut gameboy, "until 105"), 0x0105);
+        assert_eq!(
+            stop_after(&mut debugger, &mut gameboy, "until $0038"),
+            0x0038
+        );
+
+        let (_, output) = repl(&mut debugger, &mut gameboy, "until nowhere\nq\n");
+        assert!(output.contains("nowhere"), "{}", output);
+    }
+
+    #[test]
+    fn automatic_names() {
+        let (mut debugger, mut gameboy) = stopped();
+
+        // The numbers taken by name are skipped, rather than replacing
+        // what has them
+        repl(
+            &mut debugger,
+            &mut gameboy,
+            "b 110 2\nb 111\nb 112\nwatch w c000 4\nb 113\nwatch w c001\n",
+        );
+
+        let names: Vec<_> = debugger
+            .breakpoints
+            .iter()
+            .map(|breakpoint| breakpoint.name.as_str())
+            .collect();
+        assert_eq!(names, ["2", "1", "3", "5"]);
+
+        let memory = gameboy.memory();
+        let names: Vec<_> = memory
+            .watchpoints()
+            .iter()
+            .map(|watchpoint| watchpoint.name.as_str())
+            .collect();
+        assert_eq!(names, ["4", "6"]);
+    }
+
+    #[test]
+    fn watch_change() {
+        let (mut debugger, mut gameboy) = stopped();
+
+        // The return addresses pushed on the stack: $0104 by CALL, $0105 by RST
+        let (_, output) = repl(&mut debugger, &mut gameboy, "watch c fffc-fffd ret\n");
+        assert!(
+            output.contains("Watchpoint ret      c $fffc-$fffd"),
+            "{}",
+            output
+        );
+
+        assert_eq!(stop_after(&mut debugger, &mut gameboy, "c"), 0x0110);
+        let hits: Vec<_> = debugger
+            .watchpoint_hits()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            hits,
+            [
+                "Watchpoint ret: write $01 at $fffd",
+                "Watchpoint ret: write $04 at $fffc"
+            ]
+        );
+
+        // Only the low byte changes afterwards
+        assert_eq!(stop_after(&mut debugger, &mut gameboy, "c"), 0x0038);
+        let hits: Vec<_> = debugger
+            .watchpoint_hits()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(hits, ["Watchpoint ret: write $05 at $fffc"]);
+
+        assert_eq!(stop_after(&mut debugger, &mut gameboy, "c"), 0x0110);
+        let hits: Vec<_> = debugger
+            .watchpoint_hits()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(hits, ["Watchpoint ret: write $04 at $fffc"]);
+    }
+}
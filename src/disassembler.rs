@@ -0,0 +1,587 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::opcodes;
+
+// Disassembly of ROMs and memory, with names for the addresses: the
+// hardware registers, the RST and interrupt vectors, and symbols loaded
+// from a file.
+
+// Size of a ROM bank, bank 0 is at 0x0000-0x3fff and the others are
+// switched in at 0x4000-0x7fff
+pub const BANK_SIZE: u16 = 0x4000;
+
+// Names of the hardware registers, as in hardware.inc
+const HARDWARE_REGISTERS: [(u16, &str); 50] = [
+    (0xff00, "rP1"),
+    (0xff01, "rSB"),
+    (0xff02, "rSC"),
+    (0xff04, "rDIV"),
+    (0xff05, "rTIMA"),
+    (0xff06, "rTMA"),
+    (0xff07, "rTAC"),
+    (0xff0f, "rIF"),
+    (0xff10, "rNR10"),
+    (0xff11, "rNR11"),
+    (0xff12, "rNR12"),
+    (0xff13, "rNR13"),
+    (0xff14, "rNR14"),
+    (0xff16, "rNR21"),
+    (0xff17, "rNR22"),
+    (0xff18, "rNR23"),
+    (0xff19, "rNR24"),
+    (0xff1a, "rNR30"),
+    (0xff1b, "rNR31"),
+    (0xff1c, "rNR32"),
+    (0xff1d, "rNR33"),
+    (0xff1e, "rNR34"),
+    (0xff20, "rNR41"),
+    (0xff21, "rNR42"),
+    (0xff22, "rNR43"),
+    (0xff23, "rNR44"),
+    (0xff24, "rNR50"),
+    (0xff25, "rNR51"),
+    (0xff26, "rNR52"),
+    (0xff40, "rLCDC"),
+    (0xff41, "rSTAT"),
+    (0xff42, "rSCY"),
+    (0xff43, "rSCX"),
+    (0xff44, "rLY"),
+    (0xff45, "rLYC"),
+    (0xff46, "rDMA"),
+    (0xff47, "rBGP"),
+    (0xff48, "rOBP0"),
+    (0xff49, "rOBP1"),
+    (0xff4a, "rWY"),
+    (0xff4b, "rWX"),
+    (0xff4d, "rKEY1"),
+    (0xff4f, "rVBK"),
+    (0xff51, "rHDMA1"),
+    (0xff52, "rHDMA2"),
+    (0xff53, "rHDMA3"),
+    (0xff54, "rHDMA4"),
+    (0xff55, "rHDMA5"),
+    (0xff70, "rSVBK"),
+    (0xffff, "rIE"),
+];
+
+// Where RST and interrupts jump to
+const VECTORS: [(u16, &str); 14] = [
+    (0x0000, "RST_00"),
+    (0x0008, "RST_08"),
+    (0x0010, "RST_10"),
+    (0x0018, "RST_18"),
+    (0x0020, "RST_20"),
+    (0x0028, "RST_28"),
+    (0x0030, "RST_30"),
+    (0x0038, "RST_38"),
+    (0x0040, "VBlankInterrupt"),
+    (0x0048, "LCDCInterrupt"),
+    (0x0050, "TimerInterrupt"),
+    (0x0058, "SerialInterrupt"),
+    (0x0060, "JoypadInterrupt"),
+    (0x0100, "Boot"),
+];
+
+// Start of the memory regions, a symbol only locates addresses in its own
+const REGIONS: [u16; 9] = [
+    0x0000, 0x4000, 0x8000, 0xa000, 0xc000, 0xe000, 0xfe00, 0xff00, 0xff80,
+];
+
+fn region(address: u16) -> usize {
+    REGIONS
+        .iter()
+        .rposition(|&start| address >= start)
+        .unwrap_or(0)
+}
+
+// The bank address is in, as numbered in symbol files, with rom_bank the
+// ROM bank mapped at 0x4000-0x7fff. WRAM at 0xd000-0xdfff is bank 1, the
+// only one on DMG, and the other regions have a single bank 0.
+#[must_use]
+pub const fn bank_at(rom_bank: u16, address: u16) -> u16 {
+    match address {
+        0x4000..=0x7fff => rom_bank,
+        0xd000..=0xdfff => 1,
+        _ => 0,
+    }
+}
+
+// Names of addresses, in a bank.
+//
+// Symbol files, as written by RGBDS (rgblink -n), have a name per line,
+// after its bank and address in hex:
+//   01:4000 Main
+//   01:4010 Main.loop
+// with comments starting with ;
+#[derive(Clone, Default)]
+pub struct Symbols {
+    names: BTreeMap<(u16, u16), String>,
+}
+
+impl Symbols {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {e}", path.display()),
+            )
+        })
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut symbols = Self::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let invalid = || format!("line {}: invalid symbol {line}", number + 1);
+
+            let (location, name) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let (bank, address) = location.split_once(':').ok_or_else(invalid)?;
+            let bank = u16::from_str_radix(bank, 16).map_err(|_| invalid())?;
+            let address = u16::from_str_radix(address, 16).map_err(|_| invalid())?;
+
+            symbols.insert(bank, address, name.trim());
+        }
+
+        Ok(symbols)
+    }
+
+    pub fn insert(&mut self, bank: u16, address: u16, name: &str) {
+        self.names.insert((bank, address), name.to_string());
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    // The bank and address of the symbol called name
+    #[must_use]
+    pub fn find(&self, name: &str) -> Option<(u16, u16)> {
+        self.names
+            .iter()
+            .find(|(_, symbol)| *symbol == name)
+            .map(|(&location, _)| location)
+    }
+
+    // The symbol at or before address in the same memory region, and how
+    // far address is from it, e.g. for Main+$12
+    #[must_use]
+    pub fn locate(&self, bank: u16, address: u16) -> Option<(&str, u16)> {
+        let bank = bank_at(bank, address);
+
+        let (&(symbol_bank, symbol_address), name) =
+            self.names.range(..=(bank, address)).next_back()?;
+
+        if symbol_bank == bank && region(symbol_address) == region(address) {
+            Some((name.as_str(), address - symbol_address))
+        } else {
+            None
+        }
+    }
+
+    // address as the symbol it's in and the offset, or in hex
+    #[must_use]
+    pub fn describe(&self, bank: u16, address: u16) -> String {
+        match self.locate(bank, address) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{name}+${offset:x}"),
+            None => format!("${address:04x}"),
+        }
+    }
+
+    // The symbol at address, with bank the ROM bank mapped at
+    // 0x4000-0x7fff
+    #[must_use]
+    pub fn symbol(&self, bank: u16, address: u16) -> Option<&str> {
+        let bank = bank_at(bank, address);
+
+        self.names.get(&(bank, address)).map(String::as_str)
+    }
+
+    // The name of address: a symbol, then a hardware register, or a vector
+    // for code.
+    #[must_use]
+    pub fn label(&self, bank: u16, address: u16, code: bool) -> Option<String> {
+        let builtin = |names: &[(u16, &str)]| {
+            names
+                .iter()
+                .find(|(known, _)| *known == address)
+                .map(|(_, name)| (*name).to_string())
+        };
+
+        self.symbol(bank, address)
+            .map(str::to_string)
+            .or_else(|| builtin(&HARDWARE_REGISTERS))
+            .or_else(|| if code { builtin(&VECTORS) } else { None })
+    }
+}
+
+// A disassembled instruction
+pub struct Line {
+    // The bank of address, as numbered in symbol files
+    pub bank: u16,
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+    // Name of the address, shown as a label before the instruction
+    pub label: Option<String>,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(label) = &self.label {
+            writeln!(f, "{label}:")?;
+        }
+
+        let hex: Vec<String> = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+
+        write!(
+            f,
+            "  {:02x}:{:04x}  {:8}  {}",
+            self.bank,
+            self.address,
+            hex.join(" "),
+            self.text
+        )
+    }
+}
+
+// Disassemble the instruction at address, reading memory with read, with
+// bank the ROM bank mapped at 0x4000-0x7fff
+#[must_use]
+pub fn instruction(read: &dyn Fn(u16) -> u8, bank: u16, address: u16, symbols: &Symbols) -> Line {
+    let bytes = [0, 1, 2].map(|i| read(address.wrapping_add(i)));
+    let length = opcodes::lookup(&bytes).length;
+
+    Line {
+        bank: bank_at(bank, address),
+        address,
+        bytes: bytes[..usize::from(length)].to_vec(),
+        text: opcodes::disassemble_labelled(address, &bytes, &|target, code| {
+            symbols.label(bank, target, code)
+        }),
+        label: symbols.label(bank, address, true),
+    }
+}
+
+// Disassemble the instructions starting in range, with bank the ROM bank
+// mapped at 0x4000-0x7fff
+#[must_use]
+pub fn disassemble(
+    read: &dyn Fn(u16) -> u8,
+    bank: u16,
+    range: Range<u16>,
+    symbols: &Symbols,
+) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut address = range.start;
+
+    while range.contains(&address) {
+        let line = instruction(read, bank, address, symbols);
+        let length = opcodes::lookup(&line.bytes).length;
+        let (next, overflow) = address.overflowing_add(u16::from(length));
+
+        lines.push(line);
+
+        if overflow {
+            break;
+        }
+        address = next;
+    }
+
+    lines
+}
+
+// Disassemble a bank of a ROM, at the addresses it's mapped at, limited to
+// range. Bytes past the end of the ROM read as 0xff, as an open bus.
+#[must_use]
+pub fn disassemble_bank(rom: &[u8], bank: u16, range: Range<u16>, symbols: &Symbols) -> Vec<Line> {
+    let (base, offset) = if bank == 0 {
+        (0x0000, 0)
+    } else {
+        (0x4000, usize::from(bank) * usize::from(BANK_SIZE))
+    };
+
+    let read = |address: u16| {
+        let index = address.wrapping_sub(base);
+
+        if index < BANK_SIZE {
+            rom.get(usize::from(index) + offset)
+                .copied()
+                .unwrap_or(0xff)
+        } else {
+            0xff
+        }
+    };
+
+    let start = range.start.max(base);
+    let end = range.end.min(base + BANK_SIZE);
+
+    disassemble(&read, bank, start..end.max(start), symbols)
+}
+
+// Number of banks in rom
+#[must_use]
+pub fn banks(rom: &[u8]) -> u16 {
+    u16::try_from(rom.len().div_ceil(usize::from(BANK_SIZE))).unwrap_or(u16::MAX)
+}
+
+// Disassemble the whole ROM, or the given bank, to output
+pub fn write_rom(
+    rom: &[u8],
+    bank: Option<u16>,
+    range: Range<u16>,
+    symbols: &Symbols,
+    output: &mut dyn Write,
+) -> io::Result<()> {
+    let count = banks(rom);
+
+    let banks = match bank {
+        None => 0..count,
+        Some(bank) if bank < count => bank..bank + 1,
+        Some(bank) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("No bank {bank:02x}, the ROM has {count}"),
+            ))
+        }
+    };
+
+    for bank in banks {
+        let lines = disassemble_bank(rom, bank, range.clone(), symbols);
+        if lines.is_empty() {
+            continue;
+        }
+
+        writeln!(output, "; Bank {bank:02x}")?;
+        for line in lines {
+            writeln!(output, "{line}")?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing;
+
+    const SYMBOLS: &str = "; File generated by rgblink
+00:0100 Start
+00:0150 Init ; comment
+
+01:4000 Main
+01:4010 Main.loop
+02:4000 Far
+00:c000 wCounter
+01:d000 wFoo
+";
+
+    #[test]
+    fn parse() {
+        let symbols = Symbols::parse(SYMBOLS).unwrap();
+
+        assert_eq!(symbols.len(), 7);
+        assert_eq!(symbols.find("Init"), Some((0, 0x0150)));
+        assert_eq!(symbols.find("Main.loop"), Some((1, 0x4010)));
+        assert_eq!(symbols.find("Missing"), None);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for text in ["00:0100", "0100 Start", "00:xyz Start", "10000:0100 Start"] {
+            let err = Symbols::parse(&format!("00:0000 Ok\n{text}")).err();
+            assert_eq!(err, Some(format!("line 2: invalid symbol {text}")));
+        }
+    }
+
+    #[test]
+    fn banks() {
+        let symbols = Symbols::parse(SYMBOLS).unwrap();
+
+        assert_eq!(symbols.symbol(1, 0x0100), Some("Start"));
+        assert_eq!(symbols.symbol(1, 0x4000), Some("Main"));
+        assert_eq!(symbols.symbol(2, 0x4000), Some("Far"));
+        assert_eq!(symbols.symbol(2, 0xc000), Some("wCounter"));
+        assert_eq!(symbols.symbol(2, 0xd000), Some("wFoo"));
+    }
+
+    #[test]
+    fn locate() {
+        let symbols = Symbols::parse(SYMBOLS).unwrap();
+
+        assert_eq!(symbols.describe(1, 0x0152), "Init+$2");
+        assert_eq!(symbols.describe(1, 0x4012), "Main.loop+$2");
+        assert_eq!(symbols.describe(2, 0x4012), "Far+$12");
+        assert_eq!(symbols.describe(1, 0xc001), "wCounter+$1");
+        assert_eq!(symbols.describe(1, 0xd004), "wFoo+$4");
+        // Not after a symbol in the same region
+        assert_eq!(symbols.describe(1, 0x8000), "$8000");
+        assert_eq!(symbols.describe(3, 0x4000), "$4000");
+    }
+
+    // Bank 0: code at Start and Init, and at its end
+    // Bank 1: Main, waiting for LY, and Main.loop
+    fn rom() -> Vec<u8> {
+        testing::rom(&[
+            (0x0100, &[0x00, 0xc3, 0x50, 0x01]),
+            (0x0150, &[0xe0, 0x40, 0xcd, 0x00, 0x40, 0x18, 0xf9]),
+            (0x3ffd, &[0xcb, 0x7c, 0x3e]),
+            (
+                0x4000,
+                &[
+                    0xf0, 0x44, 0x20, 0xfc, 0xea, 0x00, 0xc0, 0xe8, 0xf0, 0xcd, 0x10, 0x40, 0xc9,
+                    0x00, 0x00, 0x00, 0xcb, 0x37,
+                ],
+            ),
+        ])
+    }
+
+    fn text(lines: &[Line]) -> Vec<String> {
+        lines.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn instruction() {
+        let symbols = Symbols::parse(SYMBOLS).unwrap();
+        let rom = rom();
+        let read = |address: u16| rom[usize::from(address)];
+
+        let line = super::instruction(&read, 0, 0x0150, &symbols);
+        assert_eq!(line.bytes, [0xe0, 0x40]);
+        assert_eq!(
+            line.to_string(),
+            "Init:\n  00:0150  e0 40     LDH [rLCDC],a"
+        );
+
+        let line = super::instruction(&read, 0, 0x0101, &symbols);
+        assert_eq!(line.to_string(), "  00:0101  c3 50 01  JP Init");
+
+        // Symbols in the ROM bank mapped at 0x4000
+        let line = super::instruction(&read, 1, 0x4009, &symbols);
+        assert_eq!(line.to_string(), "  01:4009  cd 10 40  CALL Main.loop");
+        let line = super::instruction(&read, 2, 0x4009, &symbols);
+        assert_eq!(line.to_string(), "  02:4009  cd 10 40  CALL $4010");
+
+        // Vectors are only named as code
+        let line = super::instruction(&read, 0, 0x0040, &symbols);
+        assert_eq!(
+            line.to_string(),
+            "VBlankInterrupt:\n  00:0040  00        NOP"
+        );
+    }
+
+    #[test]
+    fn disassemble_bank() {
+        let symbols = Symbols::parse(SYMBOLS).unwrap();
+        let rom = rom();
+
+        let lines = super::disassemble_bank(&rom, 0, 0x0150..0x0157, &symbols);
+        assert_eq!(
+            text(&lines),
+            [
+                "Init:\n  00:0150  e0 40     LDH [rLCDC],a",
+                "  00:0152  cd 00 40  CALL $4000",
+                "  00:0155  18 f9     JR Init",
+            ]
+        );
+
+        let lines = super::disassemble_bank(&rom, 1, 0x4000..0x4012, &symbols);
+        assert_eq!(
+            text(&lines),
+            [
+                "Main:\n  01:4000  f0 44     LDH a,[rLY]",
+                "  01:4002  20 fc     JR nz,Main",
+                "  01:4004  ea 00 c0  LD [wCounter],a",
+                "  01:4007  e8 f0     ADD sp,-$10",
+                "  01:4009  cd 10 40  CALL Main.loop",
+                "  01:400c  c9        RET",
+                "  01:400d  00        NOP",
+                "  01:400e  00        NOP",
+                "  01:400f  00        NOP",
+                "Main.loop:\n  01:4010  cb 37     SWAP a",
+            ]
+        );
+
+        // Other banks aren't mapped there
+        assert!(super::disassemble_bank(&rom, 1, 0x0100..0x0200, &symbols).is_empty());
+        // Past the end of the ROM
+        let lines = super::disassemble_bank(&rom, 2, 0x4000..0x4001, &symbols);
+        assert_eq!(text(&lines), ["Far:\n  02:4000  ff        RST $38"]);
+    }
+
+    #[test]
+    fn bank_boundary() {
+        let symbols = Symbols::parse(SYMBOLS).unwrap();
+        let rom = rom();
+
+        // The last instruction of bank 0 doesn't continue into bank 1: its
+        // immediate is read past the bank, as 0xff
+        let mut output = Vec::new();
+        write_rom(&rom, None, 0x3ffd..0x4004, &symbols, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "; Bank 00
+  00:3ffd  cb 7c     BIT 7,h
+  00:3fff  3e ff     LD a,$ff
+; Bank 01
+Main:
+  01:4000  f0 44     LDH a,[rLY]
+  01:4002  20 fc     JR nz,Main
+"
+        );
+
+        // Reading memory, it does
+        let read = |address: u16| rom[usize::from(address)];
+        let lines = disassemble(&read, 1, 0x3fff..0x4002, &symbols);
+        assert_eq!(
+            text(&lines),
+            [
+                "  00:3fff  3e f0     LD a,$f0",
+                "  01:4001  44        LD b,h"
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_bank() {
+        let symbols = Symbols::parse(SYMBOLS).unwrap();
+        let rom = rom();
+
+        let mut output = Vec::new();
+        write_rom(&rom, Some(1), 0x4000..0x4001, &symbols, &mut output).unwrap();
+        assert!(!output.is_empty());
+
+        for bank in [2, 0xffff] {
+            let error = write_rom(&rom, Some(bank), 0x0000..0x8000, &symbols, &mut output);
+            assert_eq!(error.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::convert::TryFrom;
+use std::error;
+
+use clap::ValueEnum;
+
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Texture, TextureCreator, WindowCanvas};
+use sdl2::video::{FullscreenType, WindowContext};
+use sdl2::VideoSubsystem;
+
+use gameperson::gpu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+// The window showing the screen, as large as it fits keeping the aspect
+// ratio, with black bars around it.
+pub struct Display {
+    canvas: WindowCanvas,
+
+    // Only scale by whole multiples, for pixels of the same size
+    integer_scale: bool,
+}
+
+impl Display {
+    pub fn new(
+        video: &VideoSubsystem,
+        scale: u32,
+        integer_scale: bool,
+        filter: Filter,
+    ) -> Result<Self, Box<dyn error::Error>> {
+        let window = video
+            .window(
+                "gameperson",
+                u32::from(SCREEN_WIDTH) * scale,
+                u32::from(SCREEN_HEIGHT) * scale,
+            )
+            .position_centered()
+            .resizable()
+            .opengl()
+            .build()?;
+
+        // Used for the textures created from now on
+        sdl2::hint::set(
+            "SDL_RENDER_SCALE_QUALITY",
+            match filter {
+                Filter::Nearest => "nearest",
+                Filter::Linear => "linear",
+            },
+        );
+
+        let mut canvas = window.into_canvas().build()?;
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        canvas.present();
+
+        Ok(Self {
+            canvas,
+            integer_scale,
+        })
+    }
+
+    pub fn texture_creator(&self) -> TextureCreator<WindowContext> {
+        self.canvas.texture_creator()
+    }
+
+    // A texture to show frames of width x height RGB888 pixels
+    pub fn create_texture(
+        creator: &TextureCreator<WindowContext>,
+        width: u32,
+        height: u32,
+    ) -> Result<Texture<'_>, Box<dyn error::Error>> {
+        Ok(creator.create_texture_streaming(PixelFormatEnum::RGB24, width, height)?)
+    }
+
+    pub fn set_title(&mut self, title: &str) -> Result<(), Box<dyn error::Error>> {
+        Ok(self.canvas.window_mut().set_title(title)?)
+    }
+
+    pub fn toggle_fullscreen(&mut self) -> Result<(), String> {
+        let window = self.canvas.window_mut();
+
+        window.set_fullscreen(match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        })
+    }
+
+    // Show texture, scaled to the window
+    pub fn present(&mut self, texture: &Texture) -> Result<(), String> {
+        let viewport = self.viewport()?;
+
+        self.canvas.clear();
+        self.canvas.copy(texture, None, viewport)?;
+        self.canvas.present();
+
+        Ok(())
+    }
+
+    // Where the screen goes in the window
+    fn viewport(&self) -> Result<Rect, String> {
+        let (width, height) = self.canvas.output_size()?;
+        let (screen_width, screen_height) = (u32::from(SCREEN_WIDTH), u32::from(SCREEN_HEIGHT));
+
+        // Largest size that fits, keeping the aspect ratio
+        let (mut scaled_width, mut scaled_height) = if width * screen_height > height * screen_width
+        {
+            (height * screen_width / screen_height, height)
+        } else {
+            (width, width * screen_height / screen_width)
+        };
+
+        if self.integer_scale {
+            let scale = (scaled_width / screen_width).max(1);
+
+            scaled_width = screen_width * scale;
+            scaled_height = screen_height * scale;
+        }
+
+        // Centered, cropped when the window is smaller than the screen
+        let offset = |size: u32, scaled: u32| {
+            i32::try_from((i64::from(size) - i64::from(scaled)) / 2).unwrap_or_default()
+        };
+
+        Ok(Rect::new(
+            offset(width, scaled_width),
+            offset(height, scaled_height),
+            scaled_width,
+            scaled_height,
+        ))
+    }
+}
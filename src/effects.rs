@@ -0,0 +1,349 @@
+use std::fmt;
+
+use clap::ValueEnum;
+
+use crate::gpu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// Post-processing of the screen, done on the CPU so that it works the same
+// with any frontend, or none.
+
+const WIDTH: usize = SCREEN_WIDTH as usize;
+const HEIGHT: usize = SCREEN_HEIGHT as usize;
+
+type Pixel = [u8; 3];
+
+// RGB888 pixels
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+// How the screen is enlarged
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum)]
+pub enum Scaler {
+    None,
+    // Pixel art scalers, smoothing diagonal edges without blurring
+    // (https://www.scale2x.it/algorithm)
+    Scale2x,
+    Scale3x,
+    // Pixels drawn as separate dots, as on the LCD
+    LcdGrid,
+}
+
+impl Scaler {
+    const ALL: [Self; 4] = [Self::None, Self::Scale2x, Self::Scale3x, Self::LcdGrid];
+
+    #[must_use]
+    pub const fn factor(self) -> u32 {
+        match self {
+            Self::None => 1,
+            Self::Scale2x => 2,
+            Self::Scale3x | Self::LcdGrid => 3,
+        }
+    }
+
+    // The next one, to cycle through them
+    #[must_use]
+    pub fn next(self) -> Self {
+        let index = Self::ALL
+            .iter()
+            .position(|&scaler| scaler == self)
+            .unwrap_or(0);
+
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Scale2x => "scale2x",
+            Self::Scale3x => "scale3x",
+            Self::LcdGrid => "lcd-grid",
+        }
+    }
+}
+
+impl fmt::Display for Scaler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+pub struct Effects {
+    pub scaler: Scaler,
+
+    // Blend every frame with the previous ones, as the slow DMG LCD does
+    pub ghosting: bool,
+    // What was last shown, with ghosting
+    previous: Vec<u8>,
+}
+
+impl Effects {
+    #[must_use]
+    pub const fn new(scaler: Scaler, ghosting: bool) -> Self {
+        Self {
+            scaler,
+            ghosting,
+            previous: Vec::new(),
+        }
+    }
+
+    // Process a 160x144 RGB888 screen, see Gpu::screen(). Ghosting is
+    // applied first, then the scaler.
+    pub fn apply(&mut self, screen: &[u8]) -> Frame {
+        let screen = if self.ghosting {
+            self.ghost(screen)
+        } else {
+            self.previous.clear();
+            screen.to_vec()
+        };
+
+        let factor = self.scaler.factor();
+        let pixels = match self.scaler {
+            Scaler::None => screen,
+            Scaler::Scale2x => scale(&screen, 2, scale2x),
+            Scaler::Scale3x => scale(&screen, 3, scale3x),
+            Scaler::LcdGrid => scale(&screen, 3, lcd_grid),
+        };
+
+        Frame {
+            width: u32::from(SCREEN_WIDTH) * factor,
+            height: u32::from(SCREEN_HEIGHT) * factor,
+            pixels,
+        }
+    }
+
+    fn ghost(&mut self, screen: &[u8]) -> Vec<u8> {
+        if self.previous.len() != screen.len() {
+            self.previous = screen.to_vec();
+        }
+
+        for (previous, &current) in self.previous.iter_mut().zip(screen) {
+            // Average, without overflowing
+            *previous = (*previous >> 1) + (current >> 1) + (*previous & current & 1);
+        }
+
+        self.previous.clone()
+    }
+}
+
+fn pixel(screen: &[u8], x: usize, y: usize) -> Pixel {
+    let offset = (y * WIDTH + x) * 3;
+
+    [screen[offset], screen[offset + 1], screen[offset + 2]]
+}
+
+// The coordinates before and after x, y, clamped to the edges
+const fn neighbors(x: usize, y: usize) -> (usize, usize, usize, usize) {
+    let left = x.saturating_sub(1);
+    let up = y.saturating_sub(1);
+    let right = if x + 1 < WIDTH { x + 1 } else { x };
+    let down = if y + 1 < HEIGHT { y + 1 } else { y };
+
+    (left, up, right, down)
+}
+
+// Enlarge screen by factor, with block computing the factor x factor
+// pixels (row by row) replacing the one at x, y.
+fn scale(screen: &[u8], factor: usize, block: fn(&[u8], usize, usize, &mut [Pixel])) -> Vec<u8> {
+    let width = WIDTH * factor;
+    let mut pixels = vec![0; width * HEIGHT * factor * 3];
+    let mut output = vec![[0; 3]; factor * factor];
+
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            block(screen, x, y, &mut output);
+
+            for (i, pixel) in output.iter().enumerate() {
+                let (dx, dy) = (i % factor, i / factor);
+                let offset = ((y * factor + dy) * width + x * factor + dx) * 3;
+
+                pixels[offset..offset + 3].copy_from_slice(pixel);
+            }
+        }
+    }
+
+    pixels
+}
+
+//   A
+// C P B  ->  E0 E1
+//   D        E2 E3
+#[allow(clippy::many_single_char_names)]
+fn scale2x(screen: &[u8], x: usize, y: usize, output: &mut [Pixel]) {
+    let (left, up, right, down) = neighbors(x, y);
+
+    let p = pixel(screen, x, y);
+    let a = pixel(screen, x, up);
+    let b = pixel(screen, right, y);
+    let c = pixel(screen, left, y);
+    let d = pixel(screen, x, down);
+
+    output.copy_from_slice(&[p; 4]);
+
+    if c == a && c != d && a != b {
+        output[0] = a;
+    }
+    if a == b && a != c && b != d {
+        output[1] = b;
+    }
+    if d == c && d != b && c != a {
+        output[2] = c;
+    }
+    if b == d && b != a && d != c {
+        output[3] = d;
+    }
+}
+
+// A B C      E0 E1 E2
+// D E F  ->  E3 E4 E5
+// G H I      E6 E7 E8
+#[allow(clippy::many_single_char_names)]
+fn scale3x(screen: &[u8], x: usize, y: usize, output: &mut [Pixel]) {
+    let (left, up, right, down) = neighbors(x, y);
+
+    let a = pixel(screen, left, up);
+    let b = pixel(screen, x, up);
+    let c = pixel(screen, right, up);
+    let d = pixel(screen, left, y);
+    let e = pixel(screen, x, y);
+    let f = pixel(screen, right, y);
+    let g = pixel(screen, left, down);
+    let h = pixel(screen, x, down);
+    let i = pixel(screen, right, down);
+
+    output.copy_from_slice(&[e; 9]);
+
+    if b == h || d == f {
+        return;
+    }
+
+    let pick = |condition: bool, pixel: Pixel| if condition { pixel } else { e };
+
+    output[0] = pick(d == b, d);
+    output[1] = pick((d == b && e != c) || (b == f && e != a), b);
+    output[2] = pick(b == f, f);
+    output[3] = pick((d == b && e != g) || (d == h && e != a), d);
+    output[5] = pick((b == f && e != i) || (h == f && e != c), f);
+    output[6] = pick(d == h, d);
+    output[7] = pick((d == h && e != i) || (h == f && e != g), h);
+    output[8] = pick(h == f, f);
+}
+
+// The pixel, with darker gaps on its right and bottom edges
+fn lcd_grid(screen: &[u8], x: usize, y: usize, output: &mut [Pixel]) {
+    let color = pixel(screen, x, y);
+    let gap = color.map(|c| c - c / 4);
+
+    for (i, pixel) in output.iter_mut().enumerate() {
+        *pixel = if i % 3 == 2 || i / 3 == 2 { gap } else { color };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: Pixel = [0x00; 3];
+    const WHITE: Pixel = [0xff; 3];
+
+    // Black above the diagonal x + y = 100, white below it
+    fn diagonal() -> Vec<u8> {
+        (0..HEIGHT)
+            .flat_map(|y| (0..WIDTH).map(move |x| if x + y < 100 { BLACK } else { WHITE }))
+            .flatten()
+            .collect()
+    }
+
+    // The factor x factor pixels replacing the one at x, y, row by row
+    fn block(frame: &Frame, factor: usize, x: usize, y: usize) -> Vec<Pixel> {
+        let width = WIDTH * factor;
+
+        (0..factor * factor)
+            .map(|i| {
+                let offset = ((y * factor + i / factor) * width + x * factor + i % factor) * 3;
+                let pixel = &frame.pixels[offset..offset + 3];
+
+                [pixel[0], pixel[1], pixel[2]]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scale2x_diagonal() {
+        let frame = Effects::new(Scaler::Scale2x, false).apply(&diagonal());
+        assert_eq!((frame.width, frame.height), (320, 288));
+
+        // Right below the edge, and right above it
+        assert_eq!(block(&frame, 2, 50, 50), [BLACK, WHITE, WHITE, WHITE]);
+        assert_eq!(block(&frame, 2, 50, 49), [BLACK, BLACK, BLACK, WHITE]);
+
+        // Away from the edge
+        assert_eq!(block(&frame, 2, 10, 10), [BLACK; 4]);
+        assert_eq!(block(&frame, 2, 100, 100), [WHITE; 4]);
+    }
+
+    #[test]
+    fn scale3x_diagonal() {
+        let frame = Effects::new(Scaler::Scale3x, false).apply(&diagonal());
+        assert_eq!((frame.width, frame.height), (480, 432));
+
+        let mut below = [WHITE; 9];
+        below[0] = BLACK;
+        assert_eq!(block(&frame, 3, 50, 50), below);
+
+        let mut above = [BLACK; 9];
+        above[8] = WHITE;
+        assert_eq!(block(&frame, 3, 50, 49), above);
+
+        assert_eq!(block(&frame, 3, 10, 10), [BLACK; 9]);
+        assert_eq!(block(&frame, 3, 100, 100), [WHITE; 9]);
+    }
+
+    #[test]
+    fn lcd_grid_gaps() {
+        let frame = Effects::new(Scaler::LcdGrid, false).apply(&vec![0xff; WIDTH * HEIGHT * 3]);
+        let gap = [0xc0; 3];
+
+        assert_eq!(
+            block(&frame, 3, 0, 0),
+            [WHITE, WHITE, gap, WHITE, WHITE, gap, gap, gap, gap]
+        );
+    }
+
+    #[test]
+    fn ghost() {
+        let screen = |value| vec![value; WIDTH * HEIGHT * 3];
+        let mut effects = Effects::new(Scaler::None, true);
+
+        // The first frame is shown as it is
+        assert_eq!(effects.apply(&screen(0x01)).pixels, screen(0x01));
+
+        // Then averaged with the one shown before, rounding down
+        assert_eq!(effects.apply(&screen(0x02)).pixels, screen(0x01));
+        assert_eq!(effects.apply(&screen(0xff)).pixels, screen(0x80));
+        assert_eq!(effects.apply(&screen(0xff)).pixels, screen(0xbf));
+        assert_eq!(effects.apply(&screen(0xff)).pixels, screen(0xdf));
+
+        // Without ghosting the previous frames are forgotten
+        effects.ghosting = false;
+        assert_eq!(effects.apply(&screen(0x00)).pixels, screen(0x00));
+        effects.ghosting = true;
+        assert_eq!(effects.apply(&screen(0x10)).pixels, screen(0x10));
+    }
+
+    #[test]
+    fn next_scaler() {
+        let mut scaler = Scaler::None;
+        let mut seen = Vec::new();
+
+        for _ in 0..Scaler::ALL.len() {
+            seen.push(scaler);
+            scaler = scaler.next();
+        }
+
+        assert_eq!(seen, Scaler::ALL);
+        assert_eq!(scaler, Scaler::None);
+    }
+}
@@ -0,0 +1,510 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+
+use crate::cpu::{CARRY_FLAG, HALF_CARRY_FLAG, SUBTRACT_FLAG, ZERO_FLAG};
+use crate::gameboy::GameBoy;
+
+// Expressions on the state of the Game Boy, for the conditions of
+// breakpoints, e.g. `a == 0x3f && [hl] != 0 && ly > 140`.
+//
+// They're made of:
+//   - numbers, decimal or hexadecimal with 0x or $, binary with %
+//   - registers: a f b c d e h l af bc de hl sp pc
+//   - flags, 0 or 1: zf nf hf cf ime
+//   - I/O registers by name: ly lcdc stat scx... and mode, the PPU mode
+//   - frame, the number of frames completed
+//   - [ADDR], the byte in memory at ADDR
+//   - the operators of C, with the same precedence:
+//       ! ~ - (unary), * / %, + -, << >>, < <= > >=, == !=, &, ^, |, &&, ||
+//
+// Comparisons and logical operators give 0 or 1.
+
+pub struct Expression {
+    text: String,
+    root: Node,
+}
+
+enum Node {
+    Number(i64),
+    Variable(Variable),
+    Memory(Box<Self>),
+    Unary(char, Box<Self>),
+    Binary(&'static str, Box<Self>, Box<Self>),
+}
+
+#[derive(Clone, Copy)]
+enum Variable {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    Af,
+    Bc,
+    De,
+    Hl,
+    Sp,
+    Pc,
+    Flag(u8),
+    Ime,
+    Io(u16),
+    // STAT bits 0-1
+    Mode,
+    Frame,
+}
+
+// I/O registers by name
+const IO_REGISTERS: [(&str, u16); 20] = [
+    ("joyp", 0xff00),
+    ("sb", 0xff01),
+    ("sc", 0xff02),
+    ("div", 0xff04),
+    ("tima", 0xff05),
+    ("tma", 0xff06),
+    ("tac", 0xff07),
+    ("if", 0xff0f),
+    ("lcdc", 0xff40),
+    ("stat", 0xff41),
+    ("scy", 0xff42),
+    ("scx", 0xff43),
+    ("ly", 0xff44),
+    ("lyc", 0xff45),
+    ("bgp", 0xff47),
+    ("obp0", 0xff48),
+    ("obp1", 0xff49),
+    ("wy", 0xff4a),
+    ("wx", 0xff4b),
+    ("ie", 0xffff),
+];
+
+impl Variable {
+    fn from_name(name: &str) -> Option<Self> {
+        let variable = match name {
+            "a" => Self::A,
+            "f" => Self::F,
+            "b" => Self::B,
+            "c" => Self::C,
+            "d" => Self::D,
+            "e" => Self::E,
+            "h" => Self::H,
+            "l" => Self::L,
+            "af" => Self::Af,
+            "bc" => Self::Bc,
+            "de" => Self::De,
+            "hl" => Self::Hl,
+            "sp" => Self::Sp,
+            "pc" => Self::Pc,
+            "zf" => Self::Flag(ZERO_FLAG),
+            "nf" => Self::Flag(SUBTRACT_FLAG),
+            "hf" => Self::Flag(HALF_CARRY_FLAG),
+            "cf" => Self::Flag(CARRY_FLAG),
+            "ime" => Self::Ime,
+            "mode" => Self::Mode,
+            "frame" => Self::Frame,
+            _ => {
+                let &(_, address) = IO_REGISTERS.iter().find(|(io, _)| *io == name)?;
+                Self::Io(address)
+            }
+        };
+
+        Some(variable)
+    }
+
+    fn value(self, gameboy: &GameBoy) -> i64 {
+        let cpu = &gameboy.cpu;
+        let regs = &cpu.regs;
+
+        let value = match self {
+            Self::A => u16::from(regs.a),
+            Self::F => u16::from(regs.flags),
+            Self::B => u16::from(regs.b),
+            Self::C => u16::from(regs.c),
+            Self::D => u16::from(regs.d),
+            Self::E => u16::from(regs.e),
+            Self::H => u16::from(regs.h),
+            Self::L => u16::from(regs.l),
+            Self::Af => u16::from_be_bytes([regs.a, regs.flags]),
+            Self::Bc => regs.bc(),
+            Self::De => regs.de(),
+            Self::Hl => regs.hl(),
+            Self::Sp => cpu.sp,
+            Self::Pc => cpu.pc,
+            Self::Flag(mask) => u16::from(regs.flags & mask != 0),
+            Self::Ime => u16::from(cpu.interrupts_enabled()),
+            Self::Io(address) => u16::from(gameboy.memory().peek(address)),
+            Self::Mode => u16::from(gameboy.memory().peek(0xff41) & 0b11),
+            Self::Frame => return i64::try_from(gameboy.frame()).unwrap_or(i64::MAX),
+        };
+
+        i64::from(value)
+    }
+}
+
+// Binary operators, from the lowest precedence to the highest
+const PRECEDENCE: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<=", ">=", "<", ">"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+// Longest first, so that e.g. <= isn't read as <
+const OPERATORS: [&str; 18] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "|", "^", "&", "<", ">", "+", "-", "*", "/",
+    "%",
+];
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+    // ( ) [ ] ! ~
+    Symbol(char),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '$' {
+            tokens.push(Token::Number(number(&mut chars)?));
+        } else if c == '%'
+            && matches!(
+                tokens.last(),
+                None | Some(Token::Operator(_) | Token::Symbol('(' | '[' | '!' | '~'))
+            )
+        {
+            // A binary number, rather than the modulo operator
+            tokens.push(Token::Number(number(&mut chars)?));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            tokens.push(Token::Name(word(&mut chars)));
+        } else if "()[]~".contains(c) || (c == '!' && !text_at(&chars).starts_with("!=")) {
+            chars.next();
+            tokens.push(Token::Symbol(c));
+        } else {
+            let rest = text_at(&chars);
+            let operator = OPERATORS
+                .iter()
+                .find(|operator| rest.starts_with(**operator))
+                .ok_or_else(|| format!("Unexpected {c} in {text}"))?;
+
+            for _ in 0..operator.len() {
+                chars.next();
+            }
+            tokens.push(Token::Operator(operator));
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn text_at(chars: &Peekable<Chars>) -> String {
+    chars.clone().collect()
+}
+
+fn word(chars: &mut Peekable<Chars>) -> String {
+    let mut word = String::new();
+
+    while let Some(&c) = chars.peek() {
+        if !c.is_ascii_alphanumeric() && c != '_' {
+            break;
+        }
+        word.push(c);
+        chars.next();
+    }
+
+    word
+}
+
+fn number(chars: &mut Peekable<Chars>) -> Result<i64, String> {
+    let (prefix, radix) = match chars.peek() {
+        Some('$') => ("$", 16),
+        Some('%') => ("%", 2),
+        _ => ("", 10),
+    };
+    if !prefix.is_empty() {
+        chars.next();
+    }
+
+    let text = word(chars);
+    let (digits, radix) = match text.strip_prefix("0x") {
+        Some(digits) if radix == 10 => (digits, 16),
+        _ => (text.as_str(), radix),
+    };
+
+    i64::from_str_radix(digits, radix).map_err(|_| format!("Invalid number {prefix}{text}"))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+
+        token
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Symbol(c)) if c == symbol => Ok(()),
+            _ => Err(format!("Expected {symbol}")),
+        }
+    }
+
+    // Binary operators of the given precedence level and higher
+    fn binary(&mut self, level: usize) -> Result<Node, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut left = self.binary(level + 1)?;
+
+        while let Some(&Token::Operator(operator)) = self.peek() {
+            if !PRECEDENCE[level].contains(&operator) {
+                break;
+            }
+            self.next();
+
+            let right = self.binary(level + 1)?;
+            left = Node::Binary(operator, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some(Token::Symbol(operator @ ('!' | '~'))) => {
+                Ok(Node::Unary(operator, Box::new(self.unary()?)))
+            }
+            Some(Token::Operator("-")) => Ok(Node::Unary('-', Box::new(self.unary()?))),
+            Some(Token::Number(value)) => Ok(Node::Number(value)),
+            Some(Token::Name(name)) => Variable::from_name(&name)
+                .map(Node::Variable)
+                .ok_or_else(|| format!("Unknown name {name}")),
+            Some(Token::Symbol('(')) => {
+                let node = self.binary(0)?;
+                self.expect(')')?;
+
+                Ok(node)
+            }
+            Some(Token::Symbol('[')) => {
+                let node = self.binary(0)?;
+                self.expect(']')?;
+
+                Ok(Node::Memory(Box::new(node)))
+            }
+            Some(token) => Err(format!("Unexpected {token:?}")),
+            None => Err("Unexpected end of expression".to_string()),
+        }
+    }
+}
+
+impl FromStr for Expression {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            position: 0,
+        };
+
+        let root = parser.binary(0)?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("Unexpected {token:?}"));
+        }
+
+        Ok(Self {
+            text: text.to_string(),
+            root,
+        })
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Expression {
+    #[must_use]
+    pub fn evaluate(&self, gameboy: &GameBoy) -> i64 {
+        evaluate(&self.root, gameboy)
+    }
+
+    // True when not 0
+    #[must_use]
+    pub fn is_true(&self, gameboy: &GameBoy) -> bool {
+        self.evaluate(gameboy) != 0
+    }
+}
+
+fn evaluate(node: &Node, gameboy: &GameBoy) -> i64 {
+    match node {
+        Node::Number(value) => *value,
+        Node::Variable(variable) => variable.value(gameboy),
+        Node::Memory(address) => {
+            // Addresses wrap around, as on the bus
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let address = evaluate(address, gameboy) as u16;
+
+            i64::from(gameboy.memory().peek(address))
+        }
+        Node::Unary(operator, operand) => {
+            let value = evaluate(operand, gameboy);
+
+            match operator {
+                '!' => i64::from(value == 0),
+                '~' => !value,
+                _ => value.wrapping_neg(),
+            }
+        }
+        // Short-circuit, e.g. for [hl] only where hl is valid
+        Node::Binary("&&", left, right) => {
+            i64::from(evaluate(left, gameboy) != 0 && evaluate(right, gameboy) != 0)
+        }
+        Node::Binary("||", left, right) => {
+            i64::from(evaluate(left, gameboy) != 0 || evaluate(right, gameboy) != 0)
+        }
+        Node::Binary(operator, left, right) => {
+            let (left, right) = (evaluate(left, gameboy), evaluate(right, gameboy));
+
+            match *operator {
+                "|" => left | right,
+                "^" => left ^ right,
+                "&" => left & right,
+                "==" => i64::from(left == right),
+                "!=" => i64::from(left != right),
+                "<" => i64::from(left < right),
+                "<=" => i64::from(left <= right),
+                ">" => i64::from(left > right),
+                ">=" => i64::from(left >= right),
+                // Shifting by 64 or more shifts all the bits out
+                "<<" => u32::try_from(right)
+                    .ok()
+                    .and_then(|shift| left.checked_shl(shift))
+                    .unwrap_or(0),
+                ">>" => left >> right.clamp(0, 63),
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "*" => left.wrapping_mul(right),
+                // No exceptions in the debugger, dividing by 0 gives 0
+                "/" => left.checked_div(right).unwrap_or(0),
+                _ => left.checked_rem(right).unwrap_or(0),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing;
+
+    // Turns the LCD on, then LD A,$3F; LD HL,$C000; LD (HL),A and loops
+    fn gameboy() -> GameBoy {
+        let mut gameboy = testing::gameboy(&[(
+            0x0100,
+            &[
+                0x3e, 0x91, 0xe0, 0x40, 0x3e, 0x3f, 0x21, 0x00, 0xc0, 0x77, 0x18, 0xfe,
+            ],
+        )]);
+        while gameboy.memory().peek(0xff44) <= 140 {
+            gameboy.step();
+            gameboy.end_frame();
+        }
+
+        gameboy
+    }
+
+    fn evaluate(text: &str) -> i64 {
+        text.parse::<Expression>().unwrap().evaluate(&gameboy())
+    }
+
+    #[test]
+    fn condition() {
+        assert_eq!(evaluate("a == 0x3f && [hl] != 0 && ly > 140"), 1);
+        assert_eq!(evaluate("a == 0x3f && [hl] != 0x3f"), 0);
+        assert_eq!(evaluate("hl == $c000 || [0] / 0"), 1);
+    }
+
+    #[test]
+    fn binary_and_modulo() {
+        assert_eq!(evaluate("%101 % 2"), 1);
+        assert_eq!(evaluate("(%101) %%11"), 2);
+    }
+
+    #[test]
+    fn unary() {
+        assert_eq!(evaluate("!a != 0"), 0);
+        assert_eq!(evaluate("~a & $ff"), 0xc0);
+        assert_eq!(evaluate("-a + a"), 0);
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(evaluate("1 + 2 * 3 == 7"), 1);
+        assert_eq!(evaluate("1 | 2 ^ 3 & 4"), 3);
+        assert_eq!(evaluate("(1 + 2) * 3"), 9);
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(evaluate("1 << 64"), 0);
+        assert_eq!(evaluate("-8 >> 64"), -1);
+        assert_eq!(evaluate("1 / 0"), 0);
+        assert_eq!(evaluate("1 % 0"), 0);
+    }
+
+    #[test]
+    fn io_registers() {
+        let gameboy = gameboy();
+        for (address, value) in [
+            (0xff43, 0x13),
+            (0xff49, 0x1b),
+            (0xff4a, 0x40),
+            (0xff4b, 0x07),
+        ] {
+            gameboy.memory_mut().write(address, value);
+        }
+
+        let evaluate = |text: &str| text.parse::<Expression>().unwrap().evaluate(&gameboy);
+        assert_eq!(evaluate("scx"), 0x13);
+        assert_eq!(evaluate("obp1"), 0x1b);
+        assert_eq!(evaluate("wy"), 0x40);
+        assert_eq!(evaluate("wx"), 0x07);
+        assert_eq!(evaluate("lcdc"), 0x91);
+    }
+
+    #[test]
+    fn invalid() {
+        for text in ["a ==", "(a", "[hl", "foo", "1 2", "0xg", "a # 1"] {
+            assert!(text.parse::<Expression>().is_err(), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use std::cell::{Ref, RefCell, RefMut};
+use std::rc::Rc;
+
+use crate::cpu::{Cpu, Registers};
+use crate::gpu::Gpu;
+use crate::memory::{Memory, Rom};
+use crate::trace::Trace;
+
+// T-cycles in a frame: 154 scanlines of 456 dots
+pub const CYCLES_PER_FRAME: u32 = 70224;
+
+// T-cycles in a second, about 59.73 frames
+pub const CYCLES_PER_SECOND: u32 = 4_194_304;
+
+// The whole system: the CPU and everything on its bus.
+pub struct GameBoy {
+    pub cpu: Cpu,
+
+    memory: Rc<RefCell<Memory>>,
+
+    // T-cycles and frames completed since power on
+    cycles: u64,
+    frame: u64,
+    // T-cycles elapsed in the current frame
+    frame_cycles: u32,
+
+    // Log of the instructions executed
+    trace: Option<Trace>,
+}
+
+impl GameBoy {
+    // Without a boot ROM the system starts in the state left by it.
+    #[must_use]
+    pub fn new(rom: Vec<u8>, boot_rom: Option<Vec<u8>>) -> Self {
+        let mut memory = Memory::new(Gpu::new());
+
+        let has_boot_rom = boot_rom.is_some();
+        if let Some(boot_rom) = boot_rom {
+            memory.map(0x0000, Box::new(Rom::new(boot_rom)));
+        }
+
+        memory.map(0x0000, Box::new(Rom::new(rom)));
+
+        let memory = Rc::new(RefCell::new(memory));
+
+        let bus = Rc::clone(&memory);
+        let cpu = if has_boot_rom {
+            Cpu::new(bus)
+        } else {
+            Cpu::new_initialized(bus)
+        };
+
+        Self {
+            cpu,
+            memory,
+            cycles: 0,
+            frame: 0,
+            frame_cycles: 0,
+            trace: None,
+        }
+    }
+
+    // Execute one instruction, returning the T-cycles elapsed.
+    pub fn step(&mut self) -> u8 {
+        // The state before the instruction, logged if one is executed
+        let line = self.trace.as_ref().map(|trace| trace.line(self));
+        let instructions = self.cpu.instructions();
+
+        let cycles = self.cpu.decode();
+        self.cycles += u64::from(cycles);
+        self.frame_cycles += u32::from(cycles);
+
+        if let (Some(trace), Some(line)) = (&mut self.trace, line) {
+            if self.cpu.instructions() != instructions {
+                trace.write(&line);
+            }
+        }
+
+        cycles
+    }
+
+    // A frame is over when the GPU completes it, or after a frame worth of
+    // cycles when the LCD is off.
+    //
+    // Returns None while the current frame isn't over, then whether the
+    // GPU completed it. Meant to be called after every step().
+    pub fn end_frame(&mut self) -> Option<bool> {
+        let drawn = self.memory.borrow_mut().take_frame();
+
+        if drawn || self.frame_cycles >= CYCLES_PER_FRAME {
+            self.frame += 1;
+            self.frame_cycles = 0;
+
+            Some(drawn)
+        } else {
+            None
+        }
+    }
+
+    // Run until the end of the current frame.
+    //
+    // Returns true if the GPU completed it.
+    pub fn run_frame(&mut self) -> bool {
+        loop {
+            self.step();
+
+            if let Some(drawn) = self.end_frame() {
+                return drawn;
+            }
+        }
+    }
+
+    // T-cycles elapsed since power on
+    #[must_use]
+    pub const fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Frames completed since power on
+    #[must_use]
+    pub const fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // Nothing was executed yet in the current frame, e.g. to change the
+    // buttons pressed at a point that doesn't depend on the frontend.
+    #[must_use]
+    pub const fn frame_start(&self) -> bool {
+        self.frame_cycles == 0
+    }
+
+    // Log every instruction executed from now on
+    pub fn set_trace(&mut self, trace: Trace) {
+        self.trace = Some(trace);
+    }
+
+    // Run in the state of gameboy-doctor's logs: the registers start as
+    // left by the DMG boot ROM rather than the DMG0 one, and LY always
+    // reads 0x90.
+    //
+    // Only possible before anything ran without a boot ROM, which starts
+    // at 0x0000. Returns whether it was.
+    pub fn set_doctor(&mut self) -> bool {
+        if self.cycles != 0 || self.cpu.pc != 0x0100 {
+            return false;
+        }
+
+        self.cpu.regs = Registers::dmg();
+        self.memory.borrow_mut().set_ly(Some(0x90));
+
+        true
+    }
+
+    #[must_use]
+    pub fn memory(&self) -> Ref<'_, Memory> {
+        self.memory.borrow()
+    }
+
+    #[must_use]
+    pub fn memory_mut(&self) -> RefMut<'_, Memory> {
+        self.memory.borrow_mut()
+    }
+}
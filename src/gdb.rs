@@ -0,0 +1,851 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::mem;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::debugger::{Action, Debugger};
+use crate::gameboy::GameBoy;
+use crate::watchpoint::{Kind, Watchpoint};
+
+// A GDB remote serial protocol stub, to debug with gdb or any other RSP
+// client rather than the debugger prompt.
+//
+// The stub drives a Debugger: gdb's breakpoints and watchpoints are added
+// to it, and when it stops, serve() answers gdb's packets until it
+// continues or steps. While the emulation runs, poll() lets gdb connect,
+// stopping the emulation, and interrupt it with Ctrl-C. After gdb detaches
+// another one can connect.
+//
+// There's no Game Boy architecture in gdb, the registers are sent as the
+// first ones of its Z80 architecture: AF BC DE HL SP PC, 16 bits little
+// endian each.
+
+// Byte sent by gdb to interrupt the emulation
+const INTERRUPT: u8 = 0x03;
+
+// Registers of gdb's Z80 architecture, the ones after PC don't exist
+const REGISTERS: usize = 13;
+
+const FEATURES: &str = "PacketSize=1000;swbreak+;hwbreak+;QStartNoAckMode+";
+
+enum State {
+    Stopped,
+    // gdb is waiting for the emulation to stop
+    Running,
+    // Stopped by gdb, with Ctrl-C
+    Interrupted,
+}
+
+// gdb tells them apart, even though they're the same here
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BreakpointType {
+    Software,
+    Hardware,
+}
+
+// What to do after a packet
+enum Response {
+    Reply(String),
+    Resume,
+    Detach,
+    Kill,
+}
+
+pub struct GdbStub {
+    // Non-blocking, polled for gdb to connect
+    listener: TcpListener,
+    // Packets are read from and written to the same socket, None until gdb
+    // connects
+    reader: Option<BufReader<TcpStream>>,
+    // Whether packets are acknowledged, until QStartNoAckMode
+    acks: bool,
+    state: State,
+    // Names of the breakpoints and watchpoints set by gdb
+    breakpoints: Vec<(String, BreakpointType)>,
+    watchpoints: Vec<String>,
+}
+
+impl GdbStub {
+    // Let gdb connect on address
+    pub fn listen<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        println!("Waiting for GDB on {}", listener.local_addr()?);
+
+        Ok(Self {
+            listener,
+            reader: None,
+            acks: true,
+            state: State::Stopped,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+        })
+    }
+
+    #[must_use]
+    pub const fn is_connected(&self) -> bool {
+        self.reader.is_some()
+    }
+
+    // Call while the emulation runs, stops it when gdb connects or
+    // interrupts it
+    pub fn poll(&mut self, debugger: &mut Debugger, gameboy: &GameBoy) -> io::Result<()> {
+        let Some(reader) = &mut self.reader else {
+            return self.accept(debugger);
+        };
+
+        // Packets are only read when stopped
+        if !matches!(self.state, State::Running) {
+            return Ok(());
+        }
+
+        reader.get_ref().set_nonblocking(true)?;
+        let received = match reader.fill_buf() {
+            Ok(bytes) => Ok(Some(bytes.to_vec())),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            // Handled as the end of the stream
+            Err(e) if lost_connection(&e) => Ok(Some(Vec::new())),
+            Err(e) => Err(e),
+        };
+        reader.get_ref().set_nonblocking(false)?;
+
+        match received? {
+            Some(bytes) if bytes.is_empty() => self.detach(debugger, gameboy),
+            Some(bytes) => {
+                reader.consume(bytes.len());
+
+                if bytes.contains(&INTERRUPT) {
+                    self.state = State::Interrupted;
+                    debugger.stop();
+                }
+            }
+            None => (),
+        }
+
+        Ok(())
+    }
+
+    // gdb attaches to the emulation stopped
+    fn accept(&mut self, debugger: &mut Debugger) -> io::Result<()> {
+        let (stream, peer) = match self.listener.accept() {
+            Ok(connection) => connection,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        println!("GDB connected from {peer}");
+
+        // Not inherited from the listener everywhere
+        stream.set_nonblocking(false)?;
+        // Packets are tiny and latency matters
+        stream.set_nodelay(true)?;
+
+        self.reader = Some(BufReader::new(stream));
+        self.acks = true;
+        self.state = State::Stopped;
+        debugger.stop();
+
+        Ok(())
+    }
+
+    // Call when the debugger stopped: tells gdb, and answers its packets
+    // until it resumes the emulation.
+    pub fn serve(&mut self, debugger: &mut Debugger, gameboy: &mut GameBoy) -> io::Result<Action> {
+        match self.answer(debugger, gameboy) {
+            Err(e) if lost_connection(&e) => {
+                self.detach(debugger, gameboy);
+                Ok(Action::Resume)
+            }
+            result => result,
+        }
+    }
+
+    fn answer(&mut self, debugger: &mut Debugger, gameboy: &mut GameBoy) -> io::Result<Action> {
+        if !matches!(self.state, State::Stopped) {
+            let reply = self.stop_reply(debugger, gameboy);
+            self.send(&reply)?;
+        }
+
+        loop {
+            let Some(packet) = self.receive()? else {
+                self.detach(debugger, gameboy);
+                return Ok(Action::Resume);
+            };
+
+            match self
+                .execute(&packet, debugger, gameboy)
+                .unwrap_or_else(|| Response::Reply("E01".to_string()))
+            {
+                Response::Reply(reply) => self.send(&reply)?,
+                Response::Resume => {
+                    self.state = State::Running;
+                    return Ok(Action::Resume);
+                }
+                Response::Detach => {
+                    self.send("OK")?;
+                    self.detach(debugger, gameboy);
+                    return Ok(Action::Resume);
+                }
+                Response::Kill => return Ok(Action::Quit),
+            }
+        }
+    }
+
+    // Remove what gdb set and let the emulation run
+    fn detach(&mut self, debugger: &mut Debugger, gameboy: &GameBoy) {
+        for (name, _) in self.breakpoints.drain(..) {
+            debugger.remove_breakpoint(&name);
+        }
+        for name in self.watchpoints.drain(..) {
+            gameboy.memory_mut().watchpoints_mut().remove(&name);
+        }
+
+        debugger.resume();
+        self.reader = None;
+        println!("GDB disconnected");
+    }
+
+    fn reader(&mut self) -> io::Result<&mut BufReader<TcpStream>> {
+        self.reader
+            .as_mut()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))
+    }
+
+    // The next valid packet, None when gdb disconnected
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        let acks = self.acks;
+        receive(self.reader()?, acks)
+    }
+
+    // Packets aren't sent again when gdb asks for it, TCP doesn't lose them
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${data}#{:02x}", checksum(data.as_bytes()));
+        self.reader()?.get_mut().write_all(packet.as_bytes())
+    }
+
+    // Why the emulation stopped, as gdb wants to know
+    fn stop_reply(&mut self, debugger: &Debugger, gameboy: &GameBoy) -> String {
+        let state = mem::replace(&mut self.state, State::Stopped);
+
+        match debugger.watchpoint_hits().first() {
+            Some(hit) => {
+                let memory = gameboy.memory();
+                let kind = memory
+                    .watchpoints()
+                    .iter()
+                    .find(|watchpoint| watchpoint.name == hit.name)
+                    .map_or(Kind::Write, |watchpoint| watchpoint.kind);
+
+                let reason = match kind {
+                    Kind::Read => "rwatch",
+                    Kind::Write | Kind::Change => "watch",
+                    Kind::ReadWrite => "awatch",
+                };
+
+                format!("T05{reason}:{:x};", hit.address)
+            }
+            None if !debugger.breakpoint_hits().is_empty() => {
+                let hardware = self.breakpoints.iter().any(|(name, kind)| {
+                    *kind == BreakpointType::Hardware && debugger.breakpoint_hits().contains(name)
+                });
+
+                if hardware {
+                    "T05hwbreak:;".to_string()
+                } else {
+                    "T05swbreak:;".to_string()
+                }
+            }
+            None if matches!(state, State::Interrupted) => "S02".to_string(),
+            None => "S05".to_string(),
+        }
+    }
+
+    // None for invalid packets
+    fn execute(
+        &mut self,
+        packet: &str,
+        debugger: &mut Debugger,
+        gameboy: &mut GameBoy,
+    ) -> Option<Response> {
+        let reply = |reply: &str| Some(Response::Reply(reply.to_string()));
+
+        if packet.is_empty() {
+            return reply("");
+        }
+        let (command, args) = packet.split_at(1);
+
+        match command {
+            "?" => Some(Response::Reply(self.stop_reply(debugger, gameboy))),
+
+            "g" => {
+                let registers: Vec<String> = (0..REGISTERS)
+                    .map(|register| {
+                        read_register(gameboy, register)
+                            .map_or_else(|| "xxxx".to_string(), |value| hex(&value.to_le_bytes()))
+                    })
+                    .collect();
+
+                Some(Response::Reply(registers.concat()))
+            }
+            "G" => {
+                for (register, value) in bytes(args)?.chunks_exact(2).enumerate() {
+                    write_register(gameboy, register, u16::from_le_bytes([value[0], value[1]]));
+                }
+                reply("OK")
+            }
+            "p" => {
+                let value = read_register(gameboy, usize::from(number(args)?))?;
+                Some(Response::Reply(hex(&value.to_le_bytes())))
+            }
+            "P" => {
+                let (register, value) = args.split_once('=')?;
+                let (register, value) = (usize::from(number(register)?), bytes(value)?);
+
+                let [low, high] = value[..] else {
+                    return None;
+                };
+
+                if write_register(gameboy, register, u16::from_le_bytes([low, high])) {
+                    reply("OK")
+                } else {
+                    None
+                }
+            }
+
+            "m" => {
+                let (address, length) = range(args)?;
+                let memory = gameboy.memory();
+                let data: Vec<u8> = (0..length)
+                    .map(|offset| memory.peek(address.wrapping_add(offset)))
+                    .collect();
+
+                Some(Response::Reply(hex(&data)))
+            }
+            "M" => {
+                let (range_args, data) = args.split_once(':')?;
+                let (address, _) = range(range_args)?;
+                let mut memory = gameboy.memory_mut();
+
+                let written = (0..)
+                    .zip(&bytes(data)?)
+                    .all(|(offset, &value)| memory.poke(address.wrapping_add(offset), value));
+
+                if written {
+                    reply("OK")
+                } else {
+                    None
+                }
+            }
+
+            "c" | "s" => {
+                if !args.is_empty() {
+                    gameboy.cpu.pc = number(args)?;
+                }
+
+                if command == "c" {
+                    debugger.resume();
+                } else {
+                    debugger.step();
+                }
+                Some(Response::Resume)
+            }
+
+            "Z" | "z" => self.set_point(command == "Z", args, debugger, gameboy),
+
+            "D" => Some(Response::Detach),
+            "k" => Some(Response::Kill),
+            // There's a single thread
+            "H" | "T" => reply("OK"),
+
+            _ => match packet {
+                "QStartNoAckMode" => {
+                    self.acks = false;
+                    reply("OK")
+                }
+                _ if packet.starts_with("qSupported") => reply(FEATURES),
+                "qAttached" => reply("1"),
+                "qC" => reply("QC1"),
+                "qfThreadInfo" => reply("m1"),
+                "qsThreadInfo" => reply("l"),
+                // Not supported
+                _ => reply(""),
+            },
+        }
+    }
+
+    // Z or z TYPE,ADDR,KIND: set or remove a breakpoint (types 0 and 1) or a
+    // write, read or access watchpoint (types 2, 3 and 4, KIND is the
+    // length)
+    fn set_point(
+        &mut self,
+        set: bool,
+        args: &str,
+        debugger: &mut Debugger,
+        gameboy: &GameBoy,
+    ) -> Option<Response> {
+        let mut fields = args.split(',');
+        let kind = fields.next()?;
+        let address = number(fields.next()?)?;
+        let length = number(fields.next()?.split(';').next()?)?;
+
+        let breakpoint_type = if kind == "1" {
+            BreakpointType::Hardware
+        } else {
+            BreakpointType::Software
+        };
+        let watchpoint_kind = match kind {
+            "0" | "1" => None,
+            "2" => Some(Kind::Write),
+            "3" => Some(Kind::Read),
+            "4" => Some(Kind::ReadWrite),
+            // Not supported
+            _ => return Some(Response::Reply(String::new())),
+        };
+        let name = format!("gdb-{kind}-{address:04x}-{length:x}");
+
+        match (watchpoint_kind, set) {
+            (None, true) => {
+                debugger.add_breakpoint(gameboy, address, Some(name.clone()), None);
+                self.breakpoints.push((name, breakpoint_type));
+            }
+            (None, false) => {
+                debugger.remove_breakpoint(&name);
+                self.breakpoints
+                    .retain(|(breakpoint, _)| *breakpoint != name);
+            }
+            (Some(kind), true) => {
+                gameboy.memory_mut().watchpoints_mut().add(Watchpoint {
+                    name: name.clone(),
+                    range: address..=address.wrapping_add(length.max(1) - 1),
+                    kind,
+                    condition: None,
+                    enabled: true,
+                });
+                self.watchpoints.push(name);
+            }
+            (Some(_), false) => {
+                gameboy.memory_mut().watchpoints_mut().remove(&name);
+                self.watchpoints.retain(|watchpoint| *watchpoint != name);
+            }
+        }
+
+        Some(Response::Reply("OK".to_string()))
+    }
+}
+
+// gdb went away without detaching, e.g. killed
+fn lost_connection(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+// The next valid packet read from stream, acknowledging it when acks is
+// set, None at the end of the stream
+fn receive<S: Read + Write>(stream: &mut BufReader<S>, acks: bool) -> io::Result<Option<String>> {
+    loop {
+        // Acknowledgements and interrupts while stopped are skipped
+        let mut byte = [0];
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'$' {
+                break;
+            }
+        }
+
+        let mut data = Vec::new();
+        stream.read_until(b'#', &mut data)?;
+        if data.pop() != Some(b'#') {
+            return Ok(None);
+        }
+
+        let mut sum = [0; 2];
+        match stream.read_exact(&mut sum) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+
+        let valid = std::str::from_utf8(&sum)
+            .ok()
+            .and_then(|sum| u8::from_str_radix(sum, 16).ok())
+            == Some(checksum(&data));
+
+        if acks {
+            stream
+                .get_mut()
+                .write_all(if valid { b"+" } else { b"-" })?;
+        }
+        if valid {
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+fn hex(data: &[u8]) -> String {
+    let hex: Vec<String> = data.iter().map(|byte| format!("{byte:02x}")).collect();
+    hex.concat()
+}
+
+fn bytes(text: &str) -> Option<Vec<u8>> {
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn number(text: &str) -> Option<u16> {
+    u16::from_str_radix(text, 16).ok()
+}
+
+// ADDR,LENGTH
+fn range(text: &str) -> Option<(u16, u16)> {
+    let (address, length) = text.split_once(',')?;
+    Some((number(address)?, number(length)?))
+}
+
+// Register number register of gdb's Z80 architecture
+fn read_register(gameboy: &GameBoy, register: usize) -> Option<u16> {
+    let cpu = &gameboy.cpu;
+    let regs = &cpu.regs;
+
+    match register {
+        0 => Some(u16::from_be_bytes([regs.a, regs.flags])),
+        1 => Some(regs.bc()),
+        2 => Some(regs.de()),
+        3 => Some(regs.hl()),
+        4 => Some(cpu.sp),
+        5 => Some(cpu.pc),
+        _ => None,
+    }
+}
+
+// Returns false for the registers that don't exist
+fn write_register(gameboy: &mut GameBoy, register: usize, value: u16) -> bool {
+    let cpu = &mut gameboy.cpu;
+
+    match register {
+        0 => cpu.regs.write_af(value),
+        1 => cpu.regs.write_bc(value),
+        2 => cpu.regs.write_de(value),
+        3 => cpu.regs.write_hl(value),
+        4 => cpu.sp = value,
+        5 => cpu.pc = value,
+        _ => return false,
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use crate::testing;
+
+    // gdb's side of the connection
+    struct Stream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Stream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Stream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // The packets received from input, and the acknowledgements sent
+    fn receive_all(input: &str, acks: bool) -> (Vec<String>, String) {
+        let mut stream = BufReader::new(Stream {
+            input: Cursor::new(input.as_bytes().to_vec()),
+            output: Vec::new(),
+        });
+
+        let mut packets = Vec::new();
+        while let Some(packet) = receive(&mut stream, acks).unwrap() {
+            packets.push(packet);
+        }
+
+        let output = String::from_utf8(stream.into_inner().output).unwrap();
+        (packets, output)
+    }
+
+    // $0100 LD A,$3F; LD [$C000],A; LD A,[$C000]; JR $0100
+    fn gameboy() -> GameBoy {
+        testing::gameboy(&[(
+            0x0100,
+            &[0x3e, 0x3f, 0xea, 0x00, 0xc0, 0xfa, 0x00, 0xc0, 0x18, 0xf6],
+        )])
+    }
+
+    fn stub() -> (GdbStub, Debugger, GameBoy) {
+        let stub = GdbStub::listen("127.0.0.1:0").unwrap();
+        (stub, Debugger::new(), gameboy())
+    }
+
+    // The reply to packet, None for invalid packets
+    fn reply(
+        stub: &mut GdbStub,
+        packet: &str,
+        debugger: &mut Debugger,
+        gameboy: &mut GameBoy,
+    ) -> Option<String> {
+        match stub.execute(packet, debugger, gameboy)? {
+            Response::Reply(reply) => Some(reply),
+            _ => panic!("No reply to {}", packet),
+        }
+    }
+
+    // Resume until the debugger stops again, returning PC
+    fn resume(debugger: &mut Debugger, gameboy: &mut GameBoy) -> u16 {
+        debugger.resume();
+        testing::run(debugger, gameboy)
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(checksum(b""), 0);
+        assert_eq!(checksum(b"OK"), 0x9a);
+        assert_eq!(checksum(b"qSupported:multiprocess+"), 0xc6);
+
+        assert_eq!(hex(&[0x0a, 0xff]), "0aff");
+        assert_eq!(bytes("0aFf"), Some(vec![0x0a, 0xff]));
+        assert_eq!(bytes(""), Some(vec![]));
+        assert_eq!(bytes("0af"), None);
+        assert_eq!(bytes("zz"), None);
+
+        assert_eq!(range("c000,10"), Some((0xc000, 0x10)));
+        assert_eq!(range("c000"), None);
+        assert_eq!(range("10000,1"), None);
+        assert_eq!(range("c000,"), None);
+    }
+
+    #[test]
+    fn receive_packets() {
+        // Acknowledgements and interrupts from gdb are skipped
+        let (packets, acks) = receive_all("+$g#67\x03-$m0100,2#8c", true);
+        assert_eq!(packets, ["g", "m0100,2"]);
+        assert_eq!(acks, "++");
+
+        // Invalid checksums are rejected, gdb sends the packet again
+        let (packets, acks) = receive_all("$g#00$g#zz$g#67", true);
+        assert_eq!(packets, ["g"]);
+        assert_eq!(acks, "--+");
+
+        let (packets, acks) = receive_all("$vMustReplyEmpty#3a$OK#00", false);
+        assert_eq!(packets, ["vMustReplyEmpty"]);
+        assert_eq!(acks, "");
+
+        // Cut off
+        for input in ["$g", "$g#", "$g#6"] {
+            assert!(receive_all(input, true).0.is_empty(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn registers() {
+        let (mut stub, mut debugger, mut gameboy) = stub();
+        let regs = &mut gameboy.cpu.regs;
+        regs.write_af(0x12b0);
+        regs.write_bc(0x3456);
+        regs.write_de(0x789a);
+        regs.write_hl(0xbcde);
+        gameboy.cpu.sp = 0xfffe;
+
+        // F first, then A
+        assert_eq!(
+            reply(&mut stub, "g", &mut debugger, &mut gameboy).unwrap(),
+            format!("b0125634 9a78debc feff0001{}", "xxxx".repeat(7)).replace(' ', "")
+        );
+        assert_eq!(
+            reply(&mut stub, "p0", &mut debugger, &mut gameboy),
+            Some("b012".to_string())
+        );
+        assert_eq!(
+            reply(&mut stub, "p5", &mut debugger, &mut gameboy),
+            Some("0001".to_string())
+        );
+        assert_eq!(reply(&mut stub, "p6", &mut debugger, &mut gameboy), None);
+
+        assert_eq!(
+            reply(
+                &mut stub,
+                "Gff2001020304050600c05001",
+                &mut debugger,
+                &mut gameboy
+            ),
+            Some("OK".to_string())
+        );
+        let regs = &gameboy.cpu.regs;
+        // The low bits of F don't exist
+        assert_eq!((regs.a, regs.flags), (0x20, 0xf0));
+        assert_eq!((regs.bc(), regs.de(), regs.hl()), (0x0201, 0x0403, 0x0605));
+        assert_eq!((gameboy.cpu.sp, gameboy.cpu.pc), (0xc000, 0x0150));
+
+        assert_eq!(
+            reply(&mut stub, "P0=7001", &mut debugger, &mut gameboy),
+            Some("OK".to_string())
+        );
+        assert_eq!((gameboy.cpu.regs.a, gameboy.cpu.regs.flags), (0x01, 0x70));
+        for packet in ["P6=0000", "P0=12", "P0", "P0=zzzz"] {
+            assert_eq!(
+                reply(&mut stub, packet, &mut debugger, &mut gameboy),
+                None,
+                "{packet}"
+            );
+        }
+    }
+
+    #[test]
+    fn memory() {
+        let (mut stub, mut debugger, mut gameboy) = stub();
+
+        assert_eq!(
+            reply(&mut stub, "m0100,4", &mut debugger, &mut gameboy),
+            Some("3e3fea00".to_string())
+        );
+        assert_eq!(
+            reply(&mut stub, "Mc000,3:abcdef", &mut debugger, &mut gameboy),
+            Some("OK".to_string())
+        );
+        assert_eq!(
+            reply(&mut stub, "mbfff,5", &mut debugger, &mut gameboy),
+            Some("00abcdef00".to_string())
+        );
+
+        // Not ROM nor I/O registers
+        for packet in ["M0100,1:00", "Mff40,1:00", "Mc000,1:zz", "mc000"] {
+            assert_eq!(
+                reply(&mut stub, packet, &mut debugger, &mut gameboy),
+                None,
+                "{packet}"
+            );
+        }
+        assert_eq!(gameboy.memory().peek(0x0100), 0x3e);
+    }
+
+    #[test]
+    fn breakpoints() {
+        let (mut stub, mut debugger, mut gameboy) = stub();
+
+        for (packet, pc, stop) in [
+            ("0,0102,1", 0x0102, "T05swbreak:;"),
+            ("1,0105,1", 0x0105, "T05hwbreak:;"),
+        ] {
+            let set = format!("Z{packet}");
+            assert_eq!(
+                reply(&mut stub, &set, &mut debugger, &mut gameboy),
+                Some("OK".to_string())
+            );
+            assert_eq!(resume(&mut debugger, &mut gameboy), pc);
+            assert_eq!(
+                debugger.breakpoint_hits(),
+                [format!("gdb-{}", packet.replace(',', "-"))]
+            );
+            assert_eq!(
+                reply(&mut stub, "?", &mut debugger, &mut gameboy),
+                Some(stop.to_string())
+            );
+
+            let remove = format!("z{packet}");
+            assert_eq!(
+                reply(&mut stub, &remove, &mut debugger, &mut gameboy),
+                Some("OK".to_string())
+            );
+        }
+        assert!(stub.breakpoints.is_empty());
+        assert!(!debugger.remove_breakpoint("gdb-0-0102-1"));
+
+        // Unsupported type
+        assert_eq!(
+            reply(&mut stub, "Z5,0102,1", &mut debugger, &mut gameboy),
+            Some(String::new())
+        );
+    }
+
+    #[test]
+    fn watchpoints() {
+        let (mut stub, mut debugger, mut gameboy) = stub();
+
+        for (packet, kind, range, pc, stop) in [
+            (
+                "2,c000,1",
+                Kind::Write,
+                0xc000..=0xc000,
+                0x0105,
+                "T05watch:c000;",
+            ),
+            (
+                "3,c000,1",
+                Kind::Read,
+                0xc000..=0xc000,
+                0x0108,
+                "T05rwatch:c000;",
+            ),
+            (
+                "4,bfff,2",
+                Kind::ReadWrite,
+                0xbfff..=0xc000,
+                0x0105,
+                "T05awatch:c000;",
+            ),
+        ] {
+            let set = format!("Z{packet}");
+            assert_eq!(
+                reply(&mut stub, &set, &mut debugger, &mut gameboy),
+                Some("OK".to_string())
+            );
+
+            let name = format!("gdb-{}", packet.replace(',', "-"));
+            {
+                let memory = gameboy.memory();
+                let watchpoint = memory
+                    .watchpoints()
+                    .iter()
+                    .find(|watchpoint| watchpoint.name == name)
+                    .unwrap();
+                assert_eq!((watchpoint.kind, &watchpoint.range), (kind, &range));
+            }
+
+            assert_eq!(resume(&mut debugger, &mut gameboy), pc);
+            assert_eq!(
+                reply(&mut stub, "?", &mut debugger, &mut gameboy),
+                Some(stop.to_string())
+            );
+
+            let remove = format!("z{packet}");
+            assert_eq!(
+                reply(&mut stub, &remove, &mut debugger, &mut gameboy),
+                Some("OK".to_string())
+            );
+            assert!(gameboy.memory().watchpoints().is_empty());
+        }
+
+        // Removed when gdb detaches
+        reply(&mut stub, "Z2,c000,1", &mut debugger, &mut gameboy);
+        reply(&mut stub, "Z0,0102,1", &mut debugger, &mut gameboy);
+        stub.detach(&mut debugger, &gameboy);
+        assert!(gameboy.memory().watchpoints().is_empty());
+        assert!(!debugger.remove_breakpoint("gdb-0-0102-1"));
+    }
+}
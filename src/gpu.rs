@@ -1,22 +1,37 @@
 use std::fmt;
 
-use sdl2::rect::Rect;
-use sdl2::render::Canvas;
-use sdl2::render::Texture;
-use sdl2::video::Window;
+use crate::memory::{INT_STAT, INT_VBLANK};
 
-const BYTES_PER_PIXEL: u8 = 4; // RGBA8888
-const BUFFER_HEIGHT: u16 = 256;
-const BUFFER_WIDTH: u16 = 256;
+pub const BYTES_PER_PIXEL: u8 = 4; // RGBA8888
+pub const BUFFER_HEIGHT: u16 = 256;
+pub const BUFFER_WIDTH: u16 = 256;
+
+// The visible part of the buffer
+pub const SCREEN_WIDTH: u16 = 160;
+pub const SCREEN_HEIGHT: u16 = 144;
+
+// Dots (T-cycles) per scanline, including HBlank
+const DOTS_PER_LINE: u16 = 456;
+
+// Dots spent in mode 2 (OAM scan) and mode 3 (pixel transfer)
+const OAM_SCAN_DOTS: u16 = 80;
+const TRANSFER_DOTS: u16 = 172;
 
 const BUFFER_SIZE: usize =
     BUFFER_HEIGHT as usize * BUFFER_WIDTH as usize * BYTES_PER_PIXEL as usize;
 
+// RGB888
+const SCREEN_SIZE: usize = SCREEN_HEIGHT as usize * SCREEN_WIDTH as usize * 3;
+
 pub struct Gpu {
     vram: [u8; 0x2000], // 8KiB
     oam: [u8; 0xa0],
 
-    buffer: [u8; BUFFER_SIZE],
+    // Boxed, as they're too large for the stack
+    buffer: Box<[u8]>,
+
+    // The last complete frame, as shown on the LCD
+    screen: Box<[u8]>,
 
     // The current vertical scanline being drawn.
     //
@@ -46,9 +61,25 @@ pub struct Gpu {
     // Bit 0 - BG Display (for CGB see below) (0=Off, 1=On)
     pub lcdc: u8,
 
-    // TODO  FF41 - STAT - LCDC Status (R/W)
+    // FF41 - STAT - LCDC Status (R/W)
+    // Bit 6 - LYC=LY Coincidence Interrupt (1=Enable) (Read/Write)
+    // Bit 5 - Mode 2 OAM Interrupt         (1=Enable) (Read/Write)
+    // Bit 4 - Mode 1 V-Blank Interrupt     (1=Enable) (Read/Write)
+    // Bit 3 - Mode 0 H-Blank Interrupt     (1=Enable) (Read/Write)
+    // Bit 2 - Coincidence Flag  (0:LYC<>LY, 1:LYC=LY) (Read Only)
+    // Bit 1-0 - Mode Flag                             (Read Only)
+    //           0: H-Blank
+    //           1: V-Blank
+    //           2: Searching OAM
+    //           3: Transferring Data to LCD Driver
+    //
+    // Only the interrupt enable bits are stored, the others are computed.
     pub stat: u8,
 
+    // The STAT interrupt is requested on the rising edge of the OR of
+    // all the enabled STAT conditions.
+    stat_line: bool,
+
     // TODO doc LY Compare
     pub lyc: u8,
 
@@ -60,14 +91,21 @@ pub struct Gpu {
     // Object Palette 1
     obp1: u8,
 
-    // TODO doc
-    // internal cycles counter
+    // Window position, kept for the CPU: the window isn't drawn
+    wy: u8,
+    wx: u8,
+
+    // Dots elapsed in the current scanline
     cycles: u16,
+
+    // A complete frame has been rendered to the buffer
+    frame_ready: bool,
 }
 
-pub enum Interrupt {
-    VBlank,
-    Status,
+impl Default for Gpu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Gpu {
@@ -75,7 +113,8 @@ impl Gpu {
         Self {
             vram: [0; 0x2000],
             oam: [0; 0xa0],
-            buffer: [0; BUFFER_SIZE],
+            buffer: vec![0; BUFFER_SIZE].into_boxed_slice(),
+            screen: vec![0; SCREEN_SIZE].into_boxed_slice(),
             ly: 0,
             scy: 0,
             scx: 0,
@@ -85,7 +124,11 @@ impl Gpu {
             bgp: 0,
             obp0: 0,
             obp1: 0,
+            wy: 0,
+            wx: 0,
             stat: 0,
+            stat_line: false,
+            frame_ready: false,
         }
     }
 
@@ -94,7 +137,11 @@ impl Gpu {
             0x8000..=0x9fff => self.vram[(address - 0x8000) as usize],
             0xfe00..=0xfe9f => self.oam[(address - 0xfe00) as usize],
             0xff40 => self.lcdc,
-            0xff41 => self.stat,
+            0xff41 => {
+                let coincidence = if self.ly == self.lyc { 0b100 } else { 0 };
+
+                0b1000_0000 | self.stat & 0b0111_1000 | coincidence | self.mode()
+            }
             0xff42 => self.scy,
             0xff43 => self.scx,
             0xff44 => self.ly,
@@ -103,20 +150,28 @@ impl Gpu {
             0xff47 => self.bgp,
             0xff48 => self.obp0,
             0xff49 => self.obp1,
-            // 0xff4a wy
-            // 0xff4b wx
+            0xff4a => self.wy,
+            0xff4b => self.wx,
             _ => 0,
         }
     }
 
-    pub fn write(&mut self, address: u16, value: u8) {
+    pub const fn write(&mut self, address: u16, value: u8) {
         // println!("(Write to VRAM at {:#06x} ({:#04x}))", address, value);
 
         match address {
             0x8000..=0x9fff => self.vram[(address - 0x8000) as usize] = value,
             0xfe00..=0xfe9f => self.oam[(address - 0xfe00) as usize] = value,
-            0xff40 => self.lcdc = value,
-            0xff41 => self.stat = value,
+            0xff40 => {
+                // Turning the LCD off resets LY and the scanline timing
+                if value & 0b1000_0000 == 0 {
+                    self.ly = 0;
+                    self.cycles = 0;
+                    self.stat_line = false;
+                }
+                self.lcdc = value;
+            }
+            0xff41 => self.stat = value & 0b0111_1000,
             0xff42 => self.scy = value,
             0xff43 => self.scx = value,
             0xff44 => self.ly = value,
@@ -125,59 +180,103 @@ impl Gpu {
             0xff47 => self.bgp = value,
             0xff48 => self.obp0 = value,
             0xff49 => self.obp1 = value,
-            // 0xff4a wy
-            // 0xff4b wx
+            0xff4a => self.wy = value,
+            0xff4b => self.wx = value,
             _ => (),
         }
+    }
 
-        if (0x9800..=0x9fff).contains(&address) {
-            println!("Write to {:#04x}: {:#04x}", address, value);
+    #[must_use]
+    pub const fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    // The last complete frame, 160x144 RGB888 pixels.
+    #[must_use]
+    pub const fn screen(&self) -> &[u8] {
+        &self.screen
+    }
+
+    // Returns true once for every completed frame.
+    pub fn take_frame(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
+    }
+
+    // Current mode, as reported in bits 1-0 of STAT.
+    const fn mode(&self) -> u8 {
+        if self.lcdc & 0b1000_0000 == 0 {
+            0
+        } else if self.ly >= 144 {
+            1
+        } else if self.cycles < OAM_SCAN_DOTS {
+            2
+        } else if self.cycles < OAM_SCAN_DOTS + TRANSFER_DOTS {
+            3
+        } else {
+            0
         }
     }
 
-    pub fn display(
-        &mut self,
-        canvas: &mut Canvas<Window>,
-        texture: &mut Texture,
-        cycles: u16,
-    ) -> Option<Interrupt> {
+    // Advance the PPU by one M-cycle (4 dots).
+    //
+    // Returns the interrupts requested, as IF bits.
+    pub fn tick(&mut self) -> u8 {
         // TODO document
         // XXX is this right?
         // Bit 7 - LCD Display Enable (0=Off, 1=On)
         if self.lcdc & 0b1000_0000 == 0 {
-            return None;
+            return 0;
         }
 
-        self.cycles += cycles;
+        let mut interrupts = 0;
+
+        self.cycles += 4;
+
+        if self.cycles >= DOTS_PER_LINE {
+            self.cycles -= DOTS_PER_LINE;
 
-        // println!("gpu cycles {:#04x} ly {:#04x}", cycles, self.ly);
-        // TODO document
-        // A new scanline every 116 ticks (1MHz clock CPU)
-        if self.cycles >= 116 {
             /* ly range is 0 through 153 (0x99) */
             self.ly = if self.ly == 153 { 0 } else { self.ly + 1 };
 
-            self.cycles = 0;
+            // VBlank
+            if self.ly == 144 {
+                self.render();
+                self.frame_ready = true;
+
+                interrupts |= INT_VBLANK;
+            }
+        }
+
+        let mode = self.mode();
+        let stat_line = (self.stat & 0b100_0000 != 0 && self.ly == self.lyc)
+            || (self.stat & 0b10_0000 != 0 && mode == 2)
+            || (self.stat & 0b1_0000 != 0 && mode == 1)
+            || (self.stat & 0b1000 != 0 && mode == 0);
+
+        if stat_line && !self.stat_line {
+            interrupts |= INT_STAT;
         }
+        self.stat_line = stat_line;
 
-        if self.ly == 0 {
-            let mut tile_x: u8;
-            let mut tile_y: u8;
+        interrupts
+    }
 
-            let tile_map_range = if self.lcdc & 0b1000 == 0 {
-                // BG Map Data 1
-                0x9800..=0x9bff
-            } else {
-                // BG Map Data 2
-                0x9c00..=0x9fff
-            };
+    // Draw the BG map and the sprites to the buffer.
+    fn render(&mut self) {
+        let tile_map: u16 = if self.lcdc & 0b1000 == 0 {
+            // BG Map Data 1
+            0x9800
+        } else {
+            // BG Map Data 2
+            0x9c00
+        };
 
-            for (i, tile_addr) in tile_map_range.enumerate() {
+        // 32x32 tiles
+        for tile_y in 0..32_u8 {
+            for tile_x in 0..32_u8 {
+                let tile_addr = tile_map + u16::from(tile_y) * 32 + u16::from(tile_x);
                 let tile_num = self.read(tile_addr);
 
-                tile_x = (i % 32) as u8;
-                tile_y = (i / 32) as u8;
-
                 self.show_tile(self.get_tile(tile_num), tile_x * 8, tile_y * 8);
             }
         }
@@ -230,31 +329,25 @@ impl Gpu {
             }
         }
 
-        // LYC=LY Coincidence Interrupt enabled
-        // if self.stat & 0b100_0000 != 0 && self.lyc == self.ly {
-        //     return Some(Interrupt::Status);
-        // }
-
-        // VBlank
-        if self.ly == 144 {
-            texture
-                .update(
-                    None,
-                    &self.buffer,
-                    BUFFER_WIDTH as usize * BYTES_PER_PIXEL as usize,
-                )
-                .unwrap();
+        self.update_screen();
+    }
 
-            let scanline_src = Rect::new(0, self.scy as i32, 160, 144);
+    // Copy the 160x144 viewport at SCX, SCY of the buffer to the screen,
+    // wrapping around its edges.
+    fn update_screen(&mut self) {
+        let pixels = self.screen.chunks_exact_mut(3).enumerate();
 
-            canvas.copy(texture, scanline_src, None).unwrap();
+        for (i, pixel) in pixels {
+            let x = (i % SCREEN_WIDTH as usize + self.scx as usize) % BUFFER_WIDTH as usize;
+            let y = (i / SCREEN_WIDTH as usize + self.scy as usize) % BUFFER_HEIGHT as usize;
 
-            canvas.present();
+            let index = (x + y * BUFFER_WIDTH as usize) * BYTES_PER_PIXEL as usize;
 
-            return Some(Interrupt::VBlank);
+            // The buffer is ABGR
+            pixel[0] = self.buffer[index + 3];
+            pixel[1] = self.buffer[index + 2];
+            pixel[2] = self.buffer[index + 1];
         }
-
-        None
     }
 
     fn get_sprite(&self, addr: u16) -> [u8; 16] {
@@ -0,0 +1,33 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+// Save RGB888 pixels as a PNG file.
+pub fn save_png(path: &Path, width: u32, height: u32, pixels: &[u8]) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(pixels))
+        .map_err(io::Error::other)
+}
+
+// The first path of the form DIR/PREFIX-NNNN.EXTENSION not yet taken,
+// counting from 1.
+#[must_use]
+pub fn numbered_path(dir: &Path, prefix: &str, extension: &str) -> PathBuf {
+    let mut n = 1;
+
+    loop {
+        let path = dir.join(format!("{prefix}-{n:04}.{extension}"));
+        if !path.exists() {
+            return path;
+        }
+
+        n += 1;
+    }
+}
@@ -1,7 +1,16 @@
-use sdl2::event::Event;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
-use sdl2::EventPump;
+use sdl2::GameControllerSubsystem;
+
+use gameperson::joypad::JoypadState;
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum JoypadButton {
     Up,
     Down,
@@ -13,45 +22,343 @@ pub enum JoypadButton {
     Start,
 }
 
-pub enum Input {
-    JoypadPress(JoypadButton),
-    JoypadRelease(JoypadButton),
+impl JoypadButton {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "up" => Some(Self::Up),
+            "down" => Some(Self::Down),
+            "left" => Some(Self::Left),
+            "right" => Some(Self::Right),
+            "a" => Some(Self::A),
+            "b" => Some(Self::B),
+            "select" => Some(Self::Select),
+            "start" => Some(Self::Start),
+            _ => None,
+        }
+    }
+
+    const fn bits(self) -> u8 {
+        match self {
+            Self::Up => JoypadState::UP,
+            Self::Down => JoypadState::DOWN,
+            Self::Left => JoypadState::LEFT,
+            Self::Right => JoypadState::RIGHT,
+            Self::A => JoypadState::A,
+            Self::B => JoypadState::B,
+            Self::Select => JoypadState::SELECT,
+            Self::Start => JoypadState::START,
+        }
+    }
 }
 
-impl Input {
-    pub fn new(event_pump: &mut EventPump) -> Option<Self> {
-        match event_pump.poll_event() {
-            Some(Event::KeyDown {
-                keycode: Some(keycode),
-                ..
-            }) => match keycode {
-                Keycode::Up => Some(Self::JoypadPress(JoypadButton::Up)),
-                Keycode::Down => Some(Self::JoypadPress(JoypadButton::Down)),
-                Keycode::Left => Some(Self::JoypadPress(JoypadButton::Left)),
-                Keycode::Right => Some(Self::JoypadPress(JoypadButton::Right)),
-                Keycode::LCtrl => Some(Self::JoypadPress(JoypadButton::A)),
-                Keycode::LAlt => Some(Self::JoypadPress(JoypadButton::B)),
-                Keycode::Return => Some(Self::JoypadPress(JoypadButton::Start)),
-                Keycode::RShift => Some(Self::JoypadPress(JoypadButton::Select)),
-                _ => None,
+// Something on the keyboard or a game controller that can be bound to a
+// button.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum Binding {
+    Key(Keycode),
+    Button(Button),
+    // Analog stick pushed past the threshold, in the positive direction
+    // (right/down) or not
+    Axis(Axis, bool),
+}
+
+// How far an analog stick must be pushed to count as a D-pad press
+const AXIS_THRESHOLD: i16 = 16384;
+
+impl Binding {
+    // Keys use SDL key names ("Left Ctrl", "Return", "Z"), game controller
+    // buttons and sticks the names of SDL controller mappings, prefixed with
+    // "pad:" ("pad:a", "pad:dpup", "pad:-leftx", "pad:+lefty").
+    fn parse(name: &str) -> Option<Self> {
+        match name.split_once(':') {
+            Some(("pad", pad)) => match pad.as_bytes().first() {
+                Some(b'+') => Axis::from_string(&pad[1..]).map(|axis| Self::Axis(axis, true)),
+                Some(b'-') => Axis::from_string(&pad[1..]).map(|axis| Self::Axis(axis, false)),
+                _ => Button::from_string(pad).map(Self::Button),
             },
+            _ => Keycode::from_name(name).map(Self::Key),
+        }
+    }
+}
+
+// Keys taken by the hotkeys, that can't be bound to buttons
+pub const HOTKEYS: [Keycode; 12] = [
+    Keycode::Tab,
+    Keycode::F2,
+    Keycode::F3,
+    Keycode::P,
+    Keycode::Period,
+    Keycode::B,
+    Keycode::F4,
+    Keycode::F5,
+    Keycode::F9,
+    Keycode::F11,
+    Keycode::F12,
+    Keycode::Escape,
+];
+
+// A button pressed by a binding, repeatedly if turbo
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Press {
+    button: JoypadButton,
+    turbo: bool,
+}
+
+// Which keys and game controller inputs press which button
+pub struct Bindings {
+    presses: HashMap<Binding, Press>,
+
+    // Frames turbo buttons stay pressed, and then released
+    turbo_frames: u64,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let bindings = [
+            (Binding::Key(Keycode::Up), JoypadButton::Up),
+            (Binding::Key(Keycode::Down), JoypadButton::Down),
+            (Binding::Key(Keycode::Left), JoypadButton::Left),
+            (Binding::Key(Keycode::Right), JoypadButton::Right),
+            (Binding::Key(Keycode::LCtrl), JoypadButton::A),
+            (Binding::Key(Keycode::LAlt), JoypadButton::B),
+            (Binding::Key(Keycode::Return), JoypadButton::Start),
+            (Binding::Key(Keycode::RShift), JoypadButton::Select),
+            (Binding::Button(Button::DPadUp), JoypadButton::Up),
+            (Binding::Button(Button::DPadDown), JoypadButton::Down),
+            (Binding::Button(Button::DPadLeft), JoypadButton::Left),
+            (Binding::Button(Button::DPadRight), JoypadButton::Right),
+            (Binding::Axis(Axis::LeftY, false), JoypadButton::Up),
+            (Binding::Axis(Axis::LeftY, true), JoypadButton::Down),
+            (Binding::Axis(Axis::LeftX, false), JoypadButton::Left),
+            (Binding::Axis(Axis::LeftX, true), JoypadButton::Right),
+            // Same layout as the Game Boy, not the same names
+            (Binding::Button(Button::B), JoypadButton::A),
+            (Binding::Button(Button::A), JoypadButton::B),
+            (Binding::Button(Button::Start), JoypadButton::Start),
+            (Binding::Button(Button::Back), JoypadButton::Select),
+        ];
+        let turbo = [
+            (Binding::Key(Keycode::S), JoypadButton::A),
+            (Binding::Key(Keycode::A), JoypadButton::B),
+            (Binding::Button(Button::Y), JoypadButton::A),
+            (Binding::Button(Button::X), JoypadButton::B),
+        ];
+
+        let presses = bindings
+            .iter()
+            .map(|&(binding, button)| {
+                (
+                    binding,
+                    Press {
+                        button,
+                        turbo: false,
+                    },
+                )
+            })
+            .chain(turbo.iter().map(|&(binding, button)| {
+                (
+                    binding,
+                    Press {
+                        button,
+                        turbo: true,
+                    },
+                )
+            }))
+            .collect();
+
+        Self {
+            presses,
+            turbo_frames: 2,
+        }
+    }
+}
+
+impl Bindings {
+    // Read bindings from a file with a line per button, listing what
+    // presses it, with a turbo- prefix for buttons pressed repeatedly while
+    // held down:
+    //
+    //   # Comment
+    //   a = Z, Left Ctrl, pad:b
+    //   up = Up, K, pad:dpup, pad:-lefty
+    //   turbo-a = S, pad:y
+    //   # Frames turbo buttons stay pressed, then released
+    //   turbo = 2
+    //
+    // Buttons not listed keep their default bindings.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut bindings = Self::default();
+        let error = |line: usize, message: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: {message}", path.display(), line + 1),
+            )
+        };
+
+        for (line, text) in fs::read_to_string(path)?.lines().enumerate() {
+            let text = text.split('#').next().unwrap_or_default().trim();
+
+            if text.is_empty() {
+                continue;
+            }
+
+            let (name, value) = text
+                .split_once('=')
+                .ok_or_else(|| error(line, "expected BUTTON = KEY, ...".to_string()))?;
+            let (name, value) = (name.trim(), value.trim());
+
+            if name == "turbo" {
+                bindings.turbo_frames = value
+                    .parse()
+                    .ok()
+                    .filter(|&frames| frames > 0)
+                    .ok_or_else(|| error(line, format!("invalid turbo frames {value}")))?;
+                continue;
+            }
+
+            let (button, turbo) = name
+                .strip_prefix("turbo-")
+                .map_or((name, false), |button| (button, true));
+            let press = Press {
+                button: JoypadButton::from_name(button)
+                    .ok_or_else(|| error(line, format!("unknown button {name}")))?,
+                turbo,
+            };
 
-            Some(Event::KeyUp{
+            bindings.presses.retain(|_, bound| *bound != press);
+
+            for key in value
+                .split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+            {
+                let binding =
+                    Binding::parse(key).ok_or_else(|| error(line, format!("unknown key {key}")))?;
+
+                if matches!(binding, Binding::Key(keycode) if HOTKEYS.contains(&keycode)) {
+                    return Err(error(line, format!("{key} is a hotkey")));
+                }
+
+                bindings.presses.insert(binding, press);
+            }
+        }
+
+        Ok(bindings)
+    }
+}
+
+// Source of the inputs held down that isn't a game controller
+const KEYBOARD: i32 = -1;
+
+// The input layer: keeps track of the keys and game controller inputs held
+// down, as told by all the events of a frame, to tell the buttons pressed
+// when the next one starts.
+//
+// A button stays pressed as long as anything bound to it is held down.
+pub struct Controls {
+    bindings: Bindings,
+
+    subsystem: GameControllerSubsystem,
+    // Game controllers plugged in, by joystick id
+    controllers: HashMap<i32, GameController>,
+
+    // Inputs held down, with the keyboard or controller they come from
+    held: HashSet<(i32, Binding)>,
+    // Buttons pressed since the last state(), so that they're seen by the
+    // game even if released within the same frame
+    tapped: u8,
+}
+
+impl Controls {
+    // Controllers already plugged in when the subsystem is initialized
+    // are reported with ControllerDeviceAdded events too.
+    pub fn new(bindings: Bindings, subsystem: GameControllerSubsystem) -> Self {
+        Self {
+            bindings,
+            subsystem,
+            controllers: HashMap::new(),
+            held: HashSet::new(),
+            tapped: 0,
+        }
+    }
+
+    pub fn handle(&mut self, event: &Event) {
+        match *event {
+            Event::KeyDown {
                 keycode: Some(keycode),
+                repeat: false,
                 ..
-            }) => match keycode {
-                Keycode::Up => Some(Self::JoypadRelease(JoypadButton::Up)),
-                Keycode::Down => Some(Self::JoypadRelease(JoypadButton::Down)),
-                Keycode::Left => Some(Self::JoypadRelease(JoypadButton::Left)),
-                Keycode::Right => Some(Self::JoypadRelease(JoypadButton::Right)),
-                Keycode::LCtrl => Some(Self::JoypadRelease(JoypadButton::A)),
-                Keycode::LAlt => Some(Self::JoypadRelease(JoypadButton::B)),
-                Keycode::Return => Some(Self::JoypadRelease(JoypadButton::Start)),
-                Keycode::RShift => Some(Self::JoypadRelease(JoypadButton::Select)),
-                _ => None,
+            } => self.hold(KEYBOARD, Binding::Key(keycode), true),
+            Event::KeyUp {
+                keycode: Some(keycode),
+                ..
+            } => self.hold(KEYBOARD, Binding::Key(keycode), false),
+            // Key releases are lost without focus
+            Event::Window {
+                win_event: WindowEvent::FocusLost,
+                ..
+            } => self.held.retain(|&(source, _)| source != KEYBOARD),
+            Event::ControllerButtonDown { which, button, .. } => {
+                self.hold(which, Binding::Button(button), true);
+            }
+            Event::ControllerButtonUp { which, button, .. } => {
+                self.hold(which, Binding::Button(button), false);
             }
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } => {
+                self.hold(which, Binding::Axis(axis, true), value > AXIS_THRESHOLD);
+                self.hold(which, Binding::Axis(axis, false), value < -AXIS_THRESHOLD);
+            }
+            Event::ControllerDeviceAdded { which, .. } => match self.subsystem.open(which) {
+                Ok(controller) => {
+                    println!("Game controller connected: {}", controller.name());
+                    self.controllers
+                        .insert(controller.instance_id(), controller);
+                }
+                Err(e) => eprintln!("Can't open game controller: {e}"),
+            },
+            Event::ControllerDeviceRemoved { which, .. } => {
+                if let Some(controller) = self.controllers.remove(&which) {
+                    println!("Game controller disconnected: {}", controller.name());
+                }
 
-            _ => None,
+                self.held.retain(|&(source, _)| source != which);
+            }
+            _ => (),
         }
     }
+
+    fn hold(&mut self, source: i32, binding: Binding, down: bool) {
+        if !down {
+            self.held.remove(&(source, binding));
+        } else if self.held.insert((source, binding)) {
+            if let Some(press) = self.bindings.presses.get(&binding) {
+                if !press.turbo {
+                    self.tapped |= press.button.bits();
+                }
+            }
+        }
+    }
+
+    // Release the keys held down, e.g. the Alt of Alt+Enter, along with
+    // the buttons pressed since the last state()
+    pub fn release_keys(&mut self) {
+        self.held.retain(|&(source, _)| source != KEYBOARD);
+        self.tapped = 0;
+    }
+
+    // Buttons pressed in frame, turbo ones alternating every few frames
+    pub fn state(&mut self, frame: u64) -> JoypadState {
+        let turbo_on = (frame / self.bindings.turbo_frames) & 1 == 0;
+
+        let held = self
+            .held
+            .iter()
+            .filter_map(|(_, binding)| self.bindings.presses.get(binding))
+            .filter(|press| !press.turbo || turbo_on)
+            .fold(0, |bits, press| bits | press.button.bits());
+
+        JoypadState(held | std::mem::take(&mut self.tapped))
+    }
 }
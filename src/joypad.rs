@@ -0,0 +1,210 @@
+// P1/JOYP - Joypad (0xff00)
+//
+// The eight Game Boy action/direction buttons are arranged as a 2x4 matrix.
+// Select either action or direction buttons by writing to this register,
+// then read out the bits 0-3. With both groups selected the lines read
+// as pressed if a button of either group is.
+//
+// Bit 7 - Not used
+// Bit 6 - Not used
+// Bit 5 - P15 Select Action buttons    (0=Select)
+// Bit 4 - P14 Select Direction buttons (0=Select)
+// Bit 3 - P13 Input: Down  or Start    (0=Pressed) (Read Only)
+// Bit 2 - P12 Input: Up    or Select   (0=Pressed) (Read Only)
+// Bit 1 - P11 Input: Left  or B        (0=Pressed) (Read Only)
+// Bit 0 - P10 Input: Right or A        (0=Pressed) (Read Only)
+//
+// A Joypad interrupt is requested when any of the lines goes from high
+// to low, pressing a button or selecting a group with buttons held.
+// A low line also wakes the CPU up from STOP.
+
+// Buttons pressed in the frame (1=Pressed):
+// Bit 7 - Start
+// Bit 6 - Select
+// Bit 5 - B
+// Bit 4 - A
+// Bit 3 - Down
+// Bit 2 - Up
+// Bit 1 - Left
+// Bit 0 - Right
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct JoypadState(pub u8);
+
+impl JoypadState {
+    pub const RIGHT: u8 = 0b0000_0001;
+    pub const LEFT: u8 = 0b0000_0010;
+    pub const UP: u8 = 0b0000_0100;
+    pub const DOWN: u8 = 0b0000_1000;
+    pub const A: u8 = 0b0001_0000;
+    pub const B: u8 = 0b0010_0000;
+    pub const SELECT: u8 = 0b0100_0000;
+    pub const START: u8 = 0b1000_0000;
+
+    // Bits 3-0 of P1 for the action buttons
+    const fn action(self) -> u8 {
+        self.0 >> 4
+    }
+
+    // Bits 3-0 of P1 for the direction buttons
+    const fn direction(self) -> u8 {
+        self.0 & 0b1111
+    }
+}
+
+pub struct Joypad {
+    // Bits 5-4
+    select: u8,
+
+    buttons: JoypadState,
+
+    // Bits 3-0 when last ticked
+    lines: u8,
+}
+
+const SELECT_ACTION: u8 = 0b0010_0000;
+const SELECT_DIRECTION: u8 = 0b0001_0000;
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Joypad {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            select: SELECT_ACTION | SELECT_DIRECTION,
+            buttons: JoypadState(0),
+            lines: 0b1111,
+        }
+    }
+
+    #[must_use]
+    pub const fn read(&self) -> u8 {
+        0b1100_0000 | self.select | self.lines()
+    }
+
+    pub const fn write(&mut self, value: u8) {
+        self.select = value & (SELECT_ACTION | SELECT_DIRECTION);
+    }
+
+    #[must_use]
+    pub const fn buttons(&self) -> JoypadState {
+        self.buttons
+    }
+
+    pub const fn set_buttons(&mut self, buttons: JoypadState) {
+        self.buttons = buttons;
+    }
+
+    // Bits 3-0 as read by the CPU
+    const fn lines(&self) -> u8 {
+        let mut pressed = 0;
+
+        if self.select & SELECT_ACTION == 0 {
+            pressed |= self.buttons.action();
+        }
+        if self.select & SELECT_DIRECTION == 0 {
+            pressed |= self.buttons.direction();
+        }
+
+        !pressed & 0b1111
+    }
+
+    // Advance by one M-cycle.
+    //
+    // Returns true if a Joypad interrupt is requested.
+    pub const fn tick(&mut self) -> bool {
+        let lines = self.lines();
+        let falling = self.lines & !lines != 0;
+
+        self.lines = lines;
+
+        falling
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACTION: u8 = 0b0001_0000;
+    const DIRECTION: u8 = 0b0010_0000;
+    const NONE: u8 = 0b0011_0000;
+
+    fn pressed(buttons: u8) -> Joypad {
+        let mut joypad = Joypad::new();
+        joypad.set_buttons(JoypadState(buttons));
+        joypad.tick();
+
+        joypad
+    }
+
+    #[test]
+    fn select() {
+        let mut joypad = pressed(JoypadState::START | JoypadState::A | JoypadState::LEFT);
+
+        joypad.write(NONE);
+        assert_eq!(joypad.read(), 0b1111_1111);
+
+        joypad.write(ACTION);
+        assert_eq!(joypad.read(), 0b1101_0110);
+
+        joypad.write(DIRECTION);
+        assert_eq!(joypad.read(), 0b1110_1101);
+
+        // Either group pulls a line low
+        joypad.write(0);
+        assert_eq!(joypad.read(), 0b1100_0100);
+
+        // Only bits 5-4 are written
+        joypad.write(0b1100_1111);
+        assert_eq!(joypad.read(), 0b1100_0100);
+    }
+
+    #[test]
+    fn press_interrupt() {
+        let mut joypad = pressed(0);
+        joypad.write(ACTION);
+        assert!(!joypad.tick());
+
+        // A line going low, once
+        joypad.set_buttons(JoypadState(JoypadState::B));
+        assert!(joypad.tick());
+        assert!(!joypad.tick());
+
+        // Another line going low, with one already low
+        joypad.set_buttons(JoypadState(JoypadState::B | JoypadState::SELECT));
+        assert!(joypad.tick());
+
+        // Releasing isn't a falling edge
+        joypad.set_buttons(JoypadState(0));
+        assert!(!joypad.tick());
+    }
+
+    #[test]
+    fn unselected_press() {
+        let mut joypad = pressed(0);
+        joypad.write(ACTION);
+        joypad.tick();
+
+        joypad.set_buttons(JoypadState(JoypadState::UP));
+        assert!(!joypad.tick());
+    }
+
+    #[test]
+    fn select_interrupt() {
+        let mut joypad = pressed(JoypadState::DOWN);
+        joypad.write(ACTION);
+        assert!(!joypad.tick());
+
+        // Selecting a group with a button held
+        joypad.write(DIRECTION);
+        assert!(joypad.tick());
+        assert!(!joypad.tick());
+
+        joypad.write(NONE);
+        assert!(!joypad.tick());
+    }
+}
@@ -0,0 +1,31 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery, clippy::cargo)]
+// Comments are not rustdoc
+#![allow(clippy::missing_errors_doc)]
+
+// The emulation core, without any frontend so it can run headless
+// (e.g. in tests).
+
+pub mod callstack;
+pub mod cartridge;
+pub mod cpu;
+pub mod debugger;
+pub mod disassembler;
+pub mod effects;
+pub mod expression;
+pub mod gameboy;
+pub mod gdb;
+pub mod gpu;
+pub mod image;
+pub mod joypad;
+pub mod link;
+pub mod memory;
+pub mod movie;
+pub mod opcodes;
+pub mod printer;
+pub mod serial;
+#[cfg(test)]
+mod testing;
+pub mod timer;
+pub mod trace;
+pub mod video;
+pub mod watchpoint;
@@ -0,0 +1,474 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::serial::LinkPartner;
+
+// Link cable between two emulators, over a TCP or Unix socket.
+//
+// Both ends run freely, but never more than WINDOW M-cycles apart: every
+// SYNC_INTERVAL M-cycles each end tells the other where it is, and waits
+// for it if it got too far ahead.
+//
+// Waiting for the other end is bounded by TIMEOUT: when it doesn't answer
+// in time (e.g. it's paused in the debugger) this end goes on without it,
+// so that the frame ends and the UI keeps responding, until it hears from
+// it again. A transfer timing out receives 0xff.
+//
+// The end driving the clock (internal clock) sends the byte along with
+// the M-cycle of the transfer, then waits for the reply. The other end
+// gets the byte when it reaches that M-cycle (or right away if it's
+// already past it) and replies with SB, or 0xff if it wasn't waiting for
+// a transfer with the external clock.
+//
+// Messages are 10 bytes: a tag, a little endian u64 M-cycle and a byte.
+//   'S' cycle 0    - Sync, the sender reached cycle
+//   'T' cycle byte - Transfer of byte started at cycle
+//   'R' 0     byte - Reply to a transfer
+pub struct LinkCable {
+    writer: Box<dyn Write + Send>,
+    messages: Receiver<Message>,
+
+    // M-cycles elapsed
+    cycles: u64,
+    // M-cycle the other end is known to have reached
+    remote_cycles: u64,
+
+    // Transfer started by the other end: M-cycle and byte
+    transfer: Option<(u64, u8)>,
+
+    connected: bool,
+
+    // The other end didn't answer in time, it's not waited for until it
+    // sends something again
+    stalled: bool,
+    // Replies to transfers that timed out, ignored when they arrive
+    late_replies: u32,
+
+    // Socket file listened on, removed on drop
+    socket: Option<PathBuf>,
+}
+
+// M-cycles between Sync messages
+const SYNC_INTERVAL: u64 = 1024;
+
+// Maximum distance between the two ends, in M-cycles (about 8ms)
+const WINDOW: u64 = 8 * SYNC_INTERVAL;
+
+const MESSAGE_SIZE: usize = 10;
+
+// Longest wait for the other end before going on without it
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug)]
+enum Message {
+    Sync(u64),
+    Transfer(u64, u8),
+    Reply(u8),
+}
+
+impl Message {
+    fn encode(self) -> [u8; MESSAGE_SIZE] {
+        let (tag, cycles, byte) = match self {
+            Self::Sync(cycles) => (b'S', cycles, 0),
+            Self::Transfer(cycles, byte) => (b'T', cycles, byte),
+            Self::Reply(byte) => (b'R', 0, byte),
+        };
+
+        let mut message = [0; MESSAGE_SIZE];
+        message[0] = tag;
+        message[1..9].copy_from_slice(&cycles.to_le_bytes());
+        message[9] = byte;
+
+        message
+    }
+
+    fn decode(message: [u8; MESSAGE_SIZE]) -> Option<Self> {
+        let mut cycles = [0; 8];
+        cycles.copy_from_slice(&message[1..9]);
+        let cycles = u64::from_le_bytes(cycles);
+
+        match message[0] {
+            b'S' => Some(Self::Sync(cycles)),
+            b'T' => Some(Self::Transfer(cycles, message[9])),
+            b'R' => Some(Self::Reply(message[9])),
+            _ => None,
+        }
+    }
+}
+
+impl LinkCable {
+    // Messages are read by a thread, so that they can be polled without
+    // blocking.
+    pub fn new<R, W>(mut reader: R, writer: W) -> Self
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        let (sender, messages) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut message = [0; MESSAGE_SIZE];
+
+            while reader.read_exact(&mut message).is_ok() {
+                match Message::decode(message) {
+                    Some(message) if sender.send(message).is_ok() => (),
+                    _ => break,
+                }
+            }
+        });
+
+        Self::with_messages(Box::new(writer), messages)
+    }
+
+    fn with_messages(writer: Box<dyn Write + Send>, messages: Receiver<Message>) -> Self {
+        Self {
+            writer,
+            messages,
+            cycles: 0,
+            remote_cycles: 0,
+            transfer: None,
+            connected: true,
+            stalled: false,
+            late_replies: 0,
+            socket: None,
+        }
+    }
+
+    // Wait for the other end to connect to address.
+    pub fn listen_tcp<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        println!("Waiting for link cable on {}", listener.local_addr()?);
+
+        let (stream, peer) = listener.accept()?;
+        println!("Link cable connected to {peer}");
+
+        Self::from_tcp(stream)
+    }
+
+    pub fn connect_tcp<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        Self::from_tcp(TcpStream::connect(address)?)
+    }
+
+    fn from_tcp(stream: TcpStream) -> io::Result<Self> {
+        // Messages are tiny and latency matters
+        stream.set_nodelay(true)?;
+
+        Ok(Self::new(stream.try_clone()?, stream))
+    }
+
+    // Wait for the other end to connect to the socket at path.
+    #[cfg(unix)]
+    pub fn listen_unix<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+
+        // A socket left behind by an emulator that didn't exit cleanly,
+        // nobody listens on it anymore
+        let stale = fs::symlink_metadata(path)
+            .is_ok_and(|metadata| metadata.file_type().is_socket())
+            && UnixStream::connect(path).is_err();
+        if stale {
+            fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+        println!("Waiting for link cable on {}", path.display());
+
+        let accepted = listener.accept();
+        let stream = match accepted {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                let _ = fs::remove_file(path);
+                return Err(e);
+            }
+        };
+        println!("Link cable connected");
+
+        let mut cable = Self::new(stream.try_clone()?, stream);
+        cable.socket = Some(path.to_path_buf());
+
+        Ok(cable)
+    }
+
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let stream = UnixStream::connect(path)?;
+
+        Ok(Self::new(stream.try_clone()?, stream))
+    }
+
+    fn disconnect(&mut self) {
+        if self.connected {
+            eprintln!("Link cable disconnected");
+        }
+
+        self.connected = false;
+        self.transfer = None;
+    }
+
+    fn send(&mut self, message: Message) {
+        if !self.connected {
+            return;
+        }
+
+        if self.writer.write_all(&message.encode()).is_err() {
+            self.disconnect();
+        }
+    }
+
+    // Returns the byte of a Reply.
+    const fn handle(&mut self, message: Message) -> Option<u8> {
+        self.stalled = false;
+
+        match message {
+            Message::Sync(cycles) => self.remote_cycles = cycles,
+            Message::Transfer(cycles, byte) => {
+                self.remote_cycles = cycles;
+                self.transfer = Some((cycles, byte));
+            }
+            Message::Reply(_) if self.late_replies > 0 => self.late_replies -= 1,
+            Message::Reply(byte) => return Some(byte),
+        }
+
+        None
+    }
+
+    // Wait for the next message until deadline and handle it.
+    fn wait(&mut self, deadline: Instant) -> Option<u8> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+
+        match self.messages.recv_timeout(timeout) {
+            Ok(message) => self.handle(message),
+            Err(RecvTimeoutError::Timeout) => {
+                self.stalled = true;
+                None
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                self.disconnect();
+                None
+            }
+        }
+    }
+
+    // Handle the messages received so far, without blocking.
+    fn poll(&mut self) {
+        loop {
+            match self.messages.try_recv() {
+                Ok(message) => {
+                    self.handle(message);
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.disconnect();
+                    break;
+                }
+            }
+        }
+    }
+
+    // The other end started a transfer, and we reached its M-cycle
+    const fn transfer_due(&self) -> bool {
+        matches!(self.transfer, Some((cycles, _)) if cycles <= self.cycles)
+    }
+
+    // Complete the transfer started by the other end, replying with
+    // outgoing if we were waiting for it, 0xff otherwise.
+    //
+    // Returns the byte received if we were waiting for it.
+    fn deliver(&mut self, outgoing: Option<u8>) -> Option<u8> {
+        let (_, byte) = self.transfer.take()?;
+        self.send(Message::Reply(outgoing.unwrap_or(0xff)));
+
+        outgoing.map(|_| byte)
+    }
+}
+
+impl LinkPartner for LinkCable {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        self.send(Message::Transfer(self.cycles, byte));
+
+        let deadline = Instant::now() + TIMEOUT;
+        while self.connected && !self.stalled {
+            if let Some(reply) = self.wait(deadline) {
+                return reply;
+            }
+
+            // Both ends started a transfer with the internal clock
+            // and are waiting for each other.
+            if self.transfer.take().is_some() {
+                self.send(Message::Reply(0xff));
+            }
+        }
+
+        if self.connected {
+            self.late_replies += 1;
+        }
+
+        0xff
+    }
+
+    fn tick(&mut self, outgoing: Option<u8>) -> Option<u8> {
+        self.cycles += 1;
+
+        if !self.connected {
+            return None;
+        }
+
+        self.poll();
+
+        if self.transfer_due() {
+            return self.deliver(outgoing);
+        }
+
+        // SYNC_INTERVAL is a power of 2
+        if self.cycles & (SYNC_INTERVAL - 1) == 0 {
+            self.send(Message::Sync(self.cycles));
+
+            // Don't get too far ahead of the other end
+            let deadline = Instant::now() + TIMEOUT;
+            while self.connected && !self.stalled && self.cycles > self.remote_cycles + WINDOW {
+                self.wait(deadline);
+
+                if self.transfer_due() {
+                    return self.deliver(outgoing);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl Drop for LinkCable {
+    // Free the path of the socket listened on for the next run
+    fn drop(&mut self) {
+        if let Some(path) = &self.socket {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::process;
+    use std::sync::mpsc::Sender;
+
+    // A cable and the other end of its socket
+    fn cable() -> (LinkCable, UnixStream) {
+        let (stream, other) = UnixStream::pair().unwrap();
+
+        (LinkCable::new(stream.try_clone().unwrap(), stream), other)
+    }
+
+    fn read_message(stream: &mut UnixStream) -> Message {
+        let mut message = [0; MESSAGE_SIZE];
+        stream.read_exact(&mut message).unwrap();
+
+        Message::decode(message).unwrap()
+    }
+
+    #[test]
+    fn exchange() {
+        let (mut cable, mut other) = cable();
+
+        let reply = thread::spawn(move || {
+            let message = read_message(&mut other);
+            other.write_all(&Message::Reply(0x42).encode()).unwrap();
+
+            message
+        });
+
+        assert_eq!(cable.exchange(0x12), 0x42);
+        assert!(matches!(reply.join().unwrap(), Message::Transfer(0, 0x12)));
+    }
+
+    // A cable receiving the messages sent on the channel, to know what it
+    // has received when
+    fn detached() -> (LinkCable, Sender<Message>) {
+        let (sender, messages) = mpsc::channel();
+
+        (
+            LinkCable::with_messages(Box::new(io::sink()), messages),
+            sender,
+        )
+    }
+
+    #[test]
+    fn exchange_timeout() {
+        let (mut cable, sender) = detached();
+
+        assert_eq!(cable.exchange(0x12), 0xff);
+        assert!(cable.stalled);
+
+        // Not waited for again until it answers: the reply is left for later
+        sender.send(Message::Reply(0x01)).unwrap();
+        assert_eq!(cable.exchange(0x34), 0xff);
+        assert_eq!(cable.late_replies, 2);
+
+        // The other end comes back
+        cable.tick(None);
+        assert!(!cable.stalled);
+
+        // The late replies aren't taken for the next one's
+        sender.send(Message::Reply(0x02)).unwrap();
+        sender.send(Message::Reply(0x03)).unwrap();
+        assert_eq!(cable.exchange(0x56), 0x03);
+        assert_eq!(cable.late_replies, 0);
+    }
+
+    #[test]
+    fn window_timeout() {
+        let (mut cable, _sender) = detached();
+
+        // Up to the first sync past the window without the other end syncing
+        while cable.cycles < WINDOW + SYNC_INTERVAL - 1 {
+            cable.tick(None);
+        }
+        assert!(!cable.stalled);
+
+        cable.tick(None);
+        assert!(cable.stalled);
+
+        // Going on without it until it sends something
+        for _ in 0..4 * WINDOW {
+            cable.tick(None);
+        }
+        assert!(cable.stalled);
+        assert_eq!(cable.remote_cycles, 0);
+    }
+
+    #[test]
+    fn listen_unix() {
+        let path = env::temp_dir().join(format!("gameperson-link-{}", process::id()));
+
+        // Left behind by a previous run
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let connect_path = path.clone();
+        let other = thread::spawn(move || {
+            while UnixStream::connect(&connect_path).is_err() {
+                thread::yield_now();
+            }
+        });
+
+        let cable = LinkCable::listen_unix(&path).unwrap();
+        other.join().unwrap();
+
+        drop(cable);
+        assert!(!path.exists());
+    }
+}
@@ -1,86 +1,430 @@
 #![warn(clippy::all, clippy::pedantic, clippy::nursery, clippy::cargo)]
 
-mod cartridge;
-mod cpu;
-mod gpu;
+mod display;
 mod input;
-mod memory;
-
-use std::cell::RefCell;
-use std::io::stdin;
+mod pacing;
 
 use std::error;
-use std::fs;
-use std::rc::Rc;
-
-use clap::Parser;
-
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use std::fs::{self, File};
+use std::io::{self, stdin, stdout};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use clap::{ArgGroup, Parser, Subcommand};
+
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
+
+use gameperson::cartridge::Cartridge;
+use gameperson::debugger::{self, Action, Debugger};
+use gameperson::disassembler::{self, Symbols};
+use gameperson::effects::{Effects, Frame, Scaler};
+use gameperson::gameboy::GameBoy;
+use gameperson::gdb::GdbStub;
+use gameperson::image;
+use gameperson::link::LinkCable;
+use gameperson::movie::{Header, Player, Recorder};
+use gameperson::printer::Printer;
+use gameperson::serial::{self, LinkPartner};
+use gameperson::trace::Trace;
+use gameperson::video::VideoRecorder;
+
+use display::{Display, Filter};
+use input::{Bindings, Controls};
+use pacing::{Meter, Pacer};
+
+// Stopped in the debugger, reading commands from the terminal, or from
+// GDB while it's connected
+fn debug(
+    debugger: &mut Debugger,
+    gameboy: &mut GameBoy,
+    gdb: &mut Option<GdbStub>,
+) -> io::Result<Action> {
+    match gdb {
+        Some(stub) if stub.is_connected() => stub.serve(debugger, gameboy),
+        _ => debugger.repl(gameboy, &mut stdin().lock(), &mut stdout()),
+    }
+}
 
-use sdl2::pixels::PixelFormatEnum;
+// Let GDB connect or interrupt the emulation while it runs
+fn poll_gdb(
+    debugger: &mut Debugger,
+    gameboy: &GameBoy,
+    gdb: &mut Option<GdbStub>,
+) -> io::Result<()> {
+    gdb.as_mut()
+        .map_or(Ok(()), |stub| stub.poll(debugger, gameboy))
+}
 
-use cartridge::Cartridge;
-use memory::Rom;
+fn parse_speed(text: &str) -> Result<f64, String> {
+    text.parse()
+        .ok()
+        .filter(|speed: &f64| speed.is_finite())
+        .ok_or_else(|| format!("Invalid number {text}"))
+}
 
-use input::{Input, JoypadButton};
+// 0 for as fast as possible
+fn parse_fast_forward(text: &str) -> Result<f64, String> {
+    Some(parse_speed(text)?)
+        .filter(|&speed| speed >= 0.0)
+        .ok_or_else(|| format!("{text} is negative"))
+}
 
-fn debug(cpu: &mut cpu::Cpu, mem: &memory::Memory) -> (u16, bool) {
-    println!("b HEX - run until - HEX = 0 to reset");
-    println!("p HEX - dump memory address");
-    println!("j HEX - dump memory address");
-    println!("n - next instruction");
-    println!("c - continue");
+fn parse_slow_motion(text: &str) -> Result<f64, String> {
+    Some(parse_speed(text)?)
+        .filter(|&speed| speed > 0.0)
+        .ok_or_else(|| format!("{text} isn't more than 0"))
+}
 
-    loop {
-        println!();
-        println!("{}", cpu);
-
-        let mut s = String::new();
-
-        stdin()
-            .read_line(&mut s)
-            .expect("Did not enter a correct string");
-        let v: Vec<&str> = s.split_whitespace().collect();
-
-        let command = v[0];
-
-        if v.len() == 2 {
-            if let Ok(addr) = u16::from_str_radix(v[1], 16) {
-                if command == "b" {
-                    return (addr, false);
-                } else if command == "j" {
-                    cpu.pc = addr;
-                    return (0, false);
-                } else if command == "p" {
-                    println!("{:#04x}", mem.load(addr as usize));
-                } else {
-                    eprintln!("Invalid command");
-                }
-            }
-        } else {
-            if command == "c" {
-                return (0, false);
-            } else if command == "n" {
-                return (0, true);
-            }
-            eprintln!("Invalid command");
+// The link partner described by spec:
+//   none              - nothing connected
+//   file:PATH         - write the bytes sent to the file at PATH
+//   tcp-listen:ADDR   - link cable, waiting for the other end on ADDR
+//   tcp:ADDR          - link cable, connecting to the other end at ADDR
+//   unix-listen:PATH  - link cable, waiting for the other end on PATH
+//   unix:PATH         - link cable, connecting to the other end at PATH
+//   printer:DIR       - Game Boy Printer, saving the prints to DIR
+fn link_partner(spec: &str) -> io::Result<Box<dyn LinkPartner>> {
+    let (kind, arg) = spec.split_once(':').unwrap_or((spec, ""));
+
+    match kind {
+        "none" => Ok(Box::new(serial::Disconnected)),
+        "file" => Ok(Box::new(serial::Output::new(File::create(arg)?))),
+        "tcp-listen" => Ok(Box::new(LinkCable::listen_tcp(arg)?)),
+        "tcp" => Ok(Box::new(LinkCable::connect_tcp(arg)?)),
+        #[cfg(unix)]
+        "unix-listen" => Ok(Box::new(LinkCable::listen_unix(arg)?)),
+        #[cfg(unix)]
+        "unix" => Ok(Box::new(LinkCable::connect_unix(arg)?)),
+        "printer" => {
+            fs::create_dir_all(arg)?;
+            Ok(Box::new(Printer::new(arg.into())))
         }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unknown link partner {spec}"),
+        )),
     }
 }
 
+// The bools are independent command line switches, not a state machine
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(group(ArgGroup::new("end").multiple(true).args(["play", "frames", "debug"])))]
 struct Args {
-    rom: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(required = true)]
+    rom: Option<String>,
     #[arg(short, long)]
     boot_rom: Option<String>,
+    /// What is connected to the serial port: `none`, `file:PATH`,
+    /// `tcp-listen:ADDR`, `tcp:ADDR`, `unix-listen:PATH`, `unix:PATH`,
+    /// `printer:DIR`
+    #[arg(long, default_value = "none")]
+    link: String,
+    /// Key and game controller bindings file
+    #[arg(long)]
+    keys: Option<PathBuf>,
+    /// Record the buttons pressed to an input movie
+    #[arg(long, value_name = "MOVIE")]
+    record: Option<PathBuf>,
+    /// Play back an input movie
+    #[arg(long, value_name = "MOVIE")]
+    play: Option<PathBuf>,
+    /// Run without a window, until the movie played back ends, after
+    /// `--frames` or until quitting the debugger started with `--debug`
+    #[arg(long, requires = "end")]
+    headless: bool,
+    /// Stop after this many frames
+    #[arg(long)]
+    frames: Option<u64>,
+    /// Save a screenshot when running headless ends
+    #[arg(long)]
+    screenshot: bool,
+    /// Where screenshots and videos started with F9 are saved
+    #[arg(long, default_value = ".")]
+    screenshot_dir: PathBuf,
+    /// Record a Y4M video, with a WAV file next to it. There's no sound
+    /// emulation yet: the WAV file is silence, as long as the video
+    #[arg(long, value_name = "FILE")]
+    video: Option<PathBuf>,
+    /// Initial window size, as a multiple of the screen size
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..))]
+    scale: u32,
+    /// Only scale the screen by whole multiples
+    #[arg(long)]
+    integer_scale: bool,
+    /// How the screen is scaled
+    #[arg(long, value_enum, default_value_t = Filter::Nearest)]
+    filter: Filter,
+    /// Scaler applied to the screen
+    #[arg(long, value_enum, default_value_t = Scaler::None)]
+    scaler: Scaler,
+    /// Blend every frame with the previous ones, as the DMG LCD does
+    #[arg(long)]
+    ghosting: bool,
+    /// Speed multiplier while fast-forwarding, 0 for as fast as possible
+    #[arg(long, default_value_t = 4.0, value_parser = parse_fast_forward)]
+    fast_forward: f64,
+    /// Speed multiplier in slow motion
+    #[arg(long, default_value_t = 0.5, value_parser = parse_slow_motion)]
+    slow_motion: f64,
+    /// Start stopped in the debugger
+    #[arg(long)]
+    debug: bool,
+    /// Set a debugger breakpoint, in hexadecimal or a symbol (can be
+    /// repeated)
+    #[arg(long = "break", value_name = "ADDR")]
+    breakpoints: Vec<String>,
+    /// Symbol file, with lines like `01:4000 Main` (ROM.sym by default)
+    #[arg(long, value_name = "FILE")]
+    symbols: Option<PathBuf>,
+    /// Log every instruction executed to FILE, in the format of
+    /// gameboy-doctor
+    #[arg(long, value_name = "FILE")]
+    trace: Option<PathBuf>,
+    /// Run as gameboy-doctor's logs expect: starting from the DMG
+    /// registers, with LY stuck at 0x90. Movies can't record that start
+    #[arg(
+        long,
+        requires = "trace",
+        conflicts_with_all = ["boot_rom", "record", "play"]
+    )]
+    trace_doctor: bool,
+    /// End the lines of the instruction trace with where PC is in the
+    /// symbols
+    #[arg(long, requires = "trace")]
+    trace_symbols: bool,
+    /// Let GDB connect on this port of localhost, and debug with it
+    #[arg(long, value_name = "PORT")]
+    gdb: Option<u16>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Disassemble a ROM
+    Disasm(DisasmArgs),
+}
+
+#[derive(clap::Args)]
+struct DisasmArgs {
+    rom: PathBuf,
+    /// Only disassemble this bank, in hexadecimal
+    #[arg(long, value_parser = debugger::parse_number)]
+    bank: Option<u16>,
+    /// Address to start at, in hexadecimal
+    #[arg(long, default_value = "0", value_parser = debugger::parse_number)]
+    start: u16,
+    /// Address to stop at, in hexadecimal
+    #[arg(long, default_value = "8000", value_parser = debugger::parse_number)]
+    end: u16,
+    /// Symbol file, with lines like `01:4000 Main` (ROM.sym by default)
+    #[arg(long, value_name = "FILE")]
+    symbols: Option<PathBuf>,
+}
+
+// The symbols in path, or in the file next to the ROM with the .sym
+// extension that RGBDS writes if there's one
+fn load_symbols(path: Option<&Path>, rom: &Path) -> io::Result<Symbols> {
+    if let Some(path) = path {
+        return Symbols::load(path);
+    }
+
+    let path = rom.with_extension("sym");
+    if path.exists() {
+        Symbols::load(&path)
+    } else {
+        Ok(Symbols::new())
+    }
+}
+
+fn disasm(args: &DisasmArgs) -> Result<(), Box<dyn error::Error>> {
+    let rom = fs::read(&args.rom)?;
+    let symbols = load_symbols(args.symbols.as_deref(), &args.rom)?;
+
+    let stdout = stdout();
+    let mut output = io::BufWriter::new(stdout.lock());
+
+    match disassembler::write_rom(&rom, args.bank, args.start..args.end, &symbols, &mut output) {
+        // Piped to e.g. head
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+// Speed the emulation is running at, as wanted with the hotkeys
+#[derive(Default)]
+struct Speed {
+    fast_forward_held: bool,
+    fast_forward: bool,
+    slow_motion: bool,
+}
+
+impl Speed {
+    // Multiple of the real speed, None for as fast as possible
+    fn multiplier(&self, args: &Args) -> Option<f64> {
+        if self.fast_forward_held || self.fast_forward {
+            Some(args.fast_forward).filter(|&speed| speed > 0.0)
+        } else if self.slow_motion {
+            Some(args.slow_motion)
+        } else {
+            Some(1.0)
+        }
+    }
+}
+
+// Input movies recorded and played back
+struct Movies {
+    player: Option<Player>,
+    recorder: Option<Recorder<File>>,
+}
+
+impl Movies {
+    fn new(args: &Args, rom: &[u8]) -> io::Result<Self> {
+        let header = Header::new(rom, args.boot_rom.is_some());
+
+        let player = match &args.play {
+            Some(path) => {
+                let player = Player::new(File::open(path)?)?;
+                player.header.check(&header)?;
+                println!("Playing back {} frames", player.frames());
+
+                Some(player)
+            }
+            None => None,
+        };
+
+        let recorder = match &args.record {
+            Some(path) => Some(Recorder::new(File::create(path)?, &header)?),
+            None => None,
+        };
+
+        Ok(Self { player, recorder })
+    }
+
+    const fn playing(&self) -> bool {
+        self.player.is_some()
+    }
+
+    // Call when the frame starts, see GameBoy::frame_start(), after setting
+    // the buttons pressed.
+    fn start_frame(&mut self, gameboy: &GameBoy) -> io::Result<()> {
+        if let Some(player) = &mut self.player {
+            if let Some(buttons) = player.play() {
+                gameboy.memory_mut().set_buttons(buttons);
+            } else {
+                println!("Movie ended at frame {}", gameboy.frame());
+                self.player = None;
+            }
+        }
+
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(gameboy.memory().buttons())?;
+        }
+
+        Ok(())
+    }
+}
+
+fn save_screenshot(dir: &Path, frame: &Frame) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+
+    let path = image::numbered_path(dir, "screenshot", "png");
+    image::save_png(&path, frame.width, frame.height, &frame.pixels)?;
+    println!("Saved {}", path.display());
+
+    Ok(())
+}
+
+fn start_video(path: &Path) -> io::Result<VideoRecorder> {
+    let recorder = VideoRecorder::create(path)?;
+    println!("Recording {}", path.display());
+
+    Ok(recorder)
+}
+
+fn headless(
+    gameboy: &mut GameBoy,
+    debugger: &mut Debugger,
+    gdb: &mut Option<GdbStub>,
+    movies: &mut Movies,
+    args: &Args,
+) -> io::Result<()> {
+    let playing = movies.playing();
+    let mut effects = Effects::new(args.scaler, args.ghosting);
+    let mut video = match &args.video {
+        Some(path) => Some(start_video(path)?),
+        None => None,
+    };
+    // Whether the current frame was started, as the debugger can stop in
+    // the middle of it
+    let mut started = false;
+    // The screen with the effects, when ghosting
+    let mut shown = None;
+
+    loop {
+        if let Some(frames) = args.frames {
+            if gameboy.frame() >= frames {
+                break;
+            }
+        }
+
+        if !started {
+            movies.start_frame(gameboy)?;
+            started = true;
+
+            if playing && !movies.playing() {
+                break;
+            }
+        }
+
+        poll_gdb(debugger, gameboy, gdb)?;
+        let Some(drawn) = debugger.run_frame(gameboy) else {
+            if debug(debugger, gameboy, gdb)? == Action::Quit {
+                break;
+            }
+            continue;
+        };
+        started = false;
+
+        // Ghosting needs every frame
+        if drawn && args.ghosting {
+            shown = Some(effects.apply(gameboy.memory().gpu().screen()));
+        }
+
+        if let Some(video) = &mut video {
+            video.frame(gameboy.memory().gpu().screen())?;
+        }
+    }
+
+    if args.screenshot {
+        // Applying ghosting again would blend the last frame twice
+        let frame = shown.unwrap_or_else(|| effects.apply(gameboy.memory().gpu().screen()));
+        save_screenshot(&args.screenshot_dir, &frame)?;
+    }
+
+    Ok(())
 }
 
 #[allow(clippy::too_many_lines)]
 fn main() -> Result<(), Box<dyn error::Error>> {
     let args = Args::parse();
 
-    let rom = fs::read(&args.rom)?;
+    let Some(rom) = &args.rom else {
+        return match &args.command {
+            Some(Command::Disasm(disasm_args)) => disasm(disasm_args),
+            None => Ok(()),
+        };
+    };
+    let symbols = load_symbols(args.symbols.as_deref(), Path::new(rom))?;
+    if !symbols.is_empty() {
+        println!("Loaded {} symbols", symbols.len());
+    }
+    let rom = fs::read(rom)?;
 
     if let Some(cartridge) = Cartridge::new(&rom) {
         println!("Cartridge info\n{}", &cartridge);
@@ -88,208 +432,273 @@ fn main() -> Result<(), Box<dyn error::Error>> {
         eprintln!("Can't parse cartridge header");
     }
 
-    let mut mem = memory::Memory::new(gpu::Gpu::new());
+    let boot_rom = match &args.boot_rom {
+        Some(boot_rom) => Some(fs::read(boot_rom)?),
+        None => None,
+    };
+
+    let mut gameboy = GameBoy::new(rom.clone(), boot_rom);
+    gameboy.memory_mut().connect_link(link_partner(&args.link)?);
+
+    if args.trace_doctor && !gameboy.set_doctor() {
+        return Err("Can't start in gameboy-doctor's state".into());
+    }
+
+    if let Some(path) = &args.trace {
+        let output = io::BufWriter::new(File::create(path)?);
+        let symbols = args.trace_symbols.then(|| symbols.clone());
 
-    if let Some(boot_rom) = &args.boot_rom {
-        let boot_rom = fs::read(&boot_rom)?;
-        mem.map(0x0000, Box::new(Rom::new(boot_rom)));
+        gameboy.set_trace(Trace::new(Box::new(output), symbols));
     }
 
-    mem.map(0x0000, Box::new(Rom::new(rom)));
+    let mut movies = Movies::new(&args, &rom)?;
 
-    let mem = Rc::new(RefCell::new(mem));
+    let mut debugger = Debugger::new();
+    debugger.set_symbols(symbols);
+    for address in &args.breakpoints {
+        let address = debugger.parse_address(address)?;
+        debugger.add_breakpoint(&gameboy, address, None, None);
+    }
+    if args.debug {
+        debugger.stop();
+    }
 
-    let mut cpu = if args.boot_rom.is_some() {
-        cpu::Cpu::new(Rc::clone(&mem))
-    } else {
-        cpu::Cpu::new_initialized(Rc::clone(&mem))
+    let mut gdb = match args.gdb {
+        Some(port) => Some(GdbStub::listen(("127.0.0.1", port))?),
+        None => None,
     };
 
+    if args.headless {
+        return Ok(headless(
+            &mut gameboy,
+            &mut debugger,
+            &mut gdb,
+            &mut movies,
+            &args,
+        )?);
+    }
+
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
 
-    let window = video_subsystem
-        .window("", 160 * 2, 144 * 2)
-        .position_centered()
-        .opengl()
-        .build()
-        .map_err(|e| e.to_string())?;
+    let mut display = Display::new(
+        &video_subsystem,
+        args.scale,
+        args.integer_scale,
+        args.filter,
+    )?;
 
-    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    let mut effects = Effects::new(args.scaler, args.ghosting);
 
-    let creator = canvas.texture_creator();
-    let mut texture = creator.create_texture_target(PixelFormatEnum::RGBA8888, 256, 256)?;
+    let creator = display.texture_creator();
+    let mut texture = Display::create_texture(&creator, 1, 1)?;
+    let mut texture_size = (0, 0);
+    // The last frame shown
+    let mut shown: Option<Frame> = None;
 
-    canvas.clear();
+    let mut video = match &args.video {
+        Some(path) => Some(start_video(path)?),
+        None => None,
+    };
 
-    canvas.present();
+    let bindings = match &args.keys {
+        Some(path) => Bindings::load(path)?,
+        None => Bindings::default(),
+    };
+    let mut controls = Controls::new(bindings, sdl_context.game_controller()?);
 
     let mut event_pump = sdl_context.event_pump()?;
 
-    let mut step = false;
+    // Whether the current frame was started, as the debugger can stop in
+    // the middle of it
+    let mut started = false;
 
-    let mut cycles: u16;
-    let mut breakpoint: u16 = 0;
+    let mut speed = Speed::default();
+    let mut paused = false;
+    // Run a single frame while paused
+    let mut advance = false;
 
-    'running: loop {
-        let mut _next = false;
-        cycles = 0;
+    let mut pacer = Pacer::new();
+    let mut meter = Meter::new(0, 0);
 
+    'running: loop {
         for event in event_pump.poll_iter() {
             match event {
                 Event::KeyDown {
-                    keycode: Some(Keycode::B),
+                    keycode: Some(Keycode::F11),
+                    repeat: false,
+                    ..
+                } => display.toggle_fullscreen()?,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Return),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => {
+                    // Alt is bound to B by default
+                    controls.release_keys();
+                    display.toggle_fullscreen()?;
+                }
+                // Redraw the black bars
+                Event::Window {
+                    win_event: WindowEvent::SizeChanged(..),
+                    ..
+                } => display.present(&texture)?,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    repeat: false,
                     ..
                 } => {
-                    step = true;
-                    println!("Break");
+                    if let Some(frame) = &shown {
+                        save_screenshot(&args.screenshot_dir, frame)?;
+                    }
                 }
                 Event::KeyDown {
-                    keycode: Some(Keycode::D),
+                    keycode: Some(Keycode::F9),
+                    repeat: false,
                     ..
                 } => {
-                    let ret = debug(&mut cpu, &mem.borrow());
-                    breakpoint = ret.0;
-                    step = ret.1;
+                    if video.take().is_some() {
+                        println!("Recording stopped");
+                    } else {
+                        fs::create_dir_all(&args.screenshot_dir)?;
+                        let path = image::numbered_path(&args.screenshot_dir, "video", "y4m");
+                        video = Some(start_video(&path)?);
+                    }
                 }
                 Event::KeyDown {
-                    keycode: Some(Keycode::N),
+                    keycode: Some(Keycode::F4),
+                    repeat: false,
                     ..
+                } => {
+                    effects.scaler = effects.scaler.next();
+                    println!("Scaler: {}", effects.scaler);
                 }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::F7),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    repeat: false,
                     ..
                 } => {
-                    step = true;
-                    println!("Next");
+                    effects.ghosting = !effects.ghosting;
+                    println!("Ghosting: {}", if effects.ghosting { "on" } else { "off" });
                 }
                 Event::KeyDown {
-                    keycode: Some(Keycode::C),
+                    keycode: Some(Keycode::Tab),
+                    repeat: false,
+                    ..
+                } => speed.fast_forward_held = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => speed.fast_forward_held = false,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    repeat: false,
+                    ..
+                } => speed.fast_forward = !speed.fast_forward,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    repeat: false,
+                    ..
+                } => speed.slow_motion = !speed.slow_motion,
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    repeat: false,
                     ..
                 } => {
-                    step = false;
+                    paused = !paused;
+                    pacer.restart(gameboy.cycles());
+                    meter = Meter::new(gameboy.frame(), gameboy.cycles());
+
+                    if paused {
+                        display.set_title("gameperson - Paused")?;
+                    }
                 }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Period),
+                    ..
+                } if paused => advance = true,
+                Event::KeyDown {
+                    keycode: Some(Keycode::B),
+                    ..
+                } => debugger.stop(),
                 Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
                 }
                 | Event::Quit { .. } => break 'running,
-                _ => {
-                    // println!("{} {}", cpu, gpu.borrow().ly);
-                }
+                event => controls.handle(&event),
             }
         }
 
-        if breakpoint != 0 && cpu.pc == breakpoint {
-            println!("Break");
-            let ret = debug(&mut cpu, &mem.borrow());
-            breakpoint = ret.0;
-            step = ret.1;
+        if paused && !advance {
+            thread::sleep(Duration::from_millis(10));
+            continue;
         }
 
-        while cycles < 200 {
-            cycles += u16::from(cpu.decode());
-        }
+        pacer.set_speed(speed.multiplier(&args), gameboy.cycles());
 
-        let ie = mem.borrow().ie;
+        if let Some(frames) = args.frames {
+            if gameboy.frame() >= frames {
+                break;
+            }
+        }
 
-        let int = mem.borrow_mut().display(&mut canvas, &mut texture, cycles);
+        // The buttons are only set when a frame starts, and the movie
+        // played back presses them
+        if !started {
+            let buttons = controls.state(gameboy.frame());
 
-        match int {
-            Some(gpu::Interrupt::VBlank) => {
-                if cpu.interrupts_enabled && mem.borrow().ie & 0x1 != 0 {
-                    cpu.vblank_int()
-                };
-            }
-            Some(gpu::Interrupt::Status) => {
-                if cpu.interrupts_enabled && ie & 0x2 != 0 {
-                    cpu.status_int();
-                }
+            if !movies.playing() {
+                gameboy.memory_mut().set_buttons(buttons);
             }
-            _ => (),
+
+            movies.start_frame(&gameboy)?;
+            started = true;
         }
 
-        // Bit 3 - P13 Input: Down  or Start    (0=Pressed) (Read Only)
-        // Bit 2 - P12 Input: Up    or Select   (0=Pressed) (Read Only)
-        // Bit 1 - P11 Input: Left  or B        (0=Pressed) (Read Only)
-        // Bit 0 - P10 Input: Right or A        (0=Pressed) (Read Only)
+        poll_gdb(&mut debugger, &gameboy, &mut gdb)?;
+        let ended = debugger.run_frame(&mut gameboy);
 
-        match Input::new(&mut event_pump) {
-            Some(Input::JoypadPress(JoypadButton::Up)) => {
-                println!("UP");
-                mem.borrow_mut().set_joy_state(0, 0b0100)
-            }
-            Some(Input::JoypadPress(JoypadButton::Down)) => {
-                println!("Down");
-                mem.borrow_mut().set_joy_state(0, 0b1000)
-            }
-            Some(Input::JoypadPress(JoypadButton::Left)) => {
-                println!("Left");
-                mem.borrow_mut().set_joy_state(0, 0b0010)
-            }
-            Some(Input::JoypadPress(JoypadButton::Right)) => {
-                println!("Right");
-                mem.borrow_mut().set_joy_state(0, 0b0001)
-            }
-            Some(Input::JoypadPress(JoypadButton::Start)) => {
-                println!("Start");
-                mem.borrow_mut().set_joy_state(0b1000, 0)
-            }
-            Some(Input::JoypadPress(JoypadButton::Select)) => {
-                println!("Select");
-                mem.borrow_mut().set_joy_state(0b0100, 0)
-            }
-            Some(Input::JoypadPress(JoypadButton::A)) => {
-                println!("A");
-                mem.borrow_mut().set_joy_state(0b0001, 0)
-            }
-            Some(Input::JoypadPress(JoypadButton::B)) => {
-                println!("B");
-                mem.borrow_mut().set_joy_state(0b0010, 0)
-            }
+        if ended == Some(true) {
+            let frame = effects.apply(gameboy.memory().gpu().screen());
 
-            Some(Input::JoypadRelease(JoypadButton::Up)) => {
-                println!("UP");
-                mem.borrow_mut().unset_joy_state(0, 0b0100)
-            }
-            Some(Input::JoypadRelease(JoypadButton::Down)) => {
-                println!("Down");
-                mem.borrow_mut().unset_joy_state(0, 0b1000)
-            }
-            Some(Input::JoypadRelease(JoypadButton::Left)) => {
-                println!("Left");
-                mem.borrow_mut().unset_joy_state(0, 0b0010)
-            }
-            Some(Input::JoypadRelease(JoypadButton::Right)) => {
-                println!("Right");
-                mem.borrow_mut().unset_joy_state(0, 0b0001)
-            }
-            Some(Input::JoypadRelease(JoypadButton::Start)) => {
-                println!("Start");
-                mem.borrow_mut().unset_joy_state(0b1000, 0)
-            }
-            Some(Input::JoypadRelease(JoypadButton::Select)) => {
-                println!("Select");
-                mem.borrow_mut().unset_joy_state(0b0100, 0)
+            // The size changes with the scaler
+            if texture_size != (frame.width, frame.height) {
+                texture = Display::create_texture(&creator, frame.width, frame.height)?;
+                texture_size = (frame.width, frame.height);
             }
-            Some(Input::JoypadRelease(JoypadButton::A)) => {
-                println!("A");
-                mem.borrow_mut().unset_joy_state(0b0001, 0)
-            }
-            Some(Input::JoypadRelease(JoypadButton::B)) => {
-                println!("B");
-                mem.borrow_mut().unset_joy_state(0b0010, 0)
+
+            texture.update(None, &frame.pixels, frame.width as usize * 3)?;
+            display.present(&texture)?;
+
+            shown = Some(frame);
+        }
+
+        if ended.is_some() {
+            started = false;
+            advance = false;
+
+            if let Some(video) = &mut video {
+                video.frame(gameboy.memory().gpu().screen())?;
             }
 
-            _ => {}
+            pacer.wait(gameboy.cycles());
+
+            if let Some((fps, speed)) = meter.update(gameboy.frame(), gameboy.cycles()) {
+                let title = format!("gameperson - {:.1} FPS - {:.0}%", fps, speed * 100.0);
+                display.set_title(&title)?;
+            }
         }
 
-        if step {
-            cpu.mem_next();
+        if ended.is_none() {
+            if debug(&mut debugger, &mut gameboy, &mut gdb)? == Action::Quit {
+                break;
+            }
 
-            let ret = debug(&mut cpu, &mem.borrow());
-            breakpoint = ret.0;
-            step = ret.1;
+            // Don't catch up with the time spent stopped
+            pacer.restart(gameboy.cycles());
+            meter = Meter::new(gameboy.frame(), gameboy.cycles());
         }
     }
 
@@ -1,12 +1,11 @@
 use std::fmt;
 use std::ops::Range;
 
-use sdl2::render::Canvas;
-use sdl2::render::Texture;
-use sdl2::video::Window;
-
 use crate::gpu::Gpu;
-use crate::gpu::Interrupt;
+use crate::joypad::{Joypad, JoypadState};
+use crate::serial::{LinkPartner, Serial};
+use crate::timer::Timer;
+use crate::watchpoint::{Access, Watchpoints};
 
 ///!  0x0000              0x4000             0x8000                                 0xffff
 ///!    ↑                    ↑                  ↑                                      ↑
@@ -108,10 +107,41 @@ use crate::gpu::Interrupt;
 ///!    0xff80-0xfffe   High RAM (AKA HRAM, AKA Zero Page)
 ///!    0xffff          Interrupt Enable Register
 
+// Interrupt bits of the IF (0xff0f) and IE (0xffff) registers.
+// The lower the bit, the higher the priority.
+pub const INT_VBLANK: u8 = 1 << 0;
+pub const INT_STAT: u8 = 1 << 1;
+pub const INT_TIMER: u8 = 1 << 2;
+pub const INT_SERIAL: u8 = 1 << 3;
+pub const INT_JOYPAD: u8 = 1 << 4;
+
+// The address space as seen by the CPU.
+//
+// read() and write() access the bus without any timing side effect,
+// the CPU calls tick() for every M-cycle it spends, so the rest of the
+// system advances in lockstep with memory accesses.
+pub trait Bus {
+    fn read(&self, address: u16) -> u8;
+    fn write(&mut self, address: u16, value: u8);
+
+    // Read without it counting as an access by the program, e.g. for the
+    // hardware polling interrupts or the debugger.
+    fn peek(&self, address: u16) -> u8 {
+        self.read(address)
+    }
+
+    // Advance the rest of the system by one M-cycle (4 T-cycles).
+    fn tick(&mut self);
+}
+
 pub trait Region {
     fn read(&self, address: u16) -> u8;
     fn write(&mut self, address: u16, value: u8);
     fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 pub struct Rom {
@@ -145,42 +175,52 @@ struct Mapping {
 
 pub struct Memory {
     gpu: Gpu,
+    timer: Timer,
+    serial: Serial,
+    joypad: Joypad,
 
     ram: Vec<u8>,
     zero_page: Vec<u8>,
     cartridge: Vec<u8>,
-    io_registers: Vec<u8>,
-    pub ie: u8,
 
-    joy_action: u8,
-    joy_direction: u8,
+    // IF - Interrupt Flag (0xff0f)
+    pub interrupt_flag: u8,
+    pub ie: u8,
 
     mappings: Vec<Mapping>,
+
+    watchpoints: Watchpoints,
+
+    // What LY reads rather than the GPU's, see set_ly()
+    ly: Option<u8>,
 }
 
 impl Memory {
     pub fn new(gpu: Gpu) -> Self {
         Self {
             gpu,
+            timer: Timer::new(),
+            serial: Serial::new(),
+            joypad: Joypad::new(),
             // 8KiB
             // ram: vec![0; 0x2000],
 
             // FIXME
             ram: vec![0; 0x20000],
 
-            io_registers: vec![0; 0x7f],
-
             // 127 bytes
             zero_page: vec![0; 127],
 
             cartridge: vec![],
             mappings: vec![],
 
-            joy_action: 0,
-            joy_direction: 0,
+            interrupt_flag: 0,
 
             // Interrupt Enable (0xffff)
             ie: 0,
+
+            watchpoints: Watchpoints::default(),
+            ly: None,
         }
     }
 
@@ -227,28 +267,49 @@ impl Memory {
     fn dma(&mut self, addr: u8) {
         let address = (addr as u16) << 8;
         for a in address..=address + 0x9f {
+            let value = self.peek(a);
+            let destination = a - address + 0xfe00;
+
+            if !self.watchpoints.is_empty() {
+                let changed = self.peek(destination) != value;
+                self.watchpoints.access(a, Access::Read, value, false, true);
+                self.watchpoints
+                    .access(destination, Access::Write, value, changed, true);
+            }
+
             // XXX improve
-            self.gpu.write(a - address + 0xfe00, self.load(a as usize))
+            self.gpu.write(destination, value);
         }
     }
 
+    // Addresses are u16, see Bus
+    #[allow(clippy::cast_possible_truncation)]
     pub fn load(&self, address: usize) -> u8 {
+        let value = self.peek(address as u16);
+
+        if !self.watchpoints.is_empty() {
+            self.watchpoints
+                .access(address as u16, Access::Read, value, false, false);
+        }
+
+        value
+    }
+
+    // load() without triggering watchpoints
+    pub fn peek(&self, address: u16) -> u8 {
+        let index = usize::from(address);
+
         if let Some(mapping) = self.mapping(address) {
-            return mapping
-                .region
-                .read(address as u16 - mapping.address_range.start);
+            return mapping.region.read(address - mapping.address_range.start);
         }
 
-        match address {
+        match index {
             // Boot ROM
-            (0x0000..=0x00ff) => {
-                println!("address: {}", address);
-                self.cartridge[address]
-            }
+            (0x0000..=0x00ff) => self.cartridge[index],
             (0x0100..=0x0103) => 0x00,
             // Cartridge ROM
             (0x0104..=0x7fff) => {
-                if address <= 0x0133 {
+                if index <= 0x0133 {
                     let nintendo_logo = [
                         0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83,
                         0x00, 0x0c, 0x00, 0x0d, 0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e,
@@ -256,19 +317,7 @@ impl Memory {
                         0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
                     ];
 
-                    println!(
-                        "ADDR: {:#06x} value: {:#04x}",
-                        address,
-                        nintendo_logo[address - 0x0104]
-                    );
-                    println!("Mappings:");
-                    for mapping in &self.mappings {
-                        println!(
-                            "  {:#04x}..{:#04x}",
-                            mapping.address_range.start, mapping.address_range.end
-                        );
-                    }
-                    return nintendo_logo[address - 0x0104];
+                    return nintendo_logo[index - 0x0104];
                 }
                 // Happens with the boot ROM which is just 256 bytes.
                 // Let's return blank data
@@ -277,34 +326,11 @@ impl Memory {
             }
 
             // Video RAM
-            (0x8000..=0x9fff) => self.gpu.read(address as u16),
-
-            // I/O Registers
-            // (0xff00..=0xff7f)
-
-            // Joypad buttons
-            // Bit 7 - Not used
-            // Bit 6 - Not used
-            // Bit 5 - P15 Select Action buttons    (0=Select)
-            // Bit 4 - P14 Select Direction buttons (0=Select)
-            // Bit 3 - P13 Input: Down  or Start    (0=Pressed) (Read Only)
-            // Bit 2 - P12 Input: Up    or Select   (0=Pressed) (Read Only)
-            // Bit 1 - P11 Input: Left  or B        (0=Pressed) (Read Only)
-            // Bit 0 - P10 Input: Right or A        (0=Pressed) (Read Only)
-            0xff00 => {
-                let high_nibble = self.io_registers[0] & 0xf0;
-
-                if self.io_registers[address - 0xff00] & 0b0010_0000 == 0 {
-                    high_nibble | !(self.joy_action & 0x0f) & 0xf
-                } else if self.io_registers[address - 0xff00] & 0b0001_0000 == 0 {
-                    high_nibble | !(self.joy_direction & 0x0f) & 0xf
-                } else {
-                    0xff
-                }
-            }
-            0xff40 => self.gpu.read(address as u16),
-            0xff41 => self.gpu.read(address as u16),
-
+            (0x8000..=0x9fff)
+            // Sprite Attribute Table (aka OAM)
+            | 0xfe00..=0xfe9f
+            // LCDC and STAT
+            | 0xff40..=0xff41
             // 0xff42 - SCY - Scroll Y
             // 0xff43 - SCX - Scroll X
             // Specifies the position in the 256x256 pixels BG map (32x32 tiles)
@@ -313,9 +339,22 @@ impl Memory {
             // Values in range from 0-255 may be used for X/Y each, the video
             // controller automatically wraps back to the upper (left) position in
             // BG map when drawing exceeds the lower (right) border of the BG map area.
-            // // 0x43 => {
-            // }
-            0xff42 => self.gpu.read(address as u16),
+            | 0xff42..=0xff43
+            // TODO doc
+            | 0xff45
+            // BGP, OBP0, OBP1, WY and WX
+            | 0xff47..=0xff4b => self.gpu.read(address),
+
+            // I/O Registers
+            // (0xff00..=0xff7f)
+
+            // P1/JOYP: Joypad
+            0xff00 => self.joypad.read(),
+            0xff01..=0xff02 => self.serial.read(address),
+            0xff04..=0xff07 => self.timer.read(address),
+
+            // IF - Interrupt Flag, upper 3 bits are unused and read as 1
+            0xff0f => self.interrupt_flag | 0b1110_0000,
 
             // 0xff44: LY - LCDC Y-Coordinate
             // Indicates the vertical line to which the present data is
@@ -323,27 +362,17 @@ impl Memory {
             //
             // It can hold any value between 0 through 153.
             // The values between 144 and 153 indicate the V-Blank period.
-            //
-            // Writing will reset the counter.
-            0xff44 => self.gpu.read(address as u16),
-
-            // TODO doc
-            0xff45 => self.gpu.read(address as u16),
-
-            0xff47..=0xff48 => self.gpu.read(address as u16),
+            0xff44 => self.ly.unwrap_or_else(|| self.gpu.read(address)),
 
             // Internal RAM
-            (0xc000..=0xdfff) => self.ram[address - 0xc000],
+            (0xc000..=0xdfff) => self.ram[index - 0xc000],
 
             // Mirror of 0xc000~0xddff (Echo RAM) - Typically not used
             // FIXME Used by Tetris?
-            0xe000..=0xfdff => self.ram[address - 0x2000 - 0xc000],
-
-            // Sprite Attribute Table (aka OAM)
-            0xfe00..=0xfe9f => self.gpu.read(address as u16),
+            0xe000..=0xfdff => self.ram[index - 0x2000 - 0xc000],
 
             // Zero Page
-            (0xff80..=0xfffe) => self.zero_page[address - 0xff80],
+            (0xff80..=0xfffe) => self.zero_page[index - 0xff80],
 
             // FIXME: unimplemented: IE Interrupt Enable
             0xffff => self.ie,
@@ -352,24 +381,27 @@ impl Memory {
         }
     }
 
-    fn mapping(&self, address: usize) -> Option<&Mapping> {
-        let addr = address as u16;
-
+    fn mapping(&self, address: u16) -> Option<&Mapping> {
         self.mappings
             .iter()
-            .find(|m| m.address_range.contains(&addr))
+            .find(|m| m.address_range.contains(&address))
     }
 
-    fn mapping_mut(&mut self, address: usize) -> Option<&mut Mapping> {
-        let addr = address as u16;
-
+    fn mapping_mut(&mut self, address: u16) -> Option<&mut Mapping> {
         self.mappings
             .iter_mut()
-            .find(|m| m.address_range.contains(&addr))
+            .find(|m| m.address_range.contains(&address))
     }
 
+    #[allow(clippy::cast_possible_truncation)]
     pub fn write(&mut self, address: usize, value: u8) {
-        if let Some(mapping) = self.mapping_mut(address) {
+        if !self.watchpoints.is_empty() {
+            let changed = self.peek(address as u16) != value;
+            self.watchpoints
+                .access(address as u16, Access::Write, value, changed, false);
+        }
+
+        if let Some(mapping) = self.mapping_mut(address as u16) {
             mapping
                 .region
                 .write(address as u16 - mapping.address_range.start, value)
@@ -391,34 +423,15 @@ impl Memory {
             // I/O Registers
 
             // P1/JOYP: Joypad
-            // The eight Game Boy action/direction buttons are arranged as a 2x4 matrix.
-            // Select either action or direction buttons by writing to this register,
-            // then read out the bits 0-3.
-
-            // Bit 7 - Not used
-            // Bit 6 - Not used
-            // Bit 5 - P15 Select Action buttons    (0=Select)
-            // Bit 4 - P14 Select Direction buttons (0=Select)
-            // Bit 3 - P13 Input: Down  or Start    (0=Pressed) (Read Only)
-            // Bit 2 - P12 Input: Up    or Select   (0=Pressed) (Read Only)
-            // Bit 1 - P11 Input: Left  or B        (0=Pressed) (Read Only)
-            // Bit 0 - P10 Input: Right or A        (0=Pressed) (Read Only)
-            0xff00 => self.io_registers[address - 0xff00] = value,
-            0xff01 => {
-                // println!("Serial: {}", value);
-            }
+            0xff00 => self.joypad.write(value),
+            // SB - Serial transfer data (R/W)
             // SC - Serial Transfer Control (R/W)
-            0xff02 => {
-                if value == 0x81 {
-                    // Print 0xff01 (SB - Serial transfer data (R/W))
-                    // print!("{}", self.mem[0x01]);
-                }
-            }
+            0xff01..=0xff02 => self.serial.write(address as u16, value),
+            0xff04..=0xff07 => self.timer.write(address as u16, value),
+            0xff0f => self.interrupt_flag = value & 0b1_1111,
+
             // LCDC - LCD Control (R/W)
-            0xff40 => {
-                self.gpu.write(address as u16, value);
-                println!("LCDC change: {:08b}", value)
-            }
+            0xff40 => self.gpu.write(address as u16, value),
             0xff41 => {
                 self.gpu.write(address as u16, value);
             }
@@ -430,14 +443,11 @@ impl Memory {
             // Values in range from 0-255 may be used for X/Y each, the video
             // controller automatically wraps back to the upper (left) position in
             // BG map when drawing exceeds the lower (right) border of the BG map area.
-            // 0x43 => {
-            // }
-            0xff42 => {
+            0xff42..=0xff43 => {
                 self.gpu.write(address as u16, value);
             }
-            0xff44 => {
-                unimplemented!();
-            }
+            // LY is read-only
+            0xff44 => (),
             // LYC - LY Compare (R/W)
             // The gameboy permanently compares the value of the LYC and LY registers.
             // When both values are identical, the coincident bit in the STAT register
@@ -456,7 +466,8 @@ impl Memory {
             //
             // value can be 0x00 to 0xf1
             0xff46 => self.dma(value),
-            0xff47..=0xff48 => self.gpu.write(address as u16, value),
+            // BGP, OBP0, OBP1, WY and WX
+            0xff47..=0xff4b => self.gpu.write(address as u16, value),
 
             // Unmap the boot ROM (TODO: Find the documentation)
             0xff50 => self.unmap(0x0000),
@@ -470,24 +481,89 @@ impl Memory {
         }
     }
 
-    // TODO: document bits, this is custom just to keep the state
-    pub fn set_joy_state(&mut self, action: u8, direction: u8) {
-        self.joy_action |= action;
-        self.joy_direction |= direction;
+    // write() for debuggers, without its side effects: only memory is
+    // changed, not the cartridge nor the I/O registers, and watchpoints
+    // aren't triggered. Returns false for the addresses left alone.
+    pub fn poke(&mut self, address: u16, value: u8) -> bool {
+        match address {
+            0x8000..=0x9fff | 0xfe00..=0xfe9f => self.gpu.write(address, value),
+            0xc000..=0xdfff => self.ram[usize::from(address - 0xc000)] = value,
+            // Echo RAM
+            0xe000..=0xfdff => self.ram[usize::from(address - 0xe000)] = value,
+            0xff80..=0xfffe => self.zero_page[usize::from(address - 0xff80)] = value,
+            _ => return false,
+        }
+
+        true
+    }
+
+    #[must_use]
+    pub const fn buttons(&self) -> JoypadState {
+        self.joypad.buttons()
+    }
+
+    // Buttons are held until set again
+    pub const fn set_buttons(&mut self, buttons: JoypadState) {
+        self.joypad.set_buttons(buttons);
+    }
+
+    pub const fn gpu(&self) -> &Gpu {
+        &self.gpu
     }
 
-    pub fn unset_joy_state(&mut self, action: u8, direction: u8) {
-        self.joy_action &= !action;
-        self.joy_direction &= !direction;
+    #[must_use]
+    pub const fn watchpoints(&self) -> &Watchpoints {
+        &self.watchpoints
     }
 
-    pub fn display(
-        &mut self,
-        canvas: &mut Canvas<Window>,
-        texture: &mut Texture,
-        cycles: u16,
-    ) -> Option<Interrupt> {
-        self.gpu.display(canvas, texture, cycles)
+    pub const fn watchpoints_mut(&mut self) -> &mut Watchpoints {
+        &mut self.watchpoints
+    }
+
+    // Plug partner on the other end of the link cable
+    pub fn connect_link(&mut self, partner: Box<dyn LinkPartner>) {
+        self.serial.connect(partner);
+    }
+
+    // LY always reads ly rather than the current scanline, which the GPU
+    // keeps drawing, or reads it again with None
+    pub const fn set_ly(&mut self, ly: Option<u8>) {
+        self.ly = ly;
+    }
+
+    // Returns true once for every frame completed by the GPU.
+    pub fn take_frame(&mut self) -> bool {
+        self.gpu.take_frame()
+    }
+}
+
+impl Bus for Memory {
+    fn read(&self, address: u16) -> u8 {
+        self.load(address as usize)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        Self::write(self, address as usize, value);
+    }
+
+    fn peek(&self, address: u16) -> u8 {
+        Self::peek(self, address)
+    }
+
+    fn tick(&mut self) {
+        self.interrupt_flag |= self.gpu.tick();
+
+        if self.timer.tick() {
+            self.interrupt_flag |= INT_TIMER;
+        }
+
+        if self.serial.tick() {
+            self.interrupt_flag |= INT_SERIAL;
+        }
+
+        if self.joypad.tick() {
+            self.interrupt_flag |= INT_JOYPAD;
+        }
     }
 }
 
@@ -0,0 +1,232 @@
+use std::io::{self, Read, Write};
+
+use crate::joypad::JoypadState;
+
+// Input movies: the buttons pressed in every frame, to replay a session
+// exactly.
+//
+// File format, version 1 (integers are little endian):
+//
+// Offset Size
+// 0      4    Magic "GPMV"
+// 4      2    Version, 1
+// 6      1    Flags
+//               Bit 0 - Started with a boot ROM
+// 7      1    Start state
+//               0 - Power on (other values are reserved)
+// 8      4    CRC-32 of the ROM
+// 12     4    Reserved, 0
+// 16     1    Buttons pressed in frame 0
+// 17     1    Buttons pressed in frame 1
+// ...         ...until the end of the file
+//
+// Buttons are stored as JoypadState bits (1=Pressed):
+// Bit 7 - Start
+// Bit 6 - Select
+// Bit 5 - B
+// Bit 4 - A
+// Bit 3 - Down
+// Bit 2 - Up
+// Bit 1 - Left
+// Bit 0 - Right
+//
+// Buttons are set when a frame starts (see GameBoy::frame_start()) and
+// held for the whole frame. Playback is deterministic as long as nothing
+// else feeds the emulation, e.g. a link cable.
+pub struct Header {
+    pub boot_rom: bool,
+    pub rom_crc: u32,
+}
+
+const MAGIC: &[u8; 4] = b"GPMV";
+const VERSION: u16 = 1;
+const HEADER_SIZE: usize = 16;
+
+const FLAG_BOOT_ROM: u8 = 0b0000_0001;
+const START_POWER_ON: u8 = 0;
+
+impl Header {
+    #[must_use]
+    pub fn new(rom: &[u8], boot_rom: bool) -> Self {
+        Self {
+            boot_rom,
+            rom_crc: crc32fast::hash(rom),
+        }
+    }
+
+    fn encode(&self) -> [u8; HEADER_SIZE] {
+        let mut header = [0; HEADER_SIZE];
+
+        header[0..4].copy_from_slice(MAGIC);
+        header[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        header[6] = if self.boot_rom { FLAG_BOOT_ROM } else { 0 };
+        header[7] = START_POWER_ON;
+        header[8..12].copy_from_slice(&self.rom_crc.to_le_bytes());
+
+        header
+    }
+
+    fn decode(header: [u8; HEADER_SIZE]) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        if &header[0..4] != MAGIC {
+            return Err(invalid("not a movie file"));
+        }
+        if u16::from_le_bytes([header[4], header[5]]) != VERSION {
+            return Err(invalid("unsupported movie version"));
+        }
+        if header[7] != START_POWER_ON {
+            return Err(invalid("unsupported movie start state"));
+        }
+
+        let mut rom_crc = [0; 4];
+        rom_crc.copy_from_slice(&header[8..12]);
+
+        Ok(Self {
+            boot_rom: header[6] & FLAG_BOOT_ROM != 0,
+            rom_crc: u32::from_le_bytes(rom_crc),
+        })
+    }
+
+    // Check that the movie was recorded the way it's going to be played.
+    pub fn check(&self, other: &Self) -> io::Result<()> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message);
+
+        if self.rom_crc != other.rom_crc {
+            return Err(invalid("movie recorded with a different ROM"));
+        }
+        if self.boot_rom != other.boot_rom {
+            return Err(invalid(if self.boot_rom {
+                "movie recorded with a boot ROM"
+            } else {
+                "movie recorded without a boot ROM"
+            }));
+        }
+
+        Ok(())
+    }
+}
+
+// Writes frames as they are played, so that the movie survives a crash.
+pub struct Recorder<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(mut writer: W, header: &Header) -> io::Result<Self> {
+        writer.write_all(&header.encode())?;
+
+        Ok(Self { writer })
+    }
+
+    // Buttons pressed in the next frame
+    pub fn record(&mut self, buttons: JoypadState) -> io::Result<()> {
+        self.writer.write_all(&[buttons.0])?;
+        self.writer.flush()
+    }
+}
+
+pub struct Player {
+    pub header: Header,
+
+    frames: Vec<u8>,
+    frame: usize,
+}
+
+impl Player {
+    pub fn new<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut header = [0; HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+
+        let mut frames = Vec::new();
+        reader.read_to_end(&mut frames)?;
+
+        Ok(Self {
+            header: Header::decode(header)?,
+            frames,
+            frame: 0,
+        })
+    }
+
+    // Buttons pressed in the next frame, None at the end of the movie
+    pub fn play(&mut self) -> Option<JoypadState> {
+        let buttons = self.frames.get(self.frame).copied().map(JoypadState);
+        self.frame += 1;
+
+        buttons
+    }
+
+    #[must_use]
+    pub const fn frames(&self) -> usize {
+        self.frames.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_layout() {
+        let header = Header {
+            boot_rom: true,
+            rom_crc: 0x1234_5678,
+        };
+
+        assert_eq!(
+            header.encode(),
+            [b'G', b'P', b'M', b'V', 1, 0, 1, 0, 0x78, 0x56, 0x34, 0x12, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn header_round_trip() {
+        for &boot_rom in &[false, true] {
+            let header = Header::new(b"rom", boot_rom);
+            let decoded = Header::decode(header.encode()).unwrap();
+
+            assert_eq!(decoded.boot_rom, boot_rom);
+            assert_eq!(decoded.rom_crc, header.rom_crc);
+            assert!(header.check(&decoded).is_ok());
+        }
+    }
+
+    #[test]
+    fn header_invalid() {
+        let valid = Header::new(b"rom", false).encode();
+
+        let mut magic = valid;
+        magic[0] = b'X';
+        let mut version = valid;
+        version[4] = 2;
+        let mut start = valid;
+        start[7] = 1;
+
+        for header in [magic, version, start] {
+            let err = Header::decode(header).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn header_check() {
+        let header = Header::new(b"rom", false);
+
+        assert!(header.check(&Header::new(b"other rom", false)).is_err());
+        assert!(header.check(&Header::new(b"rom", true)).is_err());
+    }
+
+    #[test]
+    fn frames() {
+        let mut file = Vec::new();
+        let mut recorder = Recorder::new(&mut file, &Header::new(b"rom", false)).unwrap();
+        recorder.record(JoypadState(JoypadState::A)).unwrap();
+        recorder.record(JoypadState(0)).unwrap();
+
+        let mut player = Player::new(file.as_slice()).unwrap();
+        assert_eq!(player.frames(), 2);
+        assert_eq!(player.play(), Some(JoypadState(JoypadState::A)));
+        assert_eq!(player.play(), Some(JoypadState(0)));
+        assert_eq!(player.play(), None);
+    }
+}
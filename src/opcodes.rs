@@ -0,0 +1,933 @@
+// SM83 opcode tables
+//
+// Every opcode is described by its mnemonic, operands, length in bytes,
+// duration in T-cycles and the flags it affects. The tables drive both
+// the execution in Cpu::decode() and the disassembly.
+//
+// Flags are written as "ZNHC":
+//   Z, N, H, C: flag set according to the result
+//   0, 1:       flag reset or set
+//   -:          flag not affected
+
+use std::fmt;
+
+// The tables below are much easier to read without the enum prefixes
+#[allow(clippy::enum_glob_use)]
+use Mnemonic::*;
+#[allow(clippy::enum_glob_use)]
+use Operand::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mnemonic {
+    Nop,
+    Ld,
+    Ldh,
+    Inc,
+    Dec,
+    Add,
+    Adc,
+    Sub,
+    Sbc,
+    And,
+    Xor,
+    Or,
+    Cp,
+    Rlca,
+    Rrca,
+    Rla,
+    Rra,
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+    Jr,
+    Jp,
+    Call,
+    Ret,
+    Reti,
+    Rst,
+    Push,
+    Pop,
+    Di,
+    Ei,
+    Halt,
+    Stop,
+    // 0xcb, the opcode is in the next byte
+    Prefix,
+    Rlc,
+    Rrc,
+    Rl,
+    Rr,
+    Sla,
+    Sra,
+    Swap,
+    Srl,
+    Bit,
+    Res,
+    Set,
+    // Not an SM83 instruction: hangs the CPU
+    Illegal,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    // 8 bit registers
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+
+    // 16 bit registers
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+
+    // Memory pointed by a 16 bit register
+    IndBC,
+    IndDE,
+    IndHL,
+    // (HL), then increment HL
+    IndHLI,
+    // (HL), then decrement HL
+    IndHLD,
+    // (0xff00 + C)
+    IndC,
+
+    // 8 bit immediate
+    D8,
+    // 16 bit immediate
+    D16,
+    // 16 bit immediate address: the memory there, or the target of JP
+    // and CALL
+    A16,
+    // Memory at 0xff00 + 8 bit immediate
+    A8,
+    // Signed 8 bit immediate, relative to the next instruction
+    R8,
+    // Signed 8 bit immediate, added to SP
+    S8,
+    // SP + signed 8 bit immediate
+    SPR8,
+
+    // Conditions
+    CondNZ,
+    CondZ,
+    CondNC,
+    CondC,
+
+    // Bit number of BIT, RES, SET
+    BitNum(u8),
+    // RST target address
+    Vector(u8),
+}
+
+pub struct Opcode {
+    pub mnemonic: Mnemonic,
+    pub operands: [Option<Operand>; 2],
+
+    // Length in bytes, including the 0xcb prefix
+    pub length: u8,
+
+    // T-cycles, when a conditional branch is not taken
+    pub cycles: u8,
+    // T-cycles when a conditional branch is taken
+    pub cycles_taken: u8,
+
+    // Affected flags, "ZNHC"
+    pub flags: &'static str,
+}
+
+const fn op0(
+    mnemonic: Mnemonic,
+    length: u8,
+    cycles: u8,
+    cycles_taken: u8,
+    flags: &'static str,
+) -> Opcode {
+    Opcode {
+        mnemonic,
+        operands: [None, None],
+        length,
+        cycles,
+        cycles_taken,
+        flags,
+    }
+}
+
+const fn op1(
+    mnemonic: Mnemonic,
+    operand: Operand,
+    length: u8,
+    cycles: u8,
+    cycles_taken: u8,
+    flags: &'static str,
+) -> Opcode {
+    Opcode {
+        operands: [Some(operand), None],
+        ..op0(mnemonic, length, cycles, cycles_taken, flags)
+    }
+}
+
+const fn op2(
+    mnemonic: Mnemonic,
+    dst: Operand,
+    src: Operand,
+    length: u8,
+    cycles: u8,
+    cycles_taken: u8,
+    flags: &'static str,
+) -> Opcode {
+    Opcode {
+        operands: [Some(dst), Some(src)],
+        ..op0(mnemonic, length, cycles, cycles_taken, flags)
+    }
+}
+
+// Look up the opcode at the beginning of bytes, following the 0xcb prefix.
+#[must_use]
+pub fn lookup(bytes: &[u8]) -> &'static Opcode {
+    match bytes {
+        [0xcb, cb_opcode, ..] => &CB_OPCODES[*cb_opcode as usize],
+        [opcode, ..] => &OPCODES[*opcode as usize],
+        [] => &OPCODES[0],
+    }
+}
+
+// Disassemble the instruction at address.
+//
+// bytes starts with the opcode and must hold at least 3 bytes
+// (the longest instruction).
+#[must_use]
+pub fn disassemble(address: u16, bytes: &[u8]) -> String {
+    disassemble_labelled(address, bytes, &|_, _| None)
+}
+
+// disassemble(), showing the addresses the instruction refers to by the
+// names given by label, e.g. LDH [rLCDC],a.
+//
+// label is called with the address, and whether it's the target of a jump
+// or call rather than data.
+pub fn disassemble_labelled(
+    address: u16,
+    bytes: &[u8],
+    label: &dyn Fn(u16, bool) -> Option<String>,
+) -> String {
+    let opcode = lookup(bytes);
+    let code = matches!(opcode.mnemonic, Jr | Jp | Call);
+
+    let mut text = opcode.mnemonic.to_string();
+
+    for (i, operand) in opcode.operands.iter().flatten().enumerate() {
+        text.push_str(if i == 0 { " " } else { "," });
+
+        let target = operand.target(address, bytes);
+        let name = target.and_then(|target| label(target, code));
+
+        // Jumps and calls go to the address rather than read the memory
+        // there
+        let formatted = match (name, operand, target) {
+            (Some(name), A8 | A16, _) if !code => format!("[{name}]"),
+            (Some(name), _, _) => name,
+            (None, A16, Some(target)) if code => format!("${target:04x}"),
+            (None, _, _) => operand.format(address, bytes),
+        };
+        text.push_str(&formatted);
+    }
+
+    text
+}
+
+impl Operand {
+    // The address an immediate operand refers to, if any
+    #[must_use]
+    pub fn target(self, address: u16, bytes: &[u8]) -> Option<u16> {
+        let d8 = bytes.get(1).copied().unwrap_or_default();
+        let d16 = u16::from_le_bytes([d8, bytes.get(2).copied().unwrap_or_default()]);
+
+        match self {
+            Self::D16 | Self::A16 => Some(d16),
+            Self::A8 => Some(0xff00 | u16::from(d8)),
+            Self::R8 => Some(
+                address
+                    .wrapping_add(2)
+                    .wrapping_add_signed(i8::from_le_bytes([d8]).into()),
+            ),
+            _ => None,
+        }
+    }
+
+    // Operand as text, reading immediates from the instruction bytes.
+    #[must_use]
+    pub fn format(self, address: u16, bytes: &[u8]) -> String {
+        let d8 = bytes.get(1).copied().unwrap_or_default();
+        let d16 = u16::from_le_bytes([d8, bytes.get(2).copied().unwrap_or_default()]);
+        let r8 = i8::from_le_bytes([d8]);
+
+        match self {
+            Self::A => "a".to_string(),
+            Self::B => "b".to_string(),
+            Self::C | Self::CondC => "c".to_string(),
+            Self::D => "d".to_string(),
+            Self::E => "e".to_string(),
+            Self::H => "h".to_string(),
+            Self::L => "l".to_string(),
+            Self::AF => "af".to_string(),
+            Self::BC => "bc".to_string(),
+            Self::DE => "de".to_string(),
+            Self::HL => "hl".to_string(),
+            Self::SP => "sp".to_string(),
+            Self::IndBC => "[bc]".to_string(),
+            Self::IndDE => "[de]".to_string(),
+            Self::IndHL => "[hl]".to_string(),
+            Self::IndHLI => "[hl+]".to_string(),
+            Self::IndHLD => "[hl-]".to_string(),
+            Self::IndC => "[c]".to_string(),
+            Self::D8 => format!("${d8:02x}"),
+            Self::D16 => format!("${d16:04x}"),
+            Self::A16 => format!("[${d16:04x}]"),
+            Self::A8 => format!("[$ff{d8:02x}]"),
+            // Relative jumps are shown with their target address
+            Self::R8 => format!(
+                "${:04x}",
+                address.wrapping_add(2).wrapping_add_signed(r8.into())
+            ),
+            Self::S8 => signed_hex(r8),
+            Self::SPR8 => format!("sp{}", signed_hex(r8)),
+            Self::CondNZ => "nz".to_string(),
+            Self::CondZ => "z".to_string(),
+            Self::CondNC => "nc".to_string(),
+            Self::BitNum(bit) => bit.to_string(),
+            Self::Vector(vector) => format!("${vector:02x}"),
+        }
+    }
+}
+
+fn signed_hex(value: i8) -> String {
+    if value < 0 {
+        format!("-${:02x}", value.unsigned_abs())
+    } else {
+        format!("+${value:02x}")
+    }
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Prefix => "PREFIX CB",
+            Self::Illegal => "ILLEGAL",
+            _ => return f.write_str(&format!("{self:?}").to_uppercase()),
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+// Unprefixed opcodes
+pub static OPCODES: [Opcode; 256] = [
+    /* 0x00 */ op0(Nop, 1, 4, 4, "----"),
+    /* 0x01 */ op2(Ld, BC, D16, 3, 12, 12, "----"),
+    /* 0x02 */ op2(Ld, IndBC, A, 1, 8, 8, "----"),
+    /* 0x03 */ op1(Inc, BC, 1, 8, 8, "----"),
+    /* 0x04 */ op1(Inc, B, 1, 4, 4, "Z0H-"),
+    /* 0x05 */ op1(Dec, B, 1, 4, 4, "Z1H-"),
+    /* 0x06 */ op2(Ld, B, D8, 2, 8, 8, "----"),
+    /* 0x07 */ op0(Rlca, 1, 4, 4, "000C"),
+    /* 0x08 */ op2(Ld, A16, SP, 3, 20, 20, "----"),
+    /* 0x09 */ op2(Add, HL, BC, 1, 8, 8, "-0HC"),
+    /* 0x0a */ op2(Ld, A, IndBC, 1, 8, 8, "----"),
+    /* 0x0b */ op1(Dec, BC, 1, 8, 8, "----"),
+    /* 0x0c */ op1(Inc, C, 1, 4, 4, "Z0H-"),
+    /* 0x0d */ op1(Dec, C, 1, 4, 4, "Z1H-"),
+    /* 0x0e */ op2(Ld, C, D8, 2, 8, 8, "----"),
+    /* 0x0f */ op0(Rrca, 1, 4, 4, "000C"),
+    /* 0x10 */ op1(Stop, D8, 2, 8, 8, "----"),
+    /* 0x11 */ op2(Ld, DE, D16, 3, 12, 12, "----"),
+    /* 0x12 */ op2(Ld, IndDE, A, 1, 8, 8, "----"),
+    /* 0x13 */ op1(Inc, DE, 1, 8, 8, "----"),
+    /* 0x14 */ op1(Inc, D, 1, 4, 4, "Z0H-"),
+    /* 0x15 */ op1(Dec, D, 1, 4, 4, "Z1H-"),
+    /* 0x16 */ op2(Ld, D, D8, 2, 8, 8, "----"),
+    /* 0x17 */ op0(Rla, 1, 4, 4, "000C"),
+    /* 0x18 */ op1(Jr, R8, 2, 12, 12, "----"),
+    /* 0x19 */ op2(Add, HL, DE, 1, 8, 8, "-0HC"),
+    /* 0x1a */ op2(Ld, A, IndDE, 1, 8, 8, "----"),
+    /* 0x1b */ op1(Dec, DE, 1, 8, 8, "----"),
+    /* 0x1c */ op1(Inc, E, 1, 4, 4, "Z0H-"),
+    /* 0x1d */ op1(Dec, E, 1, 4, 4, "Z1H-"),
+    /* 0x1e */ op2(Ld, E, D8, 2, 8, 8, "----"),
+    /* 0x1f */ op0(Rra, 1, 4, 4, "000C"),
+    /* 0x20 */ op2(Jr, CondNZ, R8, 2, 8, 12, "----"),
+    /* 0x21 */ op2(Ld, HL, D16, 3, 12, 12, "----"),
+    /* 0x22 */ op2(Ld, IndHLI, A, 1, 8, 8, "----"),
+    /* 0x23 */ op1(Inc, HL, 1, 8, 8, "----"),
+    /* 0x24 */ op1(Inc, H, 1, 4, 4, "Z0H-"),
+    /* 0x25 */ op1(Dec, H, 1, 4, 4, "Z1H-"),
+    /* 0x26 */ op2(Ld, H, D8, 2, 8, 8, "----"),
+    /* 0x27 */ op0(Daa, 1, 4, 4, "Z-0C"),
+    /* 0x28 */ op2(Jr, CondZ, R8, 2, 8, 12, "----"),
+    /* 0x29 */ op2(Add, HL, HL, 1, 8, 8, "-0HC"),
+    /* 0x2a */ op2(Ld, A, IndHLI, 1, 8, 8, "----"),
+    /* 0x2b */ op1(Dec, HL, 1, 8, 8, "----"),
+    /* 0x2c */ op1(Inc, L, 1, 4, 4, "Z0H-"),
+    /* 0x2d */ op1(Dec, L, 1, 4, 4, "Z1H-"),
+    /* 0x2e */ op2(Ld, L, D8, 2, 8, 8, "----"),
+    /* 0x2f */ op0(Cpl, 1, 4, 4, "-11-"),
+    /* 0x30 */ op2(Jr, CondNC, R8, 2, 8, 12, "----"),
+    /* 0x31 */ op2(Ld, SP, D16, 3, 12, 12, "----"),
+    /* 0x32 */ op2(Ld, IndHLD, A, 1, 8, 8, "----"),
+    /* 0x33 */ op1(Inc, SP, 1, 8, 8, "----"),
+    /* 0x34 */ op1(Inc, IndHL, 1, 12, 12, "Z0H-"),
+    /* 0x35 */ op1(Dec, IndHL, 1, 12, 12, "Z1H-"),
+    /* 0x36 */ op2(Ld, IndHL, D8, 2, 12, 12, "----"),
+    /* 0x37 */ op0(Scf, 1, 4, 4, "-001"),
+    /* 0x38 */ op2(Jr, CondC, R8, 2, 8, 12, "----"),
+    /* 0x39 */ op2(Add, HL, SP, 1, 8, 8, "-0HC"),
+    /* 0x3a */ op2(Ld, A, IndHLD, 1, 8, 8, "----"),
+    /* 0x3b */ op1(Dec, SP, 1, 8, 8, "----"),
+    /* 0x3c */ op1(Inc, A, 1, 4, 4, "Z0H-"),
+    /* 0x3d */ op1(Dec, A, 1, 4, 4, "Z1H-"),
+    /* 0x3e */ op2(Ld, A, D8, 2, 8, 8, "----"),
+    /* 0x3f */ op0(Ccf, 1, 4, 4, "-00C"),
+    /* 0x40 */ op2(Ld, B, B, 1, 4, 4, "----"),
+    /* 0x41 */ op2(Ld, B, C, 1, 4, 4, "----"),
+    /* 0x42 */ op2(Ld, B, D, 1, 4, 4, "----"),
+    /* 0x43 */ op2(Ld, B, E, 1, 4, 4, "----"),
+    /* 0x44 */ op2(Ld, B, H, 1, 4, 4, "----"),
+    /* 0x45 */ op2(Ld, B, L, 1, 4, 4, "----"),
+    /* 0x46 */ op2(Ld, B, IndHL, 1, 8, 8, "----"),
+    /* 0x47 */ op2(Ld, B, A, 1, 4, 4, "----"),
+    /* 0x48 */ op2(Ld, C, B, 1, 4, 4, "----"),
+    /* 0x49 */ op2(Ld, C, C, 1, 4, 4, "----"),
+    /* 0x4a */ op2(Ld, C, D, 1, 4, 4, "----"),
+    /* 0x4b */ op2(Ld, C, E, 1, 4, 4, "----"),
+    /* 0x4c */ op2(Ld, C, H, 1, 4, 4, "----"),
+    /* 0x4d */ op2(Ld, C, L, 1, 4, 4, "----"),
+    /* 0x4e */ op2(Ld, C, IndHL, 1, 8, 8, "----"),
+    /* 0x4f */ op2(Ld, C, A, 1, 4, 4, "----"),
+    /* 0x50 */ op2(Ld, D, B, 1, 4, 4, "----"),
+    /* 0x51 */ op2(Ld, D, C, 1, 4, 4, "----"),
+    /* 0x52 */ op2(Ld, D, D, 1, 4, 4, "----"),
+    /* 0x53 */ op2(Ld, D, E, 1, 4, 4, "----"),
+    /* 0x54 */ op2(Ld, D, H, 1, 4, 4, "----"),
+    /* 0x55 */ op2(Ld, D, L, 1, 4, 4, "----"),
+    /* 0x56 */ op2(Ld, D, IndHL, 1, 8, 8, "----"),
+    /* 0x57 */ op2(Ld, D, A, 1, 4, 4, "----"),
+    /* 0x58 */ op2(Ld, E, B, 1, 4, 4, "----"),
+    /* 0x59 */ op2(Ld, E, C, 1, 4, 4, "----"),
+    /* 0x5a */ op2(Ld, E, D, 1, 4, 4, "----"),
+    /* 0x5b */ op2(Ld, E, E, 1, 4, 4, "----"),
+    /* 0x5c */ op2(Ld, E, H, 1, 4, 4, "----"),
+    /* 0x5d */ op2(Ld, E, L, 1, 4, 4, "----"),
+    /* 0x5e */ op2(Ld, E, IndHL, 1, 8, 8, "----"),
+    /* 0x5f */ op2(Ld, E, A, 1, 4, 4, "----"),
+    /* 0x60 */ op2(Ld, H, B, 1, 4, 4, "----"),
+    /* 0x61 */ op2(Ld, H, C, 1, 4, 4, "----"),
+    /* 0x62 */ op2(Ld, H, D, 1, 4, 4, "----"),
+    /* 0x63 */ op2(Ld, H, E, 1, 4, 4, "----"),
+    /* 0x64 */ op2(Ld, H, H, 1, 4, 4, "----"),
+    /* 0x65 */ op2(Ld, H, L, 1, 4, 4, "----"),
+    /* 0x66 */ op2(Ld, H, IndHL, 1, 8, 8, "----"),
+    /* 0x67 */ op2(Ld, H, A, 1, 4, 4, "----"),
+    /* 0x68 */ op2(Ld, L, B, 1, 4, 4, "----"),
+    /* 0x69 */ op2(Ld, L, C, 1, 4, 4, "----"),
+    /* 0x6a */ op2(Ld, L, D, 1, 4, 4, "----"),
+    /* 0x6b */ op2(Ld, L, E, 1, 4, 4, "----"),
+    /* 0x6c */ op2(Ld, L, H, 1, 4, 4, "----"),
+    /* 0x6d */ op2(Ld, L, L, 1, 4, 4, "----"),
+    /* 0x6e */ op2(Ld, L, IndHL, 1, 8, 8, "----"),
+    /* 0x6f */ op2(Ld, L, A, 1, 4, 4, "----"),
+    /* 0x70 */ op2(Ld, IndHL, B, 1, 8, 8, "----"),
+    /* 0x71 */ op2(Ld, IndHL, C, 1, 8, 8, "----"),
+    /* 0x72 */ op2(Ld, IndHL, D, 1, 8, 8, "----"),
+    /* 0x73 */ op2(Ld, IndHL, E, 1, 8, 8, "----"),
+    /* 0x74 */ op2(Ld, IndHL, H, 1, 8, 8, "----"),
+    /* 0x75 */ op2(Ld, IndHL, L, 1, 8, 8, "----"),
+    /* 0x76 */ op0(Halt, 1, 4, 4, "----"),
+    /* 0x77 */ op2(Ld, IndHL, A, 1, 8, 8, "----"),
+    /* 0x78 */ op2(Ld, A, B, 1, 4, 4, "----"),
+    /* 0x79 */ op2(Ld, A, C, 1, 4, 4, "----"),
+    /* 0x7a */ op2(Ld, A, D, 1, 4, 4, "----"),
+    /* 0x7b */ op2(Ld, A, E, 1, 4, 4, "----"),
+    /* 0x7c */ op2(Ld, A, H, 1, 4, 4, "----"),
+    /* 0x7d */ op2(Ld, A, L, 1, 4, 4, "----"),
+    /* 0x7e */ op2(Ld, A, IndHL, 1, 8, 8, "----"),
+    /* 0x7f */ op2(Ld, A, A, 1, 4, 4, "----"),
+    /* 0x80 */ op2(Add, A, B, 1, 4, 4, "Z0HC"),
+    /* 0x81 */ op2(Add, A, C, 1, 4, 4, "Z0HC"),
+    /* 0x82 */ op2(Add, A, D, 1, 4, 4, "Z0HC"),
+    /* 0x83 */ op2(Add, A, E, 1, 4, 4, "Z0HC"),
+    /* 0x84 */ op2(Add, A, H, 1, 4, 4, "Z0HC"),
+    /* 0x85 */ op2(Add, A, L, 1, 4, 4, "Z0HC"),
+    /* 0x86 */ op2(Add, A, IndHL, 1, 8, 8, "Z0HC"),
+    /* 0x87 */ op2(Add, A, A, 1, 4, 4, "Z0HC"),
+    /* 0x88 */ op2(Adc, A, B, 1, 4, 4, "Z0HC"),
+    /* 0x89 */ op2(Adc, A, C, 1, 4, 4, "Z0HC"),
+    /* 0x8a */ op2(Adc, A, D, 1, 4, 4, "Z0HC"),
+    /* 0x8b */ op2(Adc, A, E, 1, 4, 4, "Z0HC"),
+    /* 0x8c */ op2(Adc, A, H, 1, 4, 4, "Z0HC"),
+    /* 0x8d */ op2(Adc, A, L, 1, 4, 4, "Z0HC"),
+    /* 0x8e */ op2(Adc, A, IndHL, 1, 8, 8, "Z0HC"),
+    /* 0x8f */ op2(Adc, A, A, 1, 4, 4, "Z0HC"),
+    /* 0x90 */ op1(Sub, B, 1, 4, 4, "Z1HC"),
+    /* 0x91 */ op1(Sub, C, 1, 4, 4, "Z1HC"),
+    /* 0x92 */ op1(Sub, D, 1, 4, 4, "Z1HC"),
+    /* 0x93 */ op1(Sub, E, 1, 4, 4, "Z1HC"),
+    /* 0x94 */ op1(Sub, H, 1, 4, 4, "Z1HC"),
+    /* 0x95 */ op1(Sub, L, 1, 4, 4, "Z1HC"),
+    /* 0x96 */ op1(Sub, IndHL, 1, 8, 8, "Z1HC"),
+    /* 0x97 */ op1(Sub, A, 1, 4, 4, "Z1HC"),
+    /* 0x98 */ op2(Sbc, A, B, 1, 4, 4, "Z1HC"),
+    /* 0x99 */ op2(Sbc, A, C, 1, 4, 4, "Z1HC"),
+    /* 0x9a */ op2(Sbc, A, D, 1, 4, 4, "Z1HC"),
+    /* 0x9b */ op2(Sbc, A, E, 1, 4, 4, "Z1HC"),
+    /* 0x9c */ op2(Sbc, A, H, 1, 4, 4, "Z1HC"),
+    /* 0x9d */ op2(Sbc, A, L, 1, 4, 4, "Z1HC"),
+    /* 0x9e */ op2(Sbc, A, IndHL, 1, 8, 8, "Z1HC"),
+    /* 0x9f */ op2(Sbc, A, A, 1, 4, 4, "Z1HC"),
+    /* 0xa0 */ op1(And, B, 1, 4, 4, "Z010"),
+    /* 0xa1 */ op1(And, C, 1, 4, 4, "Z010"),
+    /* 0xa2 */ op1(And, D, 1, 4, 4, "Z010"),
+    /* 0xa3 */ op1(And, E, 1, 4, 4, "Z010"),
+    /* 0xa4 */ op1(And, H, 1, 4, 4, "Z010"),
+    /* 0xa5 */ op1(And, L, 1, 4, 4, "Z010"),
+    /* 0xa6 */ op1(And, IndHL, 1, 8, 8, "Z010"),
+    /* 0xa7 */ op1(And, A, 1, 4, 4, "Z010"),
+    /* 0xa8 */ op1(Xor, B, 1, 4, 4, "Z000"),
+    /* 0xa9 */ op1(Xor, C, 1, 4, 4, "Z000"),
+    /* 0xaa */ op1(Xor, D, 1, 4, 4, "Z000"),
+    /* 0xab */ op1(Xor, E, 1, 4, 4, "Z000"),
+    /* 0xac */ op1(Xor, H, 1, 4, 4, "Z000"),
+    /* 0xad */ op1(Xor, L, 1, 4, 4, "Z000"),
+    /* 0xae */ op1(Xor, IndHL, 1, 8, 8, "Z000"),
+    /* 0xaf */ op1(Xor, A, 1, 4, 4, "Z000"),
+    /* 0xb0 */ op1(Or, B, 1, 4, 4, "Z000"),
+    /* 0xb1 */ op1(Or, C, 1, 4, 4, "Z000"),
+    /* 0xb2 */ op1(Or, D, 1, 4, 4, "Z000"),
+    /* 0xb3 */ op1(Or, E, 1, 4, 4, "Z000"),
+    /* 0xb4 */ op1(Or, H, 1, 4, 4, "Z000"),
+    /* 0xb5 */ op1(Or, L, 1, 4, 4, "Z000"),
+    /* 0xb6 */ op1(Or, IndHL, 1, 8, 8, "Z000"),
+    /* 0xb7 */ op1(Or, A, 1, 4, 4, "Z000"),
+    /* 0xb8 */ op1(Cp, B, 1, 4, 4, "Z1HC"),
+    /* 0xb9 */ op1(Cp, C, 1, 4, 4, "Z1HC"),
+    /* 0xba */ op1(Cp, D, 1, 4, 4, "Z1HC"),
+    /* 0xbb */ op1(Cp, E, 1, 4, 4, "Z1HC"),
+    /* 0xbc */ op1(Cp, H, 1, 4, 4, "Z1HC"),
+    /* 0xbd */ op1(Cp, L, 1, 4, 4, "Z1HC"),
+    /* 0xbe */ op1(Cp, IndHL, 1, 8, 8, "Z1HC"),
+    /* 0xbf */ op1(Cp, A, 1, 4, 4, "Z1HC"),
+    /* 0xc0 */ op1(Ret, CondNZ, 1, 8, 20, "----"),
+    /* 0xc1 */ op1(Pop, BC, 1, 12, 12, "----"),
+    /* 0xc2 */ op2(Jp, CondNZ, A16, 3, 12, 16, "----"),
+    /* 0xc3 */ op1(Jp, A16, 3, 16, 16, "----"),
+    /* 0xc4 */ op2(Call, CondNZ, A16, 3, 12, 24, "----"),
+    /* 0xc5 */ op1(Push, BC, 1, 16, 16, "----"),
+    /* 0xc6 */ op2(Add, A, D8, 2, 8, 8, "Z0HC"),
+    /* 0xc7 */ op1(Rst, Vector(0x00), 1, 16, 16, "----"),
+    /* 0xc8 */ op1(Ret, CondZ, 1, 8, 20, "----"),
+    /* 0xc9 */ op0(Ret, 1, 16, 16, "----"),
+    /* 0xca */ op2(Jp, CondZ, A16, 3, 12, 16, "----"),
+    /* 0xcb */ op0(Prefix, 1, 4, 4, "----"),
+    /* 0xcc */ op2(Call, CondZ, A16, 3, 12, 24, "----"),
+    /* 0xcd */ op1(Call, A16, 3, 24, 24, "----"),
+    /* 0xce */ op2(Adc, A, D8, 2, 8, 8, "Z0HC"),
+    /* 0xcf */ op1(Rst, Vector(0x08), 1, 16, 16, "----"),
+    /* 0xd0 */ op1(Ret, CondNC, 1, 8, 20, "----"),
+    /* 0xd1 */ op1(Pop, DE, 1, 12, 12, "----"),
+    /* 0xd2 */ op2(Jp, CondNC, A16, 3, 12, 16, "----"),
+    /* 0xd3 */ op0(Illegal, 1, 4, 4, "----"),
+    /* 0xd4 */ op2(Call, CondNC, A16, 3, 12, 24, "----"),
+    /* 0xd5 */ op1(Push, DE, 1, 16, 16, "----"),
+    /* 0xd6 */ op1(Sub, D8, 2, 8, 8, "Z1HC"),
+    /* 0xd7 */ op1(Rst, Vector(0x10), 1, 16, 16, "----"),
+    /* 0xd8 */ op1(Ret, CondC, 1, 8, 20, "----"),
+    /* 0xd9 */ op0(Reti, 1, 16, 16, "----"),
+    /* 0xda */ op2(Jp, CondC, A16, 3, 12, 16, "----"),
+    /* 0xdb */ op0(Illegal, 1, 4, 4, "----"),
+    /* 0xdc */ op2(Call, CondC, A16, 3, 12, 24, "----"),
+    /* 0xdd */ op0(Illegal, 1, 4, 4, "----"),
+    /* 0xde */ op2(Sbc, A, D8, 2, 8, 8, "Z1HC"),
+    /* 0xdf */ op1(Rst, Vector(0x18), 1, 16, 16, "----"),
+    /* 0xe0 */ op2(Ldh, A8, A, 2, 12, 12, "----"),
+    /* 0xe1 */ op1(Pop, HL, 1, 12, 12, "----"),
+    /* 0xe2 */ op2(Ldh, IndC, A, 1, 8, 8, "----"),
+    /* 0xe3 */ op0(Illegal, 1, 4, 4, "----"),
+    /* 0xe4 */ op0(Illegal, 1, 4, 4, "----"),
+    /* 0xe5 */ op1(Push, HL, 1, 16, 16, "----"),
+    /* 0xe6 */ op1(And, D8, 2, 8, 8, "Z010"),
+    /* 0xe7 */ op1(Rst, Vector(0x20), 1, 16, 16, "----"),
+    /* 0xe8 */ op2(Add, SP, S8, 2, 16, 16, "00HC"),
+    /* 0xe9 */ op1(Jp, HL, 1, 4, 4, "----"),
+    /* 0xea */ op2(Ld, A16, A, 3, 16, 16, "----"),
+    /* 0xeb */ op0(Illegal, 1, 4, 4, "----"),
+    /* 0xec */ op0(Illegal, 1, 4, 4, "----"),
+    /* 0xed */ op0(Illegal, 1, 4, 4, "----"),
+    /* 0xee */ op1(Xor, D8, 2, 8, 8, "Z000"),
+    /* 0xef */ op1(Rst, Vector(0x28), 1, 16, 16, "----"),
+    /* 0xf0 */ op2(Ldh, A, A8, 2, 12, 12, "----"),
+    /* 0xf1 */ op1(Pop, AF, 1, 12, 12, "ZNHC"),
+    /* 0xf2 */ op2(Ldh, A, IndC, 1, 8, 8, "----"),
+    /* 0xf3 */ op0(Di, 1, 4, 4, "----"),
+    /* 0xf4 */ op0(Illegal, 1, 4, 4, "----"),
+    /* 0xf5 */ op1(Push, AF, 1, 16, 16, "----"),
+    /* 0xf6 */ op1(Or, D8, 2, 8, 8, "Z000"),
+    /* 0xf7 */ op1(Rst, Vector(0x30), 1, 16, 16, "----"),
+    /* 0xf8 */ op2(Ld, HL, SPR8, 2, 12, 12, "00HC"),
+    /* 0xf9 */ op2(Ld, SP, HL, 1, 8, 8, "----"),
+    /* 0xfa */ op2(Ld, A, A16, 3, 16, 16, "----"),
+    /* 0xfb */ op0(Ei, 1, 4, 4, "----"),
+    /* 0xfc */ op0(Illegal, 1, 4, 4, "----"),
+    /* 0xfd */ op0(Illegal, 1, 4, 4, "----"),
+    /* 0xfe */ op1(Cp, D8, 2, 8, 8, "Z1HC"),
+    /* 0xff */ op1(Rst, Vector(0x38), 1, 16, 16, "----"),
+];
+
+// Opcodes prefixed by 0xcb
+pub static CB_OPCODES: [Opcode; 256] = [
+    /* 0x00 */ op1(Rlc, B, 2, 8, 8, "Z00C"),
+    /* 0x01 */ op1(Rlc, C, 2, 8, 8, "Z00C"),
+    /* 0x02 */ op1(Rlc, D, 2, 8, 8, "Z00C"),
+    /* 0x03 */ op1(Rlc, E, 2, 8, 8, "Z00C"),
+    /* 0x04 */ op1(Rlc, H, 2, 8, 8, "Z00C"),
+    /* 0x05 */ op1(Rlc, L, 2, 8, 8, "Z00C"),
+    /* 0x06 */ op1(Rlc, IndHL, 2, 16, 16, "Z00C"),
+    /* 0x07 */ op1(Rlc, A, 2, 8, 8, "Z00C"),
+    /* 0x08 */ op1(Rrc, B, 2, 8, 8, "Z00C"),
+    /* 0x09 */ op1(Rrc, C, 2, 8, 8, "Z00C"),
+    /* 0x0a */ op1(Rrc, D, 2, 8, 8, "Z00C"),
+    /* 0x0b */ op1(Rrc, E, 2, 8, 8, "Z00C"),
+    /* 0x0c */ op1(Rrc, H, 2, 8, 8, "Z00C"),
+    /* 0x0d */ op1(Rrc, L, 2, 8, 8, "Z00C"),
+    /* 0x0e */ op1(Rrc, IndHL, 2, 16, 16, "Z00C"),
+    /* 0x0f */ op1(Rrc, A, 2, 8, 8, "Z00C"),
+    /* 0x10 */ op1(Rl, B, 2, 8, 8, "Z00C"),
+    /* 0x11 */ op1(Rl, C, 2, 8, 8, "Z00C"),
+    /* 0x12 */ op1(Rl, D, 2, 8, 8, "Z00C"),
+    /* 0x13 */ op1(Rl, E, 2, 8, 8, "Z00C"),
+    /* 0x14 */ op1(Rl, H, 2, 8, 8, "Z00C"),
+    /* 0x15 */ op1(Rl, L, 2, 8, 8, "Z00C"),
+    /* 0x16 */ op1(Rl, IndHL, 2, 16, 16, "Z00C"),
+    /* 0x17 */ op1(Rl, A, 2, 8, 8, "Z00C"),
+    /* 0x18 */ op1(Rr, B, 2, 8, 8, "Z00C"),
+    /* 0x19 */ op1(Rr, C, 2, 8, 8, "Z00C"),
+    /* 0x1a */ op1(Rr, D, 2, 8, 8, "Z00C"),
+    /* 0x1b */ op1(Rr, E, 2, 8, 8, "Z00C"),
+    /* 0x1c */ op1(Rr, H, 2, 8, 8, "Z00C"),
+    /* 0x1d */ op1(Rr, L, 2, 8, 8, "Z00C"),
+    /* 0x1e */ op1(Rr, IndHL, 2, 16, 16, "Z00C"),
+    /* 0x1f */ op1(Rr, A, 2, 8, 8, "Z00C"),
+    /* 0x20 */ op1(Sla, B, 2, 8, 8, "Z00C"),
+    /* 0x21 */ op1(Sla, C, 2, 8, 8, "Z00C"),
+    /* 0x22 */ op1(Sla, D, 2, 8, 8, "Z00C"),
+    /* 0x23 */ op1(Sla, E, 2, 8, 8, "Z00C"),
+    /* 0x24 */ op1(Sla, H, 2, 8, 8, "Z00C"),
+    /* 0x25 */ op1(Sla, L, 2, 8, 8, "Z00C"),
+    /* 0x26 */ op1(Sla, IndHL, 2, 16, 16, "Z00C"),
+    /* 0x27 */ op1(Sla, A, 2, 8, 8, "Z00C"),
+    /* 0x28 */ op1(Sra, B, 2, 8, 8, "Z00C"),
+    /* 0x29 */ op1(Sra, C, 2, 8, 8, "Z00C"),
+    /* 0x2a */ op1(Sra, D, 2, 8, 8, "Z00C"),
+    /* 0x2b */ op1(Sra, E, 2, 8, 8, "Z00C"),
+    /* 0x2c */ op1(Sra, H, 2, 8, 8, "Z00C"),
+    /* 0x2d */ op1(Sra, L, 2, 8, 8, "Z00C"),
+    /* 0x2e */ op1(Sra, IndHL, 2, 16, 16, "Z00C"),
+    /* 0x2f */ op1(Sra, A, 2, 8, 8, "Z00C"),
+    /* 0x30 */ op1(Swap, B, 2, 8, 8, "Z000"),
+    /* 0x31 */ op1(Swap, C, 2, 8, 8, "Z000"),
+    /* 0x32 */ op1(Swap, D, 2, 8, 8, "Z000"),
+    /* 0x33 */ op1(Swap, E, 2, 8, 8, "Z000"),
+    /* 0x34 */ op1(Swap, H, 2, 8, 8, "Z000"),
+    /* 0x35 */ op1(Swap, L, 2, 8, 8, "Z000"),
+    /* 0x36 */ op1(Swap, IndHL, 2, 16, 16, "Z000"),
+    /* 0x37 */ op1(Swap, A, 2, 8, 8, "Z000"),
+    /* 0x38 */ op1(Srl, B, 2, 8, 8, "Z00C"),
+    /* 0x39 */ op1(Srl, C, 2, 8, 8, "Z00C"),
+    /* 0x3a */ op1(Srl, D, 2, 8, 8, "Z00C"),
+    /* 0x3b */ op1(Srl, E, 2, 8, 8, "Z00C"),
+    /* 0x3c */ op1(Srl, H, 2, 8, 8, "Z00C"),
+    /* 0x3d */ op1(Srl, L, 2, 8, 8, "Z00C"),
+    /* 0x3e */ op1(Srl, IndHL, 2, 16, 16, "Z00C"),
+    /* 0x3f */ op1(Srl, A, 2, 8, 8, "Z00C"),
+    /* 0x40 */ op2(Bit, BitNum(0), B, 2, 8, 8, "Z01-"),
+    /* 0x41 */ op2(Bit, BitNum(0), C, 2, 8, 8, "Z01-"),
+    /* 0x42 */ op2(Bit, BitNum(0), D, 2, 8, 8, "Z01-"),
+    /* 0x43 */ op2(Bit, BitNum(0), E, 2, 8, 8, "Z01-"),
+    /* 0x44 */ op2(Bit, BitNum(0), H, 2, 8, 8, "Z01-"),
+    /* 0x45 */ op2(Bit, BitNum(0), L, 2, 8, 8, "Z01-"),
+    /* 0x46 */ op2(Bit, BitNum(0), IndHL, 2, 12, 12, "Z01-"),
+    /* 0x47 */ op2(Bit, BitNum(0), A, 2, 8, 8, "Z01-"),
+    /* 0x48 */ op2(Bit, BitNum(1), B, 2, 8, 8, "Z01-"),
+    /* 0x49 */ op2(Bit, BitNum(1), C, 2, 8, 8, "Z01-"),
+    /* 0x4a */ op2(Bit, BitNum(1), D, 2, 8, 8, "Z01-"),
+    /* 0x4b */ op2(Bit, BitNum(1), E, 2, 8, 8, "Z01-"),
+    /* 0x4c */ op2(Bit, BitNum(1), H, 2, 8, 8, "Z01-"),
+    /* 0x4d */ op2(Bit, BitNum(1), L, 2, 8, 8, "Z01-"),
+    /* 0x4e */ op2(Bit, BitNum(1), IndHL, 2, 12, 12, "Z01-"),
+    /* 0x4f */ op2(Bit, BitNum(1), A, 2, 8, 8, "Z01-"),
+    /* 0x50 */ op2(Bit, BitNum(2), B, 2, 8, 8, "Z01-"),
+    /* 0x51 */ op2(Bit, BitNum(2), C, 2, 8, 8, "Z01-"),
+    /* 0x52 */ op2(Bit, BitNum(2), D, 2, 8, 8, "Z01-"),
+    /* 0x53 */ op2(Bit, BitNum(2), E, 2, 8, 8, "Z01-"),
+    /* 0x54 */ op2(Bit, BitNum(2), H, 2, 8, 8, "Z01-"),
+    /* 0x55 */ op2(Bit, BitNum(2), L, 2, 8, 8, "Z01-"),
+    /* 0x56 */ op2(Bit, BitNum(2), IndHL, 2, 12, 12, "Z01-"),
+    /* 0x57 */ op2(Bit, BitNum(2), A, 2, 8, 8, "Z01-"),
+    /* 0x58 */ op2(Bit, BitNum(3), B, 2, 8, 8, "Z01-"),
+    /* 0x59 */ op2(Bit, BitNum(3), C, 2, 8, 8, "Z01-"),
+    /* 0x5a */ op2(Bit, BitNum(3), D, 2, 8, 8, "Z01-"),
+    /* 0x5b */ op2(Bit, BitNum(3), E, 2, 8, 8, "Z01-"),
+    /* 0x5c */ op2(Bit, BitNum(3), H, 2, 8, 8, "Z01-"),
+    /* 0x5d */ op2(Bit, BitNum(3), L, 2, 8, 8, "Z01-"),
+    /* 0x5e */ op2(Bit, BitNum(3), IndHL, 2, 12, 12, "Z01-"),
+    /* 0x5f */ op2(Bit, BitNum(3), A, 2, 8, 8, "Z01-"),
+    /* 0x60 */ op2(Bit, BitNum(4), B, 2, 8, 8, "Z01-"),
+    /* 0x61 */ op2(Bit, BitNum(4), C, 2, 8, 8, "Z01-"),
+    /* 0x62 */ op2(Bit, BitNum(4), D, 2, 8, 8, "Z01-"),
+    /* 0x63 */ op2(Bit, BitNum(4), E, 2, 8, 8, "Z01-"),
+    /* 0x64 */ op2(Bit, BitNum(4), H, 2, 8, 8, "Z01-"),
+    /* 0x65 */ op2(Bit, BitNum(4), L, 2, 8, 8, "Z01-"),
+    /* 0x66 */ op2(Bit, BitNum(4), IndHL, 2, 12, 12, "Z01-"),
+    /* 0x67 */ op2(Bit, BitNum(4), A, 2, 8, 8, "Z01-"),
+    /* 0x68 */ op2(Bit, BitNum(5), B, 2, 8, 8, "Z01-"),
+    /* 0x69 */ op2(Bit, BitNum(5), C, 2, 8, 8, "Z01-"),
+    /* 0x6a */ op2(Bit, BitNum(5), D, 2, 8, 8, "Z01-"),
+    /* 0x6b */ op2(Bit, BitNum(5), E, 2, 8, 8, "Z01-"),
+    /* 0x6c */ op2(Bit, BitNum(5), H, 2, 8, 8, "Z01-"),
+    /* 0x6d */ op2(Bit, BitNum(5), L, 2, 8, 8, "Z01-"),
+    /* 0x6e */ op2(Bit, BitNum(5), IndHL, 2, 12, 12, "Z01-"),
+    /* 0x6f */ op2(Bit, BitNum(5), A, 2, 8, 8, "Z01-"),
+    /* 0x70 */ op2(Bit, BitNum(6), B, 2, 8, 8, "Z01-"),
+    /* 0x71 */ op2(Bit, BitNum(6), C, 2, 8, 8, "Z01-"),
+    /* 0x72 */ op2(Bit, BitNum(6), D, 2, 8, 8, "Z01-"),
+    /* 0x73 */ op2(Bit, BitNum(6), E, 2, 8, 8, "Z01-"),
+    /* 0x74 */ op2(Bit, BitNum(6), H, 2, 8, 8, "Z01-"),
+    /* 0x75 */ op2(Bit, BitNum(6), L, 2, 8, 8, "Z01-"),
+    /* 0x76 */ op2(Bit, BitNum(6), IndHL, 2, 12, 12, "Z01-"),
+    /* 0x77 */ op2(Bit, BitNum(6), A, 2, 8, 8, "Z01-"),
+    /* 0x78 */ op2(Bit, BitNum(7), B, 2, 8, 8, "Z01-"),
+    /* 0x79 */ op2(Bit, BitNum(7), C, 2, 8, 8, "Z01-"),
+    /* 0x7a */ op2(Bit, BitNum(7), D, 2, 8, 8, "Z01-"),
+    /* 0x7b */ op2(Bit, BitNum(7), E, 2, 8, 8, "Z01-"),
+    /* 0x7c */ op2(Bit, BitNum(7), H, 2, 8, 8, "Z01-"),
+    /* 0x7d */ op2(Bit, BitNum(7), L, 2, 8, 8, "Z01-"),
+    /* 0x7e */ op2(Bit, BitNum(7), IndHL, 2, 12, 12, "Z01-"),
+    /* 0x7f */ op2(Bit, BitNum(7), A, 2, 8, 8, "Z01-"),
+    /* 0x80 */ op2(Res, BitNum(0), B, 2, 8, 8, "----"),
+    /* 0x81 */ op2(Res, BitNum(0), C, 2, 8, 8, "----"),
+    /* 0x82 */ op2(Res, BitNum(0), D, 2, 8, 8, "----"),
+    /* 0x83 */ op2(Res, BitNum(0), E, 2, 8, 8, "----"),
+    /* 0x84 */ op2(Res, BitNum(0), H, 2, 8, 8, "----"),
+    /* 0x85 */ op2(Res, BitNum(0), L, 2, 8, 8, "----"),
+    /* 0x86 */ op2(Res, BitNum(0), IndHL, 2, 16, 16, "----"),
+    /* 0x87 */ op2(Res, BitNum(0), A, 2, 8, 8, "----"),
+    /* 0x88 */ op2(Res, BitNum(1), B, 2, 8, 8, "----"),
+    /* 0x89 */ op2(Res, BitNum(1), C, 2, 8, 8, "----"),
+    /* 0x8a */ op2(Res, BitNum(1), D, 2, 8, 8, "----"),
+    /* 0x8b */ op2(Res, BitNum(1), E, 2, 8, 8, "----"),
+    /* 0x8c */ op2(Res, BitNum(1), H, 2, 8, 8, "----"),
+    /* 0x8d */ op2(Res, BitNum(1), L, 2, 8, 8, "----"),
+    /* 0x8e */ op2(Res, BitNum(1), IndHL, 2, 16, 16, "----"),
+    /* 0x8f */ op2(Res, BitNum(1), A, 2, 8, 8, "----"),
+    /* 0x90 */ op2(Res, BitNum(2), B, 2, 8, 8, "----"),
+    /* 0x91 */ op2(Res, BitNum(2), C, 2, 8, 8, "----"),
+    /* 0x92 */ op2(Res, BitNum(2), D, 2, 8, 8, "----"),
+    /* 0x93 */ op2(Res, BitNum(2), E, 2, 8, 8, "----"),
+    /* 0x94 */ op2(Res, BitNum(2), H, 2, 8, 8, "----"),
+    /* 0x95 */ op2(Res, BitNum(2), L, 2, 8, 8, "----"),
+    /* 0x96 */ op2(Res, BitNum(2), IndHL, 2, 16, 16, "----"),
+    /* 0x97 */ op2(Res, BitNum(2), A, 2, 8, 8, "----"),
+    /* 0x98 */ op2(Res, BitNum(3), B, 2, 8, 8, "----"),
+    /* 0x99 */ op2(Res, BitNum(3), C, 2, 8, 8, "----"),
+    /* 0x9a */ op2(Res, BitNum(3), D, 2, 8, 8, "----"),
+    /* 0x9b */ op2(Res, BitNum(3), E, 2, 8, 8, "----"),
+    /* 0x9c */ op2(Res, BitNum(3), H, 2, 8, 8, "----"),
+    /* 0x9d */ op2(Res, BitNum(3), L, 2, 8, 8, "----"),
+    /* 0x9e */ op2(Res, BitNum(3), IndHL, 2, 16, 16, "----"),
+    /* 0x9f */ op2(Res, BitNum(3), A, 2, 8, 8, "----"),
+    /* 0xa0 */ op2(Res, BitNum(4), B, 2, 8, 8, "----"),
+    /* 0xa1 */ op2(Res, BitNum(4), C, 2, 8, 8, "----"),
+    /* 0xa2 */ op2(Res, BitNum(4), D, 2, 8, 8, "----"),
+    /* 0xa3 */ op2(Res, BitNum(4), E, 2, 8, 8, "----"),
+    /* 0xa4 */ op2(Res, BitNum(4), H, 2, 8, 8, "----"),
+    /* 0xa5 */ op2(Res, BitNum(4), L, 2, 8, 8, "----"),
+    /* 0xa6 */ op2(Res, BitNum(4), IndHL, 2, 16, 16, "----"),
+    /* 0xa7 */ op2(Res, BitNum(4), A, 2, 8, 8, "----"),
+    /* 0xa8 */ op2(Res, BitNum(5), B, 2, 8, 8, "----"),
+    /* 0xa9 */ op2(Res, BitNum(5), C, 2, 8, 8, "----"),
+    /* 0xaa */ op2(Res, BitNum(5), D, 2, 8, 8, "----"),
+    /* 0xab */ op2(Res, BitNum(5), E, 2, 8, 8, "----"),
+    /* 0xac */ op2(Res, BitNum(5), H, 2, 8, 8, "----"),
+    /* 0xad */ op2(Res, BitNum(5), L, 2, 8, 8, "----"),
+    /* 0xae */ op2(Res, BitNum(5), IndHL, 2, 16, 16, "----"),
+    /* 0xaf */ op2(Res, BitNum(5), A, 2, 8, 8, "----"),
+    /* 0xb0 */ op2(Res, BitNum(6), B, 2, 8, 8, "----"),
+    /* 0xb1 */ op2(Res, BitNum(6), C, 2, 8, 8, "----"),
+    /* 0xb2 */ op2(Res, BitNum(6), D, 2, 8, 8, "----"),
+    /* 0xb3 */ op2(Res, BitNum(6), E, 2, 8, 8, "----"),
+    /* 0xb4 */ op2(Res, BitNum(6), H, 2, 8, 8, "----"),
+    /* 0xb5 */ op2(Res, BitNum(6), L, 2, 8, 8, "----"),
+    /* 0xb6 */ op2(Res, BitNum(6), IndHL, 2, 16, 16, "----"),
+    /* 0xb7 */ op2(Res, BitNum(6), A, 2, 8, 8, "----"),
+    /* 0xb8 */ op2(Res, BitNum(7), B, 2, 8, 8, "----"),
+    /* 0xb9 */ op2(Res, BitNum(7), C, 2, 8, 8, "----"),
+    /* 0xba */ op2(Res, BitNum(7), D, 2, 8, 8, "----"),
+    /* 0xbb */ op2(Res, BitNum(7), E, 2, 8, 8, "----"),
+    /* 0xbc */ op2(Res, BitNum(7), H, 2, 8, 8, "----"),
+    /* 0xbd */ op2(Res, BitNum(7), L, 2, 8, 8, "----"),
+    /* 0xbe */ op2(Res, BitNum(7), IndHL, 2, 16, 16, "----"),
+    /* 0xbf */ op2(Res, BitNum(7), A, 2, 8, 8, "----"),
+    /* 0xc0 */ op2(Set, BitNum(0), B, 2, 8, 8, "----"),
+    /* 0xc1 */ op2(Set, BitNum(0), C, 2, 8, 8, "----"),
+    /* 0xc2 */ op2(Set, BitNum(0), D, 2, 8, 8, "----"),
+    /* 0xc3 */ op2(Set, BitNum(0), E, 2, 8, 8, "----"),
+    /* 0xc4 */ op2(Set, BitNum(0), H, 2, 8, 8, "----"),
+    /* 0xc5 */ op2(Set, BitNum(0), L, 2, 8, 8, "----"),
+    /* 0xc6 */ op2(Set, BitNum(0), IndHL, 2, 16, 16, "----"),
+    /* 0xc7 */ op2(Set, BitNum(0), A, 2, 8, 8, "----"),
+    /* 0xc8 */ op2(Set, BitNum(1), B, 2, 8, 8, "----"),
+    /* 0xc9 */ op2(Set, BitNum(1), C, 2, 8, 8, "----"),
+    /* 0xca */ op2(Set, BitNum(1), D, 2, 8, 8, "----"),
+    /* 0xcb */ op2(Set, BitNum(1), E, 2, 8, 8, "----"),
+    /* 0xcc */ op2(Set, BitNum(1), H, 2, 8, 8, "----"),
+    /* 0xcd */ op2(Set, BitNum(1), L, 2, 8, 8, "----"),
+    /* 0xce */ op2(Set, BitNum(1), IndHL, 2, 16, 16, "----"),
+    /* 0xcf */ op2(Set, BitNum(1), A, 2, 8, 8, "----"),
+    /* 0xd0 */ op2(Set, BitNum(2), B, 2, 8, 8, "----"),
+    /* 0xd1 */ op2(Set, BitNum(2), C, 2, 8, 8, "----"),
+    /* 0xd2 */ op2(Set, BitNum(2), D, 2, 8, 8, "----"),
+    /* 0xd3 */ op2(Set, BitNum(2), E, 2, 8, 8, "----"),
+    /* 0xd4 */ op2(Set, BitNum(2), H, 2, 8, 8, "----"),
+    /* 0xd5 */ op2(Set, BitNum(2), L, 2, 8, 8, "----"),
+    /* 0xd6 */ op2(Set, BitNum(2), IndHL, 2, 16, 16, "----"),
+    /* 0xd7 */ op2(Set, BitNum(2), A, 2, 8, 8, "----"),
+    /* 0xd8 */ op2(Set, BitNum(3), B, 2, 8, 8, "----"),
+    /* 0xd9 */ op2(Set, BitNum(3), C, 2, 8, 8, "----"),
+    /* 0xda */ op2(Set, BitNum(3), D, 2, 8, 8, "----"),
+    /* 0xdb */ op2(Set, BitNum(3), E, 2, 8, 8, "----"),
+    /* 0xdc */ op2(Set, BitNum(3), H, 2, 8, 8, "----"),
+    /* 0xdd */ op2(Set, BitNum(3), L, 2, 8, 8, "----"),
+    /* 0xde */ op2(Set, BitNum(3), IndHL, 2, 16, 16, "----"),
+    /* 0xdf */ op2(Set, BitNum(3), A, 2, 8, 8, "----"),
+    /* 0xe0 */ op2(Set, BitNum(4), B, 2, 8, 8, "----"),
+    /* 0xe1 */ op2(Set, BitNum(4), C, 2, 8, 8, "----"),
+    /* 0xe2 */ op2(Set, BitNum(4), D, 2, 8, 8, "----"),
+    /* 0xe3 */ op2(Set, BitNum(4), E, 2, 8, 8, "----"),
+    /* 0xe4 */ op2(Set, BitNum(4), H, 2, 8, 8, "----"),
+    /* 0xe5 */ op2(Set, BitNum(4), L, 2, 8, 8, "----"),
+    /* 0xe6 */ op2(Set, BitNum(4), IndHL, 2, 16, 16, "----"),
+    /* 0xe7 */ op2(Set, BitNum(4), A, 2, 8, 8, "----"),
+    /* 0xe8 */ op2(Set, BitNum(5), B, 2, 8, 8, "----"),
+    /* 0xe9 */ op2(Set, BitNum(5), C, 2, 8, 8, "----"),
+    /* 0xea */ op2(Set, BitNum(5), D, 2, 8, 8, "----"),
+    /* 0xeb */ op2(Set, BitNum(5), E, 2, 8, 8, "----"),
+    /* 0xec */ op2(Set, BitNum(5), H, 2, 8, 8, "----"),
+    /* 0xed */ op2(Set, BitNum(5), L, 2, 8, 8, "----"),
+    /* 0xee */ op2(Set, BitNum(5), IndHL, 2, 16, 16, "----"),
+    /* 0xef */ op2(Set, BitNum(5), A, 2, 8, 8, "----"),
+    /* 0xf0 */ op2(Set, BitNum(6), B, 2, 8, 8, "----"),
+    /* 0xf1 */ op2(Set, BitNum(6), C, 2, 8, 8, "----"),
+    /* 0xf2 */ op2(Set, BitNum(6), D, 2, 8, 8, "----"),
+    /* 0xf3 */ op2(Set, BitNum(6), E, 2, 8, 8, "----"),
+    /* 0xf4 */ op2(Set, BitNum(6), H, 2, 8, 8, "----"),
+    /* 0xf5 */ op2(Set, BitNum(6), L, 2, 8, 8, "----"),
+    /* 0xf6 */ op2(Set, BitNum(6), IndHL, 2, 16, 16, "----"),
+    /* 0xf7 */ op2(Set, BitNum(6), A, 2, 8, 8, "----"),
+    /* 0xf8 */ op2(Set, BitNum(7), B, 2, 8, 8, "----"),
+    /* 0xf9 */ op2(Set, BitNum(7), C, 2, 8, 8, "----"),
+    /* 0xfa */ op2(Set, BitNum(7), D, 2, 8, 8, "----"),
+    /* 0xfb */ op2(Set, BitNum(7), E, 2, 8, 8, "----"),
+    /* 0xfc */ op2(Set, BitNum(7), H, 2, 8, 8, "----"),
+    /* 0xfd */ op2(Set, BitNum(7), L, 2, 8, 8, "----"),
+    /* 0xfe */ op2(Set, BitNum(7), IndHL, 2, 16, 16, "----"),
+    /* 0xff */ op2(Set, BitNum(7), A, 2, 8, 8, "----"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(address: u16, bytes: &[u8]) -> String {
+        let mut padded = bytes.to_vec();
+        padded.resize(3, 0);
+
+        disassemble(address, &padded)
+    }
+
+    // Names for some data, and code at $0150
+    fn labelled(address: u16, bytes: &[u8]) -> String {
+        let mut padded = bytes.to_vec();
+        padded.resize(3, 0);
+
+        disassemble_labelled(address, &padded, &|target, code| match (target, code) {
+            (0xff40, _) => Some("rLCDC".to_string()),
+            (0xc000, false) => Some("wCounter".to_string()),
+            (0x0150, true) => Some("Loop".to_string()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn operands() {
+        assert_eq!(text(0x0100, &[0x00]), "NOP");
+        assert_eq!(text(0x0100, &[0x01, 0x34, 0x12]), "LD bc,$1234");
+        assert_eq!(text(0x0100, &[0x08, 0x00, 0xc0]), "LD [$c000],sp");
+        assert_eq!(text(0x0100, &[0x22]), "LD [hl+],a");
+        assert_eq!(text(0x0100, &[0x3a]), "LD a,[hl-]");
+        assert_eq!(text(0x0100, &[0x3e, 0x3f]), "LD a,$3f");
+        assert_eq!(text(0x0100, &[0xe0, 0x40]), "LDH [$ff40],a");
+        assert_eq!(text(0x0100, &[0xf0, 0x44]), "LDH a,[$ff44]");
+        assert_eq!(text(0x0100, &[0xe2]), "LDH [c],a");
+        assert_eq!(text(0x0100, &[0xe8, 0xf0]), "ADD sp,-$10");
+        assert_eq!(text(0x0100, &[0xf8, 0x02]), "LD hl,sp+$02");
+        assert_eq!(text(0x0100, &[0xf5]), "PUSH af");
+        assert_eq!(text(0x0100, &[0xff]), "RST $38");
+        assert_eq!(text(0x0100, &[0x10, 0x00]), "STOP $00");
+        assert_eq!(text(0x0100, &[0xd3]), "ILLEGAL");
+    }
+
+    #[test]
+    fn jumps() {
+        assert_eq!(text(0x0150, &[0x18, 0xfe]), "JR $0150");
+        assert_eq!(text(0x0150, &[0x20, 0x05]), "JR nz,$0157");
+        assert_eq!(text(0x0150, &[0x38, 0x80]), "JR c,$00d2");
+        assert_eq!(text(0x0150, &[0xc3, 0x00, 0x40]), "JP $4000");
+        assert_eq!(text(0x0150, &[0xcc, 0x00, 0x40]), "CALL z,$4000");
+        assert_eq!(text(0x0150, &[0xe9]), "JP hl");
+        assert_eq!(text(0x0150, &[0xd0]), "RET nc");
+        // Wrapping around the address space
+        assert_eq!(text(0xfffe, &[0x18, 0x01]), "JR $0001");
+    }
+
+    #[test]
+    fn prefixed() {
+        assert_eq!(text(0x0100, &[0xcb, 0x7c]), "BIT 7,h");
+        assert_eq!(text(0x0100, &[0xcb, 0x46]), "BIT 0,[hl]");
+        assert_eq!(text(0x0100, &[0xcb, 0x9f]), "RES 3,a");
+        assert_eq!(text(0x0100, &[0xcb, 0xfe]), "SET 7,[hl]");
+        assert_eq!(text(0x0100, &[0xcb, 0x37]), "SWAP a");
+        assert_eq!(text(0x0100, &[0xcb, 0x11]), "RL c");
+        assert_eq!(lookup(&[0xcb, 0x11]).length, 2);
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(labelled(0x0100, &[0xe0, 0x40]), "LDH [rLCDC],a");
+        assert_eq!(labelled(0x0100, &[0xf0, 0x40]), "LDH a,[rLCDC]");
+        assert_eq!(labelled(0x0100, &[0xfa, 0x00, 0xc0]), "LD a,[wCounter]");
+        assert_eq!(labelled(0x0100, &[0x21, 0x00, 0xc0]), "LD hl,wCounter");
+        assert_eq!(labelled(0x0155, &[0x18, 0xf9]), "JR Loop");
+        assert_eq!(labelled(0x0100, &[0xc2, 0x50, 0x01]), "JP nz,Loop");
+        assert_eq!(labelled(0x0100, &[0xcd, 0x50, 0x01]), "CALL Loop");
+        // Code and data are named separately
+        assert_eq!(labelled(0x0100, &[0xcd, 0x00, 0xc0]), "CALL $c000");
+        assert_eq!(labelled(0x0100, &[0x21, 0x50, 0x01]), "LD hl,$0150");
+        // Unnamed
+        assert_eq!(labelled(0x0100, &[0xe0, 0x41]), "LDH [$ff41],a");
+        assert_eq!(labelled(0x0100, &[0xcb, 0x7c]), "BIT 7,h");
+    }
+}
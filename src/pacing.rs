@@ -0,0 +1,98 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use gameperson::gameboy::CYCLES_PER_SECOND;
+
+// How far behind the emulation can get before giving up catching up,
+// e.g. after being stopped in the debugger
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+// Keeps the emulation running at the speed of the real thing, or a
+// multiple of it, by sleeping until the wall clock reaches the emulated
+// time.
+pub struct Pacer {
+    // Wall clock and emulated T-cycles when the speed was last changed
+    start: Instant,
+    start_cycles: u64,
+
+    // Multiple of the real speed, None for as fast as possible
+    speed: Option<f64>,
+}
+
+impl Pacer {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            start_cycles: 0,
+            speed: Some(1.0),
+        }
+    }
+
+    pub fn set_speed(&mut self, speed: Option<f64>, cycles: u64) {
+        if speed != self.speed {
+            self.speed = speed;
+            self.restart(cycles);
+        }
+    }
+
+    // Start counting again from now, e.g. after a pause
+    pub fn restart(&mut self, cycles: u64) {
+        self.start = Instant::now();
+        self.start_cycles = cycles;
+    }
+
+    // Wait until it's time for the emulation to be at cycles
+    #[allow(clippy::cast_precision_loss)]
+    pub fn wait(&mut self, cycles: u64) {
+        let Some(speed) = self.speed else {
+            return;
+        };
+
+        let emulated = (cycles - self.start_cycles) as f64 / f64::from(CYCLES_PER_SECOND);
+        let target = self.start + Duration::from_secs_f64(emulated / speed);
+        let now = Instant::now();
+
+        if target > now {
+            thread::sleep(target - now);
+        } else if now - target > MAX_LAG {
+            self.restart(cycles);
+        }
+    }
+}
+
+// Measures frames and emulated T-cycles per second
+pub struct Meter {
+    start: Instant,
+    start_frame: u64,
+    start_cycles: u64,
+}
+
+impl Meter {
+    pub fn new(frame: u64, cycles: u64) -> Self {
+        Self {
+            start: Instant::now(),
+            start_frame: frame,
+            start_cycles: cycles,
+        }
+    }
+
+    // Once a second, returns the frames per second and the speed compared
+    // to the real thing.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn update(&mut self, frame: u64, cycles: u64) -> Option<(f64, f64)> {
+        let elapsed = self.start.elapsed().as_secs_f64();
+
+        if elapsed < 1.0 {
+            return None;
+        }
+
+        let fps = (frame - self.start_frame) as f64 / elapsed;
+        let speed = (cycles - self.start_cycles) as f64 / f64::from(CYCLES_PER_SECOND) / elapsed;
+
+        self.start = Instant::now();
+        self.start_frame = frame;
+        self.start_cycles = cycles;
+
+        Some((fps, speed))
+    }
+}
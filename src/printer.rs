@@ -0,0 +1,446 @@
+use std::convert::TryFrom;
+use std::mem;
+use std::path::PathBuf;
+
+use crate::image;
+use crate::serial::LinkPartner;
+
+// Game Boy Printer
+//
+// The Game Boy talks to the printer with packets:
+//
+//   0x88 0x33 - magic bytes
+//   command   - 0x01 INIT, 0x02 PRINT, 0x04 DATA, 0x0f STATUS
+//   compression (0: none, 1: RLE)
+//   length    - of data, 2 bytes, little endian
+//   data
+//   checksum  - sum of command to data, 2 bytes, little endian
+//   0x00      - the printer replies 0x81 (alive)
+//   0x00      - the printer replies with its status
+//
+// The printer replies 0x00 to all the other bytes.
+//
+// DATA carries 640 bytes (2 rows of 20 tiles) of the image at a time,
+// PRINT prints what was received with:
+//   sheets  - copies, 0 to just feed paper
+//   margins - high nibble: lines fed before, low nibble: lines fed after
+//   palette - like BGP
+//   exposure
+//
+// Every print job is saved as a PNG in dir. Consecutive PRINTs without
+// margins between them belong to the same job (e.g. a long picture
+// printed in several parts).
+pub struct Printer {
+    dir: PathBuf,
+
+    state: State,
+
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    checksum: u16,
+
+    // Decompressed image data received with DATA
+    buffer: Vec<u8>,
+
+    // Rows of pixels printed in the current job, as shades (0: white)
+    page: Vec<u8>,
+
+    status: u8,
+
+    // M-cycles left before printing is done
+    printing: u32,
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+
+// Status bits
+const STATUS_CHECKSUM_ERROR: u8 = 1 << 0;
+const STATUS_PRINTING: u8 = 1 << 1;
+const STATUS_IMAGE_FULL: u8 = 1 << 2;
+const STATUS_UNPROCESSED: u8 = 1 << 3;
+
+const WIDTH: usize = 160;
+
+// 20 tiles of 16 bytes, 8 lines each
+const BYTES_PER_TILE_ROW: usize = 20 * 16;
+
+// The printer holds up to 9 DATA packets (144 lines)
+const BUFFER_SIZE: usize = 9 * 2 * BYTES_PER_TILE_ROW;
+
+// About a second
+const PRINTING_CYCLES: u32 = 1 << 20;
+
+impl Printer {
+    #[must_use]
+    pub const fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            state: State::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            buffer: Vec::new(),
+            page: Vec::new(),
+            status: 0,
+            printing: 0,
+        }
+    }
+
+    fn execute(&mut self) {
+        match self.command {
+            INIT => {
+                self.buffer.clear();
+                self.status = 0;
+            }
+            // The 4th byte is exposure, there's no paper to burn here
+            PRINT if self.data.len() == 4 => {
+                self.print(self.data[0], self.data[1], self.data[2]);
+            }
+            DATA => {
+                let data = mem::take(&mut self.data);
+
+                if self.compressed {
+                    self.decompress(&data);
+                } else {
+                    self.buffer.extend_from_slice(&data);
+                }
+                self.buffer.truncate(BUFFER_SIZE);
+
+                if !self.buffer.is_empty() {
+                    self.status |= STATUS_UNPROCESSED;
+                }
+                if self.buffer.len() == BUFFER_SIZE {
+                    self.status |= STATUS_IMAGE_FULL;
+                }
+            }
+            // STATUS only asks for the status, sent with every packet
+            _ => (),
+        }
+    }
+
+    // RLE: a byte with bit 7 set is followed by a byte repeated
+    // (n & 0x7f) + 2 times, otherwise by n + 1 bytes to copy.
+    fn decompress(&mut self, mut data: &[u8]) {
+        while let Some((&n, rest)) = data.split_first() {
+            if n & 0x80 == 0 {
+                let len = (usize::from(n) + 1).min(rest.len());
+
+                self.buffer.extend_from_slice(&rest[..len]);
+                data = &rest[len..];
+            } else if let Some((&byte, rest)) = rest.split_first() {
+                let len = usize::from(n & 0x7f) + 2;
+
+                self.buffer.resize(self.buffer.len() + len, byte);
+                data = rest;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn print(&mut self, sheets: u8, margins: u8, palette: u8) {
+        if margins >> 4 != 0 {
+            self.save_page();
+        }
+
+        let buffer = mem::take(&mut self.buffer);
+
+        if sheets > 0 {
+            // Palette 0x00 is used by some games as the default one
+            let palette = if palette == 0 { 0b1110_0100 } else { palette };
+
+            for _ in 0..sheets {
+                self.render(&buffer, palette);
+            }
+        }
+
+        if margins & 0x0f != 0 {
+            self.save_page();
+        }
+
+        self.status = (self.status | STATUS_PRINTING) & !(STATUS_UNPROCESSED | STATUS_IMAGE_FULL);
+        self.printing = PRINTING_CYCLES;
+    }
+
+    // Decode the 2bpp tiles, 20 per row, to shades.
+    fn render(&mut self, tiles: &[u8], palette: u8) {
+        for tile_row in tiles.chunks_exact(BYTES_PER_TILE_ROW) {
+            for line in 0..8 {
+                for x in 0..WIDTH {
+                    let tile = &tile_row[(x / 8) * 16..];
+                    let bit = 7 - (x % 8);
+
+                    let color =
+                        (tile[line * 2] >> bit) & 1 | ((tile[line * 2 + 1] >> bit) & 1) << 1;
+
+                    self.page.push((palette >> (color * 2)) & 0b11);
+                }
+            }
+        }
+    }
+
+    fn save_page(&mut self) {
+        if self.page.is_empty() {
+            return;
+        }
+
+        let page = mem::take(&mut self.page);
+
+        let pixels: Vec<u8> = page
+            .iter()
+            .flat_map(|shade| {
+                let gray = 0xff - shade * 0x55;
+                [gray, gray, gray]
+            })
+            .collect();
+
+        let path = image::numbered_path(&self.dir, "print", "png");
+        let (Ok(width), Ok(height)) = (u32::try_from(WIDTH), u32::try_from(page.len() / WIDTH))
+        else {
+            eprintln!("Can't save {}: the print is too long", path.display());
+            return;
+        };
+
+        match image::save_png(&path, width, height, &pixels) {
+            Ok(()) => println!("Printed {}", path.display()),
+            Err(err) => eprintln!("Can't save {}: {err}", path.display()),
+        }
+    }
+}
+
+impl LinkPartner for Printer {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        let mut reply = 0x00;
+
+        self.state = match self.state {
+            // A repeated first magic byte still starts a packet
+            State::Magic1 | State::Magic2 if byte == 0x88 => State::Magic2,
+            State::Magic2 if byte == 0x33 => State::Command,
+            State::Magic1 | State::Magic2 => State::Magic1,
+            State::Command => {
+                self.command = byte;
+                self.checksum = u16::from(byte);
+                State::Compression
+            }
+            State::Compression => {
+                self.compressed = byte & 1 != 0;
+                self.checksum += u16::from(byte);
+                State::LengthLow
+            }
+            State::LengthLow => {
+                self.length = u16::from(byte);
+                self.checksum += u16::from(byte);
+                State::LengthHigh
+            }
+            State::LengthHigh => {
+                self.length |= u16::from(byte) << 8;
+                self.checksum += u16::from(byte);
+                self.data.clear();
+
+                if self.length == 0 {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                }
+            }
+            State::Data => {
+                self.data.push(byte);
+                self.checksum = self.checksum.wrapping_add(u16::from(byte));
+
+                if self.data.len() == usize::from(self.length) {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                }
+            }
+            State::ChecksumLow => {
+                self.checksum ^= u16::from(byte);
+                State::ChecksumHigh
+            }
+            State::ChecksumHigh => {
+                self.checksum ^= u16::from(byte) << 8;
+
+                if self.checksum == 0 {
+                    self.status &= !STATUS_CHECKSUM_ERROR;
+                    self.execute();
+                } else {
+                    self.status |= STATUS_CHECKSUM_ERROR;
+                }
+                State::Alive
+            }
+            State::Alive => {
+                reply = 0x81;
+                State::Status
+            }
+            State::Status => {
+                reply = self.status;
+                State::Magic1
+            }
+        };
+
+        reply
+    }
+
+    fn tick(&mut self, _outgoing: Option<u8>) -> Option<u8> {
+        if self.printing > 0 {
+            self.printing -= 1;
+
+            if self.printing == 0 {
+                self.status &= !STATUS_PRINTING;
+            }
+        }
+
+        None
+    }
+}
+
+impl Drop for Printer {
+    // Don't lose a job still waiting for its final margin
+    fn drop(&mut self) {
+        self.save_page();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The replies to every byte sent
+    fn send(printer: &mut Printer, bytes: &[u8]) -> Vec<u8> {
+        bytes.iter().map(|&byte| printer.exchange(byte)).collect()
+    }
+
+    // The printer's replies to the last two bytes of a packet
+    fn replies(printer: &mut Printer, bytes: &[u8]) -> (u8, u8) {
+        let replies = send(printer, bytes);
+        let (&alive, &status) = (&replies[replies.len() - 2], &replies[replies.len() - 1]);
+
+        assert!(replies[..replies.len() - 2].iter().all(|&reply| reply == 0));
+
+        (alive, status)
+    }
+
+    fn printer() -> Printer {
+        Printer::new(std::env::temp_dir())
+    }
+
+    const INIT_PACKET: [u8; 10] = [0x88, 0x33, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00];
+
+    // The first line of the first tile in color 1, the rest of the 2 rows
+    // of tiles in color 0, RLE compressed
+    const DATA_PACKET: [u8; 23] = [
+        0x88, 0x33, 0x04, 0x01, 0x0d, 0x00, // header, 13 bytes
+        0x01, 0xff, 0x00, // 2 bytes copied
+        0xff, 0x00, 0xff, 0x00, 0xff, 0x00, 0xff, 0x00, // 4 times 129 0x00
+        0xf8, 0x00, // 122 0x00
+        0x06, 0x06, // checksum
+        0x00, 0x00,
+    ];
+
+    // 1 sheet, no margins, palette 0xe4, exposure 0x40
+    const PRINT_PACKET: [u8; 14] = [
+        0x88, 0x33, 0x02, 0x00, 0x04, 0x00, 0x01, 0x00, 0xe4, 0x40, 0x2b, 0x01, 0x00, 0x00,
+    ];
+
+    const STATUS_PACKET: [u8; 10] = [0x88, 0x33, 0x0f, 0x00, 0x00, 0x00, 0x0f, 0x00, 0x00, 0x00];
+
+    #[test]
+    fn print() {
+        let mut printer = printer();
+
+        assert_eq!(replies(&mut printer, &INIT_PACKET), (0x81, 0x00));
+
+        assert_eq!(
+            replies(&mut printer, &DATA_PACKET),
+            (0x81, STATUS_UNPROCESSED)
+        );
+        assert_eq!(printer.buffer.len(), 2 * BYTES_PER_TILE_ROW);
+        assert_eq!(printer.buffer[..3], [0xff, 0x00, 0x00]);
+
+        assert_eq!(
+            replies(&mut printer, &PRINT_PACKET),
+            (0x81, STATUS_PRINTING)
+        );
+
+        // 16 lines, the first 8 pixels in shade 1
+        assert_eq!(printer.page.len(), 16 * WIDTH);
+        assert_eq!(printer.page[..9], [1, 1, 1, 1, 1, 1, 1, 1, 0]);
+        assert!(printer.page[WIDTH..].iter().all(|&shade| shade == 0));
+
+        for _ in 0..PRINTING_CYCLES {
+            printer.tick(None);
+        }
+        assert_eq!(replies(&mut printer, &STATUS_PACKET), (0x81, 0x00));
+
+        // Nothing to save on drop
+        printer.page.clear();
+    }
+
+    #[test]
+    fn checksum_error() {
+        let mut printer = printer();
+
+        let mut packet = DATA_PACKET;
+        packet[19] ^= 1;
+        assert_eq!(
+            replies(&mut printer, &packet),
+            (0x81, STATUS_CHECKSUM_ERROR)
+        );
+        assert!(printer.buffer.is_empty());
+
+        assert_eq!(replies(&mut printer, &STATUS_PACKET), (0x81, 0x00));
+    }
+
+    #[test]
+    fn uncompressed() {
+        let mut printer = printer();
+
+        let mut packet = vec![0x88, 0x33, 0x04, 0x00, 0x80, 0x02];
+        packet.extend_from_slice(&[0x55; 640]);
+        // 0x04 + 0x80 + 0x02 + 640 * 0x55
+        packet.extend_from_slice(&[0x06, 0xd5, 0x00, 0x00]);
+
+        for _ in 0..8 {
+            assert_eq!(replies(&mut printer, &packet), (0x81, STATUS_UNPROCESSED));
+        }
+
+        // 9 packets fill the buffer, more are dropped
+        for _ in 0..2 {
+            assert_eq!(
+                replies(&mut printer, &packet),
+                (0x81, STATUS_UNPROCESSED | STATUS_IMAGE_FULL)
+            );
+        }
+        assert_eq!(printer.buffer.len(), BUFFER_SIZE);
+    }
+
+    #[test]
+    fn resync() {
+        let mut printer = printer();
+
+        // Garbage and a repeated magic byte before the packet
+        assert_eq!(send(&mut printer, &[0x12, 0x88]), [0, 0]);
+        assert_eq!(replies(&mut printer, &INIT_PACKET), (0x81, 0x00));
+    }
+}
@@ -0,0 +1,317 @@
+use std::io::Write;
+
+// Serial Data Transfer (Link Cable)
+//
+// 0xff01 - SB - Serial transfer data (R/W)
+// The byte to send, shifted out (MSB first) while the received byte is
+// shifted in.
+//
+// 0xff02 - SC - Serial Transfer Control (R/W)
+// Bit 7 - Transfer Start Flag (0=No transfer, 1=Start)
+// Bit 0 - Shift Clock (0=External Clock, 1=Internal Clock 8192Hz)
+//
+// With the internal clock the Game Boy drives the transfer, a bit every
+// 512 T-cycles. With the external clock it waits for the other end to
+// drive it. Either way a Serial interrupt is requested when the 8 bits
+// have been transferred.
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+
+    // Byte being received with the internal clock, shifted into SB
+    // a bit at a time
+    incoming: u8,
+    // Bits left to shift in the current transfer
+    bits: u8,
+    // M-cycles left before the next bit is shifted
+    counter: u8,
+
+    partner: Box<dyn LinkPartner>,
+}
+
+// M-cycles per bit with the internal clock (8192Hz)
+const CYCLES_PER_BIT: u8 = 128;
+
+const TRANSFER_START: u8 = 0b1000_0000;
+const INTERNAL_CLOCK: u8 = 0b0000_0001;
+
+// What's on the other end of the link cable.
+pub trait LinkPartner {
+    // The Game Boy drives the clock: send byte and return the one
+    // received at the same time.
+    fn exchange(&mut self, byte: u8) -> u8;
+
+    // Called every M-cycle, so the partner can follow the emulated time.
+    //
+    // outgoing is the byte in SB when a transfer with the external clock
+    // is waiting for the partner to drive it. Returns the byte received
+    // when the partner drove a transfer.
+    fn tick(&mut self, _outgoing: Option<u8>) -> Option<u8> {
+        None
+    }
+}
+
+// No cable connected: nothing is received (all bits are 1) and the
+// external clock never ticks.
+pub struct Disconnected;
+
+impl LinkPartner for Disconnected {
+    fn exchange(&mut self, _byte: u8) -> u8 {
+        0xff
+    }
+}
+
+// Write the bytes sent to writer, e.g. a file, receiving nothing.
+pub struct Output<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> Output<W> {
+    pub const fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> LinkPartner for Output<W> {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        if let Err(err) = self
+            .writer
+            .write_all(&[byte])
+            .and_then(|()| self.writer.flush())
+        {
+            eprintln!("Serial output: {err}");
+        }
+
+        0xff
+    }
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Serial {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            sb: 0,
+            sc: 0,
+            incoming: 0,
+            bits: 0,
+            counter: 0,
+            partner: Box::new(Disconnected),
+        }
+    }
+
+    pub fn connect(&mut self, partner: Box<dyn LinkPartner>) {
+        self.partner = partner;
+    }
+
+    #[must_use]
+    pub const fn read(&self, address: u16) -> u8 {
+        match address {
+            0xff01 => self.sb,
+            // Unused bits read as 1
+            0xff02 => self.sc | 0b0111_1110,
+            _ => 0xff,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0xff01 => self.sb = value,
+            0xff02 => {
+                self.sc = value & (TRANSFER_START | INTERNAL_CLOCK);
+
+                if self.sc == TRANSFER_START | INTERNAL_CLOCK {
+                    self.incoming = self.partner.exchange(self.sb);
+                    self.bits = 8;
+                    self.counter = CYCLES_PER_BIT;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // Advance the serial port by one M-cycle.
+    //
+    // Returns true if a Serial interrupt is requested.
+    pub fn tick(&mut self) -> bool {
+        let external = self.sc == TRANSFER_START;
+        let received = self
+            .partner
+            .tick(if external { Some(self.sb) } else { None });
+
+        if external {
+            if let Some(byte) = received {
+                self.sb = byte;
+                self.sc &= !TRANSFER_START;
+
+                return true;
+            }
+        }
+
+        if self.sc != TRANSFER_START | INTERNAL_CLOCK {
+            return false;
+        }
+
+        self.counter -= 1;
+        if self.counter > 0 {
+            return false;
+        }
+        self.counter = CYCLES_PER_BIT;
+
+        self.sb = self.sb << 1 | self.incoming >> 7;
+        self.incoming <<= 1;
+        self.bits -= 1;
+
+        if self.bits == 0 {
+            self.sc &= !TRANSFER_START;
+
+            return true;
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::gpu::Gpu;
+    use crate::memory::{Bus, Memory, INT_SERIAL};
+
+    // Answers with reply, keeping the bytes sent. With the external
+    // clock it drives a transfer after delay M-cycles.
+    struct Partner {
+        reply: u8,
+        sent: Rc<RefCell<Vec<u8>>>,
+        delay: Option<u32>,
+    }
+
+    impl LinkPartner for Partner {
+        fn exchange(&mut self, byte: u8) -> u8 {
+            self.sent.borrow_mut().push(byte);
+
+            self.reply
+        }
+
+        fn tick(&mut self, outgoing: Option<u8>) -> Option<u8> {
+            let byte = outgoing?;
+
+            match self.delay.as_mut() {
+                Some(0) => {
+                    self.delay = None;
+
+                    Some(self.exchange(byte))
+                }
+                Some(delay) => {
+                    *delay -= 1;
+
+                    None
+                }
+                None => None,
+            }
+        }
+    }
+
+    fn connected(reply: u8, delay: Option<u32>) -> (Serial, Rc<RefCell<Vec<u8>>>) {
+        let sent = Rc::new(RefCell::new(Vec::new()));
+
+        let mut serial = Serial::new();
+        serial.connect(Box::new(Partner {
+            reply,
+            sent: Rc::clone(&sent),
+            delay,
+        }));
+
+        (serial, sent)
+    }
+
+    // Ticks until the interrupt, returning the M-cycles elapsed
+    fn transfer(serial: &mut Serial, limit: u32) -> Option<u32> {
+        (1..=limit).find(|_| serial.tick())
+    }
+
+    #[test]
+    fn internal_clock() {
+        let (mut serial, sent) = connected(0xa5, None);
+
+        serial.write(0xff01, 0x5a);
+        serial.write(0xff02, 0x81);
+        assert_eq!(*sent.borrow(), [0x5a]);
+
+        // A bit every 128 M-cycles, MSB first
+        for _ in 0..128 {
+            assert!(!serial.tick());
+        }
+        assert_eq!(serial.read(0xff01), 0b1011_0101);
+        assert_eq!(serial.read(0xff02), 0xff);
+
+        assert_eq!(transfer(&mut serial, 8 * 128), Some(7 * 128));
+        assert_eq!(serial.read(0xff01), 0xa5);
+        assert_eq!(serial.read(0xff02), 0x7f);
+
+        // Nothing more until the next transfer
+        assert_eq!(transfer(&mut serial, 8 * 128), None);
+        assert_eq!(*sent.borrow(), [0x5a]);
+    }
+
+    #[test]
+    fn disconnected() {
+        let mut serial = Serial::new();
+
+        serial.write(0xff01, 0x42);
+        serial.write(0xff02, 0x81);
+
+        assert_eq!(transfer(&mut serial, 8 * 128), Some(8 * 128));
+        assert_eq!(serial.read(0xff01), 0xff);
+    }
+
+    #[test]
+    fn external_clock() {
+        let (mut serial, sent) = connected(0x3c, Some(1000));
+
+        serial.write(0xff01, 0xc3);
+        serial.write(0xff02, 0x80);
+
+        // The partner drives the clock, whenever it wants
+        assert_eq!(transfer(&mut serial, 2000), Some(1001));
+        assert_eq!(*sent.borrow(), [0xc3]);
+        assert_eq!(serial.read(0xff01), 0x3c);
+        assert_eq!(serial.read(0xff02), 0x7e);
+    }
+
+    #[test]
+    fn external_clock_disconnected() {
+        let mut serial = Serial::new();
+
+        serial.write(0xff01, 0xc3);
+        serial.write(0xff02, 0x80);
+
+        assert_eq!(transfer(&mut serial, 100_000), None);
+        assert_eq!(serial.read(0xff02), 0xfe);
+    }
+
+    #[test]
+    fn interrupt() {
+        let mut memory = Memory::new(Gpu::new());
+
+        memory.write(0xff01, 0x42);
+        memory.write(0xff02, 0x81);
+
+        for _ in 1..8 * 128 {
+            Bus::tick(&mut memory);
+        }
+        assert_eq!(memory.peek(0xff0f) & INT_SERIAL, 0);
+
+        Bus::tick(&mut memory);
+        assert_eq!(memory.peek(0xff0f) & INT_SERIAL, INT_SERIAL);
+    }
+}
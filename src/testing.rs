@@ -0,0 +1,31 @@
+// Helpers shared by the unit tests
+
+use crate::debugger::Debugger;
+use crate::gameboy::GameBoy;
+
+// A 32KiB ROM, blank but for the code at each address
+pub fn rom(code: &[(usize, &[u8])]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+
+    for &(address, bytes) in code {
+        rom[address..address + bytes.len()].copy_from_slice(bytes);
+    }
+
+    rom
+}
+
+// A Game Boy running rom(code) without a boot ROM, from $0100
+pub fn gameboy(code: &[(usize, &[u8])]) -> GameBoy {
+    GameBoy::new(rom(code), None)
+}
+
+// Run until the debugger stops, returning PC
+pub fn run(debugger: &mut Debugger, gameboy: &mut GameBoy) -> u16 {
+    for _ in 0..10 {
+        if debugger.run_frame(gameboy).is_none() {
+            return gameboy.cpu.pc;
+        }
+    }
+
+    panic!("The debugger didn't stop");
+}
@@ -0,0 +1,126 @@
+// Timer and Divider registers
+//
+// 0xff04 - DIV - Divider Register (R/W)
+// Upper 8 bits of an internal 16 bit counter incremented every T-cycle
+// (16384 Hz). Writing any value resets the whole counter to 0.
+//
+// 0xff05 - TIMA - Timer counter (R/W)
+// Incremented at the frequency selected in TAC. When it overflows it's
+// reloaded with TMA and a Timer interrupt is requested, one M-cycle later.
+//
+// 0xff06 - TMA - Timer Modulo (R/W)
+//
+// 0xff07 - TAC - Timer Control (R/W)
+// Bit 2   - Timer Enable
+// Bit 1-0 - Input Clock Select
+//           00: CPU Clock / 1024 (4096 Hz)
+//           01: CPU Clock / 16   (262144 Hz)
+//           10: CPU Clock / 64   (65536 Hz)
+//           11: CPU Clock / 256  (16384 Hz)
+pub struct Timer {
+    // Internal counter, DIV is its upper byte
+    counter: u16,
+
+    tima: u8,
+    tma: u8,
+    tac: u8,
+
+    // TIMA overflowed in the previous M-cycle and is waiting to be
+    // reloaded with TMA.
+    overflow: bool,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timer {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            overflow: false,
+        }
+    }
+
+    #[must_use]
+    pub const fn read(&self, address: u16) -> u8 {
+        match address {
+            0xff04 => (self.counter >> 8) as u8,
+            0xff05 => self.tima,
+            0xff06 => self.tma,
+            // Unused bits read as 1
+            0xff07 => self.tac | 0b1111_1000,
+            _ => 0xff,
+        }
+    }
+
+    pub const fn write(&mut self, address: u16, value: u8) {
+        // TIMA is incremented on the falling edge of the counter bit
+        // selected by TAC, so resetting DIV or changing TAC can trigger
+        // an increment.
+        let signal = self.signal();
+
+        match address {
+            0xff04 => self.counter = 0,
+            0xff05 => {
+                self.tima = value;
+
+                // Writing TIMA during the reload cycle cancels the reload
+                self.overflow = false;
+            }
+            0xff06 => self.tma = value,
+            0xff07 => self.tac = value & 0b111,
+            _ => (),
+        }
+
+        if signal && !self.signal() {
+            self.increment();
+        }
+    }
+
+    // Advance the timer by one M-cycle (4 T-cycles).
+    //
+    // Returns true if a Timer interrupt is requested.
+    pub const fn tick(&mut self) -> bool {
+        let interrupt = self.overflow;
+
+        if self.overflow {
+            self.tima = self.tma;
+            self.overflow = false;
+        }
+
+        let signal = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+
+        if signal && !self.signal() {
+            self.increment();
+        }
+
+        interrupt
+    }
+
+    // Timer enable AND the counter bit selected by the Input Clock Select.
+    const fn signal(&self) -> bool {
+        let bit = match self.tac & 0b11 {
+            0b00 => 9,
+            0b01 => 3,
+            0b10 => 5,
+            _ => 7,
+        };
+
+        self.tac & 0b100 != 0 && self.counter & (1 << bit) != 0
+    }
+
+    const fn increment(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+
+        self.tima = tima;
+        self.overflow = overflow;
+    }
+}
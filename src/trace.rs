@@ -0,0 +1,195 @@
+use std::io::Write;
+
+use crate::debugger::MAPPED_BANK;
+use crate::disassembler::Symbols;
+use crate::gameboy::GameBoy;
+
+// A log of the instructions executed, a line per instruction with the
+// state before it, in the format of gameboy-doctor so that it can be
+// diffed against known-good logs:
+//   A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+//
+// With symbols, the line ends with where PC is as a comment, e.g.
+// ` ; Main+$3`.
+pub struct Trace {
+    output: Box<dyn Write>,
+    symbols: Option<Symbols>,
+    // Logging stops at the first write failing
+    failed: bool,
+}
+
+impl Trace {
+    #[must_use]
+    pub fn new(output: Box<dyn Write>, symbols: Option<Symbols>) -> Self {
+        Self {
+            output,
+            symbols,
+            failed: false,
+        }
+    }
+
+    // The line for the instruction gameboy is about to execute
+    #[must_use]
+    pub fn line(&self, gameboy: &GameBoy) -> String {
+        let cpu = &gameboy.cpu;
+        let regs = &cpu.regs;
+        let memory = gameboy.memory();
+        let pcmem = [0, 1, 2, 3].map(|i| memory.peek(cpu.pc.wrapping_add(i)));
+
+        let mut line = format!(
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} \
+             SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            regs.a,
+            regs.flags,
+            regs.b,
+            regs.c,
+            regs.d,
+            regs.e,
+            regs.h,
+            regs.l,
+            cpu.sp,
+            cpu.pc,
+            pcmem[0],
+            pcmem[1],
+            pcmem[2],
+            pcmem[3],
+        );
+
+        if let Some(symbols) = &self.symbols {
+            if symbols.locate(MAPPED_BANK, cpu.pc).is_some() {
+                line.push_str(" ; ");
+                line.push_str(&symbols.describe(MAPPED_BANK, cpu.pc));
+            }
+        }
+
+        line
+    }
+
+    pub fn write(&mut self, line: &str) {
+        if self.failed {
+            return;
+        }
+
+        if let Err(e) = writeln!(self.output, "{line}") {
+            eprintln!("Instruction trace stopped: {e}");
+            self.failed = true;
+        }
+    }
+}
+
+impl Drop for Trace {
+    fn drop(&mut self) {
+        if !self.failed {
+            if let Err(e) = self.output.flush() {
+                eprintln!("Instruction trace incomplete: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+
+    use crate::testing;
+
+    // The trace written, kept after the Trace is dropped
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // The start of most ROMs, and of gameboy-doctor's logs:
+    // $0100 NOP; JP $0150
+    // $0150 LDH A,[rLY]; HALT
+    fn gameboy() -> GameBoy {
+        testing::gameboy(&[
+            (0x0100, &[0x00, 0xc3, 0x50, 0x01]),
+            (0x0150, &[0xf0, 0x44, 0x76]),
+        ])
+    }
+
+    #[test]
+    fn lines() {
+        let output = Output::default();
+        let mut gameboy = gameboy();
+        assert!(gameboy.set_doctor());
+        gameboy.set_trace(Trace::new(Box::new(output.clone()), None));
+
+        for _ in 0..3 {
+            gameboy.step();
+        }
+        gameboy.set_trace(Trace::new(Box::new(io::sink()), None));
+
+        let trace = String::from_utf8(output.0.take()).unwrap();
+        assert_eq!(
+            trace.lines().collect::<Vec<_>>(),
+            [
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,50,01",
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:C3,50,01,00",
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0150 PCMEM:F0,44,76,00",
+            ]
+        );
+
+        // LY reads 0x90, whatever the scanline
+        let line = Trace::new(Box::new(io::sink()), None).line(&gameboy);
+        assert_eq!(
+            line,
+            "A:90 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0152 PCMEM:76,00,00,00"
+        );
+    }
+
+    #[test]
+    fn side_effect_free() {
+        let output = Output::default();
+        let mut gameboy = gameboy();
+        gameboy.set_trace(Trace::new(Box::new(output.clone()), None));
+
+        for _ in 0..3 {
+            gameboy.step();
+        }
+        gameboy.set_trace(Trace::new(Box::new(io::sink()), None));
+
+        let trace = String::from_utf8(output.0.take()).unwrap();
+        assert_eq!(
+            trace.lines().next(),
+            Some("A:01 F:00 B:FF C:13 D:00 E:C1 H:84 L:03 SP:FFFE PC:0100 PCMEM:00,C3,50,01")
+        );
+
+        // LY reads the GPU's scanline
+        assert_eq!(gameboy.cpu.regs.a, gameboy.memory().gpu().ly);
+
+        // Too late to start in gameboy-doctor's state
+        assert!(!gameboy.set_doctor());
+        assert_eq!(gameboy.cpu.regs.b, 0xff);
+    }
+
+    #[test]
+    fn symbols() {
+        let mut gameboy = gameboy();
+        gameboy.step();
+        gameboy.step();
+
+        let symbols = Symbols::parse("00:0150 Main").unwrap();
+        let trace = Trace::new(Box::new(io::sink()), Some(symbols));
+        gameboy.step();
+
+        // Not traced, the registers are still the DMG0's
+        assert_eq!(
+            trace.line(&gameboy),
+            "A:00 F:00 B:FF C:13 D:00 E:C1 H:84 L:03 SP:FFFE PC:0152 PCMEM:76,00,00,00 ; Main+$2"
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::gameboy::{CYCLES_PER_FRAME, CYCLES_PER_SECOND};
+use crate::gpu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// Video recording, one frame for every frame emulated, so that it plays at
+// the right speed whatever the speed of the emulation.
+
+// Uncompressed YUV 4:4:4 video, readable by most video tools
+// (https://wiki.multimedia.cx/index.php/YUV4MPEG2)
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+
+    // Y, U and V planes of a frame
+    planes: Vec<u8>,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(mut writer: W, width: u32, height: u32) -> io::Result<Self> {
+        // The exact frame rate, about 59.73 Hz
+        writeln!(
+            writer,
+            "YUV4MPEG2 W{width} H{height} F{CYCLES_PER_SECOND}:{CYCLES_PER_FRAME} Ip A1:1 C444"
+        )?;
+
+        Ok(Self {
+            writer,
+            planes: vec![0; width as usize * height as usize * 3],
+        })
+    }
+
+    // Write a frame of RGB888 pixels
+    pub fn write_frame(&mut self, pixels: &[u8]) -> io::Result<()> {
+        let size = self.planes.len() / 3;
+        let (y, uv) = self.planes.split_at_mut(size);
+        let (u, v) = uv.split_at_mut(size);
+
+        for (i, pixel) in pixels.chunks_exact(3).enumerate() {
+            let (yy, uu, vv) = yuv(pixel[0], pixel[1], pixel[2]);
+
+            y[i] = yy;
+            u[i] = uu;
+            v[i] = vv;
+        }
+
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&self.planes)
+    }
+}
+
+// BT.601, limited range
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::many_single_char_names
+)]
+fn yuv(r: u8, g: u8, b: u8) -> (u8, u8, u8) {
+    let (r, g, b) = (i32::from(r), i32::from(g), i32::from(b));
+
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+
+    // Always in range
+    (y as u8, u as u8, v as u8)
+}
+
+pub const SAMPLE_RATE: u32 = 44100;
+const CHANNELS: u16 = 2;
+const BYTES_PER_SAMPLE: u16 = 2;
+const WAV_HEADER_SIZE: u32 = 44;
+
+const BLOCK_ALIGN: u16 = CHANNELS * BYTES_PER_SAMPLE;
+
+// The sizes in the header are 32 bits: the RIFF size, counting all but its
+// first 8 bytes, can't go past 4 GiB.
+const MAX_SAMPLES: u64 = (u32::MAX - (WAV_HEADER_SIZE - 8)) as u64 / BLOCK_ALIGN as u64;
+
+// 16 bit stereo PCM WAV file. The sizes in the header are filled in when
+// dropped.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+
+    // Sample frames written
+    samples: u64,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(b"RIFF")?;
+        // RIFF size, filled in later
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        // PCM
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&CHANNELS.to_le_bytes())?;
+        writer.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        writer.write_all(&(SAMPLE_RATE * u32::from(BLOCK_ALIGN)).to_le_bytes())?;
+        writer.write_all(&BLOCK_ALIGN.to_le_bytes())?;
+        writer.write_all(&(BYTES_PER_SAMPLE * 8).to_le_bytes())?;
+        writer.write_all(b"data")?;
+        // Data size, filled in later
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(Self { writer, samples: 0 })
+    }
+
+    // Fails without writing anything when the file would get too large.
+    pub fn write_silence(&mut self, samples: u64) -> io::Result<()> {
+        if samples > MAX_SAMPLES - self.samples {
+            return Err(io::Error::other("WAV file size limit (4 GiB) reached"));
+        }
+
+        let size = usize::try_from(samples * u64::from(BLOCK_ALIGN))
+            .map_err(|_| io::Error::other("too many samples"))?;
+
+        self.writer.write_all(&vec![0; size])?;
+        self.samples += samples;
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // Never more than MAX_SAMPLES, clamped anyway
+        let data_size = self.samples * u64::from(BLOCK_ALIGN);
+        let riff_size = u64::from(WAV_HEADER_SIZE - 8) + data_size;
+        let clamp = |size| u32::try_from(size).unwrap_or(u32::MAX);
+
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&clamp(riff_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&clamp(data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+
+        self.writer.flush()
+    }
+}
+
+impl<W: Write + Seek> Drop for WavWriter<W> {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            eprintln!("Can't finish WAV file: {e}");
+        }
+    }
+}
+
+// Records the screen to a Y4M video, and the sound to a WAV file next to
+// it. There's no sound emulation yet, so that's silence as long as the
+// video.
+pub struct VideoRecorder {
+    video: Y4mWriter<BufWriter<File>>,
+    audio: WavWriter<BufWriter<File>>,
+
+    // Frames written
+    frames: u64,
+}
+
+impl VideoRecorder {
+    // Record to path, and path with a .wav extension
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let video = Y4mWriter::new(
+            BufWriter::new(File::create(path)?),
+            u32::from(SCREEN_WIDTH),
+            u32::from(SCREEN_HEIGHT),
+        )?;
+        let audio = WavWriter::new(BufWriter::new(File::create(path.with_extension("wav"))?))?;
+
+        Ok(Self {
+            video,
+            audio,
+            frames: 0,
+        })
+    }
+
+    // Call at the end of every frame with the screen (see Gpu::screen()),
+    // whether the frame was drawn or not.
+    pub fn frame(&mut self, screen: &[u8]) -> io::Result<()> {
+        self.video.write_frame(screen)?;
+        self.frames += 1;
+
+        // As many samples as the video lasts
+        let cycles = self.frames * u64::from(CYCLES_PER_FRAME);
+        let samples = cycles * u64::from(SAMPLE_RATE) / u64::from(CYCLES_PER_SECOND);
+
+        self.audio
+            .write_silence(samples.saturating_sub(self.audio.samples))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    fn sizes(wav: &[u8]) -> (u32, u32) {
+        let field = |offset: usize| {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&wav[offset..offset + 4]);
+            u32::from_le_bytes(bytes)
+        };
+
+        (field(4), field(40))
+    }
+
+    #[test]
+    fn wav_sizes() {
+        let mut wav = Vec::new();
+
+        {
+            let mut writer = WavWriter::new(Cursor::new(&mut wav)).unwrap();
+            writer.write_silence(10).unwrap();
+            writer.write_silence(5).unwrap();
+        }
+
+        assert_eq!(wav.len(), 44 + 15 * 4);
+        assert_eq!(sizes(&wav), (36 + 15 * 4, 15 * 4));
+    }
+
+    #[test]
+    fn wav_limit() {
+        let mut wav = Vec::new();
+
+        {
+            let mut writer = WavWriter::new(Cursor::new(&mut wav)).unwrap();
+            // As if that much had been written
+            writer.samples = MAX_SAMPLES - 1;
+
+            assert!(writer.write_silence(2).is_err());
+            writer.write_silence(1).unwrap();
+            assert!(writer.write_silence(1).is_err());
+        }
+
+        let data_size = u32::try_from(MAX_SAMPLES * 4).unwrap();
+        assert_eq!(sizes(&wav), (36 + data_size, data_size));
+        assert!(u32::MAX - (36 + data_size) < 4);
+    }
+}
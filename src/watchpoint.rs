@@ -0,0 +1,409 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+// Watchpoints stop the emulation when memory is accessed, by the CPU, a
+// DMA transfer or anything else going through Memory.
+//
+// Memory records the accesses matching a watchpoint as hits, and the
+// debugger stops before the next instruction when there are any.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// Which accesses a watchpoint stops on
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    Read,
+    Write,
+    ReadWrite,
+    // Writes of a value different from the one in memory
+    Change,
+}
+
+impl Kind {
+    const fn matches(self, access: Access, changed: bool) -> bool {
+        matches!(
+            (self, access, changed),
+            (Self::Read | Self::ReadWrite, Access::Read, _)
+                | (Self::Write | Self::ReadWrite, Access::Write, _)
+                | (Self::Change, Access::Write, true)
+        )
+    }
+}
+
+impl FromStr for Kind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "r" => Ok(Self::Read),
+            "w" => Ok(Self::Write),
+            "rw" => Ok(Self::ReadWrite),
+            "c" => Ok(Self::Change),
+            _ => Err(format!(
+                "Invalid watchpoint kind {name}, expected r, w, rw or c"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Read => "r",
+            Self::Write => "w",
+            Self::ReadWrite => "rw",
+            Self::Change => "c",
+        })
+    }
+}
+
+// A test of the value read or written, e.g. == $3f
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Condition {
+    pub operator: Operator,
+    pub value: u8,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    // Any of the bits of the value set
+    And,
+}
+
+impl Condition {
+    #[must_use]
+    pub const fn matches(self, value: u8) -> bool {
+        match self.operator {
+            Operator::Equal => value == self.value,
+            Operator::NotEqual => value != self.value,
+            Operator::Less => value < self.value,
+            Operator::LessEqual => value <= self.value,
+            Operator::Greater => value > self.value,
+            Operator::GreaterEqual => value >= self.value,
+            Operator::And => value & self.value != 0,
+        }
+    }
+}
+
+impl FromStr for Operator {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "==" => Ok(Self::Equal),
+            "!=" => Ok(Self::NotEqual),
+            "<" => Ok(Self::Less),
+            "<=" => Ok(Self::LessEqual),
+            ">" => Ok(Self::Greater),
+            ">=" => Ok(Self::GreaterEqual),
+            "&" => Ok(Self::And),
+            _ => Err(format!("Invalid operator {name}")),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operator = match self.operator {
+            Operator::Equal => "==",
+            Operator::NotEqual => "!=",
+            Operator::Less => "<",
+            Operator::LessEqual => "<=",
+            Operator::Greater => ">",
+            Operator::GreaterEqual => ">=",
+            Operator::And => "&",
+        };
+
+        write!(f, "{operator} ${:02x}", self.value)
+    }
+}
+
+pub struct Watchpoint {
+    pub name: String,
+    pub range: RangeInclusive<u16>,
+    pub kind: Kind,
+    // Only stop when the value read or written matches
+    pub condition: Option<Condition>,
+    pub enabled: bool,
+}
+
+impl Watchpoint {
+    fn matches(&self, address: u16, access: Access, value: u8, changed: bool) -> bool {
+        self.enabled
+            && self.range.contains(&address)
+            && self.kind.matches(access, changed)
+            && self
+                .condition
+                .is_none_or(|condition| condition.matches(value))
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:8} {:2} ${:04x}",
+            self.name,
+            self.kind,
+            self.range.start()
+        )?;
+
+        if self.range.end() != self.range.start() {
+            write!(f, "-${:04x}", self.range.end())?;
+        }
+        if let Some(condition) = &self.condition {
+            write!(f, " {condition}")?;
+        }
+        if !self.enabled {
+            write!(f, " (disabled)")?;
+        }
+
+        Ok(())
+    }
+}
+
+// An access that matched a watchpoint
+pub struct Hit {
+    pub name: String,
+    pub address: u16,
+    pub access: Access,
+    pub value: u8,
+    // Made by an OAM DMA transfer rather than the CPU
+    pub dma: bool,
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let access = match self.access {
+            Access::Read => "read",
+            Access::Write => "write",
+        };
+
+        write!(
+            f,
+            "Watchpoint {}: {access} ${:02x} at ${:04x}{}",
+            self.name,
+            self.value,
+            self.address,
+            if self.dma { " (DMA)" } else { "" }
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct Watchpoints {
+    watchpoints: Vec<Watchpoint>,
+
+    // Memory is read through a shared reference
+    hits: RefCell<Vec<Hit>>,
+}
+
+impl Watchpoints {
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.watchpoints.is_empty()
+    }
+
+    // Replaces the watchpoint with the same name
+    pub fn add(&mut self, watchpoint: Watchpoint) {
+        self.remove(&watchpoint.name);
+        self.watchpoints.push(watchpoint);
+    }
+
+    // Returns false if there's no watchpoint with that name
+    pub fn remove(&mut self, name: &str) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints
+            .retain(|watchpoint| watchpoint.name != name);
+
+        self.watchpoints.len() != count
+    }
+
+    pub fn clear(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Watchpoint> {
+        self.watchpoints
+            .iter_mut()
+            .find(|watchpoint| watchpoint.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Watchpoint> {
+        self.watchpoints.iter()
+    }
+
+    // Record the access if it matches a watchpoint, changed is whether a
+    // write stores a value different from the previous one
+    pub fn access(&self, address: u16, access: Access, value: u8, changed: bool, dma: bool) {
+        for watchpoint in &self.watchpoints {
+            if watchpoint.matches(address, access, value, changed) {
+                self.hits.borrow_mut().push(Hit {
+                    name: watchpoint.name.clone(),
+                    address,
+                    access,
+                    value,
+                    dma,
+                });
+            }
+        }
+    }
+
+    // The accesses recorded since the last call
+    pub fn take_hits(&self) -> Vec<Hit> {
+        self.hits.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watchpoint(name: &str, range: RangeInclusive<u16>, kind: Kind) -> Watchpoint {
+        Watchpoint {
+            name: name.to_string(),
+            range,
+            kind,
+            condition: None,
+            enabled: true,
+        }
+    }
+
+    fn names(watchpoints: &Watchpoints) -> Vec<String> {
+        watchpoints
+            .take_hits()
+            .into_iter()
+            .map(|hit| hit.name)
+            .collect()
+    }
+
+    #[test]
+    fn operators() {
+        let condition = |operator, value| Condition { operator, value };
+
+        assert!(condition(Operator::Equal, 0x3f).matches(0x3f));
+        assert!(!condition(Operator::Equal, 0x3f).matches(0x40));
+        assert!(condition(Operator::NotEqual, 0x3f).matches(0x40));
+        assert!(!condition(Operator::NotEqual, 0x3f).matches(0x3f));
+        assert!(condition(Operator::Less, 0x3f).matches(0x3e));
+        assert!(!condition(Operator::Less, 0x3f).matches(0x3f));
+        assert!(condition(Operator::LessEqual, 0x3f).matches(0x3f));
+        assert!(!condition(Operator::LessEqual, 0x3f).matches(0x40));
+        assert!(condition(Operator::Greater, 0x3f).matches(0x40));
+        assert!(!condition(Operator::Greater, 0x3f).matches(0x3f));
+        assert!(condition(Operator::GreaterEqual, 0x3f).matches(0x3f));
+        assert!(!condition(Operator::GreaterEqual, 0x3f).matches(0x3e));
+        assert!(condition(Operator::And, 0x81).matches(0x80));
+        assert!(!condition(Operator::And, 0x81).matches(0x7e));
+
+        let operators = ["==", "!=", "<", "<=", ">", ">=", "&"];
+        for name in operators {
+            let operator: Operator = name.parse().unwrap();
+            assert_eq!(condition(operator, 0x3f).to_string(), format!("{name} $3f"));
+        }
+        assert!("=".parse::<Operator>().is_err());
+    }
+
+    #[test]
+    fn kinds() {
+        for name in ["r", "w", "rw", "c"] {
+            assert_eq!(name.parse::<Kind>().unwrap().to_string(), name);
+        }
+        assert!("x".parse::<Kind>().is_err());
+    }
+
+    #[test]
+    fn access() {
+        let mut watchpoints = Watchpoints::default();
+        watchpoints.add(watchpoint("read", 0xc000..=0xc0ff, Kind::Read));
+        watchpoints.add(watchpoint("write", 0xc000..=0xc000, Kind::Write));
+        watchpoints.add(watchpoint("both", 0xff40..=0xff40, Kind::ReadWrite));
+        watchpoints.add(watchpoint("change", 0xff42..=0xff42, Kind::Change));
+
+        watchpoints.access(0xc000, Access::Read, 0, false, false);
+        watchpoints.access(0xc0ff, Access::Read, 0, false, false);
+        watchpoints.access(0xc100, Access::Read, 0, false, false);
+        assert_eq!(names(&watchpoints), ["read", "read"]);
+
+        watchpoints.access(0xc000, Access::Write, 0, true, false);
+        watchpoints.access(0xc001, Access::Write, 0, true, false);
+        assert_eq!(names(&watchpoints), ["write"]);
+
+        watchpoints.access(0xff40, Access::Read, 0x91, false, false);
+        watchpoints.access(0xff40, Access::Write, 0x91, false, true);
+        let hits = watchpoints.take_hits();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(
+            hits[1].to_string(),
+            "Watchpoint both: write $91 at $ff40 (DMA)"
+        );
+
+        // Only writes of a different value
+        watchpoints.access(0xff42, Access::Read, 0x10, false, false);
+        watchpoints.access(0xff42, Access::Write, 0x10, false, false);
+        assert!(watchpoints.take_hits().is_empty());
+        watchpoints.access(0xff42, Access::Write, 0x11, true, false);
+        assert_eq!(names(&watchpoints), ["change"]);
+    }
+
+    #[test]
+    fn condition() {
+        let mut watchpoints = Watchpoints::default();
+        watchpoints.add(Watchpoint {
+            condition: Some(Condition {
+                operator: Operator::And,
+                value: 0x80,
+            }),
+            ..watchpoint("lcd-on", 0xff40..=0xff40, Kind::Write)
+        });
+
+        watchpoints.access(0xff40, Access::Write, 0x11, true, false);
+        assert!(watchpoints.take_hits().is_empty());
+        watchpoints.access(0xff40, Access::Write, 0x91, true, false);
+        assert_eq!(names(&watchpoints), ["lcd-on"]);
+    }
+
+    #[test]
+    fn disabled() {
+        let mut watchpoints = Watchpoints::default();
+        watchpoints.add(watchpoint("w", 0xc000..=0xc000, Kind::Write));
+        watchpoints.get_mut("w").unwrap().enabled = false;
+
+        watchpoints.access(0xc000, Access::Write, 0, true, false);
+        assert!(watchpoints.take_hits().is_empty());
+
+        watchpoints.get_mut("w").unwrap().enabled = true;
+        watchpoints.access(0xc000, Access::Write, 0, true, false);
+        assert_eq!(names(&watchpoints), ["w"]);
+        // Taken
+        assert!(watchpoints.take_hits().is_empty());
+    }
+
+    #[test]
+    fn add_remove() {
+        let mut watchpoints = Watchpoints::default();
+        watchpoints.add(watchpoint("w", 0xc000..=0xc000, Kind::Write));
+        // Replaced
+        watchpoints.add(watchpoint("w", 0xd000..=0xd000, Kind::Write));
+        assert_eq!(watchpoints.iter().count(), 1);
+
+        watchpoints.access(0xc000, Access::Write, 0, true, false);
+        assert!(watchpoints.take_hits().is_empty());
+
+        assert!(watchpoints.remove("w"));
+        assert!(!watchpoints.remove("w"));
+        assert!(watchpoints.is_empty());
+    }
+}
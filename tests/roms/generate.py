@@ -0,0 +1,593 @@
+#!/usr/bin/env python3
+# Assembles the test ROMs checked in under gameperson/, written for this
+# repository since the Blargg and mooneye ROMs aren't checked in (see
+# README.md). Their expected results come from Pan Docs, not from running
+# them. Run it from anywhere, it rewrites the *.gb files:
+#
+#   python3 tests/roms/generate.py
+#
+# instr_timing.gb follows Blargg's protocol, writing its result to the
+# serial port, and interrupts.gb mooneye's, ending with LD B,B. screen.gb
+# draws a still screen, its reference image being written to
+# tests/screenshots/gameperson_screen.png.
+
+import os
+import struct
+import zlib
+
+DIV, TIMA, TMA, TAC = 0x04, 0x05, 0x06, 0x07
+SB, SC, IF, IE = 0x01, 0x02, 0x0F, 0xFF
+
+
+class Rom:
+    def __init__(self):
+        self.data = bytearray(0x8000)
+        self.address = 0
+        self.labels = {}
+        self.fixups = []
+        self.count = 0
+
+    def org(self, address):
+        self.address = address
+
+    def label(self, name=None):
+        if name is None:
+            name = self.unique()
+        self.labels[name] = self.address
+        return name
+
+    def unique(self):
+        self.count += 1
+        return f".{self.count}"
+
+    def emit(self, *values):
+        for value in values:
+            if isinstance(value, str):
+                self.fixups.append((self.address, value, "word"))
+                self.address += 2
+            else:
+                self.data[self.address] = value
+                self.address += 1
+
+    # A JR offset to a label
+    def relative(self, opcode, name):
+        self.data[self.address] = opcode
+        self.fixups.append((self.address + 1, name, "relative"))
+        self.address += 2
+
+    def build(self):
+        for address, name, kind in self.fixups:
+            target = self.labels[name]
+            if kind == "word":
+                self.data[address] = target & 0xFF
+                self.data[address + 1] = target >> 8
+            else:
+                offset = target - (address + 1)
+                assert -128 <= offset <= 127, name
+                self.data[address] = offset & 0xFF
+        return bytes(self.data)
+
+    # The header, without the logo and checksums: there's no boot ROM
+    def header(self, title):
+        self.org(0x100)
+        self.emit(0x00, 0xC3, "main")  # nop, jp main
+        self.org(0x134)
+        self.emit(*title.encode().ljust(16, b"\0"))
+        self.org(0x150)
+
+    def ld_a(self, value):
+        self.emit(0x3E, value)
+
+    def ldh_store(self, register):
+        self.emit(0xE0, register)  # ldh [n],a
+
+    def ldh_load(self, register):
+        self.emit(0xF0, register)  # ldh a,[n]
+
+    def set(self, register, value):
+        self.ld_a(value)
+        self.ldh_store(register)
+
+
+DIRECTORY = os.path.dirname(os.path.abspath(__file__))
+
+
+def write(name, rom):
+    directory = os.path.join(DIRECTORY, "gameperson")
+    os.makedirs(directory, exist_ok=True)
+    with open(os.path.join(directory, name), "wb") as file:
+        file.write(rom.build())
+
+
+# Instruction timing, measured like Blargg's instr_timing: the timer counts
+# every 4 M-cycles (TAC=05), and running an instruction 4 times takes its
+# own M-cycles in timer counts more than running nothing. A test is its
+# setup, the instruction (repeated 4 times, with unique labels) and its
+# M-cycles according to Pan Docs.
+def timing_tests(rom):
+    def jump_to_next(opcode):
+        def instruction():
+            after = rom.unique()
+            rom.emit(opcode, after)
+            rom.label(after)
+
+        return instruction
+
+    def relative_to_next(opcode):
+        def instruction():
+            after = rom.unique()
+            rom.relative(opcode, after)
+            rom.label(after)
+
+        return instruction
+
+    # RET repeated 4 times, returning to the next one: the setup pushes
+    # the 4 return addresses in reverse
+    pending = []
+
+    def ret_setup():
+        pending[:] = [rom.unique() for _ in range(4)]
+        for name in reversed(pending):
+            rom.emit(0x21, name, 0xE5)  # ld hl,name, push hl
+
+    def ret(opcode):
+        def instruction():
+            rom.emit(opcode)
+            rom.label(pending.pop(0))
+
+        return instruction
+
+    def jp_hl():
+        after = rom.unique()
+        rom.emit(0x21, after, 0xE9)  # ld hl,after, jp hl
+        rom.label(after)
+
+    hl_wram = lambda: rom.emit(0x21, 0x00, 0xC0)  # ld hl,$c000
+    c_hram = lambda: rom.emit(0x0E, 0x80)  # ld c,$80
+    zero = lambda: rom.emit(0xAF)  # xor a, setting Z
+    nonzero = lambda: rom.emit(0xF6, 0x01)  # or 1, clearing Z
+    none = lambda: None
+
+    def opcode(*values):
+        return lambda: rom.emit(*values)
+
+    return [
+        ("nop", none, opcode(0x00), 1),
+        ("ld b,c", none, opcode(0x41), 1),
+        ("ld b,n", none, opcode(0x06, 0x12), 2),
+        ("ld b,[hl]", hl_wram, opcode(0x46), 2),
+        ("ld [hl],b", hl_wram, opcode(0x70), 2),
+        ("ld [hl],n", hl_wram, opcode(0x36, 0x12), 3),
+        ("ld a,[bc]", lambda: rom.emit(0x01, 0x00, 0xC0), opcode(0x0A), 2),
+        ("ld [hl+],a", hl_wram, opcode(0x22), 2),
+        ("ld a,[nn]", none, opcode(0xFA, 0x00, 0xC0), 4),
+        ("ld [nn],a", none, opcode(0xEA, 0x00, 0xC0), 4),
+        ("ldh a,[n]", none, opcode(0xF0, 0x80), 3),
+        ("ldh [n],a", none, opcode(0xE0, 0x80), 3),
+        ("ld a,[c]", c_hram, opcode(0xF2), 2),
+        ("ld [c],a", c_hram, opcode(0xE2), 2),
+        ("ld bc,nn", none, opcode(0x01, 0x34, 0x12), 3),
+        ("ld [nn],sp", none, opcode(0x08, 0x00, 0xC0), 5),
+        ("ld sp,hl", lambda: rom.emit(0x21, 0xF0, 0xDF), opcode(0xF9), 2),
+        ("push bc", none, opcode(0xC5), 4),
+        ("pop bc", none, opcode(0xC1), 3),
+        ("ld hl,sp+e", none, opcode(0xF8, 0x01), 3),
+        ("add sp,e", none, opcode(0xE8, 0x01), 4),
+        ("inc bc", none, opcode(0x03), 2),
+        ("add hl,bc", none, opcode(0x09), 2),
+        ("add a,b", none, opcode(0x80), 1),
+        ("add a,[hl]", hl_wram, opcode(0x86), 2),
+        ("add a,n", none, opcode(0xC6, 0x01), 2),
+        ("inc b", none, opcode(0x04), 1),
+        ("inc [hl]", hl_wram, opcode(0x34), 3),
+        ("daa", none, opcode(0x27), 1),
+        ("cpl", none, opcode(0x2F), 1),
+        ("rlca", none, opcode(0x07), 1),
+        ("di", none, opcode(0xF3), 1),
+        ("ei", none, opcode(0xFB), 1),
+        ("jr e", none, relative_to_next(0x18), 3),
+        ("jr nz,e not taken", zero, relative_to_next(0x20), 2),
+        ("jr z,e taken", zero, relative_to_next(0x28), 3),
+        ("jp nn", none, jump_to_next(0xC3), 4),
+        ("jp nz,nn not taken", zero, jump_to_next(0xC2), 3),
+        ("jp z,nn taken", zero, jump_to_next(0xCA), 4),
+        ("ld hl,nn + jp hl", none, jp_hl, 4),
+        ("call nn", none, jump_to_next(0xCD), 6),
+        ("call nz,nn not taken", zero, jump_to_next(0xC4), 3),
+        ("call z,nn taken", zero, jump_to_next(0xCC), 6),
+        ("ret", ret_setup, ret(0xC9), 4),
+        ("ret nz not taken", zero, opcode(0xC0), 2),
+        ("ret z taken", lambda: (ret_setup(), zero()), ret(0xC8), 5),
+        ("ret z not taken", nonzero, opcode(0xC8), 2),
+        ("reti", ret_setup, ret(0xD9), 4),
+        ("rst $38 + ret", none, opcode(0xFF), 8),
+        ("rlc b", none, opcode(0xCB, 0x00), 2),
+        ("rlc [hl]", hl_wram, opcode(0xCB, 0x06), 4),
+        ("bit 0,[hl]", hl_wram, opcode(0xCB, 0x46), 3),
+        ("set 0,[hl]", hl_wram, opcode(0xCB, 0xC6), 4),
+        ("swap b", none, opcode(0xCB, 0x30), 2),
+    ]
+
+
+def instr_timing():
+    rom = Rom()
+
+    # RST $38 returns right away
+    rom.org(0x38)
+    rom.emit(0xC9)
+
+    rom.header("INSTR TIMING")
+    rom.label("main")
+    rom.emit(0xF3)  # di
+    rom.set(IE, 0x00)
+    rom.set(TAC, 0x05)
+
+    results = 0xC100
+
+    # Run the setup and 4 times the instruction, storing the timer counts
+    def measure(setup, instruction, result):
+        rom.emit(0xF3, 0x31, 0xF0, 0xDF)  # di, ld sp,$dff0
+        setup()
+        rom.ld_a(0x00)  # unlike xor a, keeps the flags
+        rom.ldh_store(DIV)
+        rom.ldh_store(TIMA)
+        for _ in range(4):
+            instruction()
+        rom.ldh_load(TIMA)
+        rom.emit(0xEA, result & 0xFF, result >> 8)  # ld [result],a
+
+    tests = timing_tests(rom)
+
+    measure(lambda: None, lambda: None, results)
+    for i, (_, setup, instruction, _) in enumerate(tests):
+        measure(setup, instruction, results + 1 + i)
+
+    rom.emit(0x31, 0xFE, 0xFF)  # ld sp,$fffe
+
+    # Compare with the baseline, printing the failed tests
+    rom.emit(0x16, 0x00)  # ld d,0, the failures
+    for i, (name, _, _, cycles) in enumerate(tests):
+        result = results + 1 + i
+        passed = rom.unique()
+        rom.emit(0xFA, result & 0xFF, result >> 8)  # ld a,[result]
+        rom.emit(0x21, results & 0xFF, results >> 8)  # ld hl,results
+        rom.emit(0x96)  # sub [hl]
+        rom.emit(0xFE, cycles)  # cp cycles
+        rom.relative(0x28, passed)  # jr z,passed
+        rom.emit(0x47)  # ld b,a
+        rom.emit(0x21, f"name {i}", 0xCD, "print")  # ld hl,name, call print
+        rom.emit(0x78, 0xCD, "print_hex")  # ld a,b, call print_hex
+        rom.emit(0x21, "newline", 0xCD, "print")
+        rom.emit(0x14)  # inc d
+        rom.label(passed)
+
+    rom.emit(0x7A, 0xB7)  # ld a,d, or a
+    rom.emit(0x21, "passed")
+    rom.relative(0x28, "done")  # jr z,done
+    rom.emit(0x21, "failed")
+    rom.label("done")
+    rom.emit(0xCD, "print")
+    rom.label("forever")
+    rom.relative(0x18, "forever")
+
+    # Print the zero terminated string at HL
+    rom.label("print")
+    rom.emit(0x2A, 0xB7, 0xC8)  # ld a,[hl+], or a, ret z
+    rom.emit(0xCD, "send")
+    rom.relative(0x18, "print")
+
+    # Print A in hexadecimal
+    rom.label("print_hex")
+    rom.emit(0xF5, 0xCB, 0x37, 0xCD, "print_digit")  # push af, swap a, call
+    rom.emit(0xF1)  # pop af
+    rom.label("print_digit")
+    rom.emit(0xE6, 0x0F, 0xFE, 0x0A)  # and $0f, cp 10
+    rom.relative(0x38, "digit")  # jr c,digit
+    rom.emit(0xC6, ord("a") - ord("0") - 10)  # add a,"a"-"0"-10
+    rom.label("digit")
+    rom.emit(0xC6, ord("0"))  # add a,"0"
+
+    # Send A through the serial port, waiting for the transfer
+    rom.label("send")
+    rom.ldh_store(SB)
+    rom.set(SC, 0x81)
+    rom.label("wait")
+    rom.ldh_load(SC)
+    rom.emit(0xE6, 0x80)  # and $80
+    rom.relative(0x20, "wait")  # jr nz,wait
+    rom.emit(0xC9)
+
+    for i, (name, _, _, cycles) in enumerate(tests):
+        rom.label(f"name {i}")
+        rom.emit(*f"{name}: expected {cycles:x}, got ".encode(), 0)
+    rom.label("newline")
+    rom.emit(ord("\n"), 0)
+    rom.label("passed")
+    rom.emit(*b"Passed\n", 0)
+    rom.label("failed")
+    rom.emit(*b"Failed\n", 0)
+
+    write("instr_timing.gb", rom)
+
+
+# Interrupt dispatch: the handlers log their interrupt's IF bit and B at
+# LOG, then the tests compare the log with what Pan Docs describes.
+LOG = 0xC020
+LOG_END = 0xC01F
+
+
+def interrupts():
+    rom = Rom()
+
+    for vector, bit in ((0x40, 0x01), (0x48, 0x02), (0x50, 0x04), (0x58, 0x08), (0x60, 0x10)):
+        rom.org(vector)
+        rom.emit(0xF5, 0x3E, bit, 0xC3, "handler")  # push af, ld a,bit, jp handler
+
+    rom.header("INTERRUPTS")
+
+    rom.label("handler")
+    rom.emit(0xE5, 0xF5)  # push hl, push af
+    rom.emit(0x26, LOG >> 8)  # ld h,high(LOG)
+    rom.emit(0xFA, LOG_END & 0xFF, LOG_END >> 8, 0x6F)  # ld a,[LOG_END], ld l,a
+    rom.emit(0xF1, 0x22, 0x70, 0x2C)  # pop af, ld [hl+],a, ld [hl],b, inc l
+    rom.emit(0x7D, 0xEA, LOG_END & 0xFF, LOG_END >> 8)  # ld a,l, ld [LOG_END],a
+    rom.emit(0xE1, 0xF1, 0xD9)  # pop hl, pop af, reti
+
+    rom.label("main")
+
+    test = 0
+
+    def start():
+        nonlocal test
+        test += 1
+        rom.emit(0xF3)  # di
+        rom.set(TAC, 0x00)
+        rom.set(IF, 0x00)
+        rom.ld_a(LOG & 0xFF)
+        rom.emit(0xEA, LOG_END & 0xFF, LOG_END >> 8)  # ld [LOG_END],a
+        rom.emit(0x06, 0x00)  # ld b,0
+
+    # Jump to fail with the test number in C unless [address] == value
+    def expect(address, value):
+        passed = rom.unique()
+        rom.emit(0xFA, address & 0xFF, address >> 8)  # ld a,[address]
+        rom.emit(0xFE, value)  # cp value
+        rom.relative(0x28, passed)  # jr z,passed
+        rom.emit(0x0E, test, 0xC3, "fail")  # ld c,test, jp fail
+        rom.label(passed)
+
+    def expect_log(*entries):
+        expect(LOG_END, (LOG & 0xFF) + len(entries))
+        for i, value in enumerate(entries):
+            expect(LOG + i, value)
+
+    def expect_b(value):
+        rom.emit(0x78, 0xEA, 0x00, 0xC0)  # ld a,b, ld [$c000],a
+        expect(0xC000, value)
+
+    def expect_register(register, mask, value):
+        rom.ldh_load(register)
+        rom.emit(0xE6, mask, 0xEA, 0x00, 0xC0)  # and mask, ld [$c000],a
+        expect(0xC000, value)
+
+    # EI enables interrupts after the next instruction
+    start()
+    rom.set(IE, 0x04)
+    rom.set(IF, 0x04)
+    rom.emit(0xFB, 0x04, 0x04)  # ei, inc b, inc b
+    expect_log(0x04, 1)
+    expect_register(IF, 0x04, 0x00)
+
+    # DI right after EI, nothing is serviced
+    start()
+    rom.set(IE, 0x04)
+    rom.set(IF, 0x04)
+    rom.emit(0xFB, 0xF3, 0x00)  # ei, di, nop
+    expect_log()
+    expect_register(IF, 0x04, 0x04)
+
+    # Nothing is serviced when the interrupt isn't enabled in IE
+    start()
+    rom.set(IE, 0x01)
+    rom.set(IF, 0x04)
+    rom.emit(0xFB, 0x00, 0x00)  # ei, nop, nop
+    expect_log()
+
+    # The one with the lowest bit goes first, and RETI lets the next one
+    # be serviced right away
+    start()
+    rom.set(IE, 0x05)
+    rom.emit(0x06, 0x09)  # ld b,9
+    rom.set(IF, 0x05)
+    rom.emit(0xFB, 0x00, 0x04)  # ei, nop, inc b
+    expect_log(0x01, 9, 0x04, 9)
+
+    # HALT with IME clear wakes up on the timer overflow, without
+    # servicing it
+    start()
+    rom.set(IE, 0x04)
+    rom.set(TIMA, 0xF0)
+    rom.set(TMA, 0x00)
+    rom.set(TAC, 0x05)
+    rom.emit(0x76, 0x00)  # halt, nop
+    expect_log()
+    expect_register(IF, 0x04, 0x04)
+
+    # HALT with IME set services it
+    start()
+    rom.set(IE, 0x04)
+    rom.set(TIMA, 0xF0)
+    rom.set(TAC, 0x05)
+    rom.emit(0x06, 0x07, 0xFB, 0x76, 0x04)  # ld b,7, ei, halt, inc b
+    expect_log(0x04, 7)
+    expect_b(8)
+
+    # The HALT bug: with IME clear and an interrupt already pending, HALT
+    # doesn't halt and the next byte is read twice
+    start()
+    rom.set(IE, 0x04)
+    rom.set(IF, 0x04)
+    rom.emit(0x76, 0x04)  # halt, inc b
+    expect_log()
+    expect_b(2)
+
+    rom.emit(0xF3)  # di
+    rom.emit(0x06, 3, 0x0E, 5, 0x16, 8, 0x1E, 13, 0x26, 21, 0x2E, 34)
+    rom.emit(0x40)  # ld b,b
+    rom.label("forever")
+    rom.relative(0x18, "forever")
+
+    rom.label("fail")
+    rom.emit(0x06, 0x42, 0x40)  # ld b,$42, ld b,b
+    rom.relative(0x18, "forever")
+
+    write("interrupts.gb", rom)
+
+
+# The tiles, 2 bytes per row: the low bits of the color numbers, then the
+# high bits
+def tile(rows):
+    data = []
+    for row in rows:
+        data.append(sum(1 << (7 - i) for i, color in enumerate(row) if int(color) & 1))
+        data.append(sum(1 << (7 - i) for i, color in enumerate(row) if int(color) & 2))
+    return data
+
+
+# Color numbers 0-3 everywhere, in different shapes
+BG_TILES = {
+    0x00: tile(["00000000"] * 8),
+    0x01: tile(["01230123", "12301230", "23012301", "30123012"] * 2),
+    0x80: tile(["33333333", "30000003", "30111103", "30122103", "30122103", "30111103", "30000003", "33333333"]),
+    0xFF: tile(["22220000", "22220000", "22220000", "22220000", "00001111", "00001111", "00001111", "00001111"]),
+}
+
+# An arrow pointing to the top left, to see the flips
+SPRITE_TILE = tile(["33333000", "32222000", "32110000", "32101000", "30010100", "00000010", "00000001", "00000000"])
+
+SCX, SCY = 0x13, 0x0B
+OBP0, OBP1 = 0xE4, 0x1B
+
+# Y, X, tile, flags: none, X flip, Y flip, both and OBP1
+SPRITES = [
+    (40, 30, 2, 0x00),
+    (40, 60, 2, 0x20),
+    (70, 30, 2, 0x40),
+    (70, 60, 2, 0x60),
+    (120, 140, 2, 0x60),
+    (100, 90, 2, 0x10),
+]
+
+# The color of the shades on screen, as drawn by the GPU
+SHADES = [(0xD0, 0xF8, 0xE0), (0x70, 0xC0, 0x88), (0x56, 0x68, 0x34), (0x20, 0x18, 0x10)]
+
+
+def bg_map():
+    tiles = sorted(BG_TILES)
+    return [tiles[(x // 3 + y) % len(tiles)] for y in range(32) for x in range(32)]
+
+
+def screen():
+    rom = Rom()
+    rom.header("SCREEN")
+
+    rom.label("main")
+    # Without a boot ROM the LCD is off, VRAM and OAM are free to write
+    for destination, source, size in (
+        (0x9000, "tile 00", 16),
+        (0x9010, "tile 01", 16),
+        (0x8800, "tile 80", 16),
+        (0x8FF0, "tile ff", 16),
+        (0x8020, "sprite", 16),
+        (0x9C00, "map", 0x400),
+        (0xFE00, "oam", 0xA0),
+    ):
+        rom.emit(0x21, source)  # ld hl,source
+        rom.emit(0x11, destination & 0xFF, destination >> 8)  # ld de,destination
+        rom.emit(0x01, size & 0xFF, size >> 8)  # ld bc,size
+        rom.emit(0xCD, "copy")
+
+    rom.set(0x43, SCX)
+    rom.set(0x42, SCY)
+    rom.set(0x47, 0xE4)
+    rom.set(0x48, OBP0)
+    rom.set(0x49, OBP1)
+    # LCD and BG on, sprites on, BG map at 9C00, BG tiles at 8800-97FF
+    rom.set(0x40, 0x8B)
+    rom.label("forever")
+    rom.relative(0x18, "forever")
+
+    # Copy BC bytes from HL to DE
+    rom.label("copy")
+    rom.emit(0x2A, 0x12, 0x13, 0x0B)  # ld a,[hl+], ld [de],a, inc de, dec bc
+    rom.emit(0x78, 0xB1)  # ld a,b, or c
+    rom.relative(0x20, "copy")  # jr nz,copy
+    rom.emit(0xC9)
+
+    for number, data in BG_TILES.items():
+        rom.label(f"tile {number:02x}")
+        rom.emit(*data)
+    rom.label("sprite")
+    rom.emit(*SPRITE_TILE)
+    rom.label("map")
+    rom.emit(*bg_map())
+    rom.label("oam")
+    oam = [byte for sprite in SPRITES for byte in sprite]
+    rom.emit(*oam, *[0] * (0xA0 - len(oam)))
+
+    write("screen.gb", rom)
+    write_png("gameperson_screen.png", reference_screen())
+
+
+def color(data, x, y):
+    return (data[y * 2] >> (7 - x) & 1) | (data[y * 2 + 1] >> (7 - x) & 1) << 1
+
+
+def shade(palette, number):
+    return palette >> (number * 2) & 3
+
+
+# What the screen looks like according to Pan Docs
+def reference_screen():
+    tiles = bg_map()
+    pixels = []
+    for y in range(144):
+        row = []
+        for x in range(160):
+            map_x, map_y = (x + SCX) & 0xFF, (y + SCY) & 0xFF
+            data = BG_TILES[tiles[map_y // 8 * 32 + map_x // 8]]
+            row.append(shade(0xE4, color(data, map_x % 8, map_y % 8)))
+        pixels.append(row)
+
+    for sprite_y, sprite_x, _, flags in SPRITES:
+        palette = OBP1 if flags & 0x10 else OBP0
+        for y in range(8):
+            for x in range(8):
+                number = color(
+                    SPRITE_TILE,
+                    7 - x if flags & 0x20 else x,
+                    7 - y if flags & 0x40 else y,
+                )
+                if number != 0:
+                    pixels[sprite_y - 16 + y][sprite_x - 8 + x] = shade(palette, number)
+
+    return [[SHADES[pixel] for pixel in row] for row in pixels]
+
+
+def write_png(name, pixels):
+    def chunk(kind, data):
+        return struct.pack(">I", len(data)) + kind + data + struct.pack(">I", zlib.crc32(kind + data))
+
+    height, width = len(pixels), len(pixels[0])
+    rows = b"".join(b"\0" + bytes(value for pixel in row for value in pixel) for row in pixels)
+
+    with open(os.path.join(DIRECTORY, "..", "screenshots", name), "wb") as file:
+        file.write(b"\x89PNG\r\n\x1a\n")
+        file.write(chunk(b"IHDR", struct.pack(">IIBBBBB", width, height, 8, 2, 0, 0, 0)))
+        file.write(chunk(b"IDAT", zlib.compress(rows, 9)))
+        file.write(chunk(b"IEND", b""))
+
+
+instr_timing()
+interrupts()
+screen()
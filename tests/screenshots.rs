@@ -0,0 +1,146 @@
+// Screenshot regression tests
+//
+// Runs ROMs headless for a number of frames and compares the screen with
+// the reference images in tests/screenshots. See tests/screenshots/README.md.
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use gameperson::gameboy::GameBoy;
+use gameperson::gpu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use gameperson::image::save_png;
+
+fn manifest_dir() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join("screenshots")
+}
+
+// Read a PNG as RGB888 pixels
+fn read_png(path: &Path) -> (u32, u32, Vec<u8>) {
+    let decoder = png::Decoder::new(File::open(path).unwrap());
+    let mut reader = decoder.read_info().unwrap();
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).unwrap();
+    buffer.truncate(info.buffer_size());
+
+    assert_eq!(info.bit_depth, png::BitDepth::Eight, "{}", path.display());
+
+    let pixels = match info.color_type {
+        png::ColorType::Rgb => buffer,
+        png::ColorType::Rgba => buffer
+            .chunks_exact(4)
+            .flat_map(|pixel| pixel[..3].to_vec())
+            .collect(),
+        color_type => panic!("{}: unsupported {:?}", path.display(), color_type),
+    };
+
+    (info.width, info.height, pixels)
+}
+
+// The reference image, dimmed, with the mismatching pixels in red.
+fn diff(expected: &[u8], actual: &[u8]) -> Vec<u8> {
+    expected
+        .chunks_exact(3)
+        .zip(actual.chunks_exact(3))
+        .flat_map(|(expected, actual)| {
+            if expected == actual {
+                expected.iter().map(|c| c / 4 + 0xa0).collect()
+            } else {
+                vec![0xff, 0x00, 0x00]
+            }
+        })
+        .collect()
+}
+
+fn screenshot(name: &str, rom: &str, frames: u32) {
+    let rom_path = manifest_dir().join("tests/roms").join(rom);
+    let rom = match fs::read(&rom_path) {
+        Ok(rom) => rom,
+        Err(e) => panic!("{}: {}, see tests/roms/README.md", rom_path.display(), e),
+    };
+
+    let mut gameboy = GameBoy::new(rom, None);
+    for _ in 0..frames {
+        gameboy.run_frame();
+    }
+
+    let width = u32::from(SCREEN_WIDTH);
+    let height = u32::from(SCREEN_HEIGHT);
+    let actual = gameboy.memory().gpu().screen().to_vec();
+
+    let output_dir = output_dir();
+    fs::create_dir_all(&output_dir).unwrap();
+    let actual_path = output_dir.join(format!("{}.png", name));
+
+    let reference_path = manifest_dir()
+        .join("tests/screenshots")
+        .join(format!("{}.png", name));
+
+    if !reference_path.exists() {
+        save_png(&actual_path, width, height, &actual).unwrap();
+        panic!(
+            "{}: missing reference image {}, screen saved to {}",
+            name,
+            reference_path.display(),
+            actual_path.display()
+        );
+    }
+
+    let (expected_width, expected_height, expected) = read_png(&reference_path);
+    assert_eq!(
+        (expected_width, expected_height),
+        (width, height),
+        "{}: wrong reference image size",
+        name
+    );
+
+    if expected != actual {
+        let mismatching = expected
+            .chunks_exact(3)
+            .zip(actual.chunks_exact(3))
+            .filter(|(expected, actual)| expected != actual)
+            .count();
+
+        let diff_path = output_dir.join(format!("{}-diff.png", name));
+
+        save_png(&actual_path, width, height, &actual).unwrap();
+        save_png(&diff_path, width, height, &diff(&expected, &actual)).unwrap();
+
+        panic!(
+            "{}: {} pixels differ, screen saved to {}, diff to {}",
+            name,
+            mismatching,
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+// The ROM and the reference image are built by tests/roms/generate.py
+#[test]
+fn gameperson_screen() {
+    screenshot("gameperson_screen", "gameperson/screen.gb", 10);
+}
+
+// The ROMs and reference images aren't checked in, and the window, 8x16
+// sprites and sprite priority they check aren't implemented yet, see
+// tests/screenshots/README.md
+#[test]
+#[ignore = "needs the ROM in tests/roms and the reference in tests/screenshots"]
+fn dmg_acid2() {
+    screenshot("dmg_acid2", "dmg-acid2/dmg-acid2.gb", 60);
+}
+
+#[test]
+#[ignore = "needs the ROM in tests/roms and the reference in tests/screenshots"]
+fn mooneye_sprite_priority() {
+    screenshot(
+        "mooneye_sprite_priority",
+        "mooneye/manual-only/sprite_priority.gb",
+        60,
+    );
+}
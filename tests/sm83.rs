@@ -0,0 +1,277 @@
+// SM83 SingleStepTests
+//
+// Runs every instruction of tests/sm83/v1/*.json, or of the generated
+// tests/sm83/*.json without them, against Cpu on a flat 64KiB bus,
+// comparing registers, memory and bus activity of every M-cycle with the
+// recorded ones. See tests/sm83/README.md.
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use serde_json::Value;
+
+use gameperson::cpu::Cpu;
+use gameperson::memory::Bus;
+
+// What happened on the bus during an M-cycle
+#[derive(Debug, PartialEq)]
+enum Cycle {
+    Idle,
+    Read(u16, u8),
+    Write(u16, u8),
+}
+
+struct TestBus {
+    memory: Vec<u8>,
+
+    // read() only borrows the bus, but still needs to be recorded
+    cycles: RefCell<Vec<Cycle>>,
+}
+
+impl TestBus {
+    fn new() -> Self {
+        Self {
+            memory: vec![0; 0x10000],
+            cycles: RefCell::new(Vec::new()),
+        }
+    }
+
+    // The CPU ticks before every access, so the access belongs to the
+    // last M-cycle. Accesses outside of an M-cycle (e.g. the interrupt
+    // check before fetching) aren't bus activity.
+    fn record(&self, cycle: Cycle) {
+        if let Some(last) = self.cycles.borrow_mut().last_mut() {
+            *last = cycle;
+        }
+    }
+}
+
+impl Bus for TestBus {
+    fn read(&self, address: u16) -> u8 {
+        let value = self.memory[usize::from(address)];
+        self.record(Cycle::Read(address, value));
+
+        value
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.memory[usize::from(address)] = value;
+        self.record(Cycle::Write(address, value));
+    }
+
+    fn tick(&mut self) {
+        self.cycles.get_mut().push(Cycle::Idle);
+    }
+}
+
+fn field(state: &Value, name: &str) -> u16 {
+    state[name]
+        .as_u64()
+        .and_then(|value| u16::try_from(value).ok())
+        .unwrap_or_else(|| panic!("Missing or invalid field {}", name))
+}
+
+fn byte(state: &Value, name: &str) -> u8 {
+    field(state, name) as u8
+}
+
+fn setup(cpu: &mut Cpu, bus: &mut TestBus, state: &Value) {
+    cpu.pc = field(state, "pc");
+    cpu.sp = field(state, "sp");
+    cpu.regs.a = byte(state, "a");
+    cpu.regs.flags = byte(state, "f");
+    cpu.regs.b = byte(state, "b");
+    cpu.regs.c = byte(state, "c");
+    cpu.regs.d = byte(state, "d");
+    cpu.regs.e = byte(state, "e");
+    cpu.regs.h = byte(state, "h");
+    cpu.regs.l = byte(state, "l");
+    cpu.set_interrupts_enabled(state["ime"].as_u64() == Some(1));
+
+    if let Some(ie) = state["ie"].as_u64() {
+        bus.memory[0xffff] = ie as u8;
+    }
+
+    for entry in state["ram"].as_array().expect("Missing ram") {
+        bus.memory[entry[0].as_u64().unwrap() as usize] = entry[1].as_u64().unwrap() as u8;
+    }
+}
+
+// Compare the state after the instruction with the expected one,
+// returning the differences.
+fn check(cpu: &Cpu, bus: &TestBus, state: &Value, cycles: &[Value]) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    let registers = [
+        ("pc", cpu.pc),
+        ("sp", cpu.sp),
+        ("a", cpu.regs.a.into()),
+        ("f", cpu.regs.flags.into()),
+        ("b", cpu.regs.b.into()),
+        ("c", cpu.regs.c.into()),
+        ("d", cpu.regs.d.into()),
+        ("e", cpu.regs.e.into()),
+        ("h", cpu.regs.h.into()),
+        ("l", cpu.regs.l.into()),
+    ];
+
+    for &(name, value) in &registers {
+        let expected = field(state, name);
+        if value != expected {
+            errors.push(format!(
+                "{}: {:#06x}, expected {:#06x}",
+                name, value, expected
+            ));
+        }
+    }
+
+    // The test vectors consider IME set right after EI
+    if let Some(ime) = state["ime"].as_u64() {
+        let value = cpu.interrupts_enabled() || cpu.ime_scheduled();
+        if value != (ime == 1) {
+            errors.push(format!("ime: {}, expected {}", value, ime == 1));
+        }
+    }
+
+    for entry in state["ram"].as_array().expect("Missing ram") {
+        let address = entry[0].as_u64().unwrap() as usize;
+        let expected = entry[1].as_u64().unwrap() as u8;
+        let value = bus.memory[address];
+
+        if value != expected {
+            errors.push(format!(
+                "[{:#06x}]: {:#04x}, expected {:#04x}",
+                address, value, expected
+            ));
+        }
+    }
+
+    let expected: Vec<Cycle> = cycles
+        .iter()
+        .map(|cycle| match cycle.as_array() {
+            Some(cycle) => {
+                let address = cycle[0].as_u64().unwrap_or_default() as u16;
+                let value = cycle[1].as_u64().unwrap_or_default() as u8;
+
+                match cycle[2].as_str().unwrap_or_default() {
+                    kind if kind.starts_with('r') => Cycle::Read(address, value),
+                    kind if kind.contains('w') => Cycle::Write(address, value),
+                    _ => Cycle::Idle,
+                }
+            }
+            None => Cycle::Idle,
+        })
+        .collect();
+
+    if *bus.cycles.borrow() != expected {
+        errors.push(format!(
+            "cycles: {:?}, expected {:?}",
+            bus.cycles.borrow(),
+            expected
+        ));
+    }
+
+    errors
+}
+
+// Run all the tests in a file, returning the description of the first
+// failure and the number of failed tests.
+fn run_file(path: &Path) -> Option<(String, usize)> {
+    let json = fs::read_to_string(path).unwrap();
+    let tests: Value = serde_json::from_str(&json).unwrap();
+
+    let mut first_failure = None;
+    let mut failures = 0;
+
+    for test in tests.as_array().expect("Expected an array of tests") {
+        let bus = Rc::new(RefCell::new(TestBus::new()));
+
+        let cpu_bus = Rc::clone(&bus);
+        let mut cpu = Cpu::new(cpu_bus);
+
+        setup(&mut cpu, &mut bus.borrow_mut(), &test["initial"]);
+
+        let cycles = cpu.decode();
+
+        let expected_cycles = test["cycles"].as_array().expect("Missing cycles");
+        let mut errors = check(&cpu, &bus.borrow(), &test["final"], expected_cycles);
+
+        if usize::from(cycles) != expected_cycles.len() * 4 {
+            errors.push(format!(
+                "decode() returned {} T-cycles, expected {}",
+                cycles,
+                expected_cycles.len() * 4
+            ));
+        }
+
+        if !errors.is_empty() {
+            failures += 1;
+            first_failure
+                .get_or_insert_with(|| format!("{}\n    {}", test["name"], errors.join("\n    ")));
+        }
+    }
+
+    first_failure.map(|failure| (failure, failures))
+}
+
+fn json_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| path.extension() == Some("json".as_ref()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    files.sort();
+
+    files
+}
+
+// The upstream vectors in tests/sm83/v1 when there are any, the ones
+// written by generate.py otherwise
+fn test_files() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/sm83");
+
+    let upstream = json_files(&dir.join("v1"));
+    if !upstream.is_empty() {
+        return upstream;
+    }
+
+    eprintln!("No upstream vectors in tests/sm83/v1, running the generated ones");
+    json_files(&dir)
+}
+
+#[test]
+fn sm83() {
+    let files = test_files();
+
+    assert!(
+        !files.is_empty(),
+        "No SM83 test vectors in tests/sm83, see tests/sm83/README.md"
+    );
+
+    let mut failed = Vec::new();
+
+    for path in &files {
+        let opcode = path.file_stem().unwrap().to_string_lossy();
+
+        if let Some((failure, count)) = run_file(path) {
+            eprintln!("FAIL {} ({} tests failed)\n  {}", opcode, count, failure);
+            failed.push(opcode.to_string());
+        }
+    }
+
+    assert!(
+        failed.is_empty(),
+        "{}/{} opcodes failed: {}",
+        failed.len(),
+        files.len(),
+        failed.join(", ")
+    );
+}
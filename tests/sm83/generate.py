@@ -0,0 +1,423 @@
+#!/usr/bin/env python3
+# Generates the trimmed SM83 test vectors checked in next to this script, in
+# the format of SingleStepTests/sm83 (see README.md), from a model of the
+# SM83 written from Pan Docs and gbctr, independently of src/cpu.rs.
+#
+# Every opcode but HALT, STOP and the illegal ones gets TESTS tests, from
+# random states. Run it from anywhere, it rewrites the *.json files:
+#
+#   python3 tests/sm83/generate.py
+
+import json
+import os
+import random
+
+TESTS = 8
+SEED = 0x5383
+
+Z, N, H, C = 0x80, 0x40, 0x20, 0x10
+
+SKIPPED = {0x10, 0x76, 0xCB, 0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD}
+
+# The interrupt registers, never touched so that no interrupt is pending
+RESERVED = {0xFF0F, 0xFFFF}
+
+
+class Retry(Exception):
+    pass
+
+
+class Cpu:
+    def __init__(self, rng, state, ram):
+        self.rng = rng
+        self.__dict__.update(state)
+        self.ram = dict(ram)
+        self.initial = dict(ram)
+        self.cycles = []
+
+    # Memory not set up reads as random bytes, recorded in the initial state
+    def peek(self, address):
+        if address in RESERVED:
+            raise Retry()
+        if address not in self.ram:
+            value = self.rng.randrange(256)
+            self.ram[address] = value
+            self.initial[address] = value
+        return self.ram[address]
+
+    def read(self, address):
+        value = self.peek(address)
+        self.cycles.append([address, value, "r-m"])
+        return value
+
+    def write(self, address, value):
+        self.peek(address)
+        self.ram[address] = value
+        self.cycles.append([address, value, "-wm"])
+
+    def idle(self):
+        self.cycles.append(None)
+
+    def fetch(self):
+        value = self.read(self.pc)
+        self.pc = (self.pc + 1) & 0xFFFF
+        return value
+
+    def fetch16(self):
+        low = self.fetch()
+        return low | self.fetch() << 8
+
+    def flag(self, mask):
+        return 1 if self.f & mask else 0
+
+    def set_flags(self, z, n, h, c):
+        self.f = (z and Z) | (n and N) | (h and H) | (c and C)
+
+    # Registers by their 3-bit encoding, 6 being [HL]
+    def r8(self, index):
+        if index == 6:
+            return self.read(self.hl())
+        return getattr(self, "bcdehl_a"[index])
+
+    def set_r8(self, index, value):
+        if index == 6:
+            self.write(self.hl(), value)
+        else:
+            setattr(self, "bcdehl_a"[index], value)
+
+    def pair(self, high, low):
+        return getattr(self, high) << 8 | getattr(self, low)
+
+    def set_pair(self, high, low, value):
+        setattr(self, high, value >> 8)
+        setattr(self, low, value & 0xFF)
+
+    def hl(self):
+        return self.pair("h", "l")
+
+    # BC DE HL SP
+    def rr(self, index):
+        if index == 3:
+            return self.sp
+        return self.pair(*("bc", "de", "hl")[index])
+
+    def set_rr(self, index, value):
+        if index == 3:
+            self.sp = value
+        else:
+            self.set_pair(*("bc", "de", "hl")[index], value)
+
+    def condition(self, index):
+        return [
+            not self.flag(Z),
+            self.flag(Z),
+            not self.flag(C),
+            self.flag(C),
+        ][index]
+
+    def push(self, value):
+        self.sp = (self.sp - 1) & 0xFFFF
+        self.write(self.sp, value >> 8)
+        self.sp = (self.sp - 1) & 0xFFFF
+        self.write(self.sp, value & 0xFF)
+
+    def pop(self):
+        low = self.read(self.sp)
+        self.sp = (self.sp + 1) & 0xFFFF
+        high = self.read(self.sp)
+        self.sp = (self.sp + 1) & 0xFFFF
+        return high << 8 | low
+
+    def alu(self, operation, value):
+        a, carry = self.a, self.flag(C)
+        if operation == 0:  # ADD
+            result = a + value
+            self.set_flags(result & 0xFF == 0, 0, (a & 0xF) + (value & 0xF) > 0xF, result > 0xFF)
+        elif operation == 1:  # ADC
+            result = a + value + carry
+            self.set_flags(result & 0xFF == 0, 0, (a & 0xF) + (value & 0xF) + carry > 0xF, result > 0xFF)
+        elif operation in (2, 7):  # SUB, CP
+            result = a - value
+            self.set_flags(result & 0xFF == 0, 1, (a & 0xF) < (value & 0xF), result < 0)
+        elif operation == 3:  # SBC
+            result = a - value - carry
+            self.set_flags(result & 0xFF == 0, 1, (a & 0xF) - (value & 0xF) - carry < 0, result < 0)
+        elif operation == 4:  # AND
+            result = a & value
+            self.set_flags(result == 0, 0, 1, 0)
+        elif operation == 5:  # XOR
+            result = a ^ value
+            self.set_flags(result == 0, 0, 0, 0)
+        else:  # OR
+            result = a | value
+            self.set_flags(result == 0, 0, 0, 0)
+        if operation != 7:
+            self.a = result & 0xFF
+
+    # RLC RRC RL RR SLA SRA SWAP SRL, setting Z from the result
+    def shift(self, operation, value):
+        carry = self.flag(C)
+        if operation == 0:
+            out = value >> 7
+            result = (value << 1 | out) & 0xFF
+        elif operation == 1:
+            out = value & 1
+            result = value >> 1 | out << 7
+        elif operation == 2:
+            out = value >> 7
+            result = (value << 1 | carry) & 0xFF
+        elif operation == 3:
+            out = value & 1
+            result = value >> 1 | carry << 7
+        elif operation == 4:
+            out = value >> 7
+            result = value << 1 & 0xFF
+        elif operation == 5:
+            out = value & 1
+            result = value >> 1 | value & 0x80
+        elif operation == 6:
+            out = 0
+            result = (value << 4 | value >> 4) & 0xFF
+        else:
+            out = value & 1
+            result = value >> 1
+        self.set_flags(result == 0, 0, 0, out)
+        return result
+
+    def add_sp(self):
+        offset = self.fetch()
+        self.set_flags(0, 0, (self.sp & 0xF) + (offset & 0xF) > 0xF, (self.sp & 0xFF) + offset > 0xFF)
+        return (self.sp + (offset - 256 if offset > 127 else offset)) & 0xFFFF
+
+    def execute_cb(self):
+        opcode = self.fetch()
+        group, bit, index = opcode >> 6, opcode >> 3 & 7, opcode & 7
+        value = self.r8(index)
+        if group == 0:
+            self.set_r8(index, self.shift(bit, value))
+        elif group == 1:
+            self.f = (0 if value & 1 << bit else Z) | H | self.f & C
+        elif group == 2:
+            self.set_r8(index, value & ~(1 << bit))
+        else:
+            self.set_r8(index, value | 1 << bit)
+
+    def execute(self):
+        opcode = self.fetch()
+        x, y, z = opcode >> 6, opcode >> 3 & 7, opcode & 7
+        p, q = y >> 1, y & 1
+
+        if opcode == 0x00:
+            pass
+        elif x == 0 and z == 1 and q == 0:  # LD rr,nn
+            self.set_rr(p, self.fetch16())
+        elif x == 0 and z == 1:  # ADD HL,rr
+            hl, value = self.hl(), self.rr(p)
+            result = hl + value
+            self.f = self.f & Z | ((hl & 0xFFF) + (value & 0xFFF) > 0xFFF and H) | (result > 0xFFFF and C)
+            self.set_pair("h", "l", result & 0xFFFF)
+            self.idle()
+        elif x == 0 and z == 2:  # LD (rr),A / LD A,(rr) with HL+ and HL-
+            address = [self.pair("b", "c"), self.pair("d", "e"), self.hl(), self.hl()][p]
+            if q == 0:
+                self.write(address, self.a)
+            else:
+                self.a = self.read(address)
+            if p == 2:
+                self.set_pair("h", "l", (address + 1) & 0xFFFF)
+            elif p == 3:
+                self.set_pair("h", "l", (address - 1) & 0xFFFF)
+        elif x == 0 and z == 3:  # INC rr, DEC rr
+            self.set_rr(p, (self.rr(p) + (1 if q == 0 else -1)) & 0xFFFF)
+            self.idle()
+        elif x == 0 and z in (4, 5):  # INC r, DEC r
+            value = self.r8(y)
+            if z == 4:
+                result = (value + 1) & 0xFF
+                self.f = (result == 0 and Z) | ((value & 0xF) == 0xF and H) | self.f & C
+            else:
+                result = (value - 1) & 0xFF
+                self.f = (result == 0 and Z) | N | ((value & 0xF) == 0 and H) | self.f & C
+            self.set_r8(y, result)
+        elif x == 0 and z == 6:  # LD r,n
+            self.set_r8(y, self.fetch())
+        elif x == 0 and z == 7 and y < 4:  # RLCA RRCA RLA RRA
+            self.a = self.shift(y, self.a)
+            self.f &= ~Z
+        elif opcode == 0x27:  # DAA
+            a = self.a
+            carry = self.flag(C)
+            if not self.flag(N):
+                if carry or a > 0x99:
+                    a += 0x60
+                    carry = 1
+                if self.flag(H) or (a & 0xF) > 9:
+                    a += 0x06
+            else:
+                if carry:
+                    a -= 0x60
+                if self.flag(H):
+                    a -= 0x06
+            self.a = a & 0xFF
+            self.f = (self.a == 0 and Z) | self.f & N | (carry and C)
+        elif opcode == 0x2F:  # CPL
+            self.a ^= 0xFF
+            self.f |= N | H
+        elif opcode == 0x37:  # SCF
+            self.f = self.f & Z | C
+        elif opcode == 0x3F:  # CCF
+            self.f = self.f & Z | (self.f & C) ^ C
+        elif opcode == 0x08:  # LD (nn),SP
+            address = self.fetch16()
+            self.write(address, self.sp & 0xFF)
+            self.write((address + 1) & 0xFFFF, self.sp >> 8)
+        elif opcode == 0x18 or x == 0 and z == 0 and y >= 4:  # JR e, JR cc,e
+            offset = self.fetch()
+            if opcode == 0x18 or self.condition(y - 4):
+                self.pc = (self.pc + (offset - 256 if offset > 127 else offset)) & 0xFFFF
+                self.idle()
+        elif x == 1:  # LD r,r
+            self.set_r8(y, self.r8(z))
+        elif x == 2:  # ALU A,r
+            self.alu(y, self.r8(z))
+        elif x == 3 and z == 6:  # ALU A,n
+            self.alu(y, self.fetch())
+        elif x == 3 and z == 0 and y < 4:  # RET cc
+            self.idle()
+            if self.condition(y):
+                self.pc = self.pop()
+                self.idle()
+        elif opcode in (0xC9, 0xD9):  # RET, RETI
+            self.pc = self.pop()
+            self.idle()
+            if opcode == 0xD9:
+                self.ime = 1
+        elif x == 3 and z == 1 and q == 0:  # POP rr
+            value = self.pop()
+            if p == 3:
+                self.a, self.f = value >> 8, value & 0xF0
+            else:
+                self.set_rr(p, value)
+        elif x == 3 and z == 5 and q == 0:  # PUSH rr
+            value = self.pair("a", "f") if p == 3 else self.rr(p)
+            self.idle()
+            self.push(value)
+        elif opcode == 0xC3 or x == 3 and z == 2 and y < 4:  # JP nn, JP cc,nn
+            address = self.fetch16()
+            if opcode == 0xC3 or self.condition(y):
+                self.pc = address
+                self.idle()
+        elif opcode == 0xE9:  # JP HL
+            self.pc = self.hl()
+        elif opcode == 0xCD or x == 3 and z == 4 and y < 4:  # CALL nn, CALL cc,nn
+            address = self.fetch16()
+            if opcode == 0xCD or self.condition(y):
+                self.idle()
+                self.push(self.pc)
+                self.pc = address
+        elif x == 3 and z == 7:  # RST
+            self.idle()
+            self.push(self.pc)
+            self.pc = y * 8
+        elif opcode == 0xE0:
+            self.write(0xFF00 | self.fetch(), self.a)
+        elif opcode == 0xF0:
+            self.a = self.read(0xFF00 | self.fetch())
+        elif opcode == 0xE2:
+            self.write(0xFF00 | self.c, self.a)
+        elif opcode == 0xF2:
+            self.a = self.read(0xFF00 | self.c)
+        elif opcode == 0xEA:
+            self.write(self.fetch16(), self.a)
+        elif opcode == 0xFA:
+            self.a = self.read(self.fetch16())
+        elif opcode == 0xE8:  # ADD SP,e
+            self.sp = self.add_sp()
+            self.idle()
+            self.idle()
+        elif opcode == 0xF8:  # LD HL,SP+e
+            self.set_pair("h", "l", self.add_sp())
+            self.idle()
+        elif opcode == 0xF9:  # LD SP,HL
+            self.sp = self.hl()
+            self.idle()
+        elif opcode == 0xF3:
+            self.ime = 0
+        elif opcode == 0xFB:
+            self.ime = 1
+        elif opcode == 0xCB:
+            self.execute_cb()
+        else:
+            raise ValueError(f"no model for {opcode:02x}")
+
+
+def state(cpu):
+    return {
+        "pc": cpu.pc,
+        "sp": cpu.sp,
+        "a": cpu.a,
+        "b": cpu.b,
+        "c": cpu.c,
+        "d": cpu.d,
+        "e": cpu.e,
+        "f": cpu.f,
+        "h": cpu.h,
+        "l": cpu.l,
+        "ime": cpu.ime,
+        "ie": 0,
+    }
+
+
+def generate(rng, name, opcodes, number):
+    while True:
+        registers = {register: rng.randrange(256) for register in "abcdehl"}
+        # All flags clear then all set first, to take every branch both ways
+        registers["f"] = [0x00, 0xF0][number] if number < 2 else rng.randrange(16) << 4
+        registers.update(pc=rng.randrange(0x10000), sp=rng.randrange(0x10000), ime=rng.randrange(2))
+
+        ram = {(registers["pc"] + i) & 0xFFFF: opcode for i, opcode in enumerate(opcodes)}
+        if RESERVED & ram.keys():
+            continue
+
+        cpu = Cpu(rng, registers, ram)
+        initial = state(cpu)
+        try:
+            cpu.execute()
+        except Retry:
+            continue
+
+        initial["ram"] = sorted([address, value] for address, value in cpu.initial.items())
+        final = state(cpu)
+        final["ram"] = sorted([address, cpu.ram[address]] for address in cpu.initial)
+
+        return {
+            "name": f"{name} {number:04x}",
+            "initial": initial,
+            "final": final,
+            "cycles": cpu.cycles,
+        }
+
+
+def write(directory, name, tests):
+    with open(os.path.join(directory, f"{name}.json"), "w") as file:
+        file.write("[\n")
+        file.write(",\n".join(json.dumps(test, separators=(",", ":")) for test in tests))
+        file.write("\n]\n")
+
+
+def main():
+    directory = os.path.dirname(os.path.abspath(__file__))
+    rng = random.Random(SEED)
+
+    for opcode in range(256):
+        if opcode not in SKIPPED:
+            name = f"{opcode:02x}"
+            write(directory, name, [generate(rng, name, [opcode], i) for i in range(TESTS)])
+
+    for opcode in range(256):
+        name = f"cb {opcode:02x}"
+        write(directory, name, [generate(rng, name, [0xCB, opcode], i) for i in range(TESTS)])
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,186 @@
+// Blargg and mooneye test ROMs
+//
+// The ROMs run headless, without a boot ROM, until they report their
+// result or time out. See tests/roms/README.md.
+
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use gameperson::gameboy::{GameBoy, CYCLES_PER_FRAME};
+use gameperson::serial::LinkPartner;
+
+// LD B,B, the mooneye "debug breakpoint"
+const LD_B_B: u8 = 0x40;
+
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+// Keeps the bytes sent through the serial port
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl LinkPartner for Capture {
+    fn exchange(&mut self, byte: u8) -> u8 {
+        self.0.borrow_mut().push(byte);
+
+        0xff
+    }
+}
+
+// See tests/roms/README.md
+fn load(rom: &str) -> GameBoy {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/roms")
+        .join(rom);
+
+    match fs::read(&path) {
+        Ok(rom) => GameBoy::new(rom, None),
+        Err(e) => panic!("{}: {}, see tests/roms/README.md", path.display(), e),
+    }
+}
+
+// Blargg's ROMs write their output to the serial port, ending it with
+// "Passed" or "Failed".
+fn blargg(rom: &str, timeout_frames: u32) {
+    let mut gameboy = load(rom);
+
+    let serial = Rc::new(RefCell::new(Vec::new()));
+    gameboy
+        .memory_mut()
+        .connect_link(Box::new(Capture(Rc::clone(&serial))));
+
+    let mut cycles: u64 = 0;
+    // Bytes received when the output was last checked
+    let mut checked = 0;
+
+    while cycles < u64::from(timeout_frames) * u64::from(CYCLES_PER_FRAME) {
+        cycles += u64::from(gameboy.step());
+
+        if serial.borrow().len() == checked {
+            continue;
+        }
+        checked = serial.borrow().len();
+
+        let output = String::from_utf8_lossy(&serial.borrow()).into_owned();
+
+        if output.contains("Passed") {
+            return;
+        }
+        assert!(!output.contains("Failed"), "{}:\n{}", rom, output);
+    }
+
+    panic!(
+        "{}: timed out, output:\n{}",
+        rom,
+        String::from_utf8_lossy(&serial.borrow())
+    );
+}
+
+// Mooneye's ROMs execute LD B,B when done, with B, C, D, E, H, L set
+// to the Fibonacci numbers 3, 5, 8, 13, 21, 34 when they pass.
+fn mooneye(rom: &str, timeout_frames: u32) {
+    let mut gameboy = load(rom);
+
+    let mut cycles: u64 = 0;
+
+    while cycles < u64::from(timeout_frames) * u64::from(CYCLES_PER_FRAME) {
+        let opcode = gameboy.memory().peek(gameboy.cpu.pc);
+        let instructions = gameboy.cpu.instructions();
+
+        cycles += u64::from(gameboy.step());
+
+        // The step may have serviced an interrupt or stayed halted instead
+        let executed = gameboy.cpu.instructions() != instructions;
+
+        if executed && opcode == LD_B_B {
+            let regs = &gameboy.cpu.regs;
+            let result = [regs.b, regs.c, regs.d, regs.e, regs.h, regs.l];
+
+            assert_eq!(result, MOONEYE_PASS, "{}: failed, {}", rom, gameboy.cpu);
+            return;
+        }
+    }
+
+    panic!("{}: timed out, {}", rom, gameboy.cpu);
+}
+
+// Built by tests/roms/generate.py, checked in
+#[test]
+fn gameperson_instr_timing() {
+    blargg("gameperson/instr_timing.gb", 60);
+}
+
+#[test]
+fn gameperson_interrupts() {
+    mooneye("gameperson/interrupts.gb", 60);
+}
+
+// The upstream ROMs aren't checked in
+macro_rules! test_roms {
+    ($runner:ident, $timeout_frames:expr, { $($name:ident: $rom:expr,)* }) => {
+        $(
+            #[test]
+            #[ignore = "needs the test ROMs in tests/roms"]
+            fn $name() {
+                $runner($rom, $timeout_frames);
+            }
+        )*
+    };
+}
+
+test_roms!(blargg, 3600, {
+    cpu_instrs_01_special: "blargg/cpu_instrs/individual/01-special.gb",
+    cpu_instrs_02_interrupts: "blargg/cpu_instrs/individual/02-interrupts.gb",
+    cpu_instrs_03_op_sp_hl: "blargg/cpu_instrs/individual/03-op sp,hl.gb",
+    cpu_instrs_04_op_r_imm: "blargg/cpu_instrs/individual/04-op r,imm.gb",
+    cpu_instrs_05_op_rp: "blargg/cpu_instrs/individual/05-op rp.gb",
+    cpu_instrs_06_ld_r_r: "blargg/cpu_instrs/individual/06-ld r,r.gb",
+    cpu_instrs_07_jr_jp_call_ret_rst: "blargg/cpu_instrs/individual/07-jr,jp,call,ret,rst.gb",
+    cpu_instrs_08_misc_instrs: "blargg/cpu_instrs/individual/08-misc instrs.gb",
+    cpu_instrs_09_op_r_r: "blargg/cpu_instrs/individual/09-op r,r.gb",
+    cpu_instrs_10_bit_ops: "blargg/cpu_instrs/individual/10-bit ops.gb",
+    cpu_instrs_11_op_a_hl: "blargg/cpu_instrs/individual/11-op a,(hl).gb",
+    instr_timing: "blargg/instr_timing/instr_timing.gb",
+    mem_timing_01_read_timing: "blargg/mem_timing/individual/01-read_timing.gb",
+    mem_timing_02_write_timing: "blargg/mem_timing/individual/02-write_timing.gb",
+    mem_timing_03_modify_timing: "blargg/mem_timing/individual/03-modify_timing.gb",
+});
+
+test_roms!(mooneye, 600, {
+    mooneye_add_sp_e_timing: "mooneye/acceptance/add_sp_e_timing.gb",
+    mooneye_call_timing: "mooneye/acceptance/call_timing.gb",
+    mooneye_call_cc_timing: "mooneye/acceptance/call_cc_timing.gb",
+    mooneye_di_timing: "mooneye/acceptance/di_timing-GS.gb",
+    mooneye_ei_sequence: "mooneye/acceptance/ei_sequence.gb",
+    mooneye_ei_timing: "mooneye/acceptance/ei_timing.gb",
+    mooneye_halt_ime0_ei: "mooneye/acceptance/halt_ime0_ei.gb",
+    mooneye_halt_ime0_nointr_timing: "mooneye/acceptance/halt_ime0_nointr_timing.gb",
+    mooneye_halt_ime1_timing: "mooneye/acceptance/halt_ime1_timing.gb",
+    mooneye_if_ie_registers: "mooneye/acceptance/if_ie_registers.gb",
+    mooneye_intr_timing: "mooneye/acceptance/intr_timing.gb",
+    mooneye_jp_timing: "mooneye/acceptance/jp_timing.gb",
+    mooneye_jp_cc_timing: "mooneye/acceptance/jp_cc_timing.gb",
+    mooneye_ld_hl_sp_e_timing: "mooneye/acceptance/ld_hl_sp_e_timing.gb",
+    mooneye_pop_timing: "mooneye/acceptance/pop_timing.gb",
+    mooneye_push_timing: "mooneye/acceptance/push_timing.gb",
+    mooneye_rapid_di_ei: "mooneye/acceptance/rapid_di_ei.gb",
+    mooneye_ret_timing: "mooneye/acceptance/ret_timing.gb",
+    mooneye_ret_cc_timing: "mooneye/acceptance/ret_cc_timing.gb",
+    mooneye_reti_timing: "mooneye/acceptance/reti_timing.gb",
+    mooneye_reti_intr_timing: "mooneye/acceptance/reti_intr_timing.gb",
+    mooneye_rst_timing: "mooneye/acceptance/rst_timing.gb",
+    mooneye_instr_daa: "mooneye/acceptance/instr/daa.gb",
+    mooneye_timer_div_write: "mooneye/acceptance/timer/div_write.gb",
+    mooneye_timer_rapid_toggle: "mooneye/acceptance/timer/rapid_toggle.gb",
+    mooneye_timer_tim00: "mooneye/acceptance/timer/tim00.gb",
+    mooneye_timer_tim00_div_trigger: "mooneye/acceptance/timer/tim00_div_trigger.gb",
+    mooneye_timer_tim01: "mooneye/acceptance/timer/tim01.gb",
+    mooneye_timer_tim01_div_trigger: "mooneye/acceptance/timer/tim01_div_trigger.gb",
+    mooneye_timer_tim10: "mooneye/acceptance/timer/tim10.gb",
+    mooneye_timer_tim10_div_trigger: "mooneye/acceptance/timer/tim10_div_trigger.gb",
+    mooneye_timer_tim11: "mooneye/acceptance/timer/tim11.gb",
+    mooneye_timer_tim11_div_trigger: "mooneye/acceptance/timer/tim11_div_trigger.gb",
+    mooneye_timer_tima_reload: "mooneye/acceptance/timer/tima_reload.gb",
+    mooneye_timer_tima_write_reloading: "mooneye/acceptance/timer/tima_write_reloading.gb",
+    mooneye_timer_tma_write_reloading: "mooneye/acceptance/timer/tma_write_reloading.gb",
+});